@@ -1 +1,121 @@
-fn main() {}
\ No newline at end of file
+use std::env::current_exe;
+
+use image::Rgb;
+use luisa::lang::types::vector::alias::*;
+use luisa::lang::types::vector::*;
+use luisa::lang::types::*;
+
+use luisa::prelude::*;
+use luisa::rtx::{
+    AccelBuildRequest, AccelOption, AccelTraceOptions, CubicBSplineCurve, CurveBasis,
+    CurveEvaluator, Ray,
+};
+use luisa_compute as luisa;
+
+#[tracked]
+fn main() {
+    luisa::init_logger();
+
+    let args: Vec<String> = std::env::args().collect();
+    assert!(
+        args.len() <= 2,
+        "Usage: {} <backend>. <backend>: cpu, cuda, dx, metal, remote",
+        args[0]
+    );
+
+    let ctx = Context::new(current_exe().unwrap());
+    let device = ctx.create_device(if args.len() == 2 {
+        args[1].as_str()
+    } else {
+        "cpu"
+    });
+
+    // a few wavy strands, each with `CP_PER_STRAND` control points
+    const STRANDS: u32 = 16;
+    const CP_PER_STRAND: u32 = 8;
+    let mut control_points = vec![];
+    let mut segments = vec![];
+    for s in 0..STRANDS {
+        let x = -0.75 + 1.5 * (s as f32 + 0.5) / STRANDS as f32;
+        let base = control_points.len() as u32;
+        for i in 0..CP_PER_STRAND {
+            let t = i as f32 / (CP_PER_STRAND - 1) as f32;
+            let y = -0.8 + 1.6 * t;
+            let dx = 0.05 * (t * 10.0 + s as f32).sin();
+            let radius = 0.02 * (1.0 - 0.8 * t);
+            control_points.push(Float4::new(x + dx, y, 0.0, radius));
+        }
+        // a cubic b-spline segment uses 4 consecutive control points
+        for i in 0..CP_PER_STRAND - 3 {
+            segments.push(base + i);
+        }
+    }
+    let cp_buffer = device.create_buffer_from_slice(&control_points);
+    let seg_buffer = device.create_buffer_from_slice(&segments);
+    let curve = device.create_curve(
+        CurveBasis::CubicBSpline,
+        cp_buffer.view(..),
+        seg_buffer.view(..),
+        AccelOption::default(),
+    );
+    curve.build(AccelBuildRequest::ForceBuild);
+    let accel = device.create_accel(Default::default());
+    accel.push_curve(&curve, Mat4::identity(), 0xff);
+    accel.build(AccelBuildRequest::ForceBuild);
+
+    let img_w = 800;
+    let img_h = 800;
+    let img = device.create_tex2d::<Float4>(PixelStorage::Byte4, img_w, img_h, 1);
+    let curve_bases = curve.basis_set();
+    let rt_kernel = Kernel::<fn()>::new(&device, &|| {
+        let accel = accel.var();
+        let px = dispatch_id().xy();
+        let xy = px.as_::<Float2>() / Float2::expr(img_w as f32, img_h as f32);
+        let xy = 2.0 * xy - 1.0;
+        let o = Float3::expr(0.0, 0.0, -1.0);
+        let d = Float3::expr(xy.x, -xy.y, 0.0) - o;
+        let d = d.normalize();
+        let ray = Ray::new_expr(
+            Expr::<[f32; 3]>::from(o),
+            1e-3,
+            Expr::<[f32; 3]>::from(d),
+            1e9,
+        );
+        let hit = accel.intersect(
+            ray,
+            AccelTraceOptions {
+                curve_bases,
+                mask: 0xff.expr(),
+            },
+        );
+        let color = Var::<Float3>::zeroed();
+        if hit.valid() & hit.is_curve() {
+            let i0 = seg_buffer.read(hit.prim);
+            let curve = CubicBSplineCurve::new(
+                cp_buffer.read(i0),
+                cp_buffer.read(i0 + 1),
+                cp_buffer.read(i0 + 2),
+                cp_buffer.read(i0 + 3),
+            );
+            let ps = o + d * hit.committed_ray_t;
+            let (_, n) = curve.surface_position_and_normal(hit.curve_parameter(), ps);
+            *color = n * 0.5 + 0.5;
+        }
+        let img = img.view(0).var();
+        img.write(px, color.load().extend(1.0));
+    });
+    let mut img_buffer = vec![[0u8; 4]; (img_w * img_h) as usize];
+    {
+        let scope = device.default_stream().scope();
+        scope.submit([
+            rt_kernel.dispatch_async([img_w, img_h, 1]),
+            img.view(0).copy_to_async(&mut img_buffer),
+        ]);
+    }
+    let img = image::RgbImage::from_fn(img_w, img_h, |x, y| {
+        let i = x + y * img_w;
+        let px = img_buffer[i as usize];
+        Rgb([px[0], px[1], px[2]])
+    });
+    img.save("curve.png").unwrap();
+}
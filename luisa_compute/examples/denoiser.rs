@@ -15,7 +15,7 @@ use luisa::rtx::{
     offset_ray_origin, Accel, AccelBuildRequest, AccelOption, AccelVar, Index, Ray, RayComps,
 };
 use luisa_compute as luisa;
-use std::{env::current_exe, process::exit};
+use std::env::current_exe;
 
 fn main() {
     luisa::init_logger_verbose();
@@ -33,11 +33,9 @@ fn main() {
         "cpu"
     });
     if cfg!(not(feature = "oidn")) {
-        eprintln!("Please enable oidn feature to run this example");
-        exit(1);
-    } else {
-        run_pt(device);
+        eprintln!("oidn feature is not enabled, using the built-in denoiser");
     }
+    run_pt(device);
 }
 
 #[derive(Value, Clone, Copy)]
@@ -486,9 +484,7 @@ fn run_pt(device: Device) {
     let albedo_buf = device.create_buffer::<Float4>((img_w * img_h) as usize);
     let normal_buf = device.create_buffer::<Float4>((img_w * img_h) as usize);
     let output_buf = device.create_buffer::<Float4>((img_w * img_h) as usize);
-    let ext = device
-        .denoiser_ext()
-        .unwrap_or_else(|| panic!("denoiser not available on current device"));
+    let ext = device.denoiser_ext_or_builtin();
     let mut denoiser = ext.create(&device.default_stream());
     {
         let mut inputs = DenoiserInput::new(img_w, img_h);
@@ -0,0 +1,16 @@
+//! A kernel exported for [`Device::create_hot_reload_kernel`].
+//!
+//! This example is a `cdylib` without `main`. Build it with
+//! `cargo build --example hot_reload_kernel` and load the library from
+//! `target/debug/examples`. The `hot_reload_kernel` test uses it too.
+use luisa::prelude::*;
+use luisa_compute as luisa;
+
+luisa::export_hot_reload_kernel!(
+    fill,
+    fn(Buffer<f32>),
+    track!(|buf| {
+        let i = dispatch_id().x;
+        buf.write(i, i.as_f32() + 1.0);
+    })
+);
@@ -0,0 +1,796 @@
+//! Prebuilt image processing kernels over [`Float4`] images.
+//!
+//! [`ImageProcessor`] compiles each kernel the first time it is needed and
+//! keeps it, so one instance per device can be shared by all tools. Images are
+//! either textures ([`Tex2d`], [`Tex2dView`]) or row-major buffers wrapped in a
+//! [`BufferImage`], and any combination of the two can be used as source and
+//! destination.
+//!
+//! Colors are linear Rec.709 (linear sRGB) unless stated otherwise. The
+//! color functions used by the kernels, such as [`srgb_to_linear`] and
+//! [`tonemap`], are public so that custom kernels can share them.
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::internal_prelude::*;
+pub use crate::lang::functions::{linear_to_srgb, srgb_to_linear};
+use crate::runtime::{KernelArg, KernelArgEncoder};
+
+mod metrics;
+mod temporal;
+pub use metrics::*;
+pub use temporal::*;
+
+/// Storage types accepted by the [`ImageProcessor`] kernels
+pub trait ImageStorage: KernelArg + 'static {
+    #[doc(hidden)]
+    fn load(image: &Self::Parameter, p: Expr<Uint2>, width: Expr<u32>) -> Expr<Float4>;
+    #[doc(hidden)]
+    fn store(image: &Self::Parameter, p: Expr<Uint2>, width: Expr<u32>, v: Expr<Float4>);
+}
+
+impl ImageStorage for Tex2d<Float4> {
+    fn load(image: &Tex2dVar<Float4>, p: Expr<Uint2>, _: Expr<u32>) -> Expr<Float4> {
+        image.read(p)
+    }
+    fn store(image: &Tex2dVar<Float4>, p: Expr<Uint2>, _: Expr<u32>, v: Expr<Float4>) {
+        image.write(p, v);
+    }
+}
+
+impl ImageStorage for Buffer<Float4> {
+    fn load(image: &BufferVar<Float4>, p: Expr<Uint2>, width: Expr<u32>) -> Expr<Float4> {
+        image.read(track!(p.x + p.y * width))
+    }
+    fn store(image: &BufferVar<Float4>, p: Expr<Uint2>, width: Expr<u32>, v: Expr<Float4>) {
+        image.write(track!(p.x + p.y * width), v);
+    }
+}
+
+/// An image that can be passed to [`ImageProcessor`]
+pub trait Image<S: ImageStorage>: AsKernelArg<Output = S> {
+    fn image_size(&self) -> [u32; 2];
+}
+
+impl Image<Tex2d<Float4>> for Tex2dView<Float4> {
+    fn image_size(&self) -> [u32; 2] {
+        let [w, h, _] = self.size();
+        [w, h]
+    }
+}
+
+impl Image<Tex2d<Float4>> for Tex2d<Float4> {
+    fn image_size(&self) -> [u32; 2] {
+        self.view(0).image_size()
+    }
+}
+
+/// A `width` x `height` image stored row by row in a buffer
+pub struct BufferImage<'a> {
+    buffer: &'a BufferView<Float4>,
+    width: u32,
+    height: u32,
+}
+
+impl<'a> BufferImage<'a> {
+    pub fn new(buffer: &'a BufferView<Float4>, width: u32, height: u32) -> Self {
+        assert_eq!(buffer.len(), width as usize * height as usize);
+        Self {
+            buffer,
+            width,
+            height,
+        }
+    }
+}
+
+impl KernelArg for BufferImage<'_> {
+    type Parameter = BufferVar<Float4>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        self.buffer.encode(encoder);
+    }
+}
+
+impl AsKernelArg for BufferImage<'_> {
+    type Output = Buffer<Float4>;
+}
+
+impl Image<Buffer<Float4>> for BufferImage<'_> {
+    fn image_size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Blur {
+    Gaussian {
+        sigma: f32,
+    },
+    /// Average over `2 * radius + 1` pixels along each axis
+    Box {
+        radius: u32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResizeFilter {
+    Bilinear,
+    /// Lanczos with a radius of 3 pixels, widened when downscaling
+    Lanczos3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Rec.709 primaries with the sRGB transfer function
+    Srgb,
+    /// Rec.709 primaries, linear
+    LinearSrgb,
+    /// ACES AP1 primaries, linear
+    AcesCg,
+    /// Rec.2020 primaries, linear
+    Rec2020,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tonemap {
+    /// Clamps to `[0, 1]`
+    Clamp,
+    /// `c / (1 + c)` per channel
+    Reinhard,
+    /// Reinhard mapping `white` to 1
+    ReinhardExtended { white: f32 },
+    /// John Hable's filmic curve from Uncharted 2, with a white point of 11.2
+    Hable,
+    /// Krzysztof Narkowicz's single curve fit of the ACES reference transform
+    AcesNarkowicz,
+    /// Stephen Hill's fit of the ACES RRT and sRGB ODT
+    AcesHill,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TonemapOptions {
+    pub operator: Tonemap,
+    /// Exposure in stops applied before the operator
+    pub exposure: f32,
+    /// Color space of the output, usually [`ColorSpace::Srgb`] for display
+    pub output: ColorSpace,
+}
+
+impl From<Tonemap> for TonemapOptions {
+    fn from(operator: Tonemap) -> Self {
+        Self {
+            operator,
+            exposure: 0.0,
+            output: ColorSpace::Srgb,
+        }
+    }
+}
+
+impl Default for TonemapOptions {
+    fn default() -> Self {
+        Tonemap::AcesHill.into()
+    }
+}
+
+/// Histogram of `log2` luminance with `bins` equal bins over `[min_log2, max_log2]`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HistogramOptions {
+    pub bins: u32,
+    pub min_log2: f32,
+    pub max_log2: f32,
+    /// Fractions of darkest and brightest pixels ignored by [`exposure_from_histogram`]
+    pub percentiles: [f32; 2],
+}
+
+impl Default for HistogramOptions {
+    fn default() -> Self {
+        Self {
+            bins: 64,
+            min_log2: -12.0,
+            max_log2: 12.0,
+            percentiles: [0.1, 0.9],
+        }
+    }
+}
+
+/// Porter-Duff and blend operators on premultiplied colors, `a` is composited onto `b`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CompositeOp {
+    Over,
+    In,
+    Out,
+    Atop,
+    Xor,
+    Add,
+    Multiply,
+    Screen,
+}
+
+const REC709_TO_REC2020: [[f32; 3]; 3] = [
+    [0.6274040, 0.3292820, 0.0433136],
+    [0.0690970, 0.9195400, 0.0113612],
+    [0.0163916, 0.0880132, 0.8955950],
+];
+const REC2020_TO_REC709: [[f32; 3]; 3] = [
+    [1.6604910, -0.5876411, -0.0728499],
+    [-0.1245505, 1.1328999, -0.0083494],
+    [-0.0181508, -0.1005789, 1.1187297],
+];
+const REC709_TO_ACESCG: [[f32; 3]; 3] = [
+    [0.6130974, 0.3395231, 0.0473795],
+    [0.0701937, 0.9163539, 0.0134524],
+    [0.0206156, 0.1095698, 0.8698147],
+];
+const ACESCG_TO_REC709: [[f32; 3]; 3] = [
+    [1.7048587, -0.6217160, -0.0831427],
+    [-0.1300768, 1.1407358, -0.0106590],
+    [-0.0239640, -0.1289755, 1.1529395],
+];
+/// Rec.709 to the RRT input space of [`Tonemap::AcesHill`]
+const ACES_HILL_INPUT: [[f32; 3]; 3] = [
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+];
+const ACES_HILL_OUTPUT: [[f32; 3]; 3] = [
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+];
+
+fn mat_mul(m: [[f32; 3]; 3], c: Expr<Float3>) -> Expr<Float3> {
+    let row = |r: [f32; 3]| track!(r[0] * c.x + r[1] * c.y + r[2] * c.z);
+    Float3::expr(row(m[0]), row(m[1]), row(m[2]))
+}
+
+fn map_channels(c: Expr<Float3>, f: impl Fn(Expr<f32>) -> Expr<f32>) -> Expr<Float3> {
+    Float3::expr(f(c.x), f(c.y), f(c.z))
+}
+
+/// Relative luminance of a linear Rec.709 color
+pub fn luminance(c: impl AsExpr<Value = Float3>) -> Expr<f32> {
+    let c = c.as_expr();
+    track!(0.2126f32 * c.x + 0.7152f32 * c.y + 0.0722f32 * c.z)
+}
+
+/// Converts a color between color spaces
+pub fn convert_color(
+    c: impl AsExpr<Value = Float3>,
+    from: ColorSpace,
+    to: ColorSpace,
+) -> Expr<Float3> {
+    let c = c.as_expr();
+    if from == to {
+        return c;
+    }
+    let linear = match from {
+        ColorSpace::Srgb => srgb_to_linear(c),
+        ColorSpace::LinearSrgb => c,
+        ColorSpace::AcesCg => mat_mul(ACESCG_TO_REC709, c),
+        ColorSpace::Rec2020 => mat_mul(REC2020_TO_REC709, c),
+    };
+    match to {
+        ColorSpace::Srgb => linear_to_srgb(linear),
+        ColorSpace::LinearSrgb => linear,
+        ColorSpace::AcesCg => mat_mul(REC709_TO_ACESCG, linear),
+        ColorSpace::Rec2020 => mat_mul(REC709_TO_REC2020, linear),
+    }
+}
+
+const HABLE: [f32; 6] = [0.15, 0.50, 0.10, 0.20, 0.02, 0.30];
+const HABLE_WHITE: f32 = 11.2;
+
+#[tracked]
+fn hable(x: Expr<f32>) -> Expr<f32> {
+    let [a, b, c, d, e, f] = HABLE;
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+fn hable_host(x: f32) -> f32 {
+    let [a, b, c, d, e, f] = HABLE;
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+#[tracked]
+fn sinc(x: Expr<f32>) -> Expr<f32> {
+    let px = x * std::f32::consts::PI;
+    select(x.abs() < 1e-4f32, 1.0f32.expr(), px.sin() / px)
+}
+
+/// Applies a tonemapping operator to a linear Rec.709 color, the result is
+/// linear Rec.709 in `[0, 1]`
+pub fn tonemap(c: impl AsExpr<Value = Float3>, operator: Tonemap) -> Expr<Float3> {
+    let c = map_channels(c.as_expr(), |x| track!(x.max_(0.0f32)));
+    let mapped = match operator {
+        Tonemap::Clamp => c,
+        Tonemap::Reinhard => track!(c / (c + 1.0f32)),
+        Tonemap::ReinhardExtended { white } => {
+            let w2 = white * white;
+            track!(c * (c / w2 + 1.0f32) / (c + 1.0f32))
+        }
+        Tonemap::Hable => {
+            let scale = 1.0 / hable_host(HABLE_WHITE);
+            map_channels(c, |x| track!(hable(x) * scale))
+        }
+        Tonemap::AcesNarkowicz => map_channels(c, |x| {
+            track!((x * (2.51f32 * x + 0.03f32)) / (x * (2.43f32 * x + 0.59f32) + 0.14f32))
+        }),
+        Tonemap::AcesHill => {
+            let v = mat_mul(ACES_HILL_INPUT, c);
+            let v = map_channels(v, |v| {
+                track!(
+                    (v * (v + 0.0245786f32) - 0.000090537f32)
+                        / (v * (0.983729f32 * v + 0.4329510f32) + 0.238081f32)
+                )
+            });
+            mat_mul(ACES_HILL_OUTPUT, v)
+        }
+    };
+    map_channels(mapped, |x| x.clamp(0.0f32.expr(), 1.0f32.expr()))
+}
+
+/// Composites the premultiplied color `a` onto `b`
+pub fn composite(
+    a: impl AsExpr<Value = Float4>,
+    b: impl AsExpr<Value = Float4>,
+    op: CompositeOp,
+) -> Expr<Float4> {
+    let (a, b) = (a.as_expr(), b.as_expr());
+    let (aa, ab) = (a.w, b.w);
+    match op {
+        CompositeOp::Over => track!(a + b * (1.0f32 - aa)),
+        CompositeOp::In => track!(a * ab),
+        CompositeOp::Out => track!(a * (1.0f32 - ab)),
+        CompositeOp::Atop => {
+            let c = track!(a.xyz() * ab + b.xyz() * (1.0f32 - aa));
+            Float4::expr(c.x, c.y, c.z, ab)
+        }
+        CompositeOp::Xor => track!(a * (1.0f32 - ab) + b * (1.0f32 - aa)),
+        CompositeOp::Add => track!(a + b),
+        CompositeOp::Multiply => {
+            let (ca, cb) = (a.xyz(), b.xyz());
+            let c = track!(ca * cb + ca * (1.0f32 - ab) + cb * (1.0f32 - aa));
+            Float4::expr(c.x, c.y, c.z, track!(aa + ab - aa * ab))
+        }
+        CompositeOp::Screen => track!(a + b - a * b),
+    }
+}
+
+fn clamp_coord(x: Expr<i32>, y: Expr<i32>, size: Expr<Uint2>) -> Expr<Uint2> {
+    let size = size.as_int2();
+    Uint2::expr(
+        track!(x.clamp(0i32.expr(), size.x - 1)).as_u32(),
+        track!(y.clamp(0i32.expr(), size.y - 1)).as_u32(),
+    )
+}
+
+/// One pass of a separable blur along `axis`, box filters have `sigma == 0`
+#[tracked]
+fn blur_pass<S: ImageStorage, D: ImageStorage>(
+    src: &S::Parameter,
+    dst: &D::Parameter,
+    size: Expr<Uint2>,
+    axis: Expr<u32>,
+    sigma: Expr<f32>,
+    radius: Expr<u32>,
+) {
+    let p = dispatch_id().xy();
+    let r = radius.as_i32();
+    let sum = Float4::var_zeroed();
+    let weights = f32::var_zeroed();
+    for i in 0u32.expr()..(2u32 * radius + 1u32) {
+        let o = i.as_i32() - r;
+        let (ox, oy) = (
+            select(axis == 0u32, o, 0i32.expr()),
+            select(axis == 0u32, 0i32.expr(), o),
+        );
+        let q = clamp_coord(p.x.as_i32() + ox, p.y.as_i32() + oy, size);
+        let x = o.as_f32();
+        let w = select(
+            sigma > 0.0f32,
+            (-(x * x) / (2.0f32 * sigma * sigma)).exp(),
+            1.0f32.expr(),
+        );
+        *sum += S::load(src, q, size.x) * w;
+        *weights += w;
+    }
+    D::store(dst, p, size.x, sum.load() / weights.load());
+}
+
+#[tracked]
+fn resize_weight(filter: ResizeFilter, x: Expr<f32>) -> Expr<f32> {
+    match filter {
+        ResizeFilter::Bilinear => (1.0f32 - x.abs()).max_(0.0f32),
+        ResizeFilter::Lanczos3 => {
+            select(x.abs() < 3.0f32, sinc(x) * sinc(x / 3.0f32), 0.0f32.expr())
+        }
+    }
+}
+
+/// One pass of a separable resize along `axis` from `src_size` to `dst_size`,
+/// which differ only along `axis`
+#[tracked]
+fn resize_pass<S: ImageStorage, D: ImageStorage>(
+    filter: ResizeFilter,
+    src: &S::Parameter,
+    dst: &D::Parameter,
+    src_size: Expr<Uint2>,
+    dst_size: Expr<Uint2>,
+    axis: Expr<u32>,
+) {
+    let p = dispatch_id().xy();
+    let along_x = axis == 0u32;
+    let n_in = select(along_x, src_size.x, src_size.y).as_f32();
+    let n_out = select(along_x, dst_size.x, dst_size.y).as_f32();
+    let coord = select(along_x, p.x, p.y).as_f32();
+    let scale = n_in / n_out;
+    let support = scale.max_(1.0f32);
+    let center = (coord + 0.5f32) * scale;
+    let radius = match filter {
+        ResizeFilter::Bilinear => support,
+        ResizeFilter::Lanczos3 => support * 3.0f32,
+    };
+    let start = (center - radius).floor().as_i32();
+    let taps = (radius * 2.0f32).ceil().as_u32() + 1u32;
+    let sum = Float4::var_zeroed();
+    let weights = f32::var_zeroed();
+    for t in 0u32.expr()..taps {
+        let i = start + t.as_i32();
+        let w = resize_weight(filter, (i.as_f32() + 0.5f32 - center) / support);
+        if w != 0.0f32 {
+            let q = select(
+                along_x,
+                clamp_coord(i, p.y.as_i32(), src_size),
+                clamp_coord(p.x.as_i32(), i, src_size),
+            );
+            *sum += S::load(src, q, src_size.x) * w;
+            *weights += w;
+        }
+    }
+    D::store(dst, p, dst_size.x, sum.load() / weights.load());
+}
+
+/// Prebuilt image kernels, compiled on first use and cached
+pub struct ImageProcessor {
+    device: Device,
+    kernels: RefCell<HashMap<(TypeId, String), Rc<dyn Any>>>,
+    /// Intermediate images of separable filters by size
+    scratch: RefCell<HashMap<[u32; 2], Rc<Tex2d<Float4>>>>,
+}
+
+impl ImageProcessor {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            kernels: RefCell::new(HashMap::new()),
+            scratch: RefCell::new(HashMap::new()),
+        }
+    }
+    /// Returns the kernel (or other per-device resource) cached under `key`,
+    /// building it if needed
+    ///
+    /// Keys start with the name of the operation, so that two kernels of the
+    /// same type never share an entry.
+    fn kernel<K: Any>(&self, key: String, build: impl FnOnce(&Device) -> K) -> Rc<K> {
+        let key = (TypeId::of::<K>(), key);
+        if let Some(kernel) = self.kernels.borrow().get(&key) {
+            return kernel.clone().downcast::<K>().unwrap();
+        }
+        let kernel = Rc::new(build(&self.device));
+        self.kernels.borrow_mut().insert(key, kernel.clone());
+        kernel
+    }
+    fn scratch(&self, size: [u32; 2]) -> Rc<Tex2d<Float4>> {
+        self.scratch
+            .borrow_mut()
+            .entry(size)
+            .or_insert_with(|| {
+                Rc::new(
+                    self.device
+                        .create_tex2d(PixelStorage::Float4, size[0], size[1], 1),
+                )
+            })
+            .clone()
+    }
+
+    /// Blurs `src` into `dst` with a separable filter, clamping at the edges
+    ///
+    /// The horizontal pass goes through an intermediate texture owned by the
+    /// processor, so commands of concurrent blurs of the same size must not
+    /// overlap on different streams.
+    pub fn blur_async<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        blur: Blur,
+    ) -> Vec<Command<'static, 'static>> {
+        let size = src.image_size();
+        assert_eq!(size, dst.image_size());
+        let (sigma, radius) = match blur {
+            Blur::Gaussian { sigma } => {
+                assert!(sigma > 0.0, "sigma must be positive");
+                (sigma, (3.0 * sigma).ceil() as u32)
+            }
+            Blur::Box { radius } => (0.0, radius),
+        };
+        let scratch = self.scratch(size);
+        let first = self.kernel("blur_h".into(), |device| {
+            Kernel::<fn(S, Tex2d<Float4>, Uint2, u32, f32, u32)>::new(
+                device,
+                &|src, dst, size, axis, sigma, radius| {
+                    blur_pass::<S, Tex2d<Float4>>(&src, &dst, size, axis, sigma, radius)
+                },
+            )
+        });
+        let second = self.kernel("blur_v".into(), |device| {
+            Kernel::<fn(Tex2d<Float4>, D, Uint2, u32, f32, u32)>::new(
+                device,
+                &|src, dst, size, axis, sigma, radius| {
+                    blur_pass::<Tex2d<Float4>, D>(&src, &dst, size, axis, sigma, radius)
+                },
+            )
+        });
+        let dispatch = [size[0], size[1], 1];
+        let size = Uint2::from(size);
+        vec![
+            first.dispatch_async(dispatch, src, &*scratch, &size, &0u32, &sigma, &radius),
+            second.dispatch_async(dispatch, &*scratch, dst, &size, &1u32, &sigma, &radius),
+        ]
+    }
+    pub fn blur<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        blur: Blur,
+    ) {
+        submit_default_stream_and_sync(&self.device, self.blur_async(src, dst, blur));
+    }
+
+    /// Resamples `src` to the size of `dst`, filtering first horizontally and
+    /// then vertically through an intermediate texture owned by the processor
+    pub fn resize_async<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        filter: ResizeFilter,
+    ) -> Vec<Command<'static, 'static>> {
+        let (src_size, dst_size) = (src.image_size(), dst.image_size());
+        let mid_size = [dst_size[0], src_size[1]];
+        let scratch = self.scratch(mid_size);
+        let first = self.kernel(format!("resize_h {:?}", filter), |device| {
+            Kernel::<fn(S, Tex2d<Float4>, Uint2, Uint2, u32)>::new(
+                device,
+                &|src, dst, src_size, dst_size, axis| {
+                    resize_pass::<S, Tex2d<Float4>>(filter, &src, &dst, src_size, dst_size, axis)
+                },
+            )
+        });
+        let second = self.kernel(format!("resize_v {:?}", filter), |device| {
+            Kernel::<fn(Tex2d<Float4>, D, Uint2, Uint2, u32)>::new(
+                device,
+                &|src, dst, src_size, dst_size, axis| {
+                    resize_pass::<Tex2d<Float4>, D>(filter, &src, &dst, src_size, dst_size, axis)
+                },
+            )
+        });
+        let (src_u, mid_u, dst_u) = (
+            Uint2::from(src_size),
+            Uint2::from(mid_size),
+            Uint2::from(dst_size),
+        );
+        vec![
+            first.dispatch_async(
+                [mid_size[0], mid_size[1], 1],
+                src,
+                &*scratch,
+                &src_u,
+                &mid_u,
+                &0u32,
+            ),
+            second.dispatch_async(
+                [dst_size[0], dst_size[1], 1],
+                &*scratch,
+                dst,
+                &mid_u,
+                &dst_u,
+                &1u32,
+            ),
+        ]
+    }
+    pub fn resize<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        filter: ResizeFilter,
+    ) {
+        submit_default_stream_and_sync(&self.device, self.resize_async(src, dst, filter));
+    }
+
+    /// Converts the colors of `src` from one color space to another, alpha is kept
+    pub fn convert_async<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        from: ColorSpace,
+        to: ColorSpace,
+    ) -> Command<'static, 'static> {
+        let size = src.image_size();
+        assert_eq!(size, dst.image_size());
+        let kernel = self.kernel(format!("convert {:?} {:?}", from, to), |device| {
+            Kernel::<fn(S, D, Uint2)>::new(device, &|src, dst, size| {
+                let p = dispatch_id().xy();
+                let c = S::load(&src, p, size.x);
+                let rgb = convert_color(c.xyz(), from, to);
+                D::store(&dst, p, size.x, Float4::expr(rgb.x, rgb.y, rgb.z, c.w));
+            })
+        });
+        kernel.dispatch_async([size[0], size[1], 1], src, dst, &Uint2::from(size))
+    }
+    pub fn convert<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        from: ColorSpace,
+        to: ColorSpace,
+    ) {
+        submit_default_stream_and_sync(&self.device, [self.convert_async(src, dst, from, to)]);
+    }
+
+    /// Tonemaps a linear Rec.709 image for display, alpha is kept
+    pub fn tonemap_async<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        options: impl Into<TonemapOptions>,
+    ) -> Command<'static, 'static> {
+        let options = options.into();
+        let size = src.image_size();
+        assert_eq!(size, dst.image_size());
+        let (operator, output) = (options.operator, options.output);
+        let key = format!("tonemap {:?} {:?}", operator, output);
+        let kernel = self.kernel(key, |device| {
+            Kernel::<fn(S, D, Uint2, f32)>::new(device, &|src, dst, size, scale| {
+                let p = dispatch_id().xy();
+                let c = S::load(&src, p, size.x);
+                let rgb = tonemap(track!(c.xyz() * scale), operator);
+                let rgb = convert_color(rgb, ColorSpace::LinearSrgb, output);
+                D::store(&dst, p, size.x, Float4::expr(rgb.x, rgb.y, rgb.z, c.w));
+            })
+        });
+        let scale = options.exposure.exp2();
+        kernel.dispatch_async([size[0], size[1], 1], src, dst, &Uint2::from(size), &scale)
+    }
+    pub fn tonemap<S: ImageStorage, D: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        dst: &impl Image<D>,
+        options: impl Into<TonemapOptions>,
+    ) {
+        submit_default_stream_and_sync(&self.device, [self.tonemap_async(src, dst, options)]);
+    }
+
+    /// Counts the pixels of `src` per bin of `log2` luminance into `histogram`,
+    /// which must have `options.bins` elements; out of range values go to the
+    /// first or last bin
+    pub fn luminance_histogram_async<S: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        histogram: &BufferView<u32>,
+        options: &HistogramOptions,
+    ) -> Vec<Command<'static, 'static>> {
+        assert_eq!(histogram.len(), options.bins as usize);
+        let size = src.image_size();
+        let clear = self.kernel("histogram_clear".into(), |device| {
+            Kernel::<fn(Buffer<u32>)>::new(device, &|histogram| {
+                histogram.write(dispatch_id().x, 0u32);
+            })
+        });
+        let count = self.kernel("histogram_count".into(), |device| {
+            Kernel::<fn(S, Buffer<u32>, Uint2, Float2, u32)>::new(
+                device,
+                &|src, histogram, size, range, bins| {
+                    let p = dispatch_id().xy();
+                    let l = luminance(S::load(&src, p, size.x).xyz());
+                    let t = track!((l.max_(1e-20f32).log2() - range.x) / (range.y - range.x));
+                    let bin = track!((t * bins.as_f32())
+                        .clamp(0.0f32.expr(), (bins - 1u32).as_f32())
+                        .as_u32());
+                    histogram.atomic_fetch_add(bin, 1u32);
+                },
+            )
+        });
+        vec![
+            clear.dispatch_async([options.bins, 1, 1], histogram),
+            count.dispatch_async(
+                [size[0], size[1], 1],
+                src,
+                histogram,
+                &Uint2::from(size),
+                &Float2::new(options.min_log2, options.max_log2),
+                &options.bins,
+            ),
+        ]
+    }
+    pub fn luminance_histogram<S: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        options: &HistogramOptions,
+    ) -> Vec<u32> {
+        let histogram = self.device.create_buffer::<u32>(options.bins as usize);
+        submit_default_stream_and_sync(
+            &self.device,
+            self.luminance_histogram_async(src, &histogram.view(..), options),
+        );
+        histogram.copy_to_vec()
+    }
+    /// Exposure in stops that maps the average luminance of `src` to middle
+    /// grey, see [`exposure_from_histogram`]
+    pub fn auto_exposure<S: ImageStorage>(
+        &self,
+        src: &impl Image<S>,
+        options: &HistogramOptions,
+    ) -> f32 {
+        exposure_from_histogram(&self.luminance_histogram(src, options), options)
+    }
+
+    /// Composites the premultiplied image `a` onto `b`
+    pub fn composite_async<A: ImageStorage, B: ImageStorage, D: ImageStorage>(
+        &self,
+        a: &impl Image<A>,
+        b: &impl Image<B>,
+        dst: &impl Image<D>,
+        op: CompositeOp,
+    ) -> Command<'static, 'static> {
+        let size = a.image_size();
+        assert_eq!(size, b.image_size());
+        assert_eq!(size, dst.image_size());
+        let kernel = self.kernel(format!("composite {:?}", op), |device| {
+            Kernel::<fn(A, B, D, Uint2)>::new(device, &|a, b, dst, size| {
+                let p = dispatch_id().xy();
+                let c = composite(A::load(&a, p, size.x), B::load(&b, p, size.x), op);
+                D::store(&dst, p, size.x, c);
+            })
+        });
+        kernel.dispatch_async([size[0], size[1], 1], a, b, dst, &Uint2::from(size))
+    }
+    pub fn composite<A: ImageStorage, B: ImageStorage, D: ImageStorage>(
+        &self,
+        a: &impl Image<A>,
+        b: &impl Image<B>,
+        dst: &impl Image<D>,
+        op: CompositeOp,
+    ) {
+        submit_default_stream_and_sync(&self.device, [self.composite_async(a, b, dst, op)]);
+    }
+}
+
+/// Exposure in stops that maps the mean `log2` luminance of a histogram from
+/// [`ImageProcessor::luminance_histogram`] to middle grey (0.18)
+///
+/// Pixels below and above `options.percentiles` are ignored, which keeps a few
+/// very dark or bright pixels from dominating.
+pub fn exposure_from_histogram(histogram: &[u32], options: &HistogramOptions) -> f32 {
+    assert_eq!(histogram.len(), options.bins as usize);
+    let total = histogram.iter().map(|&c| c as f64).sum::<f64>();
+    if total == 0.0 {
+        return 0.0;
+    }
+    let [low, high] = options.percentiles.map(|p| p as f64 * total);
+    let bin_width = (options.max_log2 - options.min_log2) as f64 / options.bins as f64;
+    let (mut seen, mut sum, mut weight) = (0.0, 0.0, 0.0);
+    for (i, &count) in histogram.iter().enumerate() {
+        // part of this bin between the two percentiles
+        let begin = seen;
+        seen += count as f64;
+        let inside = (seen.min(high) - begin.max(low)).max(0.0);
+        let center = options.min_log2 as f64 + (i as f64 + 0.5) * bin_width;
+        sum += inside * center;
+        weight += inside;
+    }
+    if weight == 0.0 {
+        return 0.0;
+    }
+    (0.18f64.log2() - sum / weight) as f32
+}
@@ -0,0 +1,623 @@
+//! Image comparison on the device, for golden-image tests.
+//!
+//! [`ImageProcessor::compare`] computes a per-pixel error map and its mean
+//! without copying the images to the host. Inputs are linear Rec.709; FLIP
+//! clamps them to `[0, 1]` like the reference LDR implementation.
+use std::f32::consts::PI;
+use std::io::Write;
+use std::path::Path;
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageMetric {
+    /// Mean squared error of the color channels, see also [`psnr`]
+    Mse,
+    /// Structural similarity of the luminance over an 11x11 Gaussian window,
+    /// 1 for identical images
+    Ssim,
+    /// LDR-FLIP (Andersson et al. 2020), a perceptual difference in `[0, 1]`
+    /// for images seen at the given number of pixels per degree of visual angle
+    Flip { pixels_per_degree: f32 },
+}
+
+impl ImageMetric {
+    /// FLIP under the viewing conditions of the reference implementation,
+    /// a 0.7 m wide 4K monitor seen from 0.7 m
+    pub const FLIP: Self = Self::Flip {
+        pixels_per_degree: 3840.0 * PI / 180.0,
+    };
+    /// Turns a value of this metric into an error that is 0 for identical images
+    pub fn error(&self, value: f32) -> f32 {
+        match self {
+            ImageMetric::Ssim => 1.0 - value,
+            _ => value,
+        }
+    }
+}
+
+/// Peak signal-to-noise ratio in decibels of a mean squared error, for a peak of 1
+pub fn psnr(mse: f32) -> f32 {
+    -10.0 * mse.log10()
+}
+
+const SSIM_RADIUS: u32 = 5;
+const SSIM_SIGMA: f32 = 1.5;
+const SSIM_C1: f32 = 0.01 * 0.01;
+const SSIM_C2: f32 = 0.03 * 0.03;
+
+const LINRGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+const XYZ_TO_LINRGB: [[f32; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+/// The D65 white point, `LINRGB_TO_XYZ * (1, 1, 1)`
+const WHITE: [f32; 3] = [0.9504700, 1.0000001, 1.0888300];
+
+/// FLIP color difference exponent, its redistribution cutoff and the value it maps to
+const FLIP_QC: f32 = 0.7;
+const FLIP_PC: f32 = 0.4;
+const FLIP_PT: f32 = 0.95;
+/// FLIP feature difference exponent
+const FLIP_QF: f32 = 0.5;
+/// Peak to trough width in degrees of the edge detection filter
+const FLIP_FEATURE_WIDTH: f32 = 0.082;
+/// Contrast sensitivity filters of the Y, Cx and Cz channels as the sum of two
+/// Gaussians `(a1, b1, a2, b2)`
+const FLIP_CSF: [[f32; 4]; 3] = [
+    [1.0, 0.0047, 0.0, 1e-5],
+    [1.0, 0.0053, 0.0, 1e-5],
+    [34.1, 0.04, 13.5, 0.025],
+];
+
+#[tracked]
+fn xyz_to_ycxcz(xyz: Expr<Float3>) -> Expr<Float3> {
+    let n = xyz / Float3::from(WHITE).expr();
+    Float3::expr(
+        116.0f32 * n.y - 16.0f32,
+        500.0f32 * (n.x - n.y),
+        200.0f32 * (n.y - n.z),
+    )
+}
+
+#[tracked]
+fn ycxcz_to_xyz(c: Expr<Float3>) -> Expr<Float3> {
+    let y = (c.x + 16.0f32) / 116.0f32;
+    Float3::expr(c.y / 500.0f32 + y, y, y - c.z / 200.0f32) * Float3::from(WHITE).expr()
+}
+
+#[tracked]
+fn lab_f(t: Expr<f32>) -> Expr<f32> {
+    let delta = 6.0f32 / 29.0;
+    select(
+        t > delta * delta * delta,
+        t.max_(0.0f32).powf(1.0f32 / 3.0),
+        t / (3.0f32 * delta * delta) + 4.0f32 / 29.0,
+    )
+}
+
+fn lab_f_host(t: f32) -> f32 {
+    let delta = 6.0f32 / 29.0;
+    if t > delta * delta * delta {
+        t.cbrt()
+    } else {
+        t / (3.0 * delta * delta) + 4.0 / 29.0
+    }
+}
+
+/// CIELAB of a linear color with FLIP's Hunt adjustment, which scales the
+/// chroma by the lightness
+#[tracked]
+fn hunt_lab(rgb: Expr<Float3>) -> Expr<Float3> {
+    let xyz = mat_mul(LINRGB_TO_XYZ, rgb);
+    let (fx, fy, fz) = (
+        lab_f(xyz.x / WHITE[0]),
+        lab_f(xyz.y / WHITE[1]),
+        lab_f(xyz.z / WHITE[2]),
+    );
+    let l = 116.0f32 * fy - 16.0f32;
+    Float3::expr(
+        l,
+        0.01f32 * l * 500.0f32 * (fx - fy),
+        0.01f32 * l * 200.0f32 * (fy - fz),
+    )
+}
+
+fn hunt_lab_host(rgb: [f32; 3]) -> [f32; 3] {
+    let xyz = LINRGB_TO_XYZ.map(|row| row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]);
+    let [fx, fy, fz] = [0, 1, 2].map(|i| lab_f_host(xyz[i] / WHITE[i]));
+    let l = 116.0 * fy - 16.0;
+    [
+        l,
+        0.01 * l * 500.0 * (fx - fy),
+        0.01 * l * 200.0 * (fy - fz),
+    ]
+}
+
+/// HyAB distance, the lightness difference plus the Euclidean chroma difference
+#[tracked]
+fn hyab(a: Expr<Float3>, b: Expr<Float3>) -> Expr<f32> {
+    let d = a - b;
+    d.x.abs() + d.yz().length()
+}
+
+fn hyab_host(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+/// Symmetric addressing of pixel `i` of a row of `n`, as in `scipy`'s `boundary='symm'`
+#[tracked]
+fn mirror(i: Expr<i32>, n: Expr<i32>) -> Expr<i32> {
+    let i = select(i < 0i32, -i - 1i32, i);
+    select(i >= n, 2i32 * n - i - 1i32, i).clamp(0i32.expr(), n - 1i32)
+}
+
+/// Filter weights of FLIP for one viewing condition
+struct FlipFilters {
+    /// Contrast sensitivity weights of Y, Cx and Cz in `xyz`, row by row
+    csf: Buffer<Float4>,
+    csf_radius: u32,
+    /// One-dimensional Gaussian, edge and point detector weights in `xyz`;
+    /// the 2D filters are products of a detector along one axis and the
+    /// Gaussian along the other
+    features: Buffer<Float4>,
+    feature_radius: u32,
+    /// Largest color difference after the Hunt adjustment, between green and blue
+    cmax: f32,
+}
+
+impl FlipFilters {
+    fn new(device: &Device, pixels_per_degree: f32) -> Self {
+        let max_b = FLIP_CSF
+            .iter()
+            .flat_map(|p| [p[1], p[3]])
+            .fold(0.0, f32::max);
+        let r = (3.0 * (max_b / (2.0 * PI * PI)).sqrt() * pixels_per_degree).ceil() as i32;
+        let mut csf = vec![];
+        for y in -r..=r {
+            for x in -r..=r {
+                let z = ((x * x + y * y) as f32) / (pixels_per_degree * pixels_per_degree);
+                let [a, b, c] = FLIP_CSF.map(|[a1, b1, a2, b2]| {
+                    a1 * (PI / b1).sqrt() * (-PI * PI * z / b1).exp()
+                        + a2 * (PI / b2).sqrt() * (-PI * PI * z / b2).exp()
+                });
+                csf.push(Float4::new(a, b, c, 0.0));
+            }
+        }
+        let sum = csf
+            .iter()
+            .fold([0.0f32; 3], |s, w| [s[0] + w.x, s[1] + w.y, s[2] + w.z]);
+        for w in &mut csf {
+            *w = Float4::new(w.x / sum[0], w.y / sum[1], w.z / sum[2], 0.0);
+        }
+
+        let sd = 0.5 * FLIP_FEATURE_WIDTH * pixels_per_degree;
+        let rf = (3.0 * sd).ceil() as i32;
+        let xs = (-rf..=rf).map(|x| x as f32).collect::<Vec<_>>();
+        let gauss = xs
+            .iter()
+            .map(|x| (-x * x / (2.0 * sd * sd)).exp())
+            .collect::<Vec<_>>();
+        // positive and negative weights each sum to 1 in magnitude
+        let normalize = |w: Vec<f32>| {
+            let pos = w.iter().filter(|&&w| w > 0.0).sum::<f32>();
+            let neg = -w.iter().filter(|&&w| w < 0.0).sum::<f32>();
+            w.into_iter()
+                .map(|w| if w < 0.0 { w / neg } else { w / pos })
+                .collect::<Vec<_>>()
+        };
+        let edge = normalize(xs.iter().zip(&gauss).map(|(x, g)| -x * g).collect());
+        let point = normalize(
+            xs.iter()
+                .zip(&gauss)
+                .map(|(x, g)| (x * x / (sd * sd) - 1.0) * g)
+                .collect(),
+        );
+        let gauss_sum = gauss.iter().sum::<f32>();
+        let features = (0..xs.len())
+            .map(|i| Float4::new(gauss[i] / gauss_sum, edge[i], point[i], 0.0))
+            .collect::<Vec<_>>();
+
+        let green = hunt_lab_host([0.0, 1.0, 0.0]);
+        let blue = hunt_lab_host([0.0, 0.0, 1.0]);
+        Self {
+            csf: device.create_buffer_from_slice(&csf),
+            csf_radius: r as u32,
+            features: device.create_buffer_from_slice(&features),
+            feature_radius: rf as u32,
+            cmax: hyab_host(green, blue).powf(FLIP_QC),
+        }
+    }
+}
+
+#[tracked]
+fn mse<A: ImageStorage, B: ImageStorage>(
+    a: &A::Parameter,
+    b: &B::Parameter,
+    p: Expr<Uint2>,
+    size: Expr<Uint2>,
+) -> Expr<f32> {
+    let d = A::load(a, p, size.x).xyz() - B::load(b, p, size.x).xyz();
+    (d * d).reduce_sum() / 3.0f32
+}
+
+#[tracked]
+fn ssim<A: ImageStorage, B: ImageStorage>(
+    a: &A::Parameter,
+    b: &B::Parameter,
+    p: Expr<Uint2>,
+    size: Expr<Uint2>,
+) -> Expr<f32> {
+    let moments = Float4::var_zeroed();
+    let cross = f32::var_zeroed();
+    let weights = f32::var_zeroed();
+    let r = SSIM_RADIUS as i32;
+    let max = size.as_int2() - 1i32;
+    for dy in 0u32.expr()..(2 * SSIM_RADIUS + 1).expr() {
+        for dx in 0u32.expr()..(2 * SSIM_RADIUS + 1).expr() {
+            let o = Int2::expr(dx.as_i32() - r, dy.as_i32() - r);
+            let q = (p.as_int2() + o)
+                .clamp(Int2::splat_expr(0i32), max)
+                .as_uint2();
+            let w = (-(o.x * o.x + o.y * o.y).as_f32() / (2.0f32 * SSIM_SIGMA * SSIM_SIGMA)).exp();
+            let la = luminance(A::load(a, q, size.x).xyz());
+            let lb = luminance(B::load(b, q, size.x).xyz());
+            *moments += Float4::expr(la, lb, la * la, lb * lb) * w;
+            *cross += la * lb * w;
+            *weights += w;
+        }
+    }
+    let m = moments.load() / weights.load();
+    let (mu_a, mu_b) = (m.x, m.y);
+    let var_a = m.z - mu_a * mu_a;
+    let var_b = m.w - mu_b * mu_b;
+    let cov = cross.load() / weights.load() - mu_a * mu_b;
+    ((2.0f32 * mu_a * mu_b + SSIM_C1) * (2.0f32 * cov + SSIM_C2))
+        / ((mu_a * mu_a + mu_b * mu_b + SSIM_C1) * (var_a + var_b + SSIM_C2))
+}
+
+/// FLIP of pixel `p` from both images converted to YCxCz by [`ImageProcessor::compare_async`]
+#[tracked]
+fn flip(
+    ycc_a: &Tex2dVar<Float4>,
+    ycc_b: &Tex2dVar<Float4>,
+    p: Expr<Uint2>,
+    csf: &BufferVar<Float4>,
+    features: &BufferVar<Float4>,
+    radii: Expr<Uint2>,
+    cmax: Expr<f32>,
+) -> Expr<f32> {
+    let size = ycc_a.size().as_int2();
+    let at = |x: Expr<i32>, y: Expr<i32>| {
+        Uint2::expr(mirror(x, size.x).as_u32(), mirror(y, size.y).as_u32())
+    };
+
+    // color pipeline: contrast sensitivity filtering in YCxCz, then the
+    // HyAB distance in Hunt-adjusted CIELAB, redistributed to [0, 1]
+    let (filtered_a, filtered_b) = (Float3::var_zeroed(), Float3::var_zeroed());
+    let (rc, width) = (radii.x.as_i32(), 2u32 * radii.x + 1u32);
+    for dy in 0u32.expr()..width {
+        for dx in 0u32.expr()..width {
+            let q = at(
+                p.x.as_i32() + dx.as_i32() - rc,
+                p.y.as_i32() + dy.as_i32() - rc,
+            );
+            let w = csf.read(dx + dy * width).xyz();
+            *filtered_a += ycc_a.read(q).xyz() * w;
+            *filtered_b += ycc_b.read(q).xyz() * w;
+        }
+    }
+    let lab = |ycc: Expr<Float3>| {
+        let rgb = mat_mul(XYZ_TO_LINRGB, ycxcz_to_xyz(ycc));
+        hunt_lab(rgb.clamp(Float3::splat_expr(0.0f32), Float3::splat_expr(1.0f32)))
+    };
+    let delta = hyab(lab(filtered_a.load()), lab(filtered_b.load())).powf(FLIP_QC);
+    let pccmax = FLIP_PC * cmax;
+    let color = select(
+        delta < pccmax,
+        delta * (FLIP_PT / pccmax),
+        FLIP_PT + (delta - pccmax) / (cmax - pccmax) * (1.0f32 - FLIP_PT),
+    );
+
+    // feature pipeline: edges and points of the achromatic channel
+    let (edges_a, points_a) = (Float2::var_zeroed(), Float2::var_zeroed());
+    let (edges_b, points_b) = (Float2::var_zeroed(), Float2::var_zeroed());
+    let (rf, width) = (radii.y.as_i32(), 2u32 * radii.y + 1u32);
+    for dy in 0u32.expr()..width {
+        let ky = features.read(dy);
+        for dx in 0u32.expr()..width {
+            let kx = features.read(dx);
+            let q = at(
+                p.x.as_i32() + dx.as_i32() - rf,
+                p.y.as_i32() + dy.as_i32() - rf,
+            );
+            let ya = (ycc_a.read(q).x + 16.0f32) / 116.0f32;
+            let yb = (ycc_b.read(q).x + 16.0f32) / 116.0f32;
+            let edge = Float2::expr(kx.y * ky.x, ky.y * kx.x);
+            let point = Float2::expr(kx.z * ky.x, ky.z * kx.x);
+            *edges_a += edge * ya;
+            *points_a += point * ya;
+            *edges_b += edge * yb;
+            *points_b += point * yb;
+        }
+    }
+    let feature = (edges_a.load().length() - edges_b.load().length())
+        .abs()
+        .max_((points_a.load().length() - points_b.load().length()).abs());
+    let feature = (feature / 2.0f32.sqrt()).powf(FLIP_QF);
+    color.powf(1.0f32 - feature)
+}
+
+impl ImageProcessor {
+    /// Compares `a` with `b`, writing the per-pixel value of `metric` as gray
+    /// into `error_map` and its mean into `result[0]`
+    ///
+    /// FLIP goes through intermediate textures owned by the processor, so
+    /// commands of concurrent comparisons of the same size must not overlap on
+    /// different streams. They are separate from those of the other operations.
+    pub fn compare_async<A: ImageStorage, B: ImageStorage, D: ImageStorage>(
+        &self,
+        a: &impl Image<A>,
+        b: &impl Image<B>,
+        error_map: &impl Image<D>,
+        result: &BufferView<f32>,
+        metric: ImageMetric,
+    ) -> Vec<Command<'static, 'static>> {
+        let size = a.image_size();
+        assert_eq!(size, b.image_size());
+        assert_eq!(size, error_map.image_size());
+        assert_eq!(result.len(), 1);
+        let dispatch = [size[0], size[1], 1];
+        let size_u = Uint2::from(size);
+        let mut commands = vec![];
+        match metric {
+            ImageMetric::Mse | ImageMetric::Ssim => {
+                let kernel = self.kernel(format!("compare {:?}", metric), |device| {
+                    Kernel::<fn(A, B, D, Uint2)>::new(device, &|a, b, map, size| {
+                        let p = dispatch_id().xy();
+                        let e = match metric {
+                            ImageMetric::Mse => mse::<A, B>(&a, &b, p, size),
+                            _ => ssim::<A, B>(&a, &b, p, size),
+                        };
+                        D::store(&map, p, size.x, Float4::expr(e, e, e, 1.0f32));
+                    })
+                });
+                commands.push(kernel.dispatch_async(dispatch, a, b, error_map, &size_u));
+            }
+            ImageMetric::Flip { pixels_per_degree } => {
+                let filters = self
+                    .kernel(format!("flip filters {}", pixels_per_degree), |device| {
+                        FlipFilters::new(device, pixels_per_degree)
+                    });
+                let ycc_a = self.kernel(format!("flip ycxcz_a {:?}", size), |device| {
+                    device.create_tex2d::<Float4>(PixelStorage::Float4, size[0], size[1], 1)
+                });
+                let ycc_b = self.kernel(format!("flip ycxcz_b {:?}", size), |device| {
+                    device.create_tex2d::<Float4>(PixelStorage::Float4, size[0], size[1], 1)
+                });
+                let to_ycc_a = self.kernel("flip to_ycxcz_a".into(), |device| {
+                    Kernel::<fn(A, Tex2d<Float4>, Uint2)>::new(device, &|src, dst, size| {
+                        let p = dispatch_id().xy();
+                        dst.write(p, to_ycxcz::<A>(&src, p, size));
+                    })
+                });
+                let to_ycc_b = self.kernel("flip to_ycxcz_b".into(), |device| {
+                    Kernel::<fn(B, Tex2d<Float4>, Uint2)>::new(device, &|src, dst, size| {
+                        let p = dispatch_id().xy();
+                        dst.write(p, to_ycxcz::<B>(&src, p, size));
+                    })
+                });
+                let kernel = self.kernel("flip".into(), |device| {
+                    Kernel::<
+                        fn(
+                            Tex2d<Float4>,
+                            Tex2d<Float4>,
+                            D,
+                            Buffer<Float4>,
+                            Buffer<Float4>,
+                            Uint2,
+                            f32,
+                        ),
+                    >::new(
+                        device,
+                        &|ycc_a, ycc_b, map, csf, features, radii, cmax| {
+                            let p = dispatch_id().xy();
+                            let e = flip(&ycc_a, &ycc_b, p, &csf, &features, radii, cmax);
+                            let width = ycc_a.size().x;
+                            D::store(&map, p, width, Float4::expr(e, e, e, 1.0f32));
+                        },
+                    )
+                });
+                commands.extend([
+                    to_ycc_a.dispatch_async(dispatch, a, &*ycc_a, &size_u),
+                    to_ycc_b.dispatch_async(dispatch, b, &*ycc_b, &size_u),
+                    kernel.dispatch_async(
+                        dispatch,
+                        &*ycc_a,
+                        &*ycc_b,
+                        error_map,
+                        &filters.csf,
+                        &filters.features,
+                        &Uint2::new(filters.csf_radius, filters.feature_radius),
+                        &filters.cmax,
+                    ),
+                ]);
+            }
+        }
+        // rows are summed in parallel, then the row sums by a single thread
+        let rows = self.kernel(format!("compare rows {}", size[1]), |device| {
+            device.create_buffer::<f32>(size[1] as usize)
+        });
+        let sum_rows = self.kernel("compare sum_rows".into(), |device| {
+            Kernel::<fn(D, Buffer<f32>, Uint2)>::new(
+                device,
+                &track!(|map, rows, size| {
+                    let y = dispatch_id().x;
+                    let sum = f32::var_zeroed();
+                    for x in 0u32.expr()..size.x {
+                        *sum += D::load(&map, Uint2::expr(x, y), size.x).x;
+                    }
+                    rows.write(y, sum.load());
+                }),
+            )
+        });
+        let mean = self.kernel("compare mean".into(), |device| {
+            Kernel::<fn(Buffer<f32>, Buffer<f32>, Uint2)>::new(
+                device,
+                &track!(|rows, result, size| {
+                    let sum = f32::var_zeroed();
+                    for y in 0u32.expr()..size.y {
+                        *sum += rows.read(y);
+                    }
+                    result.write(0u32, sum.load() / (size.x.as_f32() * size.y.as_f32()));
+                }),
+            )
+        });
+        commands.extend([
+            sum_rows.dispatch_async([size[1], 1, 1], error_map, &*rows, &size_u),
+            mean.dispatch_async([1, 1, 1], &*rows, result, &size_u),
+        ]);
+        commands
+    }
+    /// Compares `a` with `b` and returns the mean of `metric`, writing its
+    /// per-pixel values into `error_map`
+    pub fn compare_with_map<A: ImageStorage, B: ImageStorage, D: ImageStorage>(
+        &self,
+        a: &impl Image<A>,
+        b: &impl Image<B>,
+        error_map: &impl Image<D>,
+        metric: ImageMetric,
+    ) -> f32 {
+        let result = self.device.create_buffer::<f32>(1);
+        submit_default_stream_and_sync(
+            &self.device,
+            self.compare_async(a, b, error_map, &result.view(..), metric),
+        );
+        result.copy_to_vec()[0]
+    }
+    /// Compares `a` with `b` and returns the mean of `metric`
+    pub fn compare<A: ImageStorage, B: ImageStorage>(
+        &self,
+        a: &impl Image<A>,
+        b: &impl Image<B>,
+        metric: ImageMetric,
+    ) -> f32 {
+        let size = a.image_size();
+        let map = self.kernel(format!("compare map {:?}", size), |device| {
+            device.create_tex2d::<Float4>(PixelStorage::Float4, size[0], size[1], 1)
+        });
+        self.compare_with_map(a, b, &*map, metric)
+    }
+    /// Copies `src` into `dst` of the same size
+    fn copy<S: ImageStorage, D: ImageStorage>(&self, src: &impl Image<S>, dst: &impl Image<D>) {
+        let size = src.image_size();
+        assert_eq!(size, dst.image_size());
+        let kernel = self.kernel("copy".into(), |device| {
+            Kernel::<fn(S, D, Uint2)>::new(device, &|src, dst, size| {
+                let p = dispatch_id().xy();
+                D::store(&dst, p, size.x, S::load(&src, p, size.x));
+            })
+        });
+        kernel.dispatch([size[0], size[1], 1], src, dst, &Uint2::from(size));
+    }
+}
+
+#[tracked]
+fn to_ycxcz<S: ImageStorage>(
+    src: &S::Parameter,
+    p: Expr<Uint2>,
+    size: Expr<Uint2>,
+) -> Expr<Float4> {
+    let rgb = S::load(src, p, size.x).xyz();
+    let rgb = rgb.clamp(Float3::splat_expr(0.0f32), Float3::splat_expr(1.0f32));
+    let ycc = xyz_to_ycxcz(mat_mul(LINRGB_TO_XYZ, rgb));
+    Float4::expr(ycc.x, ycc.y, ycc.z, 1.0f32)
+}
+
+/// Writes the color channels of an image as a little-endian PFM file
+fn write_pfm(path: &Path, width: u32, height: u32, pixels: &[Float4]) -> std::io::Result<()> {
+    let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    write!(file, "PF\n{} {}\n-1.0\n", width, height)?;
+    // PFM stores rows bottom to top
+    for row in pixels.chunks(width as usize).rev() {
+        for p in row {
+            for c in [p.x, p.y, p.z] {
+                file.write_all(&c.to_le_bytes())?;
+            }
+        }
+    }
+    file.flush()
+}
+
+#[doc(hidden)]
+pub fn __assert_images_close<A: ImageStorage, B: ImageStorage>(
+    processor: &ImageProcessor,
+    a: &impl Image<A>,
+    b: &impl Image<B>,
+    metric: ImageMetric,
+    max_error: f32,
+    location: &str,
+) {
+    let [w, h] = a.image_size();
+    let n = w as usize * h as usize;
+    let device = &processor.device;
+    let map = device.create_buffer::<Float4>(n);
+    let value = processor.compare_with_map(a, b, &BufferImage::new(&map.view(..), w, h), metric);
+    let error = metric.error(value);
+    if error <= max_error {
+        return;
+    }
+    let copy = device.create_buffer::<Float4>(n);
+    let copy_image = BufferImage::new(&copy.view(..), w, h);
+    let stem = location.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    let path = |name: &str| std::env::temp_dir().join(format!("{}_{}.pfm", stem, name));
+    let mut written = vec![];
+    let mut dump = |name: &str, pixels: Vec<Float4>| {
+        let path = path(name);
+        match write_pfm(&path, w, h, &pixels) {
+            Ok(()) => written.push(path.display().to_string()),
+            Err(e) => log::error!("failed to write {}: {}", path.display(), e),
+        }
+    };
+    processor.copy(a, &copy_image);
+    dump("a", copy.copy_to_vec());
+    processor.copy(b, &copy_image);
+    dump("b", copy.copy_to_vec());
+    dump("diff", map.copy_to_vec());
+    panic!(
+        "images differ at {}: {:?} error {} exceeds {}, written to {}",
+        location,
+        metric,
+        error,
+        max_error,
+        written.join(", ")
+    );
+}
+
+/// Asserts that two images differ by at most `max_error` as measured by
+/// [`ImageMetric::error`].
+///
+/// On failure both images and the error map are written as PFM files to the
+/// system temporary directory, and their paths are part of the panic message.
+///
+/// ```ignore
+/// assert_images_close!(&processor, &rendered, &golden, ImageMetric::FLIP, 0.05);
+/// ```
+#[macro_export]
+macro_rules! assert_images_close {
+    ($processor:expr, $a:expr, $b:expr, $metric:expr, $max_error:expr $(,)?) => {
+        $crate::image::__assert_images_close(
+            $processor,
+            $a,
+            $b,
+            $metric,
+            $max_error,
+            concat!(file!(), ":", line!()),
+        )
+    };
+}
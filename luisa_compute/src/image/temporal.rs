@@ -0,0 +1,263 @@
+//! Temporal accumulation with reprojection for interactive renderers.
+//!
+//! Instead of resetting the accumulation on camera motion, each frame the
+//! history is reprojected along per-pixel motion vectors, clipped against the
+//! color distribution of the current frame around the pixel to reject stale
+//! samples, and blended with the current frame by the number of samples it
+//! already holds.
+use super::*;
+
+/// View-projection matrices of the camera in the previous and current frame.
+///
+/// Clip space follows the usual convention: `x` and `y` in `[-w, w]` map to
+/// the image with `y` pointing up, while pixel rows go down.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraMotion {
+    pub previous: Mat4,
+    pub current: Mat4,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TemporalOptions {
+    /// Upper bound of the per-pixel sample count, so that the history keeps
+    /// adapting to lighting changes; the blend factor never drops below
+    /// `1 / max_samples`
+    pub max_samples: f32,
+    /// Width of the neighborhood clipping box in standard deviations of the
+    /// current frame's 3x3 neighborhood. Larger values ghost more but keep
+    /// more history on noisy input, 0 disables clipping, which suits
+    /// converging a still camera.
+    pub variance_clip: f32,
+}
+
+impl Default for TemporalOptions {
+    fn default() -> Self {
+        Self {
+            max_samples: 64.0,
+            variance_clip: 1.0,
+        }
+    }
+}
+
+/// Projects a homogeneous world position to continuous pixel coordinates,
+/// also returning whether it lies in front of the camera
+#[tracked]
+fn project(
+    m: Expr<Mat4>,
+    position: Expr<Float4>,
+    size: Expr<Float2>,
+) -> (Expr<Float2>, Expr<bool>) {
+    let clip = m * position;
+    let ndc = clip.xy() / clip.w;
+    let pixel = Float2::expr(ndc.x * 0.5f32 + 0.5f32, 0.5f32 - ndc.y * 0.5f32) * size;
+    (pixel, clip.w > 0.0f32)
+}
+
+/// Bilinearly resamples `image` at continuous pixel coordinates `p`, clamping at the edges
+#[tracked]
+fn sample_bilinear(image: &Tex2dVar<Float4>, p: Expr<Float2>, size: Expr<Uint2>) -> Expr<Float4> {
+    let t = p - 0.5f32;
+    let base = t.floor();
+    let f = t - base;
+    let base = base.as_int2();
+    let max = size.as_int2() - 1;
+    let at = |dx: i32, dy: i32| {
+        let q = (base + Int2::expr(dx, dy)).clamp(Int2::splat_expr(0i32), max);
+        image.read(q.as_uint2())
+    };
+    let top = at(0, 0) * (1.0f32 - f.x) + at(1, 0) * f.x;
+    let bottom = at(0, 1) * (1.0f32 - f.x) + at(1, 1) * f.x;
+    top * (1.0f32 - f.y) + bottom * f.y
+}
+
+/// Accumulates frames over time, reprojecting the history when the camera moves.
+///
+/// The accumulated image keeps the color average in `xyz` and the number of
+/// samples behind it in `w`; a pixel whose history is off-screen or rejected
+/// starts over from the current frame.
+pub struct TemporalAccumulator {
+    device: Device,
+    width: u32,
+    height: u32,
+    options: TemporalOptions,
+    /// Read and written alternately, `history[frame % 2]` is the latest
+    history: [Tex2d<Float4>; 2],
+    frame: Cell<usize>,
+    motion: Kernel<fn(Tex2d<Float4>, Tex2d<Float2>, Mat4, Mat4)>,
+    accumulate: Kernel<fn(Tex2d<Float4>, Tex2d<Float2>, Tex2d<Float4>, Tex2d<Float4>, f32, f32)>,
+    clear: Kernel<fn(Tex2d<Float4>)>,
+}
+
+impl TemporalAccumulator {
+    pub fn new(device: &Device, width: u32, height: u32, options: TemporalOptions) -> Self {
+        let history = [(); 2].map(|_| device.create_tex2d(PixelStorage::Float4, width, height, 1));
+        let motion = Kernel::<fn(Tex2d<Float4>, Tex2d<Float2>, Mat4, Mat4)>::new(
+            device,
+            &track!(|position, motion, previous, current| {
+                let p = dispatch_id().xy();
+                let size = motion.size().as_float2();
+                let x = position.read(p);
+                let (prev, prev_visible) = project(previous, x, size);
+                let (curr, _) = project(current, x, size);
+                // points that were behind the camera map far off-screen
+                let m = select(prev_visible, curr - prev, Float2::splat_expr(1e30f32));
+                motion.write(p, m);
+            }),
+        );
+        let accumulate = Kernel::<
+            fn(Tex2d<Float4>, Tex2d<Float2>, Tex2d<Float4>, Tex2d<Float4>, f32, f32),
+        >::new(
+            device,
+            &track!(|current, motion, history, output, max_samples, gamma| {
+                let p = dispatch_id().xy();
+                let size = current.size();
+                let c = current.read(p).xyz();
+                let h = Float4::var_zeroed();
+                let hp = p.as_float2() + 0.5f32 - motion.read(p);
+                if (hp >= 0.0f32).all() && (hp < size.as_float2()).all() {
+                    *h = sample_bilinear(&history, hp, size);
+                }
+                let h = h.load();
+                let n = h.w.min_(max_samples - 1.0f32).max_(0.0f32);
+                let hist = h.xyz().var();
+                if gamma > 0.0f32 {
+                    let m1 = Float3::var_zeroed();
+                    let m2 = Float3::var_zeroed();
+                    for dy in [-1i32, 0, 1] {
+                        for dx in [-1i32, 0, 1] {
+                            let q = (p.as_int2() + Int2::expr(dx, dy))
+                                .clamp(Int2::splat_expr(0i32), size.as_int2() - 1);
+                            let s = current.read(q.as_uint2()).xyz();
+                            *m1 += s;
+                            *m2 += s * s;
+                        }
+                    }
+                    let mean = m1.load() / 9.0f32;
+                    let sigma = (m2.load() / 9.0f32 - mean * mean).max_(0.0f32).sqrt();
+                    *hist = hist
+                        .load()
+                        .clamp(mean - sigma * gamma, mean + sigma * gamma);
+                }
+                let n = n + 1.0f32;
+                let blended = hist.load() + (c - hist.load()) / n;
+                output.write(p, Float4::expr(blended.x, blended.y, blended.z, n));
+            }),
+        );
+        let clear = Kernel::<fn(Tex2d<Float4>)>::new(
+            device,
+            &track!(|image| {
+                image.write(dispatch_id().xy(), Float4::splat_expr(0.0f32));
+            }),
+        );
+        let accumulator = Self {
+            device: device.clone(),
+            width,
+            height,
+            options,
+            history,
+            frame: Cell::new(0),
+            motion,
+            accumulate,
+            clear,
+        };
+        accumulator.reset();
+        accumulator
+    }
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+    pub fn options(&self) -> &TemporalOptions {
+        &self.options
+    }
+    pub fn set_options(&mut self, options: TemporalOptions) {
+        self.options = options;
+    }
+    /// The latest accumulated image, with the per-pixel sample count in `w`
+    pub fn output(&self) -> &Tex2d<Float4> {
+        &self.history[self.frame.get() % 2]
+    }
+
+    /// Computes per-pixel motion vectors, the displacement in pixels of the
+    /// point seen through each pixel from the previous frame to the current one.
+    ///
+    /// `position` holds the world-space position seen through each pixel with
+    /// `w = 1`, or the view direction with `w = 0` for pixels that hit nothing.
+    pub fn motion_vectors_async(
+        &self,
+        position: &Tex2d<Float4>,
+        motion: &Tex2d<Float2>,
+        camera: &CameraMotion,
+    ) -> Command<'static, 'static> {
+        self.motion.dispatch_async(
+            [self.width, self.height, 1],
+            position,
+            motion,
+            &camera.previous,
+            &camera.current,
+        )
+    }
+    pub fn motion_vectors(
+        &self,
+        position: &Tex2d<Float4>,
+        motion: &Tex2d<Float2>,
+        camera: &CameraMotion,
+    ) {
+        submit_default_stream_and_sync(
+            &self.device,
+            [self.motion_vectors_async(position, motion, camera)],
+        );
+    }
+
+    /// Blends the `xyz` of `current` into the history reprojected along
+    /// `motion`, as produced by [`TemporalAccumulator::motion_vectors_async`],
+    /// and returns the new [`TemporalAccumulator::output`]
+    pub fn accumulate_async<'a>(
+        &self,
+        scope: &Scope<'a>,
+        current: &Tex2d<Float4>,
+        motion: &Tex2d<Float2>,
+    ) -> &Tex2d<Float4> {
+        let frame = self.frame.get();
+        let (history, output) = (&self.history[frame % 2], &self.history[(frame + 1) % 2]);
+        scope.submit([self.accumulate.dispatch_async(
+            [self.width, self.height, 1],
+            current,
+            motion,
+            history,
+            output,
+            &self.options.max_samples.max(1.0),
+            &self.options.variance_clip,
+        )]);
+        self.frame.set(frame + 1);
+        output
+    }
+    pub fn accumulate(&self, current: &Tex2d<Float4>, motion: &Tex2d<Float2>) -> &Tex2d<Float4> {
+        let stream = self.device.default_stream();
+        let scope = stream.scope();
+        self.accumulate_async(&scope, current, motion)
+    }
+
+    /// Discards the history, e.g. on a camera cut
+    pub fn reset_async<'a>(&self, scope: &Scope<'a>) {
+        scope.submit(
+            self.history
+                .iter()
+                .map(|h| self.clear.dispatch_async([self.width, self.height, 1], h)),
+        );
+    }
+    pub fn reset(&self) {
+        let stream = self.device.default_stream();
+        self.reset_async(&stream.scope());
+    }
+}
+
+impl fmt::Debug for TemporalAccumulator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TemporalAccumulator")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("options", &self.options)
+            .field("frame", &self.frame.get())
+            .finish()
+    }
+}
@@ -30,14 +30,19 @@ use ir::{
 use self::index::IntoIndex;
 
 pub mod autodiff;
+pub mod block;
 pub mod control_flow;
+pub mod coop_matrix;
 pub mod debug;
 pub mod external;
 pub mod functions;
 pub mod index;
 pub mod ops;
 pub mod poly;
+pub mod precision;
 pub mod print;
+pub mod random;
+pub mod sampling;
 pub mod soa;
 pub mod types;
 
@@ -0,0 +1,297 @@
+//! Block-cooperative primitives in the spirit of CUB.
+//!
+//! Every function here must be called by all threads of the block in uniform
+//! control flow, and the kernel must have called [`set_block_size`]. Threads
+//! are numbered by [`block_thread_index`]. Scratch space is allocated in
+//! shared memory, and with a block size of 1 they reduce to the identity.
+//! The block size need not be a multiple of the warp size.
+use std::ops::Range;
+
+use crate::internal_prelude::*;
+use crate::lang::functions::{
+    sync_block, thread_id, warp_active_count_bits, warp_active_sum, warp_prefix_count_bits,
+    warp_prefix_sum_exclusive,
+};
+use crate::lang::types::core::Numeric;
+use crate::lang::types::shared::Shared;
+
+/// Number of threads in a block
+pub fn block_thread_count() -> u32 {
+    let [x, y, z] = block_size();
+    x * y * z
+}
+
+/// Linear index of the current thread within its block, with `x` varying fastest
+pub fn block_thread_index() -> Expr<u32> {
+    let [x, y, _] = block_size();
+    let tid = thread_id();
+    track!(tid.x + tid.y * x + tid.z * (x * y))
+}
+
+/// Reduces `v` over the block with an associative `op`, every thread gets the result
+pub fn block_reduce<T: Value>(
+    v: impl AsExpr<Value = T>,
+    op: impl Fn(Expr<T>, Expr<T>) -> Expr<T>,
+) -> Expr<T> {
+    let v = v.as_expr();
+    let n = block_thread_count();
+    if n == 1 {
+        return v;
+    }
+    let tid = block_thread_index();
+    let shared = Shared::<T>::new(n as usize);
+    shared.write(tid, v);
+    sync_block();
+    let mut stride = n.next_power_of_two() / 2;
+    while stride > 0 {
+        track!(if tid < stride && tid + stride < n {
+            shared.write(tid, op(shared.read(tid), shared.read(tid + stride)));
+        });
+        sync_block();
+        stride /= 2;
+    }
+    shared.read(0u32)
+}
+
+/// Inclusive scan of `v` over the block with an associative `op`
+pub fn block_scan_inclusive<T: Value>(
+    v: impl AsExpr<Value = T>,
+    op: impl Fn(Expr<T>, Expr<T>) -> Expr<T>,
+) -> Expr<T> {
+    let v = v.as_expr();
+    let n = block_thread_count();
+    if n == 1 {
+        return v;
+    }
+    let tid = block_thread_index();
+    let shared = scan_inclusive(&shared_copy(v, n), tid, n, &op);
+    shared.read(tid)
+}
+
+/// Exclusive scan of `v` over the block with an associative `op`, returning the
+/// scanned value and the reduction of the whole block
+pub fn block_scan_exclusive<T: Value>(
+    v: impl AsExpr<Value = T>,
+    identity: impl AsExpr<Value = T>,
+    op: impl Fn(Expr<T>, Expr<T>) -> Expr<T>,
+) -> (Expr<T>, Expr<T>) {
+    let v = v.as_expr();
+    let identity = identity.as_expr();
+    let n = block_thread_count();
+    if n == 1 {
+        return (identity, v);
+    }
+    let tid = block_thread_index();
+    let shared = scan_inclusive(&shared_copy(v, n), tid, n, &op);
+    let prefix = track!(if tid == 0u32 {
+        identity
+    } else {
+        shared.read(tid - 1u32)
+    });
+    (prefix, shared.read(n - 1))
+}
+
+fn shared_copy<T: Value>(v: Expr<T>, n: u32) -> Shared<T> {
+    let shared = Shared::<T>::new(n as usize);
+    shared.write(block_thread_index(), v);
+    sync_block();
+    shared
+}
+
+/// Hillis-Steele scan of `shared` in place
+fn scan_inclusive<'a, T: Value>(
+    shared: &'a Shared<T>,
+    tid: Expr<u32>,
+    n: u32,
+    op: &impl Fn(Expr<T>, Expr<T>) -> Expr<T>,
+) -> &'a Shared<T> {
+    let mut offset = 1;
+    while offset < n {
+        let x = track!({
+            let x = shared.read(tid);
+            if tid >= offset {
+                op(shared.read(tid - offset), x)
+            } else {
+                x
+            }
+        });
+        sync_block();
+        shared.write(tid, x);
+        sync_block();
+        offset *= 2;
+    }
+    shared
+}
+
+/// Sum of `v` over the block, using warp intrinsics within each warp
+pub fn block_sum<T>(v: impl AsExpr<Value = T>) -> Expr<T>
+where
+    T: Linear<Scalar = T> + Numeric,
+{
+    block_prefix_sum(v).1
+}
+
+/// Exclusive prefix sum of `v` over the block and the sum of the whole block,
+/// using warp intrinsics within each warp
+pub fn block_prefix_sum<T>(v: impl AsExpr<Value = T>) -> (Expr<T>, Expr<T>)
+where
+    T: Linear<Scalar = T> + Numeric,
+{
+    let v = v.as_expr();
+    if block_thread_count() == 1 {
+        return (T::expr_zeroed(), v);
+    }
+    prefix_sum_with(&warp_partials(), block_warp_lanes(), v)
+}
+
+/// Smallest warp size of any backend, the minimum wave size of D3D12
+const MIN_WARP_LANES: u32 = 4;
+
+/// Scratch space with one slot per warp of the block
+fn warp_partials<T: Value>() -> Shared<T> {
+    Shared::<T>::new(((block_thread_count() + MIN_WARP_LANES - 1) / MIN_WARP_LANES) as usize)
+}
+
+/// Number of lanes per warp, the same on every thread of the block
+///
+/// The last warp of a block whose size is not a multiple of the warp size is
+/// only partly filled, so its own active lane count cannot be used. The first
+/// warp is full unless the whole block fits into it, so its count is broadcast.
+fn block_warp_lanes() -> Expr<u32> {
+    let lanes = Shared::<u32>::new(1);
+    let count = warp_active_count_bits(true);
+    track!(if block_thread_index() == 0u32 {
+        lanes.write(0u32, count);
+    });
+    sync_block();
+    lanes.read(0u32)
+}
+
+/// Warp-level prefix sums combined through one partial sum per warp in `partials`
+///
+/// Warps are assumed to cover consecutive thread indices, `lanes` at a time.
+fn prefix_sum_with<T>(partials: &Shared<T>, lanes: Expr<u32>, v: Expr<T>) -> (Expr<T>, Expr<T>)
+where
+    T: Linear<Scalar = T> + Numeric,
+{
+    let n = block_thread_count();
+    let tid = block_thread_index();
+    let warp = track!(tid / lanes);
+    track!(if warp_prefix_count_bits(true.expr()) == 0u32 {
+        partials.write(warp, warp_active_sum(v));
+    });
+    let prefix = warp_prefix_sum_exclusive(v);
+    sync_block();
+    track!({
+        let offset = T::var_zeroed();
+        let total = T::var_zeroed();
+        let num_warps = (n + lanes - 1u32) / lanes;
+        for i in 0u32.expr()..num_warps {
+            let p = partials.read(i);
+            if i < warp {
+                *offset += p;
+            }
+            *total += p;
+        }
+        (prefix + offset.load(), total.load())
+    })
+}
+
+/// Sorts one `u32` key per thread across the block in ascending order,
+/// considering only the given range of bits
+///
+/// Thread `i` receives the `i`-th smallest key. The sort is stable.
+pub fn block_radix_sort(key: impl AsExpr<Value = u32>, bits: Range<u32>) -> Expr<u32> {
+    let key = key.as_expr();
+    block_radix_sort_pairs(key, key, bits).0
+}
+
+/// Sorts key-value pairs with one pair per thread across the block, see [`block_radix_sort`]
+pub fn block_radix_sort_pairs<V: Value>(
+    key: impl AsExpr<Value = u32>,
+    value: impl AsExpr<Value = V>,
+    bits: Range<u32>,
+) -> (Expr<u32>, Expr<V>) {
+    assert!(bits.end <= 32, "key only has 32 bits");
+    let n = block_thread_count();
+    let (key, value) = (key.as_expr(), value.as_expr());
+    if n == 1 {
+        return (key, value);
+    }
+    let tid = block_thread_index();
+    let keys = Shared::<u32>::new(n as usize);
+    let values = Shared::<V>::new(n as usize);
+    let partials = warp_partials::<u32>();
+    let lanes = block_warp_lanes();
+    let key = key.var();
+    let value = value.var();
+    // one stable split per bit
+    for bit in bits {
+        let zero = track!(((key >> bit) & 1u32) == 0u32);
+        let (zeros_before, zeros) = prefix_sum_with(&partials, lanes, track!(zero.as_u32()));
+        let dst = track!(if zero {
+            zeros_before
+        } else {
+            zeros + tid - zeros_before
+        });
+        keys.write(dst, key);
+        values.write(dst, value);
+        sync_block();
+        key.store(keys.read(tid));
+        value.store(values.read(tid));
+        sync_block();
+    }
+    (key.load(), value.load())
+}
+
+/// Loads `N` items per thread from `src` starting at `offset`, where thread `t`
+/// gets items `t, t + n, t + 2n, ...` for `n` threads per block
+///
+/// Consecutive threads access consecutive addresses, which coalesces well.
+pub fn block_load_striped<T: Value, const N: usize>(
+    src: &impl IndexRead<Element = T>,
+    offset: impl AsExpr<Value = u32>,
+) -> Expr<[T; N]> {
+    let n = block_thread_count();
+    let base = track!(offset.as_expr() + block_thread_index());
+    let items = std::array::from_fn(|i| src.read(track!(base + i as u32 * n)));
+    <[T; N]>::from_elems_expr(items)
+}
+
+/// Stores `N` items per thread to `dst` in the arrangement of [`block_load_striped`]
+pub fn block_store_striped<T: Value, const N: usize>(
+    dst: &impl IndexWrite<Element = T>,
+    offset: impl AsExpr<Value = u32>,
+    items: impl AsExpr<Value = [T; N]>,
+) {
+    let n = block_thread_count();
+    let base = track!(offset.as_expr() + block_thread_index());
+    let items = items.as_expr();
+    for i in 0..N {
+        dst.write(track!(base + i as u32 * n), items.read(i as u32));
+    }
+}
+
+/// Loads `N` consecutive items per thread from `src` starting at `offset`,
+/// thread `t` getting items `t * N .. (t + 1) * N`
+pub fn block_load_blocked<T: Value, const N: usize>(
+    src: &impl IndexRead<Element = T>,
+    offset: impl AsExpr<Value = u32>,
+) -> Expr<[T; N]> {
+    let base = track!(offset.as_expr() + block_thread_index() * N as u32);
+    let items = std::array::from_fn(|i| src.read(track!(base + i as u32)));
+    <[T; N]>::from_elems_expr(items)
+}
+
+/// Stores `N` items per thread to `dst` in the arrangement of [`block_load_blocked`]
+pub fn block_store_blocked<T: Value, const N: usize>(
+    dst: &impl IndexWrite<Element = T>,
+    offset: impl AsExpr<Value = u32>,
+    items: impl AsExpr<Value = [T; N]>,
+) {
+    let base = track!(offset.as_expr() + block_thread_index() * N as u32);
+    let items = items.as_expr();
+    for i in 0..N {
+        dst.write(track!(base + i as u32), items.read(i as u32));
+    }
+}
@@ -0,0 +1,188 @@
+//! Warp-cooperative matrices with the API of tensor-core matrix multiply-accumulate.
+//!
+//! A [`CoopMatrix`] is owned by a whole warp: every lane must call its methods
+//! in uniform control flow with the same arguments, and the distribution of
+//! elements among lanes is opaque.
+//!
+//! This is currently a portable emulation, not a tensor-core path. The IR in
+//! this tree has no cooperative matrix instructions, so on every backend each
+//! lane holds the full fragment in registers. The arithmetic of
+//! [`CoopMatrix::mma`] and the stores are split among the lanes, and the
+//! results of `mma` are shared with warp shuffles. Expect no speedup over a
+//! plain per-thread matrix product, and a register cost of `M * N` elements
+//! per lane and fragment. The emulation gives the same results on all
+//! backends, including the CPU backend, and code written against this API can
+//! pick up native lowering without changes once the IR supports it.
+use crate::internal_prelude::*;
+use crate::lang::functions::{warp_active_count_bits, warp_prefix_count_bits, warp_read_lane_at};
+use crate::lang::types::array::{VLArrayExpr, VLArrayVar};
+use crate::lang::types::core::Floating;
+
+/// Element types supported by [`CoopMatrix`]
+pub trait CoopElement: Floating + Linear<Scalar = Self> {}
+impl CoopElement for f16 {}
+impl CoopElement for f32 {}
+
+/// Memory layout of a matrix in a buffer or shared memory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MatrixLayout {
+    RowMajor,
+    ColumnMajor,
+}
+
+/// An `M` x `N` matrix held cooperatively by the lanes of a warp
+///
+/// Every lane currently keeps all `M * N` elements, see the [module docs](self).
+pub struct CoopMatrix<T: CoopElement, const M: usize, const N: usize> {
+    data: VLArrayVar<T>,
+}
+
+impl<T: CoopElement, const M: usize, const N: usize> Clone for CoopMatrix<T, M, N> {
+    fn clone(&self) -> Self {
+        Self::from_data(self.data.load())
+    }
+}
+
+impl<T: CoopElement, const M: usize, const N: usize> CoopMatrix<T, M, N> {
+    pub const ROWS: usize = M;
+    pub const COLUMNS: usize = N;
+    fn from_data(data: VLArrayExpr<T>) -> Self {
+        let m = Self::zeros();
+        m.data.store(data);
+        m
+    }
+    pub fn zeros() -> Self {
+        Self {
+            data: VLArrayVar::zero(M * N),
+        }
+    }
+    #[tracked]
+    pub fn fill(value: impl AsExpr<Value = T>) -> Self {
+        let value = value.as_expr();
+        let m = Self::zeros();
+        for i in 0u32.expr()..((M * N) as u32).expr() {
+            m.data.write(i, value);
+        }
+        m
+    }
+    /// Loads the matrix from `src`, where element `(row, column)` is at
+    /// `offset + row * stride + column` for [`MatrixLayout::RowMajor`] and at
+    /// `offset + column * stride + row` for [`MatrixLayout::ColumnMajor`]
+    pub fn load(
+        src: &impl IndexRead<Element = T>,
+        offset: impl AsExpr<Value = u32>,
+        stride: impl AsExpr<Value = u32>,
+        layout: MatrixLayout,
+    ) -> Self {
+        let (offset, stride) = (offset.as_expr(), stride.as_expr());
+        let m = Self::zeros();
+        m.for_each_index(|r, c, i| {
+            m.data
+                .write(i, src.read(Self::address(offset, stride, layout, r, c)));
+        });
+        m
+    }
+    /// Stores the matrix to `dst` in the arrangement of [`CoopMatrix::load`]
+    ///
+    /// Each element is written by a single lane.
+    pub fn store(
+        &self,
+        dst: &impl IndexWrite<Element = T>,
+        offset: impl AsExpr<Value = u32>,
+        stride: impl AsExpr<Value = u32>,
+        layout: MatrixLayout,
+    ) {
+        let (offset, stride) = (offset.as_expr(), stride.as_expr());
+        let (lane, lanes) = lane_and_count();
+        self.for_each_index(|r, c, i| {
+            track!(if i % lanes == lane {
+                dst.write(
+                    Self::address(offset, stride, layout, r, c),
+                    self.data.read(i),
+                );
+            });
+        });
+    }
+    #[tracked]
+    fn address(
+        offset: Expr<u32>,
+        stride: Expr<u32>,
+        layout: MatrixLayout,
+        r: Expr<u32>,
+        c: Expr<u32>,
+    ) -> Expr<u32> {
+        match layout {
+            MatrixLayout::RowMajor => offset + r * stride + c,
+            MatrixLayout::ColumnMajor => offset + c * stride + r,
+        }
+    }
+    /// Calls `f(row, column, index)` for every element on device
+    #[tracked]
+    fn for_each_index(&self, f: impl Fn(Expr<u32>, Expr<u32>, Expr<u32>)) {
+        for r in 0u32.expr()..(M as u32).expr() {
+            for c in 0u32.expr()..(N as u32).expr() {
+                f(r, c, r * N as u32 + c);
+            }
+        }
+    }
+    #[tracked]
+    pub fn read(&self, row: impl AsExpr<Value = u32>, column: impl AsExpr<Value = u32>) -> Expr<T> {
+        self.data.read(row.as_expr() * N as u32 + column.as_expr())
+    }
+    #[tracked]
+    pub fn write(
+        &self,
+        row: impl AsExpr<Value = u32>,
+        column: impl AsExpr<Value = u32>,
+        value: impl AsExpr<Value = T>,
+    ) {
+        self.data
+            .write(row.as_expr() * N as u32 + column.as_expr(), value);
+    }
+    /// Applies `f` to every element, e.g. an activation function
+    #[tracked]
+    pub fn map<U: CoopElement>(&self, f: impl Fn(Expr<T>) -> Expr<U>) -> CoopMatrix<U, M, N> {
+        let m = CoopMatrix::<U, M, N>::zeros();
+        for i in 0u32.expr()..((M * N) as u32).expr() {
+            m.data.write(i, f(self.data.read(i)));
+        }
+        m
+    }
+    /// Converts the elements to another precision
+    pub fn cast<U: CoopElement>(&self) -> CoopMatrix<U, M, N> {
+        self.map(|x| x.as_::<U>())
+    }
+    /// Computes `a * b + c`, accumulating in the element type of `c`
+    ///
+    /// The usual combination is `f16` inputs with an `f16` or `f32` accumulator.
+    /// Each lane computes every `lanes`-th element and broadcasts it to the warp.
+    #[tracked]
+    pub fn mma<I: CoopElement, const K: usize>(
+        a: &CoopMatrix<I, M, K>,
+        b: &CoopMatrix<I, K, N>,
+        c: &Self,
+    ) -> Self {
+        let d = c.clone();
+        let (lane, lanes) = lane_and_count();
+        d.for_each_index(|r, col, i| {
+            let acc = d.data.read(i).var();
+            let owner = i % lanes;
+            if owner == lane {
+                for k in 0u32.expr()..(K as u32).expr() {
+                    *acc += a.read(r, k).as_::<T>() * b.read(k, col).as_::<T>();
+                }
+            }
+            d.data.write(i, warp_read_lane_at(acc.load(), owner));
+        });
+        d
+    }
+}
+
+/// Index of the current lane and number of lanes in the warp, which is fully
+/// active since cooperative matrices are used in uniform control flow
+fn lane_and_count() -> (Expr<u32>, Expr<u32>) {
+    (
+        warp_prefix_count_bits(true.expr()),
+        warp_active_count_bits(true),
+    )
+}
@@ -199,3 +199,30 @@ pub unsafe fn bitcast<From: Value, To: Value>(expr: Expr<From>) -> Expr<To> {
         __current_scope(|b| b.call(Func::Bitcast, &[expr], <To as TypeOf>::type_())).into(),
     )
 }
+
+/// Decodes sRGB-encoded channels
+pub fn srgb_to_linear(c: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let c = c.as_expr();
+    let f = |c: Expr<f32>| {
+        track!(select(
+            c <= 0.04045f32,
+            c / 12.92f32,
+            ((c + 0.055f32) / 1.055f32).max_(0.0f32).powf(2.4f32)
+        ))
+    };
+    Float3::expr(f(c.x), f(c.y), f(c.z))
+}
+
+/// Encodes linear channels with the sRGB transfer function
+pub fn linear_to_srgb(c: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+    let c = c.as_expr();
+    let f = |c: Expr<f32>| {
+        let c = track!(c.max_(0.0f32));
+        track!(select(
+            c <= 0.0031308f32,
+            c * 12.92f32,
+            1.055f32 * c.powf(1.0f32 / 2.4f32) - 0.055f32
+        ))
+    };
+    Float3::expr(f(c.x), f(c.y), f(c.z))
+}
@@ -0,0 +1,166 @@
+//! Half-precision helpers: packed `Half2` arithmetic, mixed-precision
+//! accumulation, conversions with explicit rounding and bulk conversion kernels.
+//!
+//! Plain `f16` and `Half2/3/4` expressions support the usual operators and
+//! [`FloatExpr`] methods. The helpers here cover patterns where values are stored
+//! in half precision but accumulated in single precision.
+use crate::internal_prelude::*;
+
+/// Rounding mode of a conversion to `f16`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum RoundingMode {
+    /// Round to nearest, ties to even, as done by `as_f16`
+    #[default]
+    NearestEven,
+    TowardZero,
+    /// Toward positive infinity
+    Up,
+    /// Toward negative infinity
+    Down,
+}
+
+/// Converts `x` to `f16` with the given rounding mode
+///
+/// NaNs stay NaNs. Values beyond the half range become infinite or the largest
+/// finite half depending on the rounding direction.
+#[tracked]
+pub fn f32_to_f16(x: impl AsExpr<Value = f32>, mode: RoundingMode) -> Expr<f16> {
+    let x = x.as_expr();
+    let h = x.as_f16();
+    let back = h.as_f32();
+    // stepping the bits by one moves the magnitude by one ulp
+    let bits = h.bitcast::<u16>();
+    let positive = (bits & 0x8000u16) == 0u16;
+    let adjusted = match mode {
+        RoundingMode::NearestEven => bits,
+        RoundingMode::TowardZero => select(back.abs() > x.abs(), bits - 1u16, bits),
+        RoundingMode::Up => select(back < x, select(positive, bits + 1u16, bits - 1u16), bits),
+        RoundingMode::Down => select(back > x, select(positive, bits - 1u16, bits + 1u16), bits),
+    };
+    adjusted.bitcast::<f16>()
+}
+
+/// Host version of [`f32_to_f16`] with identical results
+pub fn f32_to_f16_host(x: f32, mode: RoundingMode) -> f16 {
+    let h = f16::from_f32(x);
+    let back = h.to_f32();
+    let bits = h.to_bits();
+    let positive = bits & 0x8000 == 0;
+    let adjusted = match mode {
+        RoundingMode::NearestEven => bits,
+        RoundingMode::TowardZero if back.abs() > x.abs() => bits - 1,
+        RoundingMode::Up if back < x => {
+            if positive {
+                bits + 1
+            } else {
+                bits - 1
+            }
+        }
+        RoundingMode::Down if back > x => {
+            if positive {
+                bits - 1
+            } else {
+                bits + 1
+            }
+        }
+        _ => bits,
+    };
+    f16::from_bits(adjusted)
+}
+
+/// Converts each component of `v` to `f16` with the given rounding mode
+pub fn float2_to_half2(v: impl AsExpr<Value = Float2>, mode: RoundingMode) -> Expr<Half2> {
+    let v = v.as_expr();
+    Half2::expr(f32_to_f16(v.x, mode), f32_to_f16(v.y, mode))
+}
+
+/// Fused `a * b + c` on packed halves
+pub fn hfma2(
+    a: impl AsExpr<Value = Half2>,
+    b: impl AsExpr<Value = Half2>,
+    c: impl AsExpr<Value = Half2>,
+) -> Expr<Half2> {
+    a.as_expr().mul_add(b.as_expr(), c.as_expr())
+}
+
+/// `a * b + c` with half inputs and a single-precision accumulator
+#[tracked]
+pub fn hfma2_f32(
+    a: impl AsExpr<Value = Half2>,
+    b: impl AsExpr<Value = Half2>,
+    c: impl AsExpr<Value = Float2>,
+) -> Expr<Float2> {
+    a.as_expr()
+        .as_float2()
+        .mul_add(b.as_expr().as_float2(), c.as_expr())
+}
+
+/// `a.x * b.x + a.y * b.y + c` with half inputs and a single-precision accumulator
+#[tracked]
+pub fn hdot2_f32(
+    a: impl AsExpr<Value = Half2>,
+    b: impl AsExpr<Value = Half2>,
+    c: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let p = a.as_expr().as_float2() * b.as_expr().as_float2();
+    p.x + p.y + c.as_expr()
+}
+
+/// Packs two halves into a `u32`, `x` in the low bits
+pub fn pack_half2(v: impl AsExpr<Value = Half2>) -> Expr<u32> {
+    v.as_expr().bitcast::<u32>()
+}
+
+/// Inverse of [`pack_half2`]
+pub fn unpack_half2(bits: impl AsExpr<Value = u32>) -> Expr<Half2> {
+    bits.as_expr().bitcast::<Half2>()
+}
+
+impl AtomicRef<Half2> {
+    /// Adds `operand` to both components and returns the old value
+    ///
+    /// The two halves are updated by separate atomics rather than one 32-bit
+    /// operation, so other threads may observe one half added and not the
+    /// other, and the returned halves may come from different moments.
+    pub fn fetch_add(&self, operand: impl AsExpr<Value = Half2>) -> Expr<Half2> {
+        let operand = operand.as_expr();
+        Half2::expr(self.x.fetch_add(operand.x), self.y.fetch_add(operand.y))
+    }
+}
+
+/// Kernels converting whole buffers between `f32` and `f16`
+pub struct HalfConverter {
+    to_half: Kernel<fn(Buffer<f32>, Buffer<f16>)>,
+    to_float: Kernel<fn(Buffer<f16>, Buffer<f32>)>,
+}
+
+impl HalfConverter {
+    /// Compiles the conversion kernels, narrowing with the given rounding mode
+    pub fn new(device: &Device, mode: RoundingMode) -> Self {
+        let to_half =
+            Kernel::<fn(Buffer<f32>, Buffer<f16>)>::new(
+                device,
+                &|src: BufferVar<f32>, dst: BufferVar<f16>| {
+                    let i = dispatch_id().x;
+                    dst.write(i, f32_to_f16(src.read(i), mode));
+                },
+            );
+        let to_float =
+            Kernel::<fn(Buffer<f16>, Buffer<f32>)>::new(
+                device,
+                &|src: BufferVar<f16>, dst: BufferVar<f32>| {
+                    let i = dispatch_id().x;
+                    dst.write(i, src.read(i).as_f32());
+                },
+            );
+        Self { to_half, to_float }
+    }
+    pub fn to_half(&self, src: &BufferView<f32>, dst: &BufferView<f16>) {
+        assert_eq!(src.len(), dst.len());
+        self.to_half.dispatch([src.len() as u32, 1, 1], src, dst);
+    }
+    pub fn to_float(&self, src: &BufferView<f16>, dst: &BufferView<f32>) {
+        assert_eq!(src.len(), dst.len());
+        self.to_float.dispatch([src.len() as u32, 1, 1], src, dst);
+    }
+}
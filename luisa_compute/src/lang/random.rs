@@ -0,0 +1,681 @@
+//! Random number generators and low-discrepancy sequences.
+//!
+//! Every generator is a [`Value`] that can be stored in buffers. Device code
+//! draws numbers through [`Sampler`], implemented on `Var<G>`, while host code
+//! uses [`HostSampler`] on the plain value. Both produce bit-identical streams,
+//! so CPU references can reproduce what a kernel computed:
+//! ```no_run
+//! use luisa_compute::lang::random::*;
+//! use luisa_compute::prelude::*;
+//! let ctx = Context::new(std::env::current_exe().unwrap());
+//! let device = ctx.create_device("cpu");
+//! let out = device.create_buffer::<f32>(1024);
+//! let kernel = Kernel::<fn()>::new(&device, &track!(|| {
+//!     let tid = dispatch_id().x;
+//!     let rng = Pcg32::new_expr(tid.as_u64(), 0u64).var();
+//!     out.write(tid, rng.next_f32());
+//! }));
+//! kernel.dispatch([1024, 1, 1]);
+//! let mut rng = Pcg32::new(7, 0);
+//! assert_eq!(out.copy_to_vec()[7], rng.next_f32());
+//! ```
+use crate::internal_prelude::*;
+
+/// Converts the high 24 bits of `x` into a float in `[0, 1)`
+#[tracked]
+fn u32_to_f32(x: Expr<u32>) -> Expr<f32> {
+    (x >> 8u32).as_f32() * (1.0f32 / 16777216.0)
+}
+fn u32_to_f32_host(x: u32) -> f32 {
+    (x >> 8) as f32 * (1.0f32 / 16777216.0)
+}
+
+/// A source of random numbers in a kernel, usually a `Var` of a generator
+pub trait Sampler {
+    fn next_u32(&self) -> Expr<u32>;
+    /// Uniform float in `[0, 1)`
+    fn next_f32(&self) -> Expr<f32> {
+        u32_to_f32(self.next_u32())
+    }
+    fn next_float2(&self) -> Expr<Float2> {
+        let x = self.next_f32();
+        let y = self.next_f32();
+        Float2::expr(x, y)
+    }
+    /// Uniform integer in `[0, bound)`, computed as `(x * bound) >> 32`.
+    /// The bias is negligible for bounds much smaller than `2^32`.
+    fn next_u32_bounded(&self, bound: impl AsExpr<Value = u32>) -> Expr<u32> {
+        let x = self.next_u32().as_u64();
+        let bound = bound.as_expr().as_u64();
+        track!(((x * bound) >> 32u64).as_u32())
+    }
+}
+
+/// Host counterpart of [`Sampler`], producing the same stream as the device
+pub trait HostSampler {
+    fn next_u32(&mut self) -> u32;
+    fn next_f32(&mut self) -> f32 {
+        u32_to_f32_host(self.next_u32())
+    }
+    fn next_float2(&mut self) -> Float2 {
+        let x = self.next_f32();
+        let y = self.next_f32();
+        Float2::new(x, y)
+    }
+    fn next_u32_bounded(&mut self, bound: u32) -> u32 {
+        ((self.next_u32() as u64 * bound as u64) >> 32) as u32
+    }
+}
+
+/// Hashes a `u32`, useful for deriving seeds from pixel coordinates or indices
+///
+/// This is the PCG hash from Jarzynski and Olano, "Hash Functions for GPU Rendering".
+#[tracked]
+pub fn hash_u32(x: impl AsExpr<Value = u32>) -> Expr<u32> {
+    let state = x.as_expr() * 747796405u32 + 2891336453u32;
+    let word = ((state >> ((state >> 28u32) + 4u32)) ^ state) * 277803737u32;
+    (word >> 22u32) ^ word
+}
+pub fn hash_u32_host(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+#[tracked]
+fn hash_combine(seed: Expr<u32>, v: Expr<u32>) -> Expr<u32> {
+    hash_u32(seed ^ hash_u32(v))
+}
+fn hash_combine_host(seed: u32, v: u32) -> u32 {
+    hash_u32_host(seed ^ hash_u32_host(v))
+}
+
+const PCG_MULTIPLIER: u64 = 6364136223846793005;
+
+/// PCG32 (XSH RR) with 64 bits of state and a selectable stream
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq, Eq)]
+pub struct Pcg32 {
+    pub state: u64,
+    pub inc: u64,
+}
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            inc: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+    fn step(&mut self) {
+        self.state = self
+            .state
+            .wrapping_mul(PCG_MULTIPLIER)
+            .wrapping_add(self.inc);
+    }
+    #[tracked]
+    pub fn new_expr(
+        seed: impl AsExpr<Value = u64>,
+        stream: impl AsExpr<Value = u64>,
+    ) -> Expr<Self> {
+        let rng = Self::var_zeroed();
+        *rng.inc = (stream.as_expr() << 1u64) | 1u64;
+        pcg_step(&rng);
+        *rng.state = rng.state.load() + seed.as_expr();
+        pcg_step(&rng);
+        rng.load()
+    }
+}
+#[tracked]
+fn pcg_step(rng: &Var<Pcg32>) {
+    *rng.state = rng.state.load() * PCG_MULTIPLIER + rng.inc.load();
+}
+impl Sampler for Var<Pcg32> {
+    #[tracked]
+    fn next_u32(&self) -> Expr<u32> {
+        let old = self.state.load();
+        pcg_step(self);
+        let xorshifted = (((old >> 18u64) ^ old) >> 27u64).as_u32();
+        let rot = (old >> 59u64).as_u32();
+        (xorshifted >> rot) | (xorshifted << ((32u32 - rot) & 31u32))
+    }
+}
+impl HostSampler for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+}
+
+#[tracked]
+fn splitmix64(state: &Var<u64>) -> Expr<u64> {
+    *state = state.load() + 0x9e3779b97f4a7c15u64;
+    let z = state.load();
+    let z = (z ^ (z >> 30u64)) * 0xbf58476d1ce4e5b9u64;
+    let z = (z ^ (z >> 27u64)) * 0x94d049bb133111ebu64;
+    z ^ (z >> 31u64)
+}
+fn splitmix64_host(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let z = *state;
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// xoshiro128++ with 128 bits of state, seeded through SplitMix64
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq, Eq)]
+pub struct Xoshiro128 {
+    pub s0: u32,
+    pub s1: u32,
+    pub s2: u32,
+    pub s3: u32,
+}
+impl Xoshiro128 {
+    pub fn new(seed: u64) -> Self {
+        let mut state = seed;
+        let a = splitmix64_host(&mut state);
+        let b = splitmix64_host(&mut state);
+        Self {
+            s0: a as u32,
+            s1: (a >> 32) as u32,
+            s2: b as u32,
+            s3: (b >> 32) as u32,
+        }
+    }
+    #[tracked]
+    pub fn new_expr(seed: impl AsExpr<Value = u64>) -> Expr<Self> {
+        let state = seed.as_expr().var();
+        let a = splitmix64(&state);
+        let b = splitmix64(&state);
+        let rng = Self::var_zeroed();
+        *rng.s0 = a.as_u32();
+        *rng.s1 = (a >> 32u64).as_u32();
+        *rng.s2 = b.as_u32();
+        *rng.s3 = (b >> 32u64).as_u32();
+        rng.load()
+    }
+}
+#[tracked]
+fn rotl(x: Expr<u32>, k: u32) -> Expr<u32> {
+    (x << k) | (x >> (32 - k))
+}
+impl Sampler for Var<Xoshiro128> {
+    #[tracked]
+    fn next_u32(&self) -> Expr<u32> {
+        let (s0, s1, s2, s3) = (
+            self.s0.load(),
+            self.s1.load(),
+            self.s2.load(),
+            self.s3.load(),
+        );
+        let result = rotl(s0 + s3, 7) + s0;
+        let t = s1 << 9u32;
+        let s2 = s2 ^ s0;
+        let s3 = s3 ^ s1;
+        *self.s1 = s1 ^ s2;
+        *self.s0 = s0 ^ s3;
+        *self.s2 = s2 ^ t;
+        *self.s3 = rotl(s3, 11);
+        result
+    }
+}
+impl HostSampler for Xoshiro128 {
+    fn next_u32(&mut self) -> u32 {
+        let result = self
+            .s0
+            .wrapping_add(self.s3)
+            .rotate_left(7)
+            .wrapping_add(self.s0);
+        let t = self.s1 << 9;
+        self.s2 ^= self.s0;
+        self.s3 ^= self.s1;
+        self.s1 ^= self.s2;
+        self.s0 ^= self.s3;
+        self.s2 ^= t;
+        self.s3 = self.s3.rotate_left(11);
+        result
+    }
+}
+
+const PHILOX_M0: u32 = 0xD2511F53;
+const PHILOX_M1: u32 = 0xCD9E8D57;
+const PHILOX_W0: u32 = 0x9E3779B9;
+const PHILOX_W1: u32 = 0xBB67AE85;
+
+#[tracked]
+fn philox_round(c: Expr<Uint4>, key: Expr<Uint2>, round: u32) -> Expr<Uint4> {
+    let k0 = key.x + PHILOX_W0.wrapping_mul(round);
+    let k1 = key.y + PHILOX_W1.wrapping_mul(round);
+    let p0 = c.x.as_u64() * PHILOX_M0 as u64;
+    let p1 = c.z.as_u64() * PHILOX_M1 as u64;
+    let (hi0, lo0) = ((p0 >> 32u64).as_u32(), p0.as_u32());
+    let (hi1, lo1) = ((p1 >> 32u64).as_u32(), p1.as_u32());
+    Uint4::expr(hi1 ^ c.y ^ k0, lo1, hi0 ^ c.w ^ k1, lo0)
+}
+fn philox_round_host(c: [u32; 4], key: Uint2, round: u32) -> [u32; 4] {
+    let k0 = key.x.wrapping_add(PHILOX_W0.wrapping_mul(round));
+    let k1 = key.y.wrapping_add(PHILOX_W1.wrapping_mul(round));
+    let p0 = c[0] as u64 * PHILOX_M0 as u64;
+    let p1 = c[2] as u64 * PHILOX_M1 as u64;
+    let (hi0, lo0) = ((p0 >> 32) as u32, p0 as u32);
+    let (hi1, lo1) = ((p1 >> 32) as u32, p1 as u32);
+    [hi1 ^ c[1] ^ k0, lo1, hi0 ^ c[3] ^ k1, lo0]
+}
+
+/// The Philox4x32-10 block function from Salmon et al., "Parallel Random Numbers: As Easy as 1, 2, 3"
+///
+/// Maps a counter and a key to four random words without any state.
+pub fn philox4x32(
+    counter: impl AsExpr<Value = Uint4>,
+    key: impl AsExpr<Value = Uint2>,
+) -> Expr<Uint4> {
+    let key = key.as_expr();
+    (0..10).fold(counter.as_expr(), |c, round| philox_round(c, key, round))
+}
+pub fn philox4x32_host(counter: Uint4, key: Uint2) -> Uint4 {
+    let c = [counter.x, counter.y, counter.z, counter.w];
+    let c = (0..10).fold(c, |c, round| philox_round_host(c, key, round));
+    Uint4::new(c[0], c[1], c[2], c[3])
+}
+
+/// Counter-based Philox4x32-10 generator
+///
+/// The low 64 bits of the counter are advanced once per block of four words,
+/// the high 64 bits select the stream. [`Sampler::next_u32`] uses the first
+/// word of each block, call `next_uint4` to get all of them.
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq)]
+pub struct Philox4x32 {
+    pub counter: Uint4,
+    pub key: Uint2,
+}
+impl Philox4x32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        Self {
+            counter: Uint4::new(0, 0, stream as u32, (stream >> 32) as u32),
+            key: Uint2::new(seed as u32, (seed >> 32) as u32),
+        }
+    }
+    #[tracked]
+    pub fn new_expr(
+        seed: impl AsExpr<Value = u64>,
+        stream: impl AsExpr<Value = u64>,
+    ) -> Expr<Self> {
+        let (seed, stream) = (seed.as_expr(), stream.as_expr());
+        let rng = Self::var_zeroed();
+        *rng.counter = Uint4::expr(0u32, 0u32, stream.as_u32(), (stream >> 32u64).as_u32());
+        *rng.key = Uint2::expr(seed.as_u32(), (seed >> 32u64).as_u32());
+        rng.load()
+    }
+    pub fn next_uint4(&mut self) -> Uint4 {
+        let r = philox4x32_host(self.counter, self.key);
+        self.counter.x = self.counter.x.wrapping_add(1);
+        if self.counter.x == 0 {
+            self.counter.y = self.counter.y.wrapping_add(1);
+        }
+        r
+    }
+}
+impl Philox4x32Var {
+    /// Returns the next block of four words
+    #[tracked]
+    pub fn next_uint4(&self) -> Expr<Uint4> {
+        let r = philox4x32(self.counter.load(), self.key.load());
+        let lo = self.counter.x.load() + 1u32;
+        *self.counter.x = lo;
+        *self.counter.y = self.counter.y.load() + select(lo == 0u32, 1u32.expr(), 0u32.expr());
+        r
+    }
+}
+impl Sampler for Var<Philox4x32> {
+    fn next_u32(&self) -> Expr<u32> {
+        self.next_uint4().x
+    }
+    fn next_float2(&self) -> Expr<Float2> {
+        let r = self.next_uint4();
+        Float2::expr(u32_to_f32(r.x), u32_to_f32(r.y))
+    }
+}
+impl HostSampler for Philox4x32 {
+    fn next_u32(&mut self) -> u32 {
+        self.next_uint4().x
+    }
+    fn next_float2(&mut self) -> Float2 {
+        let r = self.next_uint4();
+        Float2::new(u32_to_f32_host(r.x), u32_to_f32_host(r.y))
+    }
+}
+
+/// Number of dimensions supported by [`sobol`]
+pub const SOBOL_DIMENSIONS: usize = 16;
+
+/// Degree, coefficients and initial direction numbers of dimensions `1..SOBOL_DIMENSIONS`,
+/// from Joe and Kuo's `new-joe-kuo-6.21201`
+const SOBOL_PARAMETERS: [(usize, u32, &[u32]); SOBOL_DIMENSIONS - 1] = [
+    (1, 0, &[1]),
+    (2, 1, &[1, 3]),
+    (3, 1, &[1, 3, 1]),
+    (3, 2, &[1, 1, 1]),
+    (4, 1, &[1, 1, 3, 3]),
+    (4, 4, &[1, 3, 5, 13]),
+    (5, 2, &[1, 1, 5, 5, 17]),
+    (5, 4, &[1, 1, 5, 5, 5]),
+    (5, 7, &[1, 1, 7, 11, 19]),
+    (5, 11, &[1, 1, 5, 1, 1]),
+    (5, 13, &[1, 1, 1, 3, 11]),
+    (5, 14, &[1, 3, 5, 5, 31]),
+    (6, 1, &[1, 3, 3, 9, 7, 49]),
+    (6, 13, &[1, 1, 1, 15, 21, 21]),
+    (6, 16, &[1, 3, 1, 13, 27, 49]),
+];
+
+/// Generator matrix of a Sobol dimension, one column per bit of the index
+fn sobol_directions(dim: usize) -> [u32; 32] {
+    assert!(
+        dim < SOBOL_DIMENSIONS,
+        "Sobol dimension {} out of range",
+        dim
+    );
+    let mut v = [0u32; 32];
+    if dim == 0 {
+        for (i, v) in v.iter_mut().enumerate() {
+            *v = 1 << (31 - i);
+        }
+        return v;
+    }
+    let (s, a, m) = SOBOL_PARAMETERS[dim - 1];
+    for (i, &m) in m.iter().enumerate() {
+        v[i] = m << (31 - i);
+    }
+    for i in s..32 {
+        v[i] = v[i - s] ^ (v[i - s] >> s);
+        for k in 1..s {
+            v[i] ^= ((a >> (s - 1 - k)) & 1) * v[i - k];
+        }
+    }
+    v
+}
+
+/// Component `dim` of the `index`-th point of the Sobol sequence, as a 32-bit fixed point fraction
+pub fn sobol(index: impl AsExpr<Value = u32>, dim: usize) -> Expr<u32> {
+    let index = index.as_expr();
+    if dim == 0 {
+        return reverse_bits(index);
+    }
+    let mut result = 0u32.expr();
+    for (bit, v) in sobol_directions(dim).into_iter().enumerate() {
+        let bit = bit as u32;
+        result = track!(result ^ ((index >> bit) & 1u32) * v);
+    }
+    result
+}
+pub fn sobol_host(index: u32, dim: usize) -> u32 {
+    sobol_directions(dim)
+        .into_iter()
+        .enumerate()
+        .filter(|&(bit, _)| (index >> bit) & 1 != 0)
+        .fold(0, |result, (_, v)| result ^ v)
+}
+
+#[tracked]
+fn reverse_bits(x: Expr<u32>) -> Expr<u32> {
+    let x = ((x >> 1u32) & 0x55555555u32) | ((x & 0x55555555u32) << 1u32);
+    let x = ((x >> 2u32) & 0x33333333u32) | ((x & 0x33333333u32) << 2u32);
+    let x = ((x >> 4u32) & 0x0F0F0F0Fu32) | ((x & 0x0F0F0F0Fu32) << 4u32);
+    let x = ((x >> 8u32) & 0x00FF00FFu32) | ((x & 0x00FF00FFu32) << 8u32);
+    (x >> 16u32) | (x << 16u32)
+}
+
+#[tracked]
+fn laine_karras_permutation(x: Expr<u32>, seed: Expr<u32>) -> Expr<u32> {
+    let x = x + seed;
+    let x = x ^ (x * 0x6c50b47cu32);
+    let x = x ^ (x * 0xb82f1e52u32);
+    let x = x ^ (x * 0xc7afe638u32);
+    x ^ (x * 0x8d22f6e6u32)
+}
+fn laine_karras_permutation_host(x: u32, seed: u32) -> u32 {
+    let x = x.wrapping_add(seed);
+    let x = x ^ x.wrapping_mul(0x6c50b47c);
+    let x = x ^ x.wrapping_mul(0xb82f1e52);
+    let x = x ^ x.wrapping_mul(0xc7afe638);
+    x ^ x.wrapping_mul(0x8d22f6e6)
+}
+
+/// Hash-based Owen scrambling of a 32-bit fixed point fraction
+///
+/// See Burley, "Practical Hash-based Owen Scrambling". Applied to a point
+/// index instead of a coordinate, it shuffles the order of the sequence.
+pub fn owen_scramble(x: impl AsExpr<Value = u32>, seed: impl AsExpr<Value = u32>) -> Expr<u32> {
+    reverse_bits(laine_karras_permutation(
+        reverse_bits(x.as_expr()),
+        seed.as_expr(),
+    ))
+}
+pub fn owen_scramble_host(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation_host(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// Shuffled and Owen-scrambled Sobol sampler for one pixel
+///
+/// Each call draws a fresh pair of dimensions (or a single one) from the
+/// first two Sobol dimensions, with the index shuffle and scrambling seeded
+/// by the dimension, so padding stays decorrelated across calls. `index` is
+/// the sample number within the pixel and `seed` identifies the pixel.
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq, Eq)]
+pub struct SobolSampler {
+    pub index: u32,
+    pub dimension: u32,
+    pub seed: u32,
+}
+impl SobolSampler {
+    pub fn new(index: u32, seed: u32) -> Self {
+        Self {
+            index,
+            dimension: 0,
+            seed,
+        }
+    }
+    #[tracked]
+    pub fn new_expr(index: impl AsExpr<Value = u32>, seed: impl AsExpr<Value = u32>) -> Expr<Self> {
+        let s = Self::var_zeroed();
+        *s.index = index.as_expr();
+        *s.seed = seed.as_expr();
+        s.load()
+    }
+}
+impl SobolSamplerVar {
+    /// Shuffled index and scrambling seed of the next dimension, advancing by `n`
+    #[tracked]
+    fn next_dimension(&self, n: u32) -> (Expr<u32>, Expr<u32>) {
+        let dim = self.dimension.load();
+        *self.dimension = dim + n;
+        let seed = hash_combine(self.seed.load(), dim);
+        (owen_scramble(self.index.load(), seed), seed)
+    }
+}
+impl SobolSampler {
+    fn next_dimension(&mut self, n: u32) -> (u32, u32) {
+        let dim = self.dimension;
+        self.dimension += n;
+        let seed = hash_combine_host(self.seed, dim);
+        (owen_scramble_host(self.index, seed), seed)
+    }
+}
+impl Sampler for Var<SobolSampler> {
+    #[tracked]
+    fn next_u32(&self) -> Expr<u32> {
+        let (index, seed) = self.next_dimension(1);
+        owen_scramble(sobol(index, 0), hash_combine(seed, 0u32.expr()))
+    }
+    #[tracked]
+    fn next_float2(&self) -> Expr<Float2> {
+        let (index, seed) = self.next_dimension(2);
+        let x = owen_scramble(sobol(index, 0), hash_combine(seed, 0u32.expr()));
+        let y = owen_scramble(sobol(index, 1), hash_combine(seed, 1u32.expr()));
+        Float2::expr(u32_to_f32(x), u32_to_f32(y))
+    }
+}
+impl HostSampler for SobolSampler {
+    fn next_u32(&mut self) -> u32 {
+        let (index, seed) = self.next_dimension(1);
+        owen_scramble_host(sobol_host(index, 0), hash_combine_host(seed, 0))
+    }
+    fn next_float2(&mut self) -> Float2 {
+        let (index, seed) = self.next_dimension(2);
+        let x = owen_scramble_host(sobol_host(index, 0), hash_combine_host(seed, 0));
+        let y = owen_scramble_host(sobol_host(index, 1), hash_combine_host(seed, 1));
+        Float2::new(u32_to_f32_host(x), u32_to_f32_host(y))
+    }
+}
+
+/// Golden ratio conjugate as a 32-bit fixed point fraction
+const GOLDEN_RATIO_FIXED: u32 = 0x9e3779b9;
+
+/// Ranks of the pixels of a tileable `size` x `size` blue-noise mask, row by row
+///
+/// Generated with Ulichney's void-and-cluster method ("The void-and-cluster
+/// method for dither array generation") on a torus so that the mask tiles.
+/// Takes `O(size^4)` time, so masks are usually generated once at startup.
+pub fn void_and_cluster(size: u32) -> Vec<u32> {
+    let size = size as usize;
+    let n = size * size;
+    assert!(n >= 2, "Blue noise mask must have at least two pixels");
+    let sigma = 1.5f64;
+    let wrap = |d: usize| d.min(size - d) as f64;
+    let filter = (0..n)
+        .map(|i| {
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect::<Vec<_>>();
+    let splat = |energy: &mut [f64], p: usize, sign: f64| {
+        let (px, py) = (p % size, p / size);
+        for (i, e) in energy.iter_mut().enumerate() {
+            let dx = (i % size + size - px) % size;
+            let dy = (i / size + size - py) % size;
+            *e += sign * filter[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // random initial pattern, relaxed by moving the tightest cluster into
+    // the largest void until it stays in place
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0.0; n];
+    let initial = (n / 10).max(1);
+    let mut rng = Pcg32::new(0, 0);
+    let mut placed = 0;
+    while placed < initial {
+        let p = rng.next_u32_bounded(n as u32) as usize;
+        if !pattern[p] {
+            pattern[p] = true;
+            splat(&mut energy, p, 1.0);
+            placed += 1;
+        }
+    }
+    for _ in 0..n {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        splat(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0u32; n];
+    // rank the initial pattern by removing its tightest clusters
+    let (mut removed, mut removed_energy) = (pattern.clone(), energy.clone());
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&removed, &removed_energy);
+        removed[cluster] = false;
+        splat(&mut removed_energy, cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+    // then fill the largest voids. Past half of the pixels the original method
+    // looks for the tightest cluster of zeros instead, which is the same pixel
+    // since the energy of the zeros is a constant minus the energy of the ones
+    for rank in initial..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        splat(&mut energy, void, 1.0);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+/// A tileable blue-noise mask for per-pixel sampling
+///
+/// Thresholding the mask at any level gives evenly spread pixels, so the
+/// error of one sample per pixel looks like high-frequency noise that is
+/// easily filtered away. Every frame is shifted by the golden ratio, which
+/// keeps the values of each pixel well distributed over time, and every
+/// dimension uses a differently offset tile.
+pub struct BlueNoise {
+    /// Threshold of each pixel as a 32-bit fixed point fraction, row by row
+    pub thresholds: Buffer<u32>,
+    thresholds_host: Vec<u32>,
+    size: u32,
+}
+impl BlueNoise {
+    /// Generates a `size` x `size` mask with [`void_and_cluster`]. 64 is a common size
+    pub fn new(device: &Device, size: u32) -> Self {
+        let n = (size * size) as u64;
+        let thresholds_host = void_and_cluster(size)
+            .into_iter()
+            .map(|rank| (((2 * rank as u64 + 1) << 31) / n) as u32)
+            .collect::<Vec<_>>();
+        Self {
+            thresholds: device.create_buffer_from_slice(&thresholds_host),
+            thresholds_host,
+            size,
+        }
+    }
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    /// Value of `pixel` in `[0, 1)` for the given `frame` and `dimension`
+    #[tracked]
+    pub fn sample(
+        &self,
+        pixel: impl AsExpr<Value = Uint2>,
+        frame: impl AsExpr<Value = u32>,
+        dimension: impl AsExpr<Value = u32>,
+    ) -> Expr<f32> {
+        let pixel = pixel.as_expr();
+        let dimension = dimension.as_expr();
+        let x = (pixel.x + hash_u32(dimension)) % self.size;
+        let y = (pixel.y + hash_u32(dimension ^ 0x55555555u32)) % self.size;
+        let t = self.thresholds.read(y * self.size + x);
+        u32_to_f32(t + frame.as_expr() * GOLDEN_RATIO_FIXED)
+    }
+    pub fn sample_host(&self, pixel: Uint2, frame: u32, dimension: u32) -> f32 {
+        let x = pixel.x.wrapping_add(hash_u32_host(dimension)) % self.size;
+        let y = pixel.y.wrapping_add(hash_u32_host(dimension ^ 0x55555555)) % self.size;
+        let t = self.thresholds_host[(y * self.size + x) as usize];
+        u32_to_f32_host(t.wrapping_add(frame.wrapping_mul(GOLDEN_RATIO_FIXED)))
+    }
+}
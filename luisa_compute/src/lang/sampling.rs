@@ -0,0 +1,417 @@
+//! Sample warps, piecewise-constant distributions and alias tables for Monte Carlo integration.
+//!
+//! Warps map uniform samples, e.g. from [`Sampler::next_float2`](crate::lang::random::Sampler::next_float2),
+//! to directions and points. Distributions are built on the host from a list of
+//! weights, uploaded into buffers and sampled in kernels.
+use std::f32::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, PI};
+
+use crate::internal_prelude::*;
+
+/// An orthonormal basis around a normal
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq)]
+#[value_new(pub)]
+pub struct Frame {
+    pub tangent: Float3,
+    pub binormal: Float3,
+    pub normal: Float3,
+}
+impl Frame {
+    /// Builds a frame around a normalized `normal`
+    ///
+    /// See Duff et al., "Building an Orthonormal Basis, Revisited".
+    #[tracked]
+    pub fn from_normal_expr(normal: impl AsExpr<Value = Float3>) -> Expr<Self> {
+        let n = normal.as_expr();
+        let sign = select(n.z >= 0.0, 1.0f32.expr(), (-1.0f32).expr());
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        let tangent = Float3::expr(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+        let binormal = Float3::expr(b, sign + n.y * n.y * a, -n.y);
+        Self::new_expr(tangent, binormal, n)
+    }
+}
+impl FrameExpr {
+    #[tracked]
+    pub fn to_world(&self, v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        let v = v.as_expr();
+        self.tangent * v.x + self.binormal * v.y + self.normal * v.z
+    }
+    #[tracked]
+    pub fn to_local(&self, v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        let v = v.as_expr();
+        Float3::expr(
+            self.tangent.dot(v),
+            self.binormal.dot(v),
+            self.normal.dot(v),
+        )
+    }
+}
+
+/// Maps `u` to the unit disk, preserving stratification
+///
+/// See Shirley and Chiu, "A Low Distortion Map Between Disk and Square".
+#[tracked]
+pub fn sample_uniform_disk(u: impl AsExpr<Value = Float2>) -> Expr<Float2> {
+    let o = u.as_expr() * 2.0 - 1.0;
+    let x_major = o.x.abs() > o.y.abs();
+    let r = select(x_major, o.x, o.y);
+    let theta = select(
+        x_major,
+        FRAC_PI_4 * (o.y / o.x),
+        FRAC_PI_2 - FRAC_PI_4 * (o.x / o.y),
+    );
+    let p = r * Float2::expr(theta.cos(), theta.sin());
+    select((o.x == 0.0) & (o.y == 0.0), Float2::expr(0.0, 0.0), p)
+}
+
+/// Cosine-weighted direction around `+z`
+#[tracked]
+pub fn sample_cosine_hemisphere(u: impl AsExpr<Value = Float2>) -> Expr<Float3> {
+    let d = sample_uniform_disk(u);
+    let z = (1.0 - d.x * d.x - d.y * d.y).max_(0.0).sqrt();
+    Float3::expr(d.x, d.y, z)
+}
+#[tracked]
+pub fn cosine_hemisphere_pdf(cos_theta: impl AsExpr<Value = f32>) -> Expr<f32> {
+    cos_theta.as_expr() * FRAC_1_PI
+}
+
+/// Uniformly distributed direction around `+z`
+#[tracked]
+pub fn sample_uniform_hemisphere(u: impl AsExpr<Value = Float2>) -> Expr<Float3> {
+    let u = u.as_expr();
+    let z = u.x;
+    let r = (1.0 - z * z).max_(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Float3::expr(r * phi.cos(), r * phi.sin(), z)
+}
+pub fn uniform_hemisphere_pdf() -> f32 {
+    0.5 * FRAC_1_PI
+}
+
+#[tracked]
+pub fn sample_uniform_sphere(u: impl AsExpr<Value = Float2>) -> Expr<Float3> {
+    let u = u.as_expr();
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max_(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Float3::expr(r * phi.cos(), r * phi.sin(), z)
+}
+pub fn uniform_sphere_pdf() -> f32 {
+    0.25 * FRAC_1_PI
+}
+
+/// Uniformly distributed direction in the cone around `+z` with half-angle `acos(cos_theta_max)`
+#[tracked]
+pub fn sample_uniform_cone(
+    u: impl AsExpr<Value = Float2>,
+    cos_theta_max: impl AsExpr<Value = f32>,
+) -> Expr<Float3> {
+    let u = u.as_expr();
+    let z = 1.0 - u.x * (1.0 - cos_theta_max.as_expr());
+    let r = (1.0 - z * z).max_(0.0).sqrt();
+    let phi = 2.0 * PI * u.y;
+    Float3::expr(r * phi.cos(), r * phi.sin(), z)
+}
+#[tracked]
+pub fn uniform_cone_pdf(cos_theta_max: impl AsExpr<Value = f32>) -> Expr<f32> {
+    1.0 / (2.0 * PI * (1.0 - cos_theta_max.as_expr()))
+}
+
+/// Uniformly distributed barycentrics `(b1, b2)` of a triangle, the first one being `1 - b1 - b2`
+///
+/// The pdf with respect to area is one over the area of the triangle.
+#[tracked]
+pub fn sample_uniform_triangle(u: impl AsExpr<Value = Float2>) -> Expr<Float2> {
+    let u = u.as_expr();
+    let s = u.x.sqrt();
+    Float2::expr(1.0 - s, u.y * s)
+}
+
+/// Multiple importance sampling weight of a sample from strategy `a`, using the balance heuristic
+#[tracked]
+pub fn balance_heuristic(
+    pdf_a: impl AsExpr<Value = f32>,
+    pdf_b: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let (a, b) = (pdf_a.as_expr(), pdf_b.as_expr());
+    let sum = a + b;
+    select(sum > 0.0, a / sum, 0.0f32.expr())
+}
+/// Multiple importance sampling weight of a sample from strategy `a`, using the power heuristic with `beta = 2`
+#[tracked]
+pub fn power_heuristic(
+    pdf_a: impl AsExpr<Value = f32>,
+    pdf_b: impl AsExpr<Value = f32>,
+) -> Expr<f32> {
+    let (a, b) = (pdf_a.as_expr(), pdf_b.as_expr());
+    let sum = a * a + b * b;
+    select(sum > 0.0, a * a / sum, 0.0f32.expr())
+}
+
+fn inv_or_zero(x: f32) -> f32 {
+    if x > 0.0 {
+        1.0 / x
+    } else {
+        0.0
+    }
+}
+
+/// Builds the normalized CDF of `func` and returns it with the integral of `func` over `[0, 1]`
+fn build_cdf(func: &[f32]) -> (Vec<f32>, f32) {
+    let n = func.len();
+    let mut cdf = Vec::with_capacity(n + 1);
+    cdf.push(0.0);
+    for (i, f) in func.iter().enumerate() {
+        assert!(*f >= 0.0, "negative weight {} at {}", f, i);
+        cdf.push(cdf[i] + f / n as f32);
+    }
+    let integral = cdf[n];
+    for (i, c) in cdf.iter_mut().enumerate().skip(1) {
+        *c = if integral == 0.0 {
+            i as f32 / n as f32
+        } else {
+            *c / integral
+        };
+    }
+    (cdf, integral)
+}
+
+/// Samples one piecewise-constant row of `n` values stored at `func[func_offset..]`
+/// with its CDF at `cdf[cdf_offset..]`. Returns the continuous sample, its pdf and the index of the piece.
+#[tracked]
+fn sample_piecewise(
+    func: &Buffer<f32>,
+    cdf: &Buffer<f32>,
+    func_offset: Expr<u32>,
+    cdf_offset: Expr<u32>,
+    n: u32,
+    integral: Expr<f32>,
+    u: Expr<f32>,
+) -> (Expr<f32>, Expr<f32>, Expr<u32>) {
+    // find the last `i` with `cdf[i] <= u`
+    let first = 1u32.var();
+    let size = (n - 1).var();
+    while size > 0u32 {
+        let half = size >> 1u32;
+        let middle = first + half;
+        if cdf.read(cdf_offset + middle) <= u {
+            *first = middle + 1;
+            *size -= half + 1;
+        } else {
+            *size = half;
+        }
+    }
+    let i = first - 1;
+    let (c0, c1) = (cdf.read(cdf_offset + i), cdf.read(cdf_offset + i + 1));
+    let du = u - c0;
+    let du = select(c1 > c0, du / (c1 - c0), du);
+    let f = func.read(func_offset + i);
+    let pdf = select(integral > 0.0, f / integral, 0.0f32.expr());
+    let x = (i.as_f32() + du) / n as f32;
+    (x.min_(1.0 - f32::EPSILON), pdf, i)
+}
+
+/// A piecewise-constant distribution over `[0, 1]`
+pub struct Distribution1D {
+    pub func: Buffer<f32>,
+    pub cdf: Buffer<f32>,
+    integral: f32,
+}
+impl Distribution1D {
+    /// Creates a distribution proportional to non-negative `weights`.
+    /// If all weights are zero, samples are spread uniformly but have a pdf of zero.
+    pub fn new(device: &Device, weights: &[f32]) -> Self {
+        assert!(!weights.is_empty(), "empty distribution");
+        let (cdf, integral) = build_cdf(weights);
+        Self {
+            func: device.create_buffer_from_slice(weights),
+            cdf: device.create_buffer_from_slice(&cdf),
+            integral,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+    /// Integral of the weights over `[0, 1]`, that is their average
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+    /// Returns a point in `[0, 1)`, its pdf and the index of the piece it lies in
+    pub fn sample_continuous(
+        &self,
+        u: impl AsExpr<Value = f32>,
+    ) -> (Expr<f32>, Expr<f32>, Expr<u32>) {
+        sample_piecewise(
+            &self.func,
+            &self.cdf,
+            0u32.expr(),
+            0u32.expr(),
+            self.len() as u32,
+            self.integral.expr(),
+            u.as_expr(),
+        )
+    }
+    /// Returns an index with probability proportional to its weight, and that probability
+    pub fn sample_discrete(&self, u: impl AsExpr<Value = f32>) -> (Expr<u32>, Expr<f32>) {
+        let (_, pdf, i) = self.sample_continuous(u);
+        (i, track!(pdf / self.len() as f32))
+    }
+    pub fn pdf(&self, x: impl AsExpr<Value = f32>) -> Expr<f32> {
+        let n = self.len() as u32;
+        let inv_integral = inv_or_zero(self.integral);
+        let x = x.as_expr();
+        track!({
+            let i = (x * n as f32).as_u32().min_(n - 1);
+            self.func.read(i) * inv_integral
+        })
+    }
+}
+
+/// A piecewise-constant distribution over `[0, 1]^2`, e.g. of an environment map
+///
+/// Samples a row from the marginal distribution, then a column within the row.
+pub struct Distribution2D {
+    width: u32,
+    height: u32,
+    pub conditional_func: Buffer<f32>,
+    pub conditional_cdf: Buffer<f32>,
+    pub marginal: Distribution1D,
+}
+impl Distribution2D {
+    /// Creates a distribution from row-major `weights` of `width x height` pieces
+    pub fn new(device: &Device, weights: &[f32], width: u32, height: u32) -> Self {
+        let (w, h) = (width as usize, height as usize);
+        assert_eq!(
+            weights.len(),
+            w * h,
+            "weights must have width * height elements"
+        );
+        let mut cdf = Vec::with_capacity(h * (w + 1));
+        let mut marginal = Vec::with_capacity(h);
+        for row in weights.chunks(w) {
+            let (row_cdf, row_integral) = build_cdf(row);
+            cdf.extend(row_cdf);
+            marginal.push(row_integral);
+        }
+        Self {
+            width,
+            height,
+            conditional_func: device.create_buffer_from_slice(weights),
+            conditional_cdf: device.create_buffer_from_slice(&cdf),
+            marginal: Distribution1D::new(device, &marginal),
+        }
+    }
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    /// Returns a point in `[0, 1)^2` and its pdf
+    #[tracked]
+    pub fn sample_continuous(&self, u: impl AsExpr<Value = Float2>) -> (Expr<Float2>, Expr<f32>) {
+        let u = u.as_expr();
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u.y);
+        let (x, pdf_x, _) = sample_piecewise(
+            &self.conditional_func,
+            &self.conditional_cdf,
+            row * self.width,
+            row * (self.width + 1),
+            self.width,
+            self.marginal.func.read(row),
+            u.x,
+        );
+        (Float2::expr(x, y), pdf_x * pdf_y)
+    }
+    pub fn pdf(&self, p: impl AsExpr<Value = Float2>) -> Expr<f32> {
+        let (w, h) = (self.width, self.height);
+        let inv_integral = inv_or_zero(self.marginal.integral());
+        let p = p.as_expr();
+        track!({
+            let x = (p.x * w as f32).as_u32().min_(w - 1);
+            let y = (p.y * h as f32).as_u32().min_(h - 1);
+            self.conditional_func.read(y * w + x) * inv_integral
+        })
+    }
+}
+
+/// An entry of an [`AliasTable`]
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq)]
+pub struct AliasEntry {
+    /// Probability of keeping this entry instead of jumping to `alias`
+    pub threshold: f32,
+    pub alias: u32,
+    /// Probability of sampling this entry
+    pub pmf: f32,
+}
+
+/// Walker's alias method for sampling discrete distributions in constant time
+pub struct AliasTable {
+    pub table: Buffer<AliasEntry>,
+}
+impl AliasTable {
+    /// Builds the table with Vose's algorithm. If all weights are zero the distribution is uniform.
+    pub fn new(device: &Device, weights: &[f32]) -> Self {
+        Self {
+            table: device.create_buffer_from_slice(&Self::build(weights)),
+        }
+    }
+    /// Builds the entries of the table on the host
+    pub fn build(weights: &[f32]) -> Vec<AliasEntry> {
+        let n = weights.len();
+        assert!(n > 0, "empty distribution");
+        let sum: f64 = weights.iter().map(|w| *w as f64).sum();
+        let pmf = weights
+            .iter()
+            .map(|w| {
+                assert!(*w >= 0.0, "negative weight {}", w);
+                if sum > 0.0 {
+                    (*w as f64 / sum) as f32
+                } else {
+                    1.0 / n as f32
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut scaled = pmf.iter().map(|p| *p as f64 * n as f64).collect::<Vec<_>>();
+        let mut entries = (0..n)
+            .map(|i| AliasEntry {
+                threshold: 1.0,
+                alias: i as u32,
+                pmf: pmf[i],
+            })
+            .collect::<Vec<_>>();
+        let (mut small, mut large): (Vec<_>, Vec<_>) = (0..n).partition(|i| scaled[*i] < 1.0);
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            entries[s].threshold = scaled[s] as f32;
+            entries[s].alias = l as u32;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+        // whatever is left over is 1 up to rounding and keeps its default entry
+        entries
+    }
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+    /// Returns an index with probability proportional to its weight, and that probability
+    #[tracked]
+    pub fn sample(&self, u: impl AsExpr<Value = f32>) -> (Expr<u32>, Expr<f32>) {
+        let n = self.len() as u32;
+        let x = u.as_expr() * n as f32;
+        let i = x.as_u32().min_(n - 1);
+        let entry = self.table.read(i);
+        let i = select(x - i.as_f32() < entry.threshold, i, entry.alias);
+        (i, self.table.read(i).pmf)
+    }
+    #[tracked]
+    pub fn pmf(&self, i: impl AsExpr<Value = u32>) -> Expr<f32> {
+        self.table.read(i.as_expr()).pmf
+    }
+}
@@ -11,6 +11,7 @@ pub mod array;
 pub mod core;
 pub mod dynamic;
 pub mod shared;
+pub mod tagged_union;
 pub mod vector;
 
 // TODO: Check up on comments.
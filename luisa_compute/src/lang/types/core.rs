@@ -359,6 +359,7 @@ impl_atomic!(u32);
 impl_atomic!(i64);
 impl_atomic!(u64);
 impl_atomic!(f32);
+impl_atomic!(f16);
 impl_atomic_bit!(u32);
 impl_atomic_bit!(u64);
 impl_atomic_bit!(i32);
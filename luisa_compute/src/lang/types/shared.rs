@@ -93,3 +93,49 @@ impl<T: Value> IndexWrite for Shared<T> {
         });
     }
 }
+
+/// A `W x H` tile in shared memory, indexed by `(x, y)`
+///
+/// Rows are padded by one element, so threads accessing the same column of
+/// different rows, e.g. when transposing, hit different memory banks.
+pub struct SharedTile<T: Value, const W: usize, const H: usize> {
+    shared: Shared<T>,
+}
+impl<T: Value, const W: usize, const H: usize> SharedTile<T, W, H> {
+    /// Distance in elements between the starts of two rows
+    pub const STRIDE: usize = W + 1;
+
+    pub fn new() -> Self {
+        Self {
+            shared: Shared::new(Self::STRIDE * H),
+        }
+    }
+    pub fn width(&self) -> usize {
+        W
+    }
+    pub fn height(&self) -> usize {
+        H
+    }
+    /// The underlying padded storage
+    pub fn shared(&self) -> &Shared<T> {
+        &self.shared
+    }
+    fn index(&self, xy: impl AsExpr<Value = Uint2>) -> Expr<u32> {
+        let xy = xy.as_expr();
+        if need_runtime_check() {
+            lc_assert!(track!((xy.x < W as u32) & (xy.y < H as u32)));
+        }
+        track!(xy.y * Self::STRIDE as u32 + xy.x)
+    }
+    pub fn read(&self, xy: impl AsExpr<Value = Uint2>) -> Expr<T> {
+        self.shared.read(self.index(xy))
+    }
+    pub fn write(&self, xy: impl AsExpr<Value = Uint2>, value: impl AsExpr<Value = T>) {
+        self.shared.write(self.index(xy), value)
+    }
+}
+impl<T: Value, const W: usize, const H: usize> Default for SharedTile<T, W, H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,122 @@
+//! Support code for `#[derive(Value)]` on `#[repr(C, u32)]` enums with payloads.
+//!
+//! Such an enum is laid out by rustc as a `u32` tag followed by a union of the
+//! variants' fields, placed at an offset equal to the alignment of the enum.
+//! On the device it is a struct of the tag and opaque payload words. Reading a
+//! variant bitcasts the whole value to a struct with the variant's payload at
+//! the same offset.
+use ir::{ArrayType, StructType};
+
+use super::*;
+
+fn words(element: CArc<Type>, bytes: usize) -> Option<CArc<Type>> {
+    let element_size = element.size();
+    assert_eq!(bytes % element_size, 0);
+    if bytes == 0 {
+        None
+    } else {
+        Some(register_type(Type::Array(ArrayType {
+            element,
+            length: bytes / element_size,
+        })))
+    }
+}
+
+/// Fields of a tagged union with the given payload, and the index of the payload field
+fn layout(size: usize, alignment: usize, payload: Option<CArc<Type>>) -> (Vec<CArc<Type>>, usize) {
+    assert!(
+        alignment >= 4 && size >= alignment,
+        "Enum must be #[repr(C, u32)]"
+    );
+    let mut fields = vec![u32::type_()];
+    fields.extend(words(u32::type_(), alignment - 4));
+    let payload_index = fields.len();
+    let payload_size = payload.as_ref().map(|p| p.size()).unwrap_or(0);
+    assert!(alignment + payload_size <= size);
+    fields.extend(payload);
+    let tail = size - alignment - payload_size;
+    if tail % 4 == 0 {
+        fields.extend(words(u32::type_(), tail));
+    } else {
+        fields.extend(words(u8::type_(), tail));
+    }
+    (fields, payload_index)
+}
+
+fn struct_type(size: usize, alignment: usize, fields: Vec<CArc<Type>>) -> CArc<Type> {
+    let type_ = Type::Struct(StructType {
+        fields: CBoxedSlice::new(fields),
+        size,
+        alignment,
+    });
+    assert_eq!(type_.size(), size);
+    register_type(type_)
+}
+
+fn variant_type<U: Value, V: Value>() -> (CArc<Type>, usize) {
+    let size = std::mem::size_of::<U>();
+    let alignment = std::mem::align_of::<U>();
+    let (fields, payload_index) = layout(size, alignment, Some(V::type_()));
+    (struct_type(size, alignment, fields), payload_index)
+}
+
+#[doc(hidden)]
+pub fn __tagged_union_type(size: usize, alignment: usize) -> CArc<Type> {
+    let (fields, _) = layout(size, alignment, None);
+    struct_type(size, alignment, fields)
+}
+
+#[doc(hidden)]
+pub fn __tagged_union_tag<U: Value>(u: Expr<U>) -> Expr<u32> {
+    Expr::<u32>::from_node(__extract::<u32>(u.node(), 0))
+}
+
+/// Reads the payload of `u` as variant `V`, regardless of the tag
+#[doc(hidden)]
+pub fn __tagged_union_payload<U: Value, V: Value>(u: Expr<U>) -> Expr<V> {
+    let (ty, payload_index) = variant_type::<U, V>();
+    let u = u.node().get();
+    let node = __current_scope(|b| b.bitcast(u, ty));
+    Expr::<V>::from_node(__extract::<V>(node.into(), payload_index))
+}
+
+/// Builds a `U` with the given tag and payload
+#[doc(hidden)]
+pub fn __tagged_union_new<U: Value, V: Value>(tag: u32, payload: Expr<V>) -> Expr<U> {
+    let (ty, payload_index) = variant_type::<U, V>();
+    let payload = payload.node().get();
+    let node = __current_scope(|b| {
+        let fields = match ty.as_ref() {
+            Type::Struct(st) => st.fields.as_ref().to_vec(),
+            _ => unreachable!(),
+        };
+        let nodes = fields
+            .iter()
+            .enumerate()
+            .map(|(i, f)| {
+                if i == 0 {
+                    b.const_(Const::Uint32(tag))
+                } else if i == payload_index {
+                    payload
+                } else {
+                    b.zero_initializer(f.clone())
+                }
+            })
+            .collect::<Vec<_>>();
+        let v = b.call(Func::Struct, &nodes, ty.clone());
+        b.bitcast(v, U::type_())
+    });
+    Expr::<U>::from_node(node.into())
+}
+
+/// Builds a `U` with the given tag and no payload
+#[doc(hidden)]
+pub fn __tagged_union_new_unit<U: Value>(tag: u32) -> Expr<U> {
+    let node = __current_scope(|b| {
+        let v = b.zero_initializer(U::type_());
+        let tag = b.const_(Const::Uint32(tag));
+        let i = b.const_(Const::Int32(0));
+        b.call(Func::InsertElement, &[v, tag, i], U::type_())
+    });
+    Expr::<U>::from_node(node.into())
+}
@@ -12,9 +12,13 @@ mod nalgebra;
 pub mod coords;
 mod element;
 mod impls;
+mod matrix;
+mod quat;
 pub mod swizzle;
 
 pub use impls::*;
+pub use matrix::*;
+pub use quat::*;
 pub use swizzle::*;
 
 pub trait VectorElement: VectorAlign<2> + VectorAlign<3> + VectorAlign<4> {}
@@ -422,6 +426,34 @@ pub mod alias {
     pub type Bool2 = Vec2<bool>;
     pub type Bool3 = Vec3<bool>;
     pub type Bool4 = Vec4<bool>;
+    // `R`x`C`: `R` rows and `C` columns, so `Float3x4` is an affine transform
+    pub type Half2x2 = Matrix<f16, 2, 2>;
+    pub type Half2x3 = Matrix<f16, 2, 3>;
+    pub type Half2x4 = Matrix<f16, 2, 4>;
+    pub type Half3x2 = Matrix<f16, 3, 2>;
+    pub type Half3x3 = Matrix<f16, 3, 3>;
+    pub type Half3x4 = Matrix<f16, 3, 4>;
+    pub type Half4x2 = Matrix<f16, 4, 2>;
+    pub type Half4x3 = Matrix<f16, 4, 3>;
+    pub type Half4x4 = Matrix<f16, 4, 4>;
+    pub type Float2x2 = Matrix<f32, 2, 2>;
+    pub type Float2x3 = Matrix<f32, 2, 3>;
+    pub type Float2x4 = Matrix<f32, 2, 4>;
+    pub type Float3x2 = Matrix<f32, 3, 2>;
+    pub type Float3x3 = Matrix<f32, 3, 3>;
+    pub type Float3x4 = Matrix<f32, 3, 4>;
+    pub type Float4x2 = Matrix<f32, 4, 2>;
+    pub type Float4x3 = Matrix<f32, 4, 3>;
+    pub type Float4x4 = Matrix<f32, 4, 4>;
+    pub type Double2x2 = Matrix<f64, 2, 2>;
+    pub type Double2x3 = Matrix<f64, 2, 3>;
+    pub type Double2x4 = Matrix<f64, 2, 4>;
+    pub type Double3x2 = Matrix<f64, 3, 2>;
+    pub type Double3x3 = Matrix<f64, 3, 3>;
+    pub type Double3x4 = Matrix<f64, 3, 4>;
+    pub type Double4x2 = Matrix<f64, 4, 2>;
+    pub type Double4x3 = Matrix<f64, 4, 3>;
+    pub type Double4x4 = Matrix<f64, 4, 4>;
 }
 
 // Matrix
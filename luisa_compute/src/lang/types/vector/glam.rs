@@ -115,3 +115,42 @@ impl From<::glam::Mat4> for Mat4 {
         Self::from_column_array(&value.to_cols_array_2d())
     }
 }
+
+macro_rules! impl_glam_matrix_conversions {
+    ($($T:ty = $G:ty),+ $(,)?) => {
+        $(
+            impl From<$T> for $G {
+                fn from(value: $T) -> Self {
+                    <$G>::from_cols_array_2d(&value.to_column_array())
+                }
+            }
+            impl From<$G> for $T {
+                fn from(value: $G) -> Self {
+                    <$T>::from_column_array(&value.to_cols_array_2d())
+                }
+            }
+        )+
+    };
+}
+
+impl_glam_matrix_conversions!(
+    Matrix<f32, 2, 2> = ::glam::Mat2,
+    Matrix<f32, 3, 3> = ::glam::Mat3,
+    Matrix<f32, 4, 4> = ::glam::Mat4,
+    Matrix<f64, 2, 2> = ::glam::DMat2,
+    Matrix<f64, 3, 3> = ::glam::DMat3,
+    Matrix<f64, 4, 4> = ::glam::DMat4,
+    Matrix<f32, 3, 4> = ::glam::Affine3A,
+    Matrix<f64, 3, 4> = ::glam::DAffine3,
+);
+
+impl From<Quat> for ::glam::Quat {
+    fn from(value: Quat) -> Self {
+        Self::from_xyzw(value.x, value.y, value.z, value.w)
+    }
+}
+impl From<::glam::Quat> for Quat {
+    fn from(value: ::glam::Quat) -> Self {
+        Self::new(value.x, value.y, value.z, value.w)
+    }
+}
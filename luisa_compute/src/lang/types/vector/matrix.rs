@@ -0,0 +1,430 @@
+use super::*;
+use crate::lang::ops::{CrossExpr, DotExpr, MatExpr, MulExpr};
+use crate::lang::types::core::Floating;
+
+/// Element type of a [`Matrix`]
+pub trait MatrixElement: Floating + VectorElement + PartialEq + Debug {
+    const ZERO: Self;
+    const ONE: Self;
+}
+impl MatrixElement for f16 {
+    const ZERO: Self = f16::ZERO;
+    const ONE: Self = f16::ONE;
+}
+impl MatrixElement for f32 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+}
+impl MatrixElement for f64 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+}
+
+/// A column-major matrix with `R` rows and `C` columns
+///
+/// Unlike [`SquareMatrix`], which maps to the native `float2x2`..`float4x4` types,
+/// a `Matrix` is stored as a struct of `C` column vectors. This allows `f16` and `f64`
+/// elements and non-square shapes such as the 3x4 affine transform [`Float3x4`](alias::Float3x4).
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct Matrix<T: VectorAlign<R>, const R: usize, const C: usize> {
+    pub cols: [Vector<T, R>; C],
+}
+
+impl<T: VectorAlign<R> + Debug, const R: usize, const C: usize> Debug for Matrix<T, R, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.cols.fmt(f)
+    }
+}
+
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> TypeOf for Matrix<T, R, C> {
+    fn type_() -> CArc<Type> {
+        let type_ = Type::Struct(ir::StructType {
+            fields: CBoxedSlice::new(vec![Vector::<T, R>::type_(); C]),
+            size: std::mem::size_of::<Self>(),
+            alignment: std::mem::align_of::<Self>(),
+        });
+        assert_eq!(std::mem::size_of::<Self>(), type_.size());
+        register_type(type_)
+    }
+}
+
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> Value for Matrix<T, R, C> {
+    type Expr = MatrixExpr<T, R, C>;
+    type Var = MatrixVar<T, R, C>;
+    type AtomicRef = MatrixAtomicRef<T, R, C>;
+}
+
+#[derive(Copy, Clone)]
+pub struct MatrixExpr<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> {
+    self_: Expr<Matrix<T, R, C>>,
+    pub cols: [Expr<Vector<T, R>>; C],
+}
+#[derive(Copy, Clone)]
+pub struct MatrixVar<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> {
+    self_: Var<Matrix<T, R, C>>,
+    pub cols: [Var<Vector<T, R>>; C],
+}
+#[derive(Copy, Clone)]
+pub struct MatrixAtomicRef<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> {
+    self_: AtomicRef<Matrix<T, R, C>>,
+    pub cols: [AtomicRef<Vector<T, R>>; C],
+}
+
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> ExprProxy
+    for MatrixExpr<T, R, C>
+{
+    type Value = Matrix<T, R, C>;
+    fn from_expr(e: Expr<Self::Value>) -> Self {
+        Self {
+            self_: e,
+            cols: std::array::from_fn(|i| {
+                FromNode::from_node(__extract::<Vector<T, R>>(e.node(), i))
+            }),
+        }
+    }
+    fn as_expr_from_proxy(&self) -> &Expr<Self::Value> {
+        &self.self_
+    }
+}
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> VarProxy
+    for MatrixVar<T, R, C>
+{
+    type Value = Matrix<T, R, C>;
+    fn from_var(e: Var<Self::Value>) -> Self {
+        Self {
+            self_: e,
+            cols: std::array::from_fn(|i| {
+                FromNode::from_node(__extract::<Vector<T, R>>(e.node(), i))
+            }),
+        }
+    }
+    fn as_var_from_proxy(&self) -> &Var<Self::Value> {
+        &self.self_
+    }
+}
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> Deref
+    for MatrixVar<T, R, C>
+{
+    type Target = Expr<Matrix<T, R, C>>;
+    fn deref(&self) -> &Self::Target {
+        _deref_proxy(self)
+    }
+}
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> AtomicRefProxy
+    for MatrixAtomicRef<T, R, C>
+{
+    type Value = Matrix<T, R, C>;
+    fn from_atomic_ref(e: AtomicRef<Self::Value>) -> Self {
+        Self {
+            self_: e,
+            cols: std::array::from_fn(|i| {
+                FromNode::from_node(__extract::<Vector<T, R>>(e.node(), i))
+            }),
+        }
+    }
+    fn as_atomic_ref_from_proxy(&self) -> &AtomicRef<Self::Value> {
+        &self.self_
+    }
+}
+
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> Matrix<T, R, C> {
+    pub fn from_cols(cols: [Vector<T, R>; C]) -> Self {
+        Self { cols }
+    }
+    pub fn from_column_array(array: &[[T; R]; C]) -> Self {
+        Self {
+            cols: array.map(Vector::<T, R>::from_elements),
+        }
+    }
+    pub fn to_column_array(&self) -> [[T; R]; C] {
+        self.cols.map(|x| x.elements)
+    }
+    pub fn zero() -> Self {
+        Self {
+            cols: [Vector::splat(T::ZERO); C],
+        }
+    }
+    /// Ones on the main diagonal, also for non-square shapes
+    pub fn identity() -> Self {
+        let mut m = Self::zero();
+        for i in 0..R.min(C) {
+            m.cols[i].elements[i] = T::ONE;
+        }
+        m
+    }
+    pub fn transpose(&self) -> Matrix<T, C, R>
+    where
+        T: VectorAlign<C>,
+    {
+        Matrix {
+            cols: std::array::from_fn(|r| {
+                Vector::from_elements(std::array::from_fn(|c| self.cols[c].elements[r]))
+            }),
+        }
+    }
+    pub fn from_elems_expr(cols: [Expr<Vector<T, R>>; C]) -> Expr<Self> {
+        let cols = cols.map(|x| x.node().get());
+        Expr::<Self>::from_node(__compose::<Self>(&cols).into())
+    }
+}
+
+impl<T: MatrixElement> Matrix<T, 3, 4> {
+    /// Drops the last row of an affine 4x4 transform
+    pub fn from_affine4x4(m: Matrix<T, 4, 4>) -> Self {
+        Self {
+            cols: m
+                .cols
+                .map(|c| Vector::from_elements([c.elements[0], c.elements[1], c.elements[2]])),
+        }
+    }
+    pub fn to_affine4x4(&self) -> Matrix<T, 4, 4> {
+        let mut cols = self
+            .cols
+            .map(|c| Vector::from_elements([c.elements[0], c.elements[1], c.elements[2], T::ZERO]));
+        cols[3].elements[3] = T::ONE;
+        Matrix { cols }
+    }
+}
+
+impl<const N: usize> From<SquareMatrix<N>> for Matrix<f32, N, N>
+where
+    f32: VectorAlign<N>,
+{
+    fn from(value: SquareMatrix<N>) -> Self {
+        Self { cols: value.cols }
+    }
+}
+impl<const N: usize> From<Matrix<f32, N, N>> for SquareMatrix<N>
+where
+    f32: VectorAlign<N>,
+{
+    fn from(value: Matrix<f32, N, N>) -> Self {
+        Self { cols: value.cols }
+    }
+}
+
+// generic `R` and `C` are not covered by `Linear`, so the column arithmetic
+// below is recorded directly
+fn elem<T: MatrixElement + VectorAlign<N>, const N: usize>(
+    v: Expr<Vector<T, N>>,
+    i: usize,
+) -> Expr<T> {
+    Expr::<T>::from_node(__extract::<T>(v.node(), i))
+}
+fn axpy<T: MatrixElement + VectorAlign<N>, const N: usize>(
+    a: Expr<T>,
+    x: Expr<Vector<T, N>>,
+    y: Option<Expr<Vector<T, N>>>,
+) -> Expr<Vector<T, N>> {
+    let ax: Expr<Vector<T, N>> = Func::Mul.call2(Vector::<T, N>::splat_expr(a), x);
+    match y {
+        Some(y) => Func::Add.call2(ax, y),
+        None => ax,
+    }
+}
+
+impl<T: MatrixElement + VectorAlign<R>, const R: usize, const C: usize> MatrixExpr<T, R, C> {
+    pub fn row(&self, i: usize) -> Expr<Vector<T, C>>
+    where
+        T: VectorAlign<C>,
+    {
+        assert!(i < R, "Row index {} out of bounds", i);
+        Vector::<T, C>::from_elems_expr(self.cols.map(|c| elem(c, i)))
+    }
+    pub fn transpose(&self) -> Expr<Matrix<T, C, R>>
+    where
+        T: VectorAlign<C>,
+    {
+        Matrix::<T, C, R>::from_elems_expr(std::array::from_fn(|r| self.row(r)))
+    }
+    pub fn comp_mul(&self, rhs: impl AsExpr<Value = Matrix<T, R, C>>) -> Expr<Matrix<T, R, C>> {
+        let rhs = rhs.as_expr();
+        Matrix::<T, R, C>::from_elems_expr(std::array::from_fn(|c| {
+            Func::Mul.call2(self.cols[c], rhs.cols[c])
+        }))
+    }
+    pub fn scale(&self, s: impl AsExpr<Value = T>) -> Expr<Matrix<T, R, C>> {
+        let s = s.as_expr();
+        Matrix::<T, R, C>::from_elems_expr(self.cols.map(|c| axpy(s, c, None)))
+    }
+}
+
+impl<T: MatrixElement + VectorAlign<R> + VectorAlign<C>, const R: usize, const C: usize>
+    MulExpr<Expr<Vector<T, C>>> for Expr<Matrix<T, R, C>>
+{
+    type Output = Expr<Vector<T, R>>;
+    fn mul(self, rhs: Expr<Vector<T, C>>) -> Self::Output {
+        let mut acc = None;
+        for (c, col) in self.cols.iter().enumerate() {
+            acc = Some(axpy(elem(rhs, c), *col, acc));
+        }
+        acc.expect("Matrix must have at least one column")
+    }
+}
+impl<
+        T: MatrixElement + VectorAlign<R> + VectorAlign<C>,
+        const R: usize,
+        const C: usize,
+        const K: usize,
+    > MulExpr<Expr<Matrix<T, C, K>>> for Expr<Matrix<T, R, C>>
+{
+    type Output = Expr<Matrix<T, R, K>>;
+    fn mul(self, rhs: Expr<Matrix<T, C, K>>) -> Self::Output {
+        Matrix::<T, R, K>::from_elems_expr(
+            rhs.cols
+                .map(|c| <Self as MulExpr<Expr<Vector<T, C>>>>::mul(self, c)),
+        )
+    }
+}
+
+impl<T: MatrixElement> MatrixExpr<T, 3, 4> {
+    /// Applies the affine transform to a point
+    pub fn transform_point(&self, p: impl AsExpr<Value = Vec3<T>>) -> Expr<Vec3<T>> {
+        let p = p.as_expr();
+        let v = self.transform_vector(p);
+        Func::Add.call2(v, self.cols[3])
+    }
+    /// Applies the linear part of the affine transform to a direction
+    pub fn transform_vector(&self, v: impl AsExpr<Value = Vec3<T>>) -> Expr<Vec3<T>> {
+        let v = v.as_expr();
+        let x = axpy(elem(v, 0), self.cols[0], None);
+        let xy = axpy(elem(v, 1), self.cols[1], Some(x));
+        axpy(elem(v, 2), self.cols[2], Some(xy))
+    }
+    pub fn to_affine4x4(&self) -> Expr<Matrix<T, 4, 4>> {
+        let zero = T::ZERO.expr();
+        Matrix::<T, 4, 4>::from_elems_expr(std::array::from_fn(|c| {
+            let w = if c == 3 { T::ONE.expr() } else { zero };
+            let col = self.cols[c];
+            Vec4::<T>::from_elems_expr([elem(col, 0), elem(col, 1), elem(col, 2), w])
+        }))
+    }
+}
+impl<T: MatrixElement> MatrixExpr<T, 4, 4> {
+    /// Drops the last row of an affine 4x4 transform
+    pub fn to_affine3x4(&self) -> Expr<Matrix<T, 3, 4>> {
+        Matrix::<T, 3, 4>::from_elems_expr(
+            self.cols
+                .map(|c| Vec3::<T>::from_elems_expr([elem(c, 0), elem(c, 1), elem(c, 2)])),
+        )
+    }
+}
+impl<const N: usize> MatrixExpr<f32, N, N>
+where
+    f32: VectorAlign<N>,
+    SquareMatrix<N>: Value,
+{
+    /// Converts to the native matrix type, e.g. to use it with [`MatExpr`] builtins
+    pub fn to_square(&self) -> Expr<SquareMatrix<N>> {
+        SquareMatrix::<N>::from_elems_expr(self.cols)
+    }
+}
+
+impl<T: MatrixElement> MatExpr for Expr<Matrix<T, 2, 2>> {
+    type Scalar = Expr<T>;
+    type Value = Matrix<T, 2, 2>;
+    fn comp_mul(&self, rhs: impl AsExpr<Value = Self::Value>) -> Self {
+        let m: &MatrixExpr<T, 2, 2> = self;
+        m.comp_mul(rhs)
+    }
+    fn transpose(&self) -> Self {
+        let m: &MatrixExpr<T, 2, 2> = self;
+        m.transpose()
+    }
+    fn determinant(&self) -> Self::Scalar {
+        let [a, b] = self.cols;
+        let ad: Expr<T> = Func::Mul.call2(elem(a, 0), elem(b, 1));
+        let bc: Expr<T> = Func::Mul.call2(elem(b, 0), elem(a, 1));
+        Func::Sub.call2(ad, bc)
+    }
+    fn inverse(&self) -> Self {
+        let [a, b] = self.cols;
+        let inv_det: Expr<T> = Func::Div.call2(T::ONE.expr(), self.determinant());
+        let neg = |x: Expr<T>| -> Expr<T> { Func::Neg.call(x) };
+        let adj = Matrix::<T, 2, 2>::from_elems_expr([
+            Vec2::<T>::from_elems_expr([elem(b, 1), neg(elem(a, 1))]),
+            Vec2::<T>::from_elems_expr([neg(elem(b, 0)), elem(a, 0)]),
+        ]);
+        adj.scale(inv_det)
+    }
+}
+impl<T: MatrixElement> MatExpr for Expr<Matrix<T, 3, 3>> {
+    type Scalar = Expr<T>;
+    type Value = Matrix<T, 3, 3>;
+    fn comp_mul(&self, rhs: impl AsExpr<Value = Self::Value>) -> Self {
+        let m: &MatrixExpr<T, 3, 3> = self;
+        m.comp_mul(rhs)
+    }
+    fn transpose(&self) -> Self {
+        let m: &MatrixExpr<T, 3, 3> = self;
+        m.transpose()
+    }
+    fn determinant(&self) -> Self::Scalar {
+        let [a, b, c] = self.cols;
+        a.cross(b).dot(c)
+    }
+    fn inverse(&self) -> Self {
+        let [a, b, c] = self.cols;
+        let r0 = b.cross(c);
+        let r1 = c.cross(a);
+        let r2 = a.cross(b);
+        let inv_det: Expr<T> = Func::Div.call2(T::ONE.expr(), r2.dot(c));
+        // rows of the inverse are r0, r1, r2
+        Matrix::<T, 3, 3>::from_elems_expr([r0, r1, r2])
+            .transpose()
+            .scale(inv_det)
+    }
+}
+impl<T: MatrixElement> MatExpr for Expr<Matrix<T, 4, 4>> {
+    type Scalar = Expr<T>;
+    type Value = Matrix<T, 4, 4>;
+    fn comp_mul(&self, rhs: impl AsExpr<Value = Self::Value>) -> Self {
+        let m: &MatrixExpr<T, 4, 4> = self;
+        m.comp_mul(rhs)
+    }
+    fn transpose(&self) -> Self {
+        let m: &MatrixExpr<T, 4, 4> = self;
+        m.transpose()
+    }
+    fn determinant(&self) -> Self::Scalar {
+        let (_, _, det) = self.inverse_parts();
+        det
+    }
+    fn inverse(&self) -> Self {
+        let ([a, b, c, d], [s, t, u, v], det) = self.inverse_parts();
+        let inv_det: Expr<T> = Func::Div.call2(T::ONE.expr(), det);
+        let [x, y, z, w] = self.cols.map(|col| elem(col, 3));
+        let neg = |x: Expr<T>| -> Expr<T> { Func::Neg.call(x) };
+        let r0 = axpy(y, t, Some(b.cross(v)));
+        let r1 = axpy(neg(x), t, Some(v.cross(a)));
+        let r2 = axpy(w, s, Some(d.cross(u)));
+        let r3 = axpy(neg(z), s, Some(u.cross(c)));
+        let rows = [
+            (r0, neg(b.dot(t))),
+            (r1, a.dot(t)),
+            (r2, neg(d.dot(s))),
+            (r3, c.dot(s)),
+        ]
+        .map(|(r, e)| Vec4::<T>::from_elems_expr([elem(r, 0), elem(r, 1), elem(r, 2), e]));
+        Matrix::<T, 4, 4>::from_elems_expr(rows)
+            .transpose()
+            .scale(inv_det)
+    }
+}
+impl<T: MatrixElement> MatrixExpr<T, 4, 4> {
+    /// Upper 3x3 columns, the cross products of "Foundations of Game Engine Development"
+    /// (Lengyel, 2016) and the determinant
+    fn inverse_parts(&self) -> ([Expr<Vec3<T>>; 4], [Expr<Vec3<T>>; 4], Expr<T>) {
+        let [a, b, c, d] = self
+            .cols
+            .map(|col| Vec3::<T>::from_elems_expr([elem(col, 0), elem(col, 1), elem(col, 2)]));
+        let [x, y, z, w] = self.cols.map(|col| elem(col, 3));
+        let s = a.cross(b);
+        let t = c.cross(d);
+        let u: Expr<Vec3<T>> = Func::Sub.call2(axpy(y, a, None), axpy(x, b, None));
+        let v: Expr<Vec3<T>> = Func::Sub.call2(axpy(w, c, None), axpy(z, d, None));
+        let det: Expr<T> = Func::Add.call2(s.dot(v), t.dot(u));
+        ([a, b, c, d], [s, t, u, v], det)
+    }
+}
@@ -34,3 +34,32 @@ where
         Self::from(value.to_column_array())
     }
 }
+
+impl<const R: usize, const C: usize, T: MatrixElement + VectorAlign<R>> From<na::SMatrix<T, R, C>>
+    for Matrix<T, R, C>
+{
+    fn from(value: na::SMatrix<T, R, C>) -> Self {
+        Self::from_column_array(&value.into())
+    }
+}
+
+impl<const R: usize, const C: usize, T: MatrixElement + VectorAlign<R>> From<Matrix<T, R, C>>
+    for na::SMatrix<T, R, C>
+{
+    fn from(value: Matrix<T, R, C>) -> Self {
+        Self::from(value.to_column_array())
+    }
+}
+
+impl From<na::UnitQuaternion<f32>> for Quat {
+    fn from(value: na::UnitQuaternion<f32>) -> Self {
+        let q = value.into_inner();
+        Self::new(q.i, q.j, q.k, q.w)
+    }
+}
+
+impl From<Quat> for na::UnitQuaternion<f32> {
+    fn from(value: Quat) -> Self {
+        Self::new_unchecked(na::Quaternion::new(value.w, value.x, value.y, value.z))
+    }
+}
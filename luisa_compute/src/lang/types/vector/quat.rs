@@ -0,0 +1,184 @@
+use super::*;
+use crate::lang::control_flow::select;
+use crate::lang::ops::{AbsExpr, CrossExpr, DotExpr, FloatExpr, MinMaxExpr, MulExpr, NormExpr};
+
+/// A rotation quaternion `xi + yj + zk + w`
+///
+/// All methods on [`QuatExpr`] are built from regular arithmetic, so they can be
+/// differentiated with [`autodiff`](crate::lang::autodiff).
+/// Methods other than [`QuatExpr::normalize`] assume unit quaternions.
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Clone, Copy, Value, Debug, Soa, PartialEq)]
+#[value_new(pub)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+
+impl Quat {
+    pub const IDENTITY: Self = Self {
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+        w: 1.0,
+    };
+    pub fn new(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+    /// Rotation by `angle` radians around `axis`, which must be normalized
+    pub fn from_axis_angle(axis: Float3, angle: f32) -> Self {
+        let (s, c) = (angle * 0.5).sin_cos();
+        Self::new(axis.x * s, axis.y * s, axis.z * s, c)
+    }
+    /// Rotation by `angle` radians around `axis`, which must be normalized
+    #[tracked]
+    pub fn from_axis_angle_expr(
+        axis: impl AsExpr<Value = Float3>,
+        angle: impl AsExpr<Value = f32>,
+    ) -> Expr<Self> {
+        let axis = axis.as_expr();
+        let half = angle.as_expr() * 0.5;
+        let v = axis * half.sin();
+        Self::new_expr(v.x, v.y, v.z, half.cos())
+    }
+    /// Rotation part of an orthonormal matrix
+    #[tracked]
+    pub fn from_mat3_expr(m: impl AsExpr<Value = Mat3>) -> Expr<Self> {
+        let m = m.as_expr();
+        let (m00, m01, m02) = (m.x.x, m.y.x, m.z.x);
+        let (m10, m11, m12) = (m.x.y, m.y.y, m.z.y);
+        let (m20, m21, m22) = (m.x.z, m.y.z, m.z.z);
+        let trace = m00 + m11 + m22;
+        // all four candidates are computed so that the result stays differentiable;
+        // the clamp keeps the discarded ones finite
+        let s0 = (trace + 1.0).max_(1e-12f32).sqrt() * 2.0;
+        let q0 = Self::new_expr(
+            (m21 - m12) / s0,
+            (m02 - m20) / s0,
+            (m10 - m01) / s0,
+            0.25 * s0,
+        );
+        let s1 = (1.0 + m00 - m11 - m22).max_(1e-12f32).sqrt() * 2.0;
+        let q1 = Self::new_expr(
+            0.25 * s1,
+            (m01 + m10) / s1,
+            (m02 + m20) / s1,
+            (m21 - m12) / s1,
+        );
+        let s2 = (1.0 + m11 - m00 - m22).max_(1e-12f32).sqrt() * 2.0;
+        let q2 = Self::new_expr(
+            (m01 + m10) / s2,
+            0.25 * s2,
+            (m12 + m21) / s2,
+            (m02 - m20) / s2,
+        );
+        let s3 = (1.0 + m22 - m00 - m11).max_(1e-12f32).sqrt() * 2.0;
+        let q3 = Self::new_expr(
+            (m02 + m20) / s3,
+            (m12 + m21) / s3,
+            0.25 * s3,
+            (m10 - m01) / s3,
+        );
+        let q23 = select(m11 > m22, q2, q3);
+        let q123 = select((m00 > m11) & (m00 > m22), q1, q23);
+        select(trace > 0.0, q0, q123)
+    }
+}
+
+impl QuatExpr {
+    #[tracked]
+    pub fn xyz(&self) -> Expr<Float3> {
+        Float3::expr(self.x, self.y, self.z)
+    }
+    #[tracked]
+    pub fn xyzw(&self) -> Expr<Float4> {
+        Float4::expr(self.x, self.y, self.z, self.w)
+    }
+    #[tracked]
+    pub fn conjugate(&self) -> Expr<Quat> {
+        Quat::new_expr(-self.x, -self.y, -self.z, self.w)
+    }
+    #[tracked]
+    pub fn inverse(&self) -> Expr<Quat> {
+        let inv = 1.0 / self.xyzw().length_squared();
+        let v = self.conjugate().xyzw() * inv;
+        Quat::new_expr(v.x, v.y, v.z, v.w)
+    }
+    #[tracked]
+    pub fn dot(&self, other: impl AsExpr<Value = Quat>) -> Expr<f32> {
+        self.xyzw().dot(other.as_expr().xyzw())
+    }
+    #[tracked]
+    pub fn normalize(&self) -> Expr<Quat> {
+        let v = self.xyzw().normalize();
+        Quat::new_expr(v.x, v.y, v.z, v.w)
+    }
+    /// Hamilton product, applies `other` first and then `self`
+    #[tracked]
+    pub fn mul(&self, other: impl AsExpr<Value = Quat>) -> Expr<Quat> {
+        let other = other.as_expr();
+        let (a, b) = (self.xyz(), other.xyz());
+        let v = self.w * b + other.w * a + a.cross(b);
+        Quat::new_expr(v.x, v.y, v.z, self.w * other.w - a.dot(b))
+    }
+    /// Rotates `v` by this quaternion
+    #[tracked]
+    pub fn rotate(&self, v: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        let v = v.as_expr();
+        let q = self.xyz();
+        let t = 2.0 * q.cross(v);
+        v + self.w * t + q.cross(t)
+    }
+    #[tracked]
+    pub fn to_mat3(&self) -> Expr<Mat3> {
+        let (x, y, z, w) = (self.x, self.y, self.z, self.w);
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (w * x, w * y, w * z);
+        Mat3::expr(
+            Float3::expr(1.0 - 2.0 * (yy + zz), 2.0 * (xy + wz), 2.0 * (xz - wy)),
+            Float3::expr(2.0 * (xy - wz), 1.0 - 2.0 * (xx + zz), 2.0 * (yz + wx)),
+            Float3::expr(2.0 * (xz + wy), 2.0 * (yz - wx), 1.0 - 2.0 * (xx + yy)),
+        )
+    }
+    /// Spherical linear interpolation along the shortest arc
+    #[tracked]
+    pub fn slerp(
+        &self,
+        other: impl AsExpr<Value = Quat>,
+        t: impl AsExpr<Value = f32>,
+    ) -> Expr<Quat> {
+        let t = t.as_expr();
+        let a = self.xyzw();
+        let b = other.as_expr().xyzw();
+        let d = a.dot(b);
+        let b = select(d < 0.0, -b, b);
+        let d = d.abs();
+        // fall back to normalized lerp when the quaternions are nearly parallel;
+        // the clamp keeps the discarded branch finite
+        let theta = d.min_(0.9995f32).acos();
+        let inv_sin = 1.0 / theta.sin();
+        let wa = ((1.0 - t) * theta).sin() * inv_sin;
+        let wb = (t * theta).sin() * inv_sin;
+        let slerp = a * wa + b * wb;
+        let nlerp = (a + (b - a) * t).normalize();
+        let v = select(d > 0.9995, nlerp, slerp);
+        Quat::new_expr(v.x, v.y, v.z, v.w)
+    }
+}
+
+impl MulExpr<Expr<Quat>> for Expr<Quat> {
+    type Output = Expr<Quat>;
+    fn mul(self, rhs: Expr<Quat>) -> Self::Output {
+        QuatExpr::mul(&self, rhs)
+    }
+}
+impl MulExpr<Expr<Float3>> for Expr<Quat> {
+    type Output = Expr<Float3>;
+    fn mul(self, rhs: Expr<Float3>) -> Self::Output {
+        self.rotate(rhs)
+    }
+}
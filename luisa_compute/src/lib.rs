@@ -7,9 +7,12 @@ use std::backtrace::Backtrace;
 use std::path::Path;
 use std::sync::Arc;
 
+pub mod image;
 pub mod lang;
+pub mod nn;
 #[deprecated(note = "use device_log! instead for builtin kernel printing")]
 pub mod printer;
+pub mod raster;
 pub mod resource;
 pub mod rtx;
 pub mod runtime;
@@ -0,0 +1,420 @@
+//! Small fully connected networks evaluated per thread, in the spirit of tiny-cuda-nn.
+//!
+//! A [`Sequential`] network keeps all of its parameters in a single buffer, so it
+//! can be evaluated from any kernel with [`Sequential::forward`]. Training
+//! accumulates weight gradients of every thread into [`Sequential::grads`] with
+//! atomics, and an [`Adam`] kernel then applies and clears them.
+//!
+//! ```no_run
+//! use luisa_compute::prelude::*;
+//! use luisa_compute::nn::*;
+//! let ctx = Context::new(std::env::current_exe().unwrap());
+//! let device = ctx.create_device("cpu");
+//! let net = Sequential::<f16>::new(
+//!     &device,
+//!     &[
+//!         Linear::new(1, 32, Activation::ReLU),
+//!         Linear::new(32, 1, Activation::None),
+//!     ],
+//!     0,
+//! );
+//! let mut adam = Adam::new(&device, &net, AdamConfig::default());
+//! let train = Kernel::<fn()>::new(&device, &track!(|| {
+//!     let x = dispatch_id().x.as_f32() / 1024.0;
+//!     net.train(&[x], |y| {
+//!         let e = y[0] - x.sin();
+//!         e * e
+//!     });
+//! }));
+//! for _ in 0..100 {
+//!     train.dispatch([1024, 1, 1]);
+//!     adam.step(1.0 / 1024.0);
+//! }
+//! ```
+use crate::internal_prelude::*;
+use crate::lang::autodiff::{autodiff, backward, gradient, requires_grad};
+use crate::lang::random::{HostSampler, Pcg32};
+use crate::lang::types::array::VLArrayVar;
+use crate::lang::types::core::Floating;
+
+mod hash_grid;
+pub use hash_grid::*;
+
+/// Storage type of network parameters
+pub trait Weight: Floating + Linear<Scalar = Self> {
+    fn from_f32(x: f32) -> Self;
+    fn to_f32(self) -> f32;
+}
+impl Weight for f16 {
+    fn from_f32(x: f32) -> Self {
+        f16::from_f32(x)
+    }
+    fn to_f32(self) -> f32 {
+        f16::to_f32(self)
+    }
+}
+impl Weight for f32 {
+    fn from_f32(x: f32) -> Self {
+        x
+    }
+    fn to_f32(self) -> f32 {
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Activation {
+    None,
+    ReLU,
+    LeakyReLU(f32),
+    Sigmoid,
+    Tanh,
+    Exp,
+    Softplus,
+}
+
+impl Activation {
+    pub fn apply(&self, x: impl AsExpr<Value = f32>) -> Expr<f32> {
+        let x = x.as_expr();
+        match *self {
+            Activation::None => x,
+            Activation::ReLU => track!(x.max_(0.0f32)),
+            Activation::LeakyReLU(slope) => track!(select(x > 0.0, x, x * slope)),
+            Activation::Sigmoid => track!(1.0 / (1.0 + (-x).exp())),
+            Activation::Tanh => x.tanh(),
+            Activation::Exp => x.exp(),
+            Activation::Softplus => track!((1.0 + x.exp()).ln()),
+        }
+    }
+    /// Derivative at the pre-activation value `x`
+    pub fn derivative(&self, x: impl AsExpr<Value = f32>) -> Expr<f32> {
+        let x = x.as_expr();
+        match *self {
+            Activation::None => 1.0f32.expr(),
+            Activation::ReLU => track!(select(x > 0.0, 1.0f32.expr(), 0.0f32.expr())),
+            Activation::LeakyReLU(slope) => track!(select(x > 0.0, 1.0f32.expr(), slope.expr())),
+            Activation::Sigmoid => {
+                let s = Activation::Sigmoid.apply(x);
+                track!(s * (1.0 - s))
+            }
+            Activation::Tanh => {
+                let t = x.tanh();
+                track!(1.0 - t * t)
+            }
+            Activation::Softplus => Activation::Sigmoid.apply(x),
+            Activation::Exp => x.exp(),
+        }
+    }
+}
+
+/// A model whose parameters can be optimized by [`Adam`]
+pub trait Trainable {
+    type Weight: Weight;
+    fn params(&self) -> &Buffer<Self::Weight>;
+    /// Accumulated gradients, with the same layout as [`Trainable::params`]
+    fn grads(&self) -> &Buffer<f32>;
+}
+
+/// A fully connected layer `y = activation(W x + b)`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Linear {
+    pub inputs: usize,
+    pub outputs: usize,
+    pub activation: Activation,
+}
+
+impl Linear {
+    pub fn new(inputs: usize, outputs: usize, activation: Activation) -> Self {
+        Self {
+            inputs,
+            outputs,
+            activation,
+        }
+    }
+    /// Number of weights and biases
+    pub fn num_params(&self) -> usize {
+        self.inputs * self.outputs + self.outputs
+    }
+}
+
+/// A stack of [`Linear`] layers
+///
+/// The parameters of layer `l` start at an offset in [`Sequential::params`],
+/// with the `outputs x inputs` weight matrix in row-major order followed by the
+/// biases. [`Sequential::grads`] has the same layout and is always `f32`.
+pub struct Sequential<T: Weight = f32> {
+    layers: Vec<Linear>,
+    offsets: Vec<u32>,
+    pub params: Buffer<T>,
+    pub grads: Buffer<f32>,
+}
+
+impl<T: Weight> Sequential<T> {
+    /// Creates a network with Xavier-uniform weights and zero biases
+    pub fn new(device: &Device, layers: &[Linear], seed: u64) -> Self {
+        assert!(!layers.is_empty(), "network has no layers");
+        for w in layers.windows(2) {
+            assert_eq!(
+                w[0].outputs, w[1].inputs,
+                "layer outputs do not match the inputs of the next layer"
+            );
+        }
+        let mut rng = Pcg32::new(seed, 0);
+        let mut offsets = vec![];
+        let mut params = vec![];
+        for layer in layers {
+            offsets.push(params.len() as u32);
+            let bound = (6.0 / (layer.inputs + layer.outputs) as f32).sqrt();
+            for _ in 0..layer.inputs * layer.outputs {
+                params.push(T::from_f32((rng.next_f32() * 2.0 - 1.0) * bound));
+            }
+            for _ in 0..layer.outputs {
+                params.push(T::from_f32(0.0));
+            }
+        }
+        let grads = device.create_buffer(params.len());
+        grads.view(..).fill(0.0);
+        Self {
+            layers: layers.to_vec(),
+            offsets,
+            params: device.create_buffer_from_slice(&params),
+            grads,
+        }
+    }
+    pub fn layers(&self) -> &[Linear] {
+        &self.layers
+    }
+    pub fn inputs(&self) -> usize {
+        self.layers[0].inputs
+    }
+    pub fn outputs(&self) -> usize {
+        self.layers.last().unwrap().outputs
+    }
+    pub fn num_params(&self) -> usize {
+        self.params.len()
+    }
+    /// Evaluates the network for the current thread
+    pub fn forward(&self, input: &[Expr<f32>]) -> Vec<Expr<f32>> {
+        let mut x = self.input_array(input);
+        for l in 0..self.layers.len() {
+            x = self.layer_forward(l, &x).1;
+        }
+        to_exprs(&x)
+    }
+    /// Backpropagates `d_output`, the gradient of a loss with respect to the
+    /// outputs at `input`, adds the parameter gradients to [`Sequential::grads`]
+    /// and returns the gradient with respect to `input`
+    ///
+    /// The forward pass is evaluated again to recover the activations.
+    pub fn backward(&self, input: &[Expr<f32>], d_output: &[Expr<f32>]) -> Vec<Expr<f32>> {
+        assert_eq!(d_output.len(), self.outputs());
+        let mut xs = vec![self.input_array(input)];
+        let mut zs = vec![];
+        for l in 0..self.layers.len() {
+            let (z, y) = self.layer_forward(l, xs.last().unwrap());
+            zs.push(z);
+            xs.push(y);
+        }
+        let params = self.params.var();
+        let grads = self.grads.var();
+        let mut delta = to_array(d_output);
+        for (l, layer) in self.layers.iter().enumerate().rev() {
+            let (x, z) = (&xs[l], &zs[l]);
+            let (n_in, n_out) = (layer.inputs as u32, layer.outputs as u32);
+            let (w, b) = (self.offsets[l], self.offsets[l] + n_in * n_out);
+            let activation = layer.activation;
+            let dx = VLArrayVar::<f32>::zero(layer.inputs);
+            track!({
+                for o in 0u32.expr()..n_out.expr() {
+                    let d = delta.read(o) * activation.derivative(z.read(o));
+                    grads.atomic_fetch_add(b + o, d);
+                    for i in 0u32.expr()..n_in.expr() {
+                        let k = w + o * n_in + i;
+                        grads.atomic_fetch_add(k, d * x.read(i));
+                        dx.write(i, dx.read(i) + params.read(k).as_::<f32>() * d);
+                    }
+                }
+            });
+            delta = dx;
+        }
+        to_exprs(&delta)
+    }
+    /// Evaluates `loss` on the outputs at `input`, differentiates it with
+    /// [`autodiff`] and accumulates the parameter gradients, returning the loss
+    pub fn train(
+        &self,
+        input: &[Expr<f32>],
+        loss: impl Fn(&[Expr<f32>]) -> Expr<f32>,
+    ) -> Expr<f32> {
+        // the forward pass is differentiated by hand in `backward`, only the
+        // loss is recorded for autodiff, starting from its own loads of the outputs
+        let output = self
+            .forward(input)
+            .into_iter()
+            .map(|y| y.var())
+            .collect::<Vec<_>>();
+        let value = f32::var_zeroed();
+        let d_output = output.iter().map(|_| f32::var_zeroed()).collect::<Vec<_>>();
+        autodiff(|| {
+            let output = output.iter().map(|y| y.load()).collect::<Vec<_>>();
+            for y in &output {
+                requires_grad(*y);
+            }
+            let l = loss(&output);
+            value.store(l);
+            backward(l);
+            for (d, y) in d_output.iter().zip(&output) {
+                d.store(gradient(*y));
+            }
+        });
+        let d_output = d_output.iter().map(|d| d.load()).collect::<Vec<_>>();
+        self.backward(input, &d_output);
+        value.load()
+    }
+    fn input_array(&self, input: &[Expr<f32>]) -> VLArrayVar<f32> {
+        assert_eq!(input.len(), self.inputs());
+        to_array(input)
+    }
+    /// Returns the pre-activations and outputs of layer `l`
+    fn layer_forward(&self, l: usize, x: &VLArrayVar<f32>) -> (VLArrayVar<f32>, VLArrayVar<f32>) {
+        let layer = self.layers[l];
+        let (n_in, n_out) = (layer.inputs as u32, layer.outputs as u32);
+        let (w, b) = (self.offsets[l], self.offsets[l] + n_in * n_out);
+        let params = self.params.var();
+        let z = VLArrayVar::<f32>::zero(layer.outputs);
+        let y = VLArrayVar::<f32>::zero(layer.outputs);
+        track!({
+            for o in 0u32.expr()..n_out.expr() {
+                let acc = params.read(b + o).as_::<f32>().var();
+                for i in 0u32.expr()..n_in.expr() {
+                    *acc += params.read(w + o * n_in + i).as_::<f32>() * x.read(i);
+                }
+                z.write(o, acc.load());
+                y.write(o, layer.activation.apply(acc.load()));
+            }
+        });
+        (z, y)
+    }
+}
+
+impl<T: Weight> Trainable for Sequential<T> {
+    type Weight = T;
+    fn params(&self) -> &Buffer<T> {
+        &self.params
+    }
+    fn grads(&self) -> &Buffer<f32> {
+        &self.grads
+    }
+}
+
+fn to_array(v: &[Expr<f32>]) -> VLArrayVar<f32> {
+    let a = VLArrayVar::<f32>::zero(v.len());
+    for (i, x) in v.iter().enumerate() {
+        a.write(i as u32, *x);
+    }
+    a
+}
+
+fn to_exprs(a: &VLArrayVar<f32>) -> Vec<Expr<f32>> {
+    (0..a.len()).map(|i| a.read(i as u32)).collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdamConfig {
+    pub learning_rate: f32,
+    pub beta1: f32,
+    pub beta2: f32,
+    pub epsilon: f32,
+}
+
+impl Default for AdamConfig {
+    fn default() -> Self {
+        Self {
+            learning_rate: 1e-3,
+            beta1: 0.9,
+            beta2: 0.999,
+            epsilon: 1e-8,
+        }
+    }
+}
+
+/// The Adam optimizer for a [`Trainable`] model
+///
+/// Updates are applied to an `f32` copy of the parameters, which is converted
+/// to the storage type of the model after each step. The model must outlive
+/// the optimizer.
+pub struct Adam {
+    config: AdamConfig,
+    t: i32,
+    len: usize,
+    // kernels only hold weak references to captured buffers
+    master: Buffer<f32>,
+    m: Buffer<f32>,
+    v: Buffer<f32>,
+    kernel: Kernel<fn(Float2, f32)>,
+}
+
+impl Adam {
+    pub fn new<M: Trainable>(device: &Device, model: &M, config: AdamConfig) -> Self {
+        let (params, grads) = (model.params(), model.grads());
+        let len = params.len();
+        let master = device.create_buffer::<f32>(len);
+        let convert = Kernel::<fn()>::new(
+            device,
+            &track!(|| {
+                let i = dispatch_id().x;
+                master.write(i, params.read(i).as_::<f32>());
+            }),
+        );
+        convert.dispatch([len as u32, 1, 1]);
+        let m = device.create_buffer::<f32>(len);
+        let v = device.create_buffer::<f32>(len);
+        m.view(..).fill(0.0);
+        v.view(..).fill(0.0);
+        let AdamConfig {
+            learning_rate,
+            beta1,
+            beta2,
+            epsilon,
+        } = config;
+        let kernel = Kernel::<fn(Float2, f32)>::new(
+            device,
+            &track!(|correction: Expr<Float2>, grad_scale: Expr<f32>| {
+                let i = dispatch_id().x;
+                let g = grads.read(i) * grad_scale;
+                let m_i = beta1 * m.read(i) + (1.0 - beta1) * g;
+                let v_i = beta2 * v.read(i) + (1.0 - beta2) * g * g;
+                let step = (m_i / correction.x) / ((v_i / correction.y).sqrt() + epsilon);
+                let p = master.read(i) - learning_rate * step;
+                m.write(i, m_i);
+                v.write(i, v_i);
+                master.write(i, p);
+                params.write(i, p.as_::<M::Weight>());
+                grads.write(i, 0.0f32);
+            }),
+        );
+        Self {
+            config,
+            t: 0,
+            len,
+            master,
+            m,
+            v,
+            kernel,
+        }
+    }
+    pub fn config(&self) -> &AdamConfig {
+        &self.config
+    }
+    /// Applies the accumulated gradients scaled by `grad_scale`, usually one
+    /// over the batch size, and clears them
+    pub fn step(&mut self, grad_scale: f32) {
+        self.t += 1;
+        let correction = Float2::new(
+            1.0 - self.config.beta1.powi(self.t),
+            1.0 - self.config.beta2.powi(self.t),
+        );
+        self.kernel
+            .dispatch([self.len as u32, 1, 1], &correction, &grad_scale);
+    }
+}
@@ -0,0 +1,163 @@
+use super::*;
+use crate::lang::types::dynamic::DynExpr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HashGridConfig {
+    pub levels: u32,
+    /// Features per level
+    pub features: u32,
+    /// Entries per level are `2^log2_table_size`
+    pub log2_table_size: u32,
+    /// Grid resolution of the coarsest level
+    pub base_resolution: u32,
+    /// Resolution growth factor between consecutive levels
+    pub per_level_scale: f32,
+}
+
+impl Default for HashGridConfig {
+    fn default() -> Self {
+        Self {
+            levels: 16,
+            features: 2,
+            log2_table_size: 19,
+            base_resolution: 16,
+            per_level_scale: 2.0,
+        }
+    }
+}
+
+/// Multi-resolution hash encoding of positions in `[0, 1]^3`
+///
+/// Each level trilinearly interpolates feature vectors stored at the corners of
+/// a grid. Coarse levels that fit into the table are indexed densely and finer
+/// ones through a spatial hash. See Müller et al., "Instant Neural Graphics
+/// Primitives with a Multiresolution Hash Encoding".
+///
+/// Entry `e` of level `l` stores its features at
+/// `(l * 2^log2_table_size + e) * features` in [`HashGridEncoding::params`].
+pub struct HashGridEncoding<T: Weight = f16> {
+    config: HashGridConfig,
+    pub params: Buffer<T>,
+    pub grads: Buffer<f32>,
+}
+
+impl<T: Weight> HashGridEncoding<T> {
+    /// Creates an encoding with features drawn uniformly from `[-1e-4, 1e-4]`
+    pub fn new(device: &Device, config: HashGridConfig, seed: u64) -> Self {
+        assert!(config.levels > 0 && config.features > 0);
+        assert!(config.log2_table_size < 32, "table size must fit into u32");
+        let len = (config.levels << config.log2_table_size) as usize * config.features as usize;
+        let mut rng = Pcg32::new(seed, 0);
+        let params = (0..len)
+            .map(|_| T::from_f32((rng.next_f32() * 2.0 - 1.0) * 1e-4))
+            .collect::<Vec<_>>();
+        let grads = device.create_buffer(len);
+        grads.view(..).fill(0.0);
+        Self {
+            config,
+            params: device.create_buffer_from_slice(&params),
+            grads,
+        }
+    }
+    pub fn config(&self) -> &HashGridConfig {
+        &self.config
+    }
+    /// Number of encoded features, `levels * features`
+    pub fn outputs(&self) -> usize {
+        (self.config.levels * self.config.features) as usize
+    }
+    /// Encodes `pos` into [`HashGridEncoding::outputs`] features, level by level
+    ///
+    /// The encoding is inlined into the caller, prefer
+    /// [`HashGridEncoding::encode_callable`] when it is evaluated more than once
+    /// per kernel.
+    pub fn encode(&self, pos: impl AsExpr<Value = Float3>) -> Vec<Expr<f32>> {
+        let pos = pos.as_expr();
+        let params = self.params.var();
+        let features = self.config.features;
+        let mut out = vec![];
+        for level in 0..self.config.levels {
+            let mut acc = vec![0.0f32.expr(); features as usize];
+            self.for_each_corner(level, pos, |index, w| {
+                for (f, acc) in acc.iter_mut().enumerate() {
+                    let x = params.read(track!(index * features + f as u32));
+                    let a = *acc;
+                    *acc = track!(a + w * x.as_::<f32>());
+                }
+            });
+            out.extend(acc);
+        }
+        out
+    }
+    /// [`HashGridEncoding::encode`] as a callable, so that the encoding is
+    /// emitted once however often it is called
+    ///
+    /// The callable returns the features as an array of
+    /// [`HashGridEncoding::outputs`] floats, see [`DynExpr::get_array`].
+    pub fn encode_callable(&self) -> Callable<fn(Expr<Float3>) -> DynExpr> {
+        Callable::new(&self.params.device, |pos: Expr<Float3>| {
+            let features = to_array(&self.encode(pos));
+            DynExpr::from_node(features.load().node())
+        })
+    }
+    /// Adds the gradients of the features at `pos`, given the gradient
+    /// `d_output` of a loss with respect to [`HashGridEncoding::encode`], to
+    /// [`HashGridEncoding::grads`] with atomics
+    pub fn backward(&self, pos: impl AsExpr<Value = Float3>, d_output: &[Expr<f32>]) {
+        assert_eq!(d_output.len(), self.outputs());
+        let pos = pos.as_expr();
+        let grads = self.grads.var();
+        let features = self.config.features;
+        for level in 0..self.config.levels {
+            let d = &d_output[(level * features) as usize..((level + 1) * features) as usize];
+            self.for_each_corner(level, pos, |index, w| {
+                for (f, &d) in d.iter().enumerate() {
+                    grads.atomic_fetch_add(track!(index * features + f as u32), track!(w * d));
+                }
+            });
+        }
+    }
+    /// Calls `f(entry, weight)` for the 8 corners of the cell containing `pos` on `level`
+    fn for_each_corner(
+        &self,
+        level: u32,
+        pos: Expr<Float3>,
+        mut f: impl FnMut(Expr<u32>, Expr<f32>),
+    ) {
+        let config = &self.config;
+        let scale = config.base_resolution as f32 * config.per_level_scale.powi(level as i32) - 1.0;
+        let resolution = scale.ceil() as u64 + 2;
+        let table_size = 1u64 << config.log2_table_size;
+        let dense = resolution.pow(3) <= table_size;
+        let (resolution, mask) = (resolution as u32, (table_size - 1) as u32);
+        let base = level << config.log2_table_size;
+        let p = track!(pos * scale + 0.5);
+        let cell = p.floor();
+        let frac = track!(p - cell);
+        let cell = cell.as_::<Uint3>();
+        for corner in 0..8u32 {
+            let offset = Uint3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let c = track!(cell + offset.expr());
+            let index = if dense {
+                track!(c.x + c.y * resolution + c.z * (resolution * resolution))
+            } else {
+                track!(c.x ^ (c.y * 2654435761u32) ^ (c.z * 805459861u32))
+            };
+            let weight = |o: u32, t: Expr<f32>| if o == 1 { t } else { track!(1.0 - t) };
+            let w = track!(
+                weight(offset.x, frac.x) * weight(offset.y, frac.y) * weight(offset.z, frac.z)
+            );
+            f(track!(base + (index & mask)), w);
+        }
+    }
+}
+
+impl<T: Weight> Trainable for HashGridEncoding<T> {
+    type Weight = T;
+    fn params(&self) -> &Buffer<T> {
+        &self.params
+    }
+    fn grads(&self) -> &Buffer<f32> {
+        &self.grads
+    }
+}
@@ -0,0 +1,617 @@
+//! Triangle rasterization into [`Tex2d`] render targets, for G-buffers,
+//! shadow maps and debug overlays next to the compute and ray tracing paths.
+//!
+//! A [`RasterShader`] pairs a vertex and a fragment stage written in the DSL
+//! with a fixed [`RasterState`], and draws the triangles of an
+//! [`rtx::Mesh`](crate::rtx::Mesh) with [`Scope::draw`]. The C API does not
+//! expose raster pipelines of the native backends yet, so every backend
+//! draws through a tile-based compute rasterizer:
+//!
+//! 1. the vertex stage runs once per vertex;
+//! 2. each triangle is set up for homogeneous rasterization, which needs no
+//!    clipping against the near plane, and appended to the lists of the
+//!    16x16 pixel tiles its bounds touch. Triangles that do not fit in a full
+//!    tile list go to a global list every pixel visits instead, which is
+//!    slower but never drops geometry;
+//! 3. each pixel resolves the visible triangle of its tile and the global
+//!    list against the depth buffer, then runs the fragment stage once.
+//!
+//! As fragments are shaded once per pixel after visibility is resolved,
+//! there is no blending and the fragment stage cannot discard.
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::internal_prelude::*;
+use crate::rtx::Mesh;
+use crate::runtime::{AsKernelArg, KernelArg};
+
+/// Maximum number of color render targets of a [`RasterShader`]
+pub const MAX_RENDER_TARGETS: usize = 4;
+/// Width and height in pixels of the tiles triangles are binned into
+const TILE_SIZE: u32 = 16;
+/// Triangles listed per tile before they overflow into the global list
+const BIN_CAPACITY: u32 = 256;
+/// Primitive id of pixels no triangle covers
+const NO_PRIMITIVE: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    /// Culls triangles whose vertices are counter-clockwise in normalized
+    /// device coordinates
+    Front,
+    /// Culls triangles whose vertices are clockwise in normalized device coordinates
+    Back,
+}
+
+/// Comparison of a fragment's depth against the depth buffer, the fragment
+/// is drawn when `fragment <op> stored` holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthCompare {
+    Never,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Always,
+}
+
+impl DepthCompare {
+    #[tracked]
+    fn passes(&self, depth: Expr<f32>, stored: Expr<f32>) -> Expr<bool> {
+        match self {
+            DepthCompare::Never => false.expr(),
+            DepthCompare::Less => depth < stored,
+            DepthCompare::LessEqual => depth <= stored,
+            DepthCompare::Greater => depth > stored,
+            DepthCompare::GreaterEqual => depth >= stored,
+            DepthCompare::Always => true.expr(),
+        }
+    }
+}
+
+/// Fixed-function state of a [`RasterShader`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RasterState {
+    pub cull: CullMode,
+    /// Ignored when drawing without a depth buffer
+    pub depth_compare: DepthCompare,
+    pub depth_write: bool,
+}
+
+impl Default for RasterState {
+    fn default() -> Self {
+        Self {
+            cull: CullMode::Back,
+            depth_compare: DepthCompare::Less,
+            depth_write: true,
+        }
+    }
+}
+
+/// The fragment a [`RasterShader`]'s fragment stage shades
+pub struct Fragment {
+    /// Pixel coordinates in the render targets
+    pub pixel: Expr<Uint2>,
+    /// `z / w` of the clip-space position, in `[0, 1]`
+    pub depth: Expr<f32>,
+    /// Index of the triangle in the mesh
+    pub primitive_id: Expr<u32>,
+    /// Perspective-correct barycentric coordinates of the second and third
+    /// vertex, see [`TriangleInterpolate`](crate::rtx::TriangleInterpolate)
+    pub bary: Expr<Float2>,
+    /// Whether the vertices are counter-clockwise in normalized device coordinates
+    pub front_facing: Expr<bool>,
+}
+
+/// A single-mip `f32` texture holding the depth of the nearest fragments
+pub struct DepthBuffer {
+    texture: Tex2d<f32>,
+    clear: Kernel<fn(Tex2d<f32>, f32)>,
+}
+
+impl DepthBuffer {
+    pub fn new(device: &Device, width: u32, height: u32) -> Self {
+        Self {
+            texture: device.create_tex2d(PixelStorage::Float1, width, height, 1),
+            clear: Kernel::<fn(Tex2d<f32>, f32)>::new(device, &|texture, depth| {
+                texture.write(dispatch_id().xy(), depth);
+            }),
+        }
+    }
+    pub fn width(&self) -> u32 {
+        self.texture.width()
+    }
+    pub fn height(&self) -> u32 {
+        self.texture.height()
+    }
+    pub fn texture(&self) -> &Tex2d<f32> {
+        &self.texture
+    }
+    /// Fills the buffer with `depth`, usually 1 for [`DepthCompare::Less`]
+    pub fn clear_async(&self, depth: f32) -> Command<'static, 'static> {
+        self.clear
+            .dispatch_async([self.width(), self.height(), 1], &self.texture, &depth)
+    }
+    pub fn clear(&self, depth: f32) {
+        self.clear
+            .dispatch([self.width(), self.height(), 1], &self.texture, &depth);
+    }
+}
+
+/// Where a draw writes to, all targets must have the same size
+#[derive(Clone, Copy)]
+pub struct RenderTargets<'a> {
+    /// One texture per output of the fragment stage
+    pub colors: &'a [&'a Tex2d<Float4>],
+    /// Without a depth buffer, later triangles of a draw cover earlier ones
+    pub depth: Option<&'a DepthBuffer>,
+}
+
+impl<'a> RenderTargets<'a> {
+    fn size(&self) -> [u32; 2] {
+        let sizes = self
+            .colors
+            .iter()
+            .map(|c| [c.width(), c.height()])
+            .chain(self.depth.map(|d| [d.width(), d.height()]))
+            .collect::<Vec<_>>();
+        assert!(!sizes.is_empty(), "no render targets");
+        assert!(
+            sizes.iter().all(|s| *s == sizes[0]),
+            "render targets differ in size: {:?}",
+            sizes
+        );
+        sizes[0]
+    }
+}
+
+/// Per-draw intermediate buffers, reused by later draws that fit
+struct Workspace<T: Value> {
+    vertices: usize,
+    triangles: usize,
+    tiles: usize,
+    clip: Buffer<Float4>,
+    varyings: Buffer<T>,
+    /// Four rows per triangle: the inverse of the matrix of the clip-space
+    /// `(x, y, w)` of its vertices, then their `z` and whether it is front facing
+    setups: Buffer<Float4>,
+    /// Length of the global list, then of each tile list
+    counts: Buffer<u32>,
+    bins: Buffer<u32>,
+    overflow: Buffer<u32>,
+}
+
+/// Vertex and fragment stages and the state to rasterize a mesh with.
+///
+/// - `V` is the vertex type of the mesh, read at the start of each vertex
+///   and therefore possibly a prefix of the actual vertex layout
+/// - `T` is passed from the vertex stage to the fragment stage, which
+///   receives it for all three vertices of the triangle
+/// - `U` holds per-draw parameters available to both stages, for instance a
+///   `Mat4` view-projection matrix or a buffer of material data
+///
+/// Clip space is the one of [`CameraMotion`](crate::image::CameraMotion):
+/// `x` and `y` in `[-w, w]` map to the image with `y` pointing up, and `z`
+/// in `[0, w]` maps to the depth range `[0, 1]`.
+#[allow(clippy::type_complexity)]
+pub struct RasterShader<V: Value, T: Value, U: KernelArg + 'static> {
+    device: Device,
+    state: RasterState,
+    color_targets: usize,
+    vertex: Kernel<fn(ByteBuffer, u32, Buffer<Float4>, Buffer<T>, U)>,
+    setup: Kernel<
+        fn(
+            ByteBuffer,
+            Buffer<Float4>,
+            Buffer<Float4>,
+            Buffer<u32>,
+            Buffer<u32>,
+            Buffer<u32>,
+            Uint2,
+        ),
+    >,
+    clear: Kernel<fn(Buffer<u32>)>,
+    raster: Kernel<
+        fn(
+            ByteBuffer,
+            Buffer<Float4>,
+            Buffer<T>,
+            Buffer<u32>,
+            Buffer<u32>,
+            Buffer<u32>,
+            Tex2d<f32>,
+            Tex2d<Float4>,
+            Tex2d<Float4>,
+            Tex2d<Float4>,
+            Tex2d<Float4>,
+            bool,
+            U,
+        ),
+    >,
+    /// Bound to the unused color slots and the depth slot of draws without one
+    dummy_color: Tex2d<Float4>,
+    dummy_depth: Tex2d<f32>,
+    workspace: RefCell<Option<Rc<Workspace<T>>>>,
+    _marker: PhantomData<fn(V)>,
+}
+
+/// The three vertex indices of triangle `t`
+fn triangle_indices(indices: &ByteBufferVar, t: Expr<u32>) -> [Expr<u32>; 3] {
+    let base = t.as_u64() * 12u64;
+    [0u64, 4, 8].map(|k| indices.read_as::<u32>(track!(base + k)))
+}
+
+/// Homogeneous coordinates `a` of the pixel with normalized device
+/// coordinates `ndc` relative to triangle `t`, so that the pixel is covered
+/// when all are non-negative and `a / sum(a)` are its barycentric coordinates
+#[tracked]
+fn edge_functions(setups: &BufferVar<Float4>, t: Expr<u32>, ndc: Expr<Float3>) -> Expr<Float3> {
+    let rows = [0u32, 1, 2].map(|r| setups.read(4u32 * t + r).xyz());
+    Float3::expr(rows[0].dot(ndc), rows[1].dot(ndc), rows[2].dot(ndc))
+}
+
+impl<V: Value, T: Value, U: KernelArg + 'static> RasterShader<V, T, U> {
+    /// Builds the shader. The vertex stage returns the clip-space position of
+    /// a vertex and the values to pass on, the fragment stage returns one
+    /// color per render target.
+    pub fn new(
+        device: &Device,
+        state: RasterState,
+        vertex_stage: &dyn Fn(Expr<V>, &U::Parameter) -> (Expr<Float4>, Expr<T>),
+        fragment_stage: &dyn Fn(&Fragment, [Expr<T>; 3], &U::Parameter) -> Vec<Expr<Float4>>,
+    ) -> Self {
+        let vertex = Kernel::<fn(ByteBuffer, u32, Buffer<Float4>, Buffer<T>, U)>::new(
+            device,
+            &|vertices, stride, clip, varyings, uniforms| {
+                let i = dispatch_id().x;
+                let v = vertices.read_as::<V>(track!(i.as_u64() * stride.as_u64()));
+                let (position, varying) = vertex_stage(v, &uniforms);
+                clip.write(i, position);
+                varyings.write(i, varying);
+            },
+        );
+        let setup = Kernel::<
+            fn(
+                ByteBuffer,
+                Buffer<Float4>,
+                Buffer<Float4>,
+                Buffer<u32>,
+                Buffer<u32>,
+                Buffer<u32>,
+                Uint2,
+            ),
+        >::new(
+            device,
+            &track!(|indices, clip, setups, counts, bins, overflow, size| {
+                let t = dispatch_id().x;
+                let [c0, c1, c2] = triangle_indices(&indices, t).map(|i| clip.read(i));
+                let (m0, m1, m2) = (
+                    Float3::expr(c0.x, c0.y, c0.w),
+                    Float3::expr(c1.x, c1.y, c1.w),
+                    Float3::expr(c2.x, c2.y, c2.w),
+                );
+                // the determinant is the signed volume of the eye and the
+                // triangle, positive when it faces the eye counter-clockwise
+                let r0 = m1.cross(m2);
+                let det = m0.dot(r0);
+                let culled = match state.cull {
+                    CullMode::None => det == 0.0f32,
+                    CullMode::Front => det >= 0.0f32,
+                    CullMode::Back => det <= 0.0f32,
+                };
+                let behind = c0.w <= 0.0f32 && c1.w <= 0.0f32 && c2.w <= 0.0f32;
+                if !culled && !behind {
+                    let rows = [r0, m2.cross(m0), m0.cross(m1)];
+                    for (r, row) in rows.into_iter().enumerate() {
+                        let row = row / det;
+                        setups.write(
+                            4u32 * t + r as u32,
+                            Float4::expr(row.x, row.y, row.z, 0.0f32),
+                        );
+                    }
+                    let front = select(det > 0.0f32, 1.0f32.expr(), 0.0f32.expr());
+                    setups.write(4u32 * t + 3u32, Float4::expr(c0.z, c1.z, c2.z, front));
+
+                    let tiles = (size + TILE_SIZE - 1u32) / TILE_SIZE;
+                    // triangles crossing the plane of the eye project to
+                    // unbounded regions, they are binned everywhere
+                    let lo = Uint2::var_zeroed();
+                    let hi = (tiles - 1u32).var();
+                    let on_screen = true.var();
+                    if c0.w > 0.0f32 && c1.w > 0.0f32 && c2.w > 0.0f32 {
+                        let pixel = |c: Expr<Float4>| {
+                            let ndc = c.xy() / c.w;
+                            Float2::expr(ndc.x * 0.5f32 + 0.5f32, 0.5f32 - ndc.y * 0.5f32)
+                                * size.as_float2()
+                        };
+                        let (p0, p1, p2) = (pixel(c0), pixel(c1), pixel(c2));
+                        let min = p0.min_(p1).min_(p2).floor();
+                        let max = p0.max_(p1).max_(p2).floor();
+                        let upper = (size.as_float2() - 1.0f32).max_(0.0f32);
+                        *lo = (min.clamp(Float2::splat_expr(0.0f32), upper).as_uint2()) / TILE_SIZE;
+                        *hi = (max.clamp(Float2::splat_expr(0.0f32), upper).as_uint2()) / TILE_SIZE;
+                        *on_screen = (max >= 0.0f32).all() && (min < size.as_float2()).all();
+                    }
+                    let (lo, hi) = (lo.load(), hi.load());
+                    let overflowed = false.var();
+                    if on_screen.load() {
+                        for ty in lo.y..hi.y + 1u32 {
+                            for tx in lo.x..hi.x + 1u32 {
+                                let tile = 1u32 + tx + ty * tiles.x;
+                                let slot = counts.atomic_fetch_add(tile, 1u32);
+                                if slot < BIN_CAPACITY {
+                                    bins.write((tile - 1u32) * BIN_CAPACITY + slot, t);
+                                } else {
+                                    *overflowed = true.expr();
+                                }
+                            }
+                        }
+                    }
+                    if overflowed.load() {
+                        overflow.write(counts.atomic_fetch_add(0u32, 1u32), t);
+                    }
+                }
+            }),
+        );
+        let clear = Kernel::<fn(Buffer<u32>)>::new(device, &|counts| {
+            counts.write(dispatch_id().x, 0u32);
+        });
+
+        let color_targets = Cell::new(0);
+        let ordered = state.depth_write
+            && !matches!(
+                state.depth_compare,
+                DepthCompare::Never | DepthCompare::Always
+            );
+        let raster = Kernel::<
+            fn(
+                ByteBuffer,
+                Buffer<Float4>,
+                Buffer<T>,
+                Buffer<u32>,
+                Buffer<u32>,
+                Buffer<u32>,
+                Tex2d<f32>,
+                Tex2d<Float4>,
+                Tex2d<Float4>,
+                Tex2d<Float4>,
+                Tex2d<Float4>,
+                bool,
+                U,
+            ),
+        >::new(
+            device,
+            &track!(|indices,
+                     setups,
+                     varyings,
+                     counts,
+                     bins,
+                     overflow,
+                     depth,
+                     c0,
+                     c1,
+                     c2,
+                     c3,
+                     has_depth,
+                     uniforms| {
+                set_block_size([TILE_SIZE, TILE_SIZE, 1]);
+                let p = dispatch_id().xy();
+                let size = dispatch_size().xy();
+                let uv = (p.as_float2() + 0.5f32) / size.as_float2();
+                let ndc = Float3::expr(uv.x * 2.0f32 - 1.0f32, 1.0f32 - uv.y * 2.0f32, 1.0f32);
+                let tiles_x = (size.x + TILE_SIZE - 1u32) / TILE_SIZE;
+                let tile = p.x / TILE_SIZE + p.y / TILE_SIZE * tiles_x;
+
+                let stored = 1.0f32.var();
+                if has_depth {
+                    *stored = depth.read(p);
+                }
+                let stored = stored.load();
+                let best = NO_PRIMITIVE.var();
+                let best_depth = stored.var();
+                let consider = |t: Expr<u32>| {
+                    let a = edge_functions(&setups, t, ndc);
+                    if (a >= 0.0f32).all() && a.reduce_sum() > 0.0f32 {
+                        let d = a.dot(setups.read(4u32 * t + 3u32).xyz());
+                        let visible = d >= 0.0f32 && d <= 1.0f32;
+                        let passes = !has_depth || state.depth_compare.passes(d, stored);
+                        // with depth writes the nearest fragment wins and
+                        // ties go to the first or last triangle as the
+                        // comparison dictates, otherwise the last one drawn
+                        // that passes against the stored depth wins
+                        let (b, bd) = (best.load(), best_depth.load());
+                        let wins = if ordered {
+                            let (closer, tie_break) = match state.depth_compare {
+                                DepthCompare::Less => (d < bd, t < b),
+                                DepthCompare::LessEqual => (d < bd, t > b),
+                                DepthCompare::Greater => (d > bd, t < b),
+                                _ => (d > bd, t > b),
+                            };
+                            !has_depth && t > b || has_depth && (closer || d == bd && tie_break)
+                        } else {
+                            t > b
+                        };
+                        if visible && passes && (b == NO_PRIMITIVE || wins) {
+                            *best = t;
+                            *best_depth = d;
+                        }
+                    }
+                };
+                for i in 0u32.expr()..counts.read(1u32 + tile).min_(BIN_CAPACITY) {
+                    consider(bins.read(tile * BIN_CAPACITY + i));
+                }
+                for i in 0u32.expr()..counts.read(0u32) {
+                    consider(overflow.read(i));
+                }
+
+                if best.load() != NO_PRIMITIVE {
+                    let t = best.load();
+                    let a = edge_functions(&setups, t, ndc);
+                    let bary = a / a.reduce_sum();
+                    let vertices = triangle_indices(&indices, t).map(|i| varyings.read(i));
+                    let input = Fragment {
+                        pixel: p,
+                        depth: best_depth.load(),
+                        primitive_id: t,
+                        bary: bary.yz(),
+                        front_facing: setups.read(4u32 * t + 3u32).w > 0.5f32,
+                    };
+                    let colors = fragment_stage(&input, vertices, &uniforms);
+                    assert!(
+                        colors.len() <= MAX_RENDER_TARGETS,
+                        "at most {} render targets are supported",
+                        MAX_RENDER_TARGETS
+                    );
+                    color_targets.set(colors.len());
+                    for (target, color) in [&c0, &c1, &c2, &c3].into_iter().zip(colors) {
+                        target.write(p, color);
+                    }
+                    if has_depth && state.depth_write {
+                        depth.write(p, best_depth.load());
+                    }
+                }
+            }),
+        );
+        Self {
+            device: device.clone(),
+            state,
+            color_targets: color_targets.get(),
+            vertex,
+            setup,
+            clear,
+            raster,
+            dummy_color: device.create_tex2d(PixelStorage::Float4, 1, 1, 1),
+            dummy_depth: device.create_tex2d(PixelStorage::Float1, 1, 1, 1),
+            workspace: RefCell::new(None),
+            _marker: PhantomData,
+        }
+    }
+    pub fn state(&self) -> &RasterState {
+        &self.state
+    }
+    /// Number of colors the fragment stage returns
+    pub fn color_targets(&self) -> usize {
+        self.color_targets
+    }
+    /// Returns intermediate buffers large enough for a draw, growing the
+    /// previous ones if needed
+    fn workspace(&self, vertices: usize, triangles: usize, tiles: usize) -> Rc<Workspace<T>> {
+        let mut workspace = self.workspace.borrow_mut();
+        let (vertices, triangles, tiles) = match &*workspace {
+            Some(w) if w.vertices >= vertices && w.triangles >= triangles && w.tiles >= tiles => {
+                return w.clone();
+            }
+            Some(w) => (
+                vertices.max(w.vertices),
+                triangles.max(w.triangles),
+                tiles.max(w.tiles),
+            ),
+            None => (vertices.max(1), triangles.max(1), tiles),
+        };
+        let device = &self.device;
+        let w = Rc::new(Workspace {
+            vertices,
+            triangles,
+            tiles,
+            clip: device.create_buffer(vertices),
+            varyings: device.create_buffer(vertices),
+            setups: device.create_buffer(4 * triangles),
+            counts: device.create_buffer(1 + tiles),
+            bins: device.create_buffer(tiles * BIN_CAPACITY as usize),
+            overflow: device.create_buffer(triangles),
+        });
+        *workspace = Some(w.clone());
+        w
+    }
+
+    /// Rasterizes the triangles of `mesh` into `targets`, see [`Scope::draw`]
+    ///
+    /// The intermediate buffers are owned by the shader and kept alive by
+    /// the returned commands, so commands of draws with the same shader must
+    /// not overlap on different streams.
+    pub fn draw_async(
+        &self,
+        mesh: &Mesh,
+        targets: &RenderTargets<'_>,
+        uniforms: &impl AsKernelArg<Output = U>,
+    ) -> Vec<Command<'static, 'static>> {
+        assert_eq!(
+            targets.colors.len(),
+            self.color_targets,
+            "the fragment stage writes {} render targets",
+            self.color_targets
+        );
+        assert!(
+            std::mem::size_of::<V>() <= mesh.vertex_stride,
+            "vertex type is larger than the vertices of the mesh"
+        );
+        let [width, height] = targets.size();
+        let (vertices, triangles) = (mesh.vertex_count(), mesh.triangle_count());
+        let tiles = (width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE)) as usize;
+        let ws = self.workspace(vertices, triangles, tiles);
+        let (vertex_bytes, index_bytes) = (mesh.vertex_bytes(), mesh.index_bytes());
+        let color = |i: usize| targets.colors.get(i).copied().unwrap_or(&self.dummy_color);
+        let depth = targets.depth.map_or(&self.dummy_depth, |d| d.texture());
+        let mut commands = vec![
+            self.clear
+                .dispatch_async([1 + tiles as u32, 1, 1], &ws.counts),
+            self.vertex.dispatch_async(
+                [vertices as u32, 1, 1],
+                &vertex_bytes,
+                &(mesh.vertex_stride as u32),
+                &ws.clip,
+                &ws.varyings,
+                uniforms,
+            ),
+            self.setup.dispatch_async(
+                [triangles as u32, 1, 1],
+                &index_bytes,
+                &ws.clip,
+                &ws.setups,
+                &ws.counts,
+                &ws.bins,
+                &ws.overflow,
+                &Uint2::new(width, height),
+            ),
+            self.raster.dispatch_async(
+                [width, height, 1],
+                &index_bytes,
+                &ws.setups,
+                &ws.varyings,
+                &ws.counts,
+                &ws.bins,
+                &ws.overflow,
+                depth,
+                color(0),
+                color(1),
+                color(2),
+                color(3),
+                &targets.depth.is_some(),
+                uniforms,
+            ),
+        ];
+        let last = commands.last_mut().unwrap();
+        last.resource_tracker
+            .add(ws.clip.handle.clone())
+            .add(ws.varyings.handle.clone())
+            .add(ws.setups.handle.clone())
+            .add(ws.counts.handle.clone())
+            .add(ws.bins.handle.clone())
+            .add(ws.overflow.handle.clone());
+        commands
+    }
+}
+
+impl<'a> Scope<'a> {
+    /// Rasterizes the triangles of `mesh` with `shader` into `targets`
+    pub fn draw<V: Value, T: Value, U: KernelArg + 'static>(
+        &self,
+        shader: &RasterShader<V, T, U>,
+        mesh: &Mesh,
+        targets: &RenderTargets<'_>,
+        uniforms: &impl AsKernelArg<Output = U>,
+    ) -> &Self {
+        self.submit(shader.draw_async(mesh, targets, uniforms))
+    }
+}
@@ -17,6 +17,19 @@ use crate::runtime::*;
 use api::{BufferDownloadCommand, BufferUploadCommand, INVALID_RESOURCE_HANDLE};
 use std::ffi::c_void;
 
+mod bc;
+mod bindless_heap;
+mod layered;
+mod mipmap;
+mod sparse;
+mod vdb;
+pub use bc::*;
+pub use bindless_heap::*;
+pub use layered::*;
+pub use mipmap::*;
+pub use sparse::*;
+pub use vdb::*;
+
 pub type ByteBuffer = Buffer<u8>;
 pub type ByteBufferView = BufferView<u8>;
 pub type ByteBufferVar = BufferVar<u8>;
@@ -437,6 +450,7 @@ impl<T: Value> BufferView<T> {
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            on_submit: None,
         }
     }
     pub fn copy_to_vec(&self) -> Vec<T> {
@@ -468,6 +482,7 @@ impl<T: Value> BufferView<T> {
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            on_submit: None,
         }
     }
     pub fn copy_from(&self, data: &[T]) {
@@ -495,6 +510,7 @@ impl<T: Value> BufferView<T> {
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            on_submit: None,
         }
     }
     pub fn copy_to_buffer(&self, dst: &BufferView<T>) {
@@ -591,6 +607,14 @@ impl BindlessArray {
         self.emplace_buffer_view_async(index, &buffer.view(..))
     }
     pub fn emplace_buffer_view_async<T: Value>(&self, index: usize, bufferview: &BufferView<T>) {
+        self.emplace_raw_buffer_async(index, bufferview._handle(), bufferview.offset);
+    }
+    pub(crate) fn emplace_raw_buffer_async(
+        &self,
+        index: usize,
+        buffer: Arc<BufferHandle>,
+        offset: usize,
+    ) {
         self.lock();
         self.modifications
             .borrow_mut()
@@ -603,11 +627,11 @@ impl BindlessArray {
             })
             .buffer = api::BindlessArrayUpdateBuffer {
             op: api::BindlessArrayUpdateOperation::Emplace,
-            handle: bufferview.handle(),
-            offset: bufferview.offset,
+            handle: buffer.handle,
+            offset,
         };
         let mut slots = self.slots.borrow_mut();
-        slots[index].buffer = Some(bufferview._handle());
+        slots[index].buffer = Some(buffer);
         self.unlock();
     }
     pub fn emplace_tex2d_async<T: IoTexel>(
@@ -615,6 +639,14 @@ impl BindlessArray {
         index: usize,
         texture: &Tex2d<T>,
         sampler: Sampler,
+    ) {
+        self.emplace_raw_tex2d_async(index, texture.handle.clone(), sampler);
+    }
+    pub(crate) fn emplace_raw_tex2d_async(
+        &self,
+        index: usize,
+        texture: Arc<TextureHandle>,
+        sampler: Sampler,
     ) {
         self.lock();
         self.modifications
@@ -628,11 +660,11 @@ impl BindlessArray {
             })
             .tex2d = api::BindlessArrayUpdateTexture {
             op: api::BindlessArrayUpdateOperation::Emplace,
-            handle: texture.handle(),
+            handle: texture.handle,
             sampler,
         };
         let mut slots = self.slots.borrow_mut();
-        slots[index].tex2d = Some(texture.handle.clone());
+        slots[index].tex2d = Some(texture);
         self.unlock();
     }
     pub fn emplace_tex3d_async<T: IoTexel>(
@@ -640,6 +672,14 @@ impl BindlessArray {
         index: usize,
         texture: &Tex3d<T>,
         sampler: Sampler,
+    ) {
+        self.emplace_raw_tex3d_async(index, texture.handle.clone(), sampler);
+    }
+    pub(crate) fn emplace_raw_tex3d_async(
+        &self,
+        index: usize,
+        texture: Arc<TextureHandle>,
+        sampler: Sampler,
     ) {
         self.lock();
         self.modifications
@@ -653,11 +693,11 @@ impl BindlessArray {
             })
             .tex3d = api::BindlessArrayUpdateTexture {
             op: api::BindlessArrayUpdateOperation::Emplace,
-            handle: texture.handle(),
+            handle: texture.handle,
             sampler,
         };
         let mut slots = self.slots.borrow_mut();
-        slots[index].tex3d = Some(texture.handle.clone());
+        slots[index].tex3d = Some(texture);
         self.unlock();
     }
     pub fn remove_buffer_async(&self, index: usize) {
@@ -788,6 +828,7 @@ impl BindlessArray {
             callback: Some(Box::new(move || unsafe {
                 lock.unlock();
             })),
+            on_submit: None,
         }
     }
     pub fn tex2d(&self, tex2d_index: impl AsExpr<Value = u32>) -> BindlessTex2dVar {
@@ -840,6 +881,10 @@ impl GetPixelFormat for f32 {
             PixelStorage::Float1 => PixelFormat::R32f,
             PixelStorage::Float2 => PixelFormat::Rg32f,
             PixelStorage::Float4 => PixelFormat::Rgba32f,
+            PixelStorage::Bc1 => PixelFormat::Bc1Unorm,
+            PixelStorage::Bc4 => PixelFormat::Bc4Unorm,
+            PixelStorage::Bc5 => PixelFormat::Bc5Unorm,
+            PixelStorage::Bc7 => PixelFormat::Bc7Unorm,
             _ => panic!("Invalid pixel storage for f32"),
         }
     }
@@ -1219,6 +1264,7 @@ macro_rules! impl_tex_view {
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    on_submit: None,
                 }
             }
             pub fn copy_to<U: StorageTexel<T>>(&self, data: &mut [U]) {
@@ -1253,6 +1299,7 @@ macro_rules! impl_tex_view {
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    on_submit: None,
                 }
             }
             pub fn copy_from<U: StorageTexel<T>>(&self, data: &[U]) {
@@ -1279,6 +1326,7 @@ macro_rules! impl_tex_view {
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    on_submit: None,
                 }
             }
             pub fn copy_to_buffer<U: StorageTexel<T> + Value>(&self, buffer_view: &BufferView<U>) {
@@ -1308,6 +1356,7 @@ macro_rules! impl_tex_view {
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    on_submit: None,
                 }
             }
             pub fn copy_from_buffer<U: StorageTexel<T> + Value>(
@@ -1338,6 +1387,7 @@ macro_rules! impl_tex_view {
                     resource_tracker: rt,
                     marker: PhantomData,
                     callback: None,
+                    on_submit: None,
                 }
             }
             pub fn copy_to_texture(&self, other: &$name<T>) {
@@ -2275,6 +2325,7 @@ impl_atomic!(u32);
 impl_atomic!(i64);
 impl_atomic!(u64);
 impl_atomic!(f32);
+impl_atomic!(f16);
 impl_atomic_bit!(u32);
 impl_atomic_bit!(u64);
 impl_atomic_bit!(i32);
@@ -0,0 +1,809 @@
+//! Block compression: BC1, BC4, BC5 and BC7.
+//!
+//! Compressed images are stored as 4x4 texel blocks in row-major block order,
+//! with two `u32` words per BC1 and BC4 block and four per BC5 and BC7 block.
+//! Textures created with [`BcFormat::storage`] use this layout on backends that
+//! support compressed formats. On other backends, including the CPU backend,
+//! the blocks can live in a `Buffer<u32>` and be decoded in kernels with
+//! [`BcFormat::read`] and [`BcFormat::sample`]. [`BcEncoder`] compresses
+//! textures on the device.
+//!
+//! Decoded values are computed in single precision and may differ from
+//! hardware decoders by rounding.
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BcFormat {
+    /// RGB with 1-bit alpha, 4 bits per texel
+    Bc1,
+    /// One channel, 4 bits per texel
+    Bc4,
+    /// Two channels, 8 bits per texel
+    Bc5,
+    /// RGBA, 8 bits per texel
+    Bc7,
+}
+
+impl BcFormat {
+    pub fn from_storage(storage: PixelStorage) -> Option<Self> {
+        match storage {
+            PixelStorage::Bc1 => Some(BcFormat::Bc1),
+            PixelStorage::Bc4 => Some(BcFormat::Bc4),
+            PixelStorage::Bc5 => Some(BcFormat::Bc5),
+            PixelStorage::Bc7 => Some(BcFormat::Bc7),
+            _ => None,
+        }
+    }
+    /// Storage of textures in this format, read as `Float4`
+    pub fn storage(&self) -> PixelStorage {
+        match self {
+            BcFormat::Bc1 => PixelStorage::Bc1,
+            BcFormat::Bc4 => PixelStorage::Bc4,
+            BcFormat::Bc5 => PixelStorage::Bc5,
+            BcFormat::Bc7 => PixelStorage::Bc7,
+        }
+    }
+    /// `u32` words per block
+    pub fn block_words(&self) -> u32 {
+        match self {
+            BcFormat::Bc1 | BcFormat::Bc4 => 2,
+            BcFormat::Bc5 | BcFormat::Bc7 => 4,
+        }
+    }
+    /// Blocks along each axis of a `width` x `height` image
+    pub fn block_count(width: u32, height: u32) -> [u32; 2] {
+        [(width + 3) / 4, (height + 3) / 4]
+    }
+    /// `u32` words of a compressed `width` x `height` image
+    pub fn image_words(&self, width: u32, height: u32) -> usize {
+        let [x, y] = Self::block_count(width, height);
+        (x * y * self.block_words()) as usize
+    }
+    /// Decodes texel `x + 4 * y` of `block`
+    ///
+    /// Two-word formats only use `block.xy`. Missing channels are 0 and
+    /// missing alpha is 1.
+    pub fn decode(
+        &self,
+        block: impl AsExpr<Value = Uint4>,
+        texel: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        let (block, texel) = (block.as_expr(), texel.as_expr());
+        match self {
+            BcFormat::Bc1 => bc1_decode(block.xy(), texel),
+            BcFormat::Bc4 => Float4::expr(bc4_decode(block.xy(), texel), 0.0f32, 0.0f32, 1.0f32),
+            BcFormat::Bc5 => {
+                let rg = bc5_decode(block, texel);
+                Float4::expr(rg.x, rg.y, 0.0f32, 1.0f32)
+            }
+            BcFormat::Bc7 => bc7_decode(block, texel),
+        }
+    }
+    /// [`BcFormat::decode`] as a callable, so that the decoder is emitted once
+    /// however often it is called. Must be called while recording a kernel.
+    pub fn decode_callable(&self) -> Callable<fn(Expr<Uint4>, Expr<u32>) -> Expr<Float4>> {
+        match self {
+            BcFormat::Bc1 => {
+                Callable::new_static(|block, texel| BcFormat::Bc1.decode(block, texel))
+            }
+            BcFormat::Bc4 => {
+                Callable::new_static(|block, texel| BcFormat::Bc4.decode(block, texel))
+            }
+            BcFormat::Bc5 => {
+                Callable::new_static(|block, texel| BcFormat::Bc5.decode(block, texel))
+            }
+            BcFormat::Bc7 => {
+                Callable::new_static(|block, texel| BcFormat::Bc7.decode(block, texel))
+            }
+        }
+    }
+    /// Block containing `coord` of a compressed `width` texels wide image and
+    /// the index of the texel within it
+    fn load_block(
+        &self,
+        data: &BufferVar<u32>,
+        width: Expr<u32>,
+        coord: Expr<Uint2>,
+    ) -> (Expr<Uint4>, Expr<u32>) {
+        let words = self.block_words();
+        let blocks_x = track!((width + 3u32) / 4u32);
+        let base = track!(((coord.y / 4u32) * blocks_x + coord.x / 4u32) * words);
+        let texel = track!(coord.x % 4u32 + (coord.y % 4u32) * 4u32);
+        let word = |i: u32| {
+            if i < words {
+                data.read(track!(base + i))
+            } else {
+                0u32.expr()
+            }
+        };
+        (Uint4::expr(word(0), word(1), word(2), word(3)), texel)
+    }
+    /// Reads the texel at `coord` of a compressed `width` texels wide image in `data`
+    pub fn read(
+        &self,
+        data: &BufferVar<u32>,
+        width: impl AsExpr<Value = u32>,
+        coord: impl AsExpr<Value = Uint2>,
+    ) -> Expr<Float4> {
+        let (block, texel) = self.load_block(data, width.as_expr(), coord.as_expr());
+        self.decode(block, texel)
+    }
+    /// Bilinearly samples a compressed `size` image in `data` at `uv` in
+    /// `[0, 1]^2`, clamping at the edges
+    pub fn sample(
+        &self,
+        data: &BufferVar<u32>,
+        size: impl AsExpr<Value = Uint2>,
+        uv: impl AsExpr<Value = Float2>,
+    ) -> Expr<Float4> {
+        let decode = self.decode_callable();
+        let size = size.as_expr();
+        let p = track!(uv.as_expr() * size.as_::<Float2>() - 0.5f32);
+        let p0 = p.floor();
+        let f = track!(p - p0);
+        let p0 = p0.as_::<Int2>();
+        let last = track!(size.as_::<Int2>() - 1i32);
+        let texel = |dx: i32, dy: i32| {
+            let c = track!((p0 + Int2::expr(dx, dy)).clamp(Int2::splat_expr(0i32), last));
+            let (block, t) = self.load_block(data, size.x, c.as_::<Uint2>());
+            decode.call(block, t)
+        };
+        let (t00, t10, t01, t11) = (texel(0, 0), texel(1, 0), texel(0, 1), texel(1, 1));
+        let top = track!(t00 + (t10 - t00) * f.x);
+        let bottom = track!(t01 + (t11 - t01) * f.x);
+        track!(top + (bottom - top) * f.y)
+    }
+    fn encode(&self, texels: &[Expr<Float4>]) -> Vec<Expr<u32>> {
+        let channel = |c: fn(&Expr<Float4>) -> Expr<f32>| texels.iter().map(c).collect::<Vec<_>>();
+        match self {
+            BcFormat::Bc1 => bc1_encode(texels),
+            BcFormat::Bc4 => bc4_encode(&channel(|t| t.x)),
+            BcFormat::Bc5 => {
+                let mut words = bc4_encode(&channel(|t| t.x));
+                words.extend(bc4_encode(&channel(|t| t.y)));
+                words
+            }
+            BcFormat::Bc7 => bc7_encode(texels),
+        }
+    }
+}
+
+/// `count` bits of `block` starting at bit `offset`, where bit 0 is the lowest
+/// bit of `block.x`; `count` must be less than 32
+#[tracked]
+fn extract_bits(block: Expr<Uint4>, offset: Expr<u32>, count: Expr<u32>) -> Expr<u32> {
+    let word = |i: Expr<u32>| {
+        select(
+            i == 0u32,
+            block.x,
+            select(i == 1u32, block.y, select(i == 2u32, block.z, block.w)),
+        )
+    };
+    let i = offset >> 5u32;
+    let shift = offset & 31u32;
+    let lo = word(i) >> shift;
+    let hi = select(
+        shift == 0u32,
+        0u32.expr(),
+        word(i + 1u32) << (32u32 - shift),
+    );
+    (lo | hi) & ((1u32.expr() << count) - 1u32)
+}
+
+/// ORs the low `count` bits of `value` into `words` at bit `offset`
+fn put_bits(words: &mut [Expr<u32>], offset: u32, count: u32, value: Expr<u32>) {
+    let (i, shift) = ((offset / 32) as usize, offset % 32);
+    let w = words[i];
+    words[i] = track!(w | (value << shift));
+    if shift + count > 32 {
+        let w = words[i + 1];
+        words[i + 1] = track!(w | (value >> (32 - shift)));
+    }
+}
+
+#[tracked]
+fn rgb565(c: Expr<u32>) -> Expr<Float3> {
+    Float3::expr(
+        ((c >> 11u32) & 31u32).as_f32() / 31.0,
+        ((c >> 5u32) & 63u32).as_f32() / 63.0,
+        (c & 31u32).as_f32() / 31.0,
+    )
+}
+
+#[tracked]
+fn pack_rgb565(c: Expr<Float4>) -> Expr<u32> {
+    let c = c.saturate();
+    let r = (c.x * 31.0).round().as_u32();
+    let g = (c.y * 63.0).round().as_u32();
+    let b = (c.z * 31.0).round().as_u32();
+    (r << 11u32) | (g << 5u32) | b
+}
+
+/// Decodes texel `x + 4 * y` of a BC1 block
+#[tracked]
+pub fn bc1_decode(
+    block: impl AsExpr<Value = Uint2>,
+    texel: impl AsExpr<Value = u32>,
+) -> Expr<Float4> {
+    let block = block.as_expr();
+    let c0 = block.x & 0xffffu32;
+    let c1 = block.x >> 16u32;
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let index = (block.y >> (texel.as_expr() * 2u32)) & 3u32;
+    // c0 <= c1 selects the mode with a single midpoint and transparent black
+    let four = c0 > c1;
+    let rgb = select(
+        index == 0u32,
+        e0,
+        select(
+            index == 1u32,
+            e1,
+            select(
+                four,
+                select(index == 2u32, (e0 * 2.0 + e1) / 3.0, (e0 + e1 * 2.0) / 3.0),
+                select(index == 2u32, (e0 + e1) * 0.5, Float3::splat_expr(0.0f32)),
+            ),
+        ),
+    );
+    let alpha = select(!four & (index == 3u32), 0.0f32.expr(), 1.0f32.expr());
+    Float4::expr(rgb.x, rgb.y, rgb.z, alpha)
+}
+
+/// Decodes texel `x + 4 * y` of a BC4 block
+#[tracked]
+pub fn bc4_decode(block: impl AsExpr<Value = Uint2>, texel: impl AsExpr<Value = u32>) -> Expr<f32> {
+    let block = block.as_expr();
+    let index = extract_bits(
+        Uint4::expr(block.x, block.y, 0u32, 0u32),
+        16u32 + texel.as_expr() * 3u32,
+        3u32.expr(),
+    );
+    let r0 = (block.x & 0xffu32).as_f32();
+    let r1 = ((block.x >> 8u32) & 0xffu32).as_f32();
+    let k = index.as_f32();
+    // r0 <= r1 selects the mode with 4 interpolated values, 0 and 1
+    let value = select(
+        index == 0u32,
+        r0,
+        select(
+            index == 1u32,
+            r1,
+            select(
+                r0 > r1,
+                ((8.0 - k) * r0 + (k - 1.0) * r1) / 7.0,
+                select(
+                    index == 6u32,
+                    0.0f32.expr(),
+                    select(
+                        index == 7u32,
+                        255.0f32.expr(),
+                        ((6.0 - k) * r0 + (k - 1.0) * r1) / 5.0,
+                    ),
+                ),
+            ),
+        ),
+    );
+    value / 255.0
+}
+
+/// Decodes texel `x + 4 * y` of a BC5 block, red in `block.xy` and green in `block.zw`
+pub fn bc5_decode(
+    block: impl AsExpr<Value = Uint4>,
+    texel: impl AsExpr<Value = u32>,
+) -> Expr<Float2> {
+    let (block, texel) = (block.as_expr(), texel.as_expr());
+    Float2::expr(bc4_decode(block.xy(), texel), bc4_decode(block.zw(), texel))
+}
+
+/// Subset of each texel for the 2-subset partitions, one bit per texel
+const BC7_PARTITIONS_2: [u32; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+/// Subset of each texel for the 3-subset partitions, two bits per texel
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+/// Anchor texel of subset 1 for the 2-subset partitions
+const BC7_ANCHORS_2: [u32; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+/// Anchor texels of subsets 1 (low nibble) and 2 for the 3-subset partitions
+const BC7_ANCHORS_3: [u32; 64] = [
+    0xf3, 0x83, 0x8f, 0x3f, 0xf8, 0xf3, 0x3f, 0x8f, 0xf8, 0xf8, 0xf6, 0xf6, 0xf6, 0xf5, 0xf3, 0x83,
+    0xf3, 0x83, 0xf8, 0x3f, 0xf3, 0x83, 0xf6, 0x8a, 0x35, 0xf8, 0x68, 0xa6, 0xf8, 0xf5, 0xaf, 0x8f,
+    0xf8, 0x3f, 0xf3, 0xa5, 0xa6, 0x8a, 0x98, 0xaf, 0x6f, 0xf3, 0x8f, 0xf5, 0x3f, 0x6f, 0x6f, 0x8f,
+    0xf3, 0x3f, 0xf5, 0xf5, 0xf5, 0xf8, 0xf5, 0xfa, 0xf5, 0xfa, 0xf8, 0xfd, 0x3f, 0xfc, 0xf3, 0x83,
+];
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Fields of the BC7 modes: subsets, partition bits, rotation bits, index
+/// selection bits, color bits, alpha bits, p-bits per endpoint, p-bits per
+/// subset, index bits and secondary index bits
+const BC7_MODES: [[u32; 10]; 8] = [
+    [3, 4, 0, 0, 4, 0, 1, 0, 3, 0],
+    [2, 6, 0, 0, 6, 0, 0, 1, 3, 0],
+    [3, 6, 0, 0, 5, 0, 0, 0, 2, 0],
+    [2, 6, 0, 0, 7, 0, 1, 0, 2, 0],
+    [1, 0, 2, 1, 5, 6, 0, 0, 2, 3],
+    [1, 0, 2, 0, 7, 8, 0, 0, 2, 2],
+    [1, 0, 0, 0, 7, 7, 1, 0, 4, 0],
+    [2, 6, 0, 0, 5, 5, 1, 0, 2, 0],
+];
+
+fn bc7_weight(index: Expr<u32>, bits: u32) -> Expr<u32> {
+    match bits {
+        2 => BC7_WEIGHTS_2.expr().read(index),
+        3 => BC7_WEIGHTS_3.expr().read(index),
+        _ => BC7_WEIGHTS_4.expr().read(index),
+    }
+}
+
+fn bc7_decode_mode(mode: u32, block: Expr<Uint4>, texel: Expr<u32>) -> Expr<Float4> {
+    let m = BC7_MODES[mode as usize];
+    let (subsets, partition_bits, rotation_bits, selection_bits) = (m[0], m[1], m[2], m[3]);
+    let (color_bits, alpha_bits, endpoint_pbits, subset_pbits) = (m[4], m[5], m[6], m[7]);
+    let (index_bits, index_bits2) = (m[8], m[9]);
+    let bits = |offset: Expr<u32>, count: Expr<u32>| extract_bits(block, offset, count);
+    let fixed = |offset: u32, count: u32| extract_bits(block, offset.expr(), count.expr());
+    let mut offset = mode + 1;
+    let partition = fixed(offset, partition_bits);
+    offset += partition_bits;
+    let rotation = fixed(offset, rotation_bits);
+    offset += rotation_bits;
+    let selection = fixed(offset, selection_bits);
+    offset += selection_bits;
+
+    // the indices of anchor texels lack their top bit, which is implicitly 0
+    let (subset, anchors) = match subsets {
+        1 => (0u32.expr(), vec![0u32.expr()]),
+        2 => {
+            let mask = BC7_PARTITIONS_2.expr().read(partition);
+            let anchor = BC7_ANCHORS_2.expr().read(partition);
+            (track!((mask >> texel) & 1u32), vec![0u32.expr(), anchor])
+        }
+        _ => {
+            let packed = BC7_PARTITIONS_3.expr().read(partition);
+            let anchors = BC7_ANCHORS_3.expr().read(partition);
+            (
+                track!((packed >> (texel * 2u32)) & 3u32),
+                vec![
+                    0u32.expr(),
+                    track!(anchors & 15u32),
+                    track!(anchors >> 4u32),
+                ],
+            )
+        }
+    };
+
+    let endpoints = 2 * subsets;
+    let channel_bits = [color_bits, color_bits, color_bits, alpha_bits];
+    let mut channel_offsets = [0; 4];
+    for (c, bits) in channel_bits.iter().enumerate() {
+        channel_offsets[c] = offset;
+        offset += endpoints * bits;
+    }
+    let pbit_offset = offset;
+    offset += endpoints * endpoint_pbits + subsets * subset_pbits;
+    let index_offset = offset;
+
+    // channels of endpoint `k` of the texel's subset, expanded to 8 bits
+    let endpoint = |k: u32| -> [Expr<u32>; 4] {
+        let e = track!(subset * 2u32 + k);
+        let pbit = if endpoint_pbits == 1 {
+            Some(bits(track!(pbit_offset + e), 1u32.expr()))
+        } else if subset_pbits == 1 {
+            Some(bits(track!(pbit_offset + subset), 1u32.expr()))
+        } else {
+            None
+        };
+        std::array::from_fn(|c| {
+            let (base, count) = (channel_offsets[c], channel_bits[c]);
+            if count == 0 {
+                return 255u32.expr();
+            }
+            let v = bits(track!(base + e * count), count.expr());
+            let (v, count) = match pbit {
+                Some(p) => (track!((v << 1u32) | p), count + 1),
+                None => (v, count),
+            };
+            let (l, r) = (8 - count, 2 * count - 8);
+            track!((v << l) | (v >> r))
+        })
+    };
+    // bit position and length of the texel's index in a set of indices
+    let index = |start: u32, index_bits: u32| -> Expr<u32> {
+        let mut position = track!(start + texel * index_bits);
+        let mut count = index_bits.expr();
+        for &a in &anchors {
+            position = track!(position - select(a < texel, 1u32.expr(), 0u32.expr()));
+            count = track!(count - select(a == texel, 1u32.expr(), 0u32.expr()));
+        }
+        bits(position, count)
+    };
+
+    let primary = bc7_weight(index(index_offset, index_bits), index_bits);
+    let (color_weight, alpha_weight) = if index_bits2 > 0 {
+        let secondary = index(index_offset + 16 * index_bits - 1, index_bits2);
+        let secondary = bc7_weight(secondary, index_bits2);
+        let swap = track!(selection == 1u32);
+        (
+            select(swap, secondary, primary),
+            select(swap, primary, secondary),
+        )
+    } else {
+        (primary, primary)
+    };
+    let (e0, e1) = (endpoint(0), endpoint(1));
+    let interpolate = |c: usize, w: Expr<u32>| {
+        let (a, b) = (e0[c], e1[c]);
+        track!((((64u32 - w) * a + w * b + 32u32) >> 6u32).as_f32() / 255.0)
+    };
+    let (r, g, b, a) = (
+        interpolate(0, color_weight),
+        interpolate(1, color_weight),
+        interpolate(2, color_weight),
+        interpolate(3, alpha_weight),
+    );
+    if rotation_bits == 0 {
+        return Float4::expr(r, g, b, a);
+    }
+    // rotations swap alpha with one of the color channels
+    track!(Float4::expr(
+        select(rotation == 1u32, a, r),
+        select(rotation == 2u32, a, g),
+        select(rotation == 3u32, a, b),
+        select(
+            rotation == 1u32,
+            r,
+            select(rotation == 2u32, g, select(rotation == 3u32, b, a)),
+        ),
+    ))
+}
+
+/// Decodes texel `x + 4 * y` of a BC7 block
+///
+/// Blocks with an invalid mode decode to transparent black.
+pub fn bc7_decode(
+    block: impl AsExpr<Value = Uint4>,
+    texel: impl AsExpr<Value = u32>,
+) -> Expr<Float4> {
+    let (block, texel) = (block.as_expr(), texel.as_expr());
+    let mode = track!((block.x & 0xffu32).trailing_zeros());
+    let mut cases = switch::<Expr<Float4>>(mode.as_i32());
+    for m in 0..8 {
+        cases = cases.case(m as i32, || bc7_decode_mode(m, block, texel));
+    }
+    cases.default(|| Float4::splat_expr(0.0f32)).finish()
+}
+
+fn bounds(values: &[Expr<Float4>]) -> (Expr<Float4>, Expr<Float4>) {
+    values[1..]
+        .iter()
+        .fold((values[0], values[0]), |(lo, hi), &v| {
+            (lo.min_(v), hi.max_(v))
+        })
+}
+
+/// Endpoints at the corners of the bounding box diagonal that best follows
+/// `values`
+///
+/// The channel with the largest range runs from `lo` to `hi`, and every
+/// channel with a negative covariance with it runs the other way.
+fn endpoints(values: &[Expr<Float4>]) -> (Expr<Float4>, Expr<Float4>) {
+    let (lo, hi) = bounds(values);
+    let n = values.len() as f32;
+    let mean = values[1..]
+        .iter()
+        .fold(values[0], |acc, &v| track!(acc + v));
+    let mean = track!(mean / n);
+    let r = track!(hi - lo);
+    let pivot = track!(select(
+        (r.x >= r.y) & (r.x >= r.z) & (r.x >= r.w),
+        Float4::expr(1.0f32, 0.0f32, 0.0f32, 0.0f32),
+        select(
+            (r.y >= r.z) & (r.y >= r.w),
+            Float4::expr(0.0f32, 1.0f32, 0.0f32, 0.0f32),
+            select(
+                r.z >= r.w,
+                Float4::expr(0.0f32, 0.0f32, 1.0f32, 0.0f32),
+                Float4::expr(0.0f32, 0.0f32, 0.0f32, 1.0f32),
+            ),
+        ),
+    ));
+    let cov = values.iter().fold(Float4::splat_expr(0.0f32), |acc, &v| {
+        let d = track!(v - mean);
+        track!(acc + d * d.dot(pivot))
+    });
+    let flip = |c: Expr<f32>, a: Expr<f32>, b: Expr<f32>| track!(select(c < 0.0f32, b, a));
+    (
+        Float4::expr(
+            flip(cov.x, lo.x, hi.x),
+            flip(cov.y, lo.y, hi.y),
+            flip(cov.z, lo.z, hi.z),
+            flip(cov.w, lo.w, hi.w),
+        ),
+        Float4::expr(
+            flip(cov.x, hi.x, lo.x),
+            flip(cov.y, hi.y, lo.y),
+            flip(cov.z, hi.z, lo.z),
+            flip(cov.w, hi.w, lo.w),
+        ),
+    )
+}
+
+/// Position of `x` projected onto the segment from `e0` to `e1`, rounded to one
+/// of `steps + 1` equidistant points
+#[tracked]
+fn project(x: Expr<Float4>, e0: Expr<Float4>, e1: Expr<Float4>, steps: f32) -> Expr<u32> {
+    let d = e1 - e0;
+    let dd = d.dot(d);
+    let t = select(dd > 0.0, ((x - e0).dot(d) / dd).saturate(), 0.0f32.expr());
+    (t * steps).round().as_u32()
+}
+
+fn bc1_encode(texels: &[Expr<Float4>]) -> Vec<Expr<u32>> {
+    let colors = texels
+        .iter()
+        .map(|t| Float4::expr(t.x, t.y, t.z, 0.0f32))
+        .collect::<Vec<_>>();
+    let (lo, hi) = endpoints(&colors);
+    let inset = track!((hi - lo) / 16.0f32);
+    let c0 = pack_rgb565(track!(hi - inset));
+    let c1 = pack_rgb565(track!(lo + inset));
+    // c0 > c1 selects the mode with two midpoints, equal endpoints leave all
+    // indices at 0
+    let swap = track!(c0 < c1);
+    let (c0, c1) = (select(swap, c1, c0), select(swap, c0, c1));
+    let (e0, e1) = (rgb565(c0), rgb565(c1));
+    let (e0, e1) = (
+        Float4::expr(e0.x, e0.y, e0.z, 0.0f32),
+        Float4::expr(e1.x, e1.y, e1.z, 0.0f32),
+    );
+    let mut words = vec![track!(c0 | (c1 << 16u32)), 0u32.expr()];
+    for (i, &color) in colors.iter().enumerate() {
+        let q = project(color, e0, e1, 3.0);
+        let index = track!(select(
+            q == 0u32,
+            0u32.expr(),
+            select(q == 3u32, 1u32.expr(), q + 1u32)
+        ));
+        put_bits(&mut words, 32 + 2 * i as u32, 2, index);
+    }
+    words
+}
+
+fn bc4_encode(values: &[Expr<f32>]) -> Vec<Expr<u32>> {
+    let (lo, hi) = values[1..]
+        .iter()
+        .fold((values[0], values[0]), |(lo, hi), &v| {
+            (lo.min_(v), hi.max_(v))
+        });
+    // r0 > r1 selects the mode with 6 interpolated values, equal endpoints
+    // leave all indices at 0
+    let r0 = track!((hi.saturate() * 255.0f32).round().as_u32());
+    let r1 = track!((lo.saturate() * 255.0f32).round().as_u32());
+    let (e0, e1) = (
+        track!(r0.as_f32() / 255.0f32),
+        track!(r1.as_f32() / 255.0f32),
+    );
+    let mut words = vec![track!(r0 | (r1 << 8u32)), 0u32.expr()];
+    for (i, &v) in values.iter().enumerate() {
+        let t = track!(select(
+            r0 > r1,
+            ((v - e0) / (e1 - e0)).saturate(),
+            0.0f32.expr()
+        ));
+        let q = track!((t * 7.0f32).round().as_u32());
+        let index = track!(select(
+            q == 0u32,
+            0u32.expr(),
+            select(q == 7u32, 1u32.expr(), q + 1u32)
+        ));
+        put_bits(&mut words, 16 + 3 * i as u32, 3, index);
+    }
+    words
+}
+
+/// Quantizes `c` to 7 bits per channel and a p-bit, choosing the p-bit with the
+/// smaller error
+fn quantize_rgba7(c: Expr<Float4>) -> (Expr<Uint4>, Expr<u32>) {
+    let candidate = |p: f32| {
+        let q = track!(((c * 255.0f32 - p) * 0.5f32)
+            .round()
+            .clamp(0.0f32, 127.0f32));
+        let d = track!(q * 2.0f32 + p - c * 255.0f32);
+        (q.as_::<Uint4>(), d.dot(d))
+    };
+    let ((q0, err0), (q1, err1)) = (candidate(0.0), candidate(1.0));
+    let one = track!(err1 < err0);
+    (select(one, q1, q0), select(one, 1u32.expr(), 0u32.expr()))
+}
+
+/// Encodes a block in mode 6, a single RGBA subset with 4-bit indices
+fn bc7_encode(texels: &[Expr<Float4>]) -> Vec<Expr<u32>> {
+    let texels = texels.iter().map(|t| t.saturate()).collect::<Vec<_>>();
+    let (lo, hi) = endpoints(&texels);
+    let (q0, p0) = quantize_rgba7(lo);
+    let (q1, p1) = quantize_rgba7(hi);
+    let e0 = track!((q0.as_::<Float4>() * 2.0f32 + p0.as_f32()) / 255.0f32);
+    let e1 = track!((q1.as_::<Float4>() * 2.0f32 + p1.as_f32()) / 255.0f32);
+    let indices = texels
+        .iter()
+        .map(|&t| project(t, e0, e1, 15.0))
+        .collect::<Vec<_>>();
+    // the top bit of the first index is implicitly 0, so the endpoints are
+    // swapped when it would be set
+    let swap = track!(indices[0] >= 8u32);
+    let (q0, q1) = (select(swap, q1, q0), select(swap, q0, q1));
+    let (p0, p1) = (select(swap, p1, p0), select(swap, p0, p1));
+    let mut words = vec![0u32.expr(); 4];
+    put_bits(&mut words, 0, 7, 1u32.expr() << 6u32);
+    for (c, (a, b)) in [(q0.x, q1.x), (q0.y, q1.y), (q0.z, q1.z), (q0.w, q1.w)]
+        .into_iter()
+        .enumerate()
+    {
+        put_bits(&mut words, 7 + 14 * c as u32, 7, a);
+        put_bits(&mut words, 14 + 14 * c as u32, 7, b);
+    }
+    put_bits(&mut words, 63, 1, p0);
+    put_bits(&mut words, 64, 1, p1);
+    for (i, &index) in indices.iter().enumerate() {
+        let index = track!(select(swap, 15u32 - index, index));
+        match i {
+            0 => put_bits(&mut words, 65, 3, index),
+            _ => put_bits(&mut words, 64 + 4 * i as u32, 4, index),
+        }
+    }
+    words
+}
+
+/// Kernel compressing `Float4` textures into blocks of a [`BcFormat`]
+///
+/// BC1 uses the four-color mode and BC7 only mode 6, a single subset with
+/// 4-bit indices, which is fast but lower quality than offline encoders for
+/// blocks with more than one color gradient.
+pub struct BcEncoder {
+    format: BcFormat,
+    kernel: Kernel<fn(Tex2d<Float4>, Buffer<u32>)>,
+}
+
+impl BcEncoder {
+    pub fn new(device: &Device, format: BcFormat) -> Self {
+        let encode = |src: Tex2dVar<Float4>, dst: BufferVar<u32>| {
+            let block = dispatch_id().xy();
+            let last = track!(src.size() - 1u32);
+            let texels = (0..16u32)
+                .map(|i| {
+                    let offset = Uint2::new(i % 4, i / 4).expr();
+                    src.read(track!((block * 4u32 + offset).min_(last)))
+                })
+                .collect::<Vec<_>>();
+            let base = track!((block.y * dispatch_size().x + block.x) * format.block_words());
+            for (i, word) in format.encode(&texels).into_iter().enumerate() {
+                let i = i as u32;
+                dst.write(track!(base + i), word);
+            }
+        };
+        let kernel = Kernel::<fn(Tex2d<Float4>, Buffer<u32>)>::new(device, &encode);
+        Self { format, kernel }
+    }
+    pub fn format(&self) -> BcFormat {
+        self.format
+    }
+    /// Compresses `src` into `dst`, which holds [`BcFormat::image_words`] words
+    ///
+    /// Texels are clamped to `[0, 1]`.
+    pub fn encode_async(
+        &self,
+        src: &Tex2dView<Float4>,
+        dst: &BufferView<u32>,
+    ) -> Command<'static, 'static> {
+        let [w, h, _] = src.size();
+        assert_eq!(dst.len(), self.format.image_words(w, h));
+        let [x, y] = BcFormat::block_count(w, h);
+        self.kernel.dispatch_async([x, y, 1], src, dst)
+    }
+    pub fn encode(&self, src: &Tex2dView<Float4>, dst: &BufferView<u32>) {
+        submit_default_stream_and_sync(&src.device, [self.encode_async(src, dst)]);
+    }
+}
+
+impl<T: IoTexel> Tex2dView<T> {
+    fn image_words(&self) -> usize {
+        let format = BcFormat::from_storage(self.storage)
+            .unwrap_or_else(|| panic!("{:?} is not a supported compressed storage", self.storage));
+        let [w, h, _] = self.size();
+        format.image_words(w, h)
+    }
+    /// Uploads the blocks of a texture with compressed storage, see [`BcFormat`]
+    pub fn copy_from_blocks_async(&self, data: &[u32]) -> Command<'static, 'static> {
+        assert_eq!(data.len(), self.image_words());
+        let mut rt = ResourceTracker::new();
+        rt.add(self._handle());
+        Command {
+            inner: api::Command::TextureUpload(api::TextureUploadCommand {
+                texture: self.handle(),
+                storage: self.storage,
+                level: self.level,
+                size: self.size(),
+                data: data.as_ptr() as *const u8,
+            }),
+            resource_tracker: rt,
+            marker: PhantomData,
+            callback: None,
+            on_submit: None,
+        }
+    }
+    pub fn copy_from_blocks(&self, data: &[u32]) {
+        submit_default_stream_and_sync(&self.device, [self.copy_from_blocks_async(data)]);
+    }
+    /// Downloads the blocks of a texture with compressed storage, see [`BcFormat`]
+    pub fn copy_to_blocks_async<'a>(&self, data: &'a mut [u32]) -> Command<'a, 'a> {
+        assert_eq!(data.len(), self.image_words());
+        let mut rt = ResourceTracker::new();
+        rt.add(self._handle());
+        Command {
+            inner: api::Command::TextureDownload(api::TextureDownloadCommand {
+                texture: self.handle(),
+                storage: self.storage,
+                level: self.level,
+                size: self.size(),
+                data: data.as_mut_ptr() as *mut u8,
+            }),
+            resource_tracker: rt,
+            marker: PhantomData,
+            callback: None,
+            on_submit: None,
+        }
+    }
+    pub fn copy_to_blocks(&self, data: &mut [u32]) {
+        submit_default_stream_and_sync(&self.device, [self.copy_to_blocks_async(data)]);
+    }
+    /// Copies blocks, e.g. written by a [`BcEncoder`], from `buffer_view` into
+    /// a texture with compressed storage
+    pub fn copy_from_block_buffer_async(
+        &self,
+        buffer_view: &BufferView<u32>,
+    ) -> Command<'static, 'static> {
+        assert_eq!(buffer_view.len, self.image_words());
+        let mut rt = ResourceTracker::new();
+        rt.add(self._handle());
+        rt.add(buffer_view._handle());
+        Command {
+            inner: api::Command::BufferToTextureCopy(api::BufferToTextureCopyCommand {
+                texture: self.handle(),
+                storage: self.storage,
+                texture_level: self.level,
+                texture_size: self.size(),
+                buffer: buffer_view.handle(),
+                buffer_offset: buffer_view.offset,
+            }),
+            resource_tracker: rt,
+            marker: PhantomData,
+            callback: None,
+            on_submit: None,
+        }
+    }
+    pub fn copy_from_block_buffer(&self, buffer_view: &BufferView<u32>) {
+        submit_default_stream_and_sync(
+            &self.device,
+            [self.copy_from_block_buffer_async(buffer_view)],
+        );
+    }
+}
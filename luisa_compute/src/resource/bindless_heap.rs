@@ -0,0 +1,316 @@
+use std::cell::{Cell, RefCell};
+use std::ops::Deref;
+
+use luisa_compute_ir::context::type_hash;
+
+use super::*;
+
+/// Typed slot of a buffer in a [`BindlessHeap`]
+///
+/// It is a plain `u32` on the device, so it can be stored in buffers and
+/// structs and read in kernels with [`BindlessHeapVar::buffer`].
+#[repr(transparent)]
+pub struct BindlessBufferHandle<T: Value> {
+    slot: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+impl<T: Value> Clone for BindlessBufferHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T: Value> Copy for BindlessBufferHandle<T> {}
+impl<T: Value> PartialEq for BindlessBufferHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.slot == other.slot
+    }
+}
+impl<T: Value> Eq for BindlessBufferHandle<T> {}
+impl<T: Value> fmt::Debug for BindlessBufferHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BindlessBufferHandle")
+            .field(&self.slot)
+            .finish()
+    }
+}
+impl<T: Value> BindlessBufferHandle<T> {
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+}
+impl<T: Value> TypeOf for BindlessBufferHandle<T> {
+    fn type_() -> ir::CArc<Type> {
+        u32::type_()
+    }
+}
+impl<T: Value> Value for BindlessBufferHandle<T> {
+    type Expr = BindlessBufferHandleExpr<T>;
+    type Var = BindlessBufferHandleVar<T>;
+    type AtomicRef = BindlessBufferHandleAtomicRef<T>;
+}
+impl_simple_expr_proxy!([T: Value] BindlessBufferHandleExpr[T] for BindlessBufferHandle<T>);
+impl_simple_var_proxy!([T: Value] BindlessBufferHandleVar[T] for BindlessBufferHandle<T>);
+impl_simple_atomic_ref_proxy!([T: Value] BindlessBufferHandleAtomicRef[T] for BindlessBufferHandle<T>);
+
+/// Slot of a 2D texture in a [`BindlessHeap`]
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq, Eq)]
+pub struct BindlessTex2dHandle {
+    pub slot: u32,
+}
+/// Slot of a 3D texture in a [`BindlessHeap`]
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq, Eq)]
+pub struct BindlessTex3dHandle {
+    pub slot: u32,
+}
+
+struct SlotTable<E> {
+    entries: Vec<Option<E>>,
+    free: Vec<u32>,
+    /// freed slots are only reused after the removal has been submitted
+    pending_free: Vec<u32>,
+}
+impl<E> SlotTable<E> {
+    fn new() -> Self {
+        Self {
+            entries: vec![],
+            free: vec![],
+            pending_free: vec![],
+        }
+    }
+    /// number of slots needed to hold one more entry
+    fn required_capacity(&self) -> usize {
+        if self.free.is_empty() {
+            self.entries.len() + 1
+        } else {
+            self.entries.len()
+        }
+    }
+    fn insert(&mut self, entry: E) -> u32 {
+        if let Some(slot) = self.free.pop() {
+            self.entries[slot as usize] = Some(entry);
+            slot
+        } else {
+            self.entries.push(Some(entry));
+            (self.entries.len() - 1) as u32
+        }
+    }
+    fn remove(&mut self, slot: u32) {
+        let entry = self.entries.get_mut(slot as usize).and_then(|e| e.take());
+        assert!(entry.is_some(), "Slot {} is not allocated", slot);
+        self.pending_free.push(slot);
+    }
+    fn flush(&mut self) {
+        self.free.append(&mut self.pending_free);
+    }
+    fn len(&self) -> usize {
+        self.entries.len() - self.free.len() - self.pending_free.len()
+    }
+}
+
+/// A [`BindlessArray`] that allocates slots automatically
+///
+/// Resources are added with `insert_*`, which return typed handles, and removed
+/// with `remove_*`. Modifications are batched until [`BindlessHeap::update`] or
+/// [`BindlessHeap::update_async`] is called. Slots of removed resources are
+/// only reused after the removal has been submitted.
+///
+/// When the array is full, a new one with twice the capacity is created and
+/// all resources are copied over. Kernels capturing the old array must be
+/// recorded again, see [`BindlessHeap::generation`].
+pub struct BindlessHeap {
+    device: Device,
+    array: RefCell<BindlessArray>,
+    capacity: Cell<usize>,
+    generation: Cell<usize>,
+    validate: Cell<bool>,
+    buffers: RefCell<SlotTable<(Arc<BufferHandle>, usize)>>,
+    tex2ds: RefCell<SlotTable<(Arc<TextureHandle>, Sampler)>>,
+    tex3ds: RefCell<SlotTable<(Arc<TextureHandle>, Sampler)>>,
+}
+
+impl BindlessHeap {
+    pub(crate) fn new(device: &Device, slots: usize) -> Self {
+        Self {
+            device: device.clone(),
+            array: RefCell::new(device.create_bindless_array(slots)),
+            capacity: Cell::new(slots),
+            generation: Cell::new(0),
+            validate: Cell::new(false),
+            buffers: RefCell::new(SlotTable::new()),
+            tex2ds: RefCell::new(SlotTable::new()),
+            tex3ds: RefCell::new(SlotTable::new()),
+        }
+    }
+    /// Number of slots of the underlying array
+    pub fn capacity(&self) -> usize {
+        self.capacity.get()
+    }
+    /// Incremented each time the underlying array is recreated
+    pub fn generation(&self) -> usize {
+        self.generation.get()
+    }
+    /// Number of live buffers, 2D and 3D textures
+    pub fn len(&self) -> (usize, usize, usize) {
+        (
+            self.buffers.borrow().len(),
+            self.tex2ds.borrow().len(),
+            self.tex3ds.borrow().len(),
+        )
+    }
+    /// When enabled, kernels recorded afterwards check that the type of each buffer read
+    /// through [`BindlessHeapVar::buffer`] matches the type it was inserted with.
+    ///
+    /// Relies on [`BindlessBufferVar::__type`], which is not supported by every backend.
+    pub fn set_validation(&self, enabled: bool) {
+        self.validate.set(enabled);
+    }
+    fn reserve(&self, required: usize) {
+        let capacity = self.capacity.get();
+        if required <= capacity {
+            return;
+        }
+        let capacity = (capacity * 2).max(required);
+        let array = self.device.create_bindless_array(capacity);
+        for (slot, entry) in self.buffers.borrow().entries.iter().enumerate() {
+            if let Some((buffer, offset)) = entry {
+                array.emplace_raw_buffer_async(slot, buffer.clone(), *offset);
+            }
+        }
+        for (slot, entry) in self.tex2ds.borrow().entries.iter().enumerate() {
+            if let Some((texture, sampler)) = entry {
+                array.emplace_raw_tex2d_async(slot, texture.clone(), *sampler);
+            }
+        }
+        for (slot, entry) in self.tex3ds.borrow().entries.iter().enumerate() {
+            if let Some((texture, sampler)) = entry {
+                array.emplace_raw_tex3d_async(slot, texture.clone(), *sampler);
+            }
+        }
+        *self.array.borrow_mut() = array;
+        self.capacity.set(capacity);
+        self.generation.set(self.generation.get() + 1);
+    }
+    pub fn insert_buffer<T: Value>(&self, buffer: &Buffer<T>) -> BindlessBufferHandle<T> {
+        self.insert_buffer_view(&buffer.view(..))
+    }
+    pub fn insert_buffer_view<T: Value>(&self, view: &BufferView<T>) -> BindlessBufferHandle<T> {
+        self.reserve(self.buffers.borrow().required_capacity());
+        let handle = view._handle();
+        let slot = self
+            .buffers
+            .borrow_mut()
+            .insert((handle.clone(), view.offset));
+        self.array
+            .borrow()
+            .emplace_raw_buffer_async(slot as usize, handle, view.offset);
+        BindlessBufferHandle {
+            slot,
+            _marker: PhantomData,
+        }
+    }
+    pub fn insert_tex2d<T: IoTexel>(
+        &self,
+        texture: &Tex2d<T>,
+        sampler: Sampler,
+    ) -> BindlessTex2dHandle {
+        self.reserve(self.tex2ds.borrow().required_capacity());
+        let slot = self
+            .tex2ds
+            .borrow_mut()
+            .insert((texture.handle.clone(), sampler));
+        self.array
+            .borrow()
+            .emplace_raw_tex2d_async(slot as usize, texture.handle.clone(), sampler);
+        BindlessTex2dHandle { slot }
+    }
+    pub fn insert_tex3d<T: IoTexel>(
+        &self,
+        texture: &Tex3d<T>,
+        sampler: Sampler,
+    ) -> BindlessTex3dHandle {
+        self.reserve(self.tex3ds.borrow().required_capacity());
+        let slot = self
+            .tex3ds
+            .borrow_mut()
+            .insert((texture.handle.clone(), sampler));
+        self.array
+            .borrow()
+            .emplace_raw_tex3d_async(slot as usize, texture.handle.clone(), sampler);
+        BindlessTex3dHandle { slot }
+    }
+    pub fn remove_buffer<T: Value>(&self, handle: BindlessBufferHandle<T>) {
+        self.buffers.borrow_mut().remove(handle.slot);
+        self.array
+            .borrow()
+            .remove_buffer_async(handle.slot as usize);
+    }
+    pub fn remove_tex2d(&self, handle: BindlessTex2dHandle) {
+        self.tex2ds.borrow_mut().remove(handle.slot);
+        self.array.borrow().remove_tex2d_async(handle.slot as usize);
+    }
+    pub fn remove_tex3d(&self, handle: BindlessTex3dHandle) {
+        self.tex3ds.borrow_mut().remove(handle.slot);
+        self.array.borrow().remove_tex3d_async(handle.slot as usize);
+    }
+    /// Submits all pending insertions and removals
+    pub fn update_async(&self) -> Command<'static, 'static> {
+        let array = self.array.borrow();
+        let mut command = unsafe { array.update_async().lift() };
+        // the array may be recreated before the command is executed
+        command.resource_tracker.add(array.handle.clone());
+        self.buffers.borrow_mut().flush();
+        self.tex2ds.borrow_mut().flush();
+        self.tex3ds.borrow_mut().flush();
+        command
+    }
+    pub fn update(&self) {
+        submit_default_stream_and_sync(&self.device, [self.update_async()]);
+    }
+    /// The underlying array. It changes when the heap grows
+    pub fn array(&self) -> std::cell::Ref<'_, BindlessArray> {
+        self.array.borrow()
+    }
+    pub fn var(&self) -> BindlessHeapVar {
+        BindlessHeapVar {
+            array: self.array.borrow().var(),
+            validate: self.validate.get(),
+        }
+    }
+}
+
+/// Device side view of a [`BindlessHeap`]
+///
+/// Derefs to [`BindlessArrayVar`] for untyped access.
+#[derive(Clone)]
+pub struct BindlessHeapVar {
+    array: BindlessArrayVar,
+    validate: bool,
+}
+impl BindlessHeapVar {
+    pub fn buffer<T: Value>(
+        &self,
+        handle: impl AsExpr<Value = BindlessBufferHandle<T>>,
+    ) -> BindlessBufferVar<T> {
+        let slot = Expr::<u32>::from_node(handle.as_expr().node());
+        let v = self.array.buffer::<T>(slot);
+        if self.validate {
+            let expected = type_hash(&T::type_());
+            lc_assert!(v.__type().eq(expected));
+        }
+        v
+    }
+    pub fn tex2d(&self, handle: impl AsExpr<Value = BindlessTex2dHandle>) -> BindlessTex2dVar {
+        self.array.tex2d(handle.as_expr().slot)
+    }
+    pub fn tex3d(&self, handle: impl AsExpr<Value = BindlessTex3dHandle>) -> BindlessTex3dVar {
+        self.array.tex3d(handle.as_expr().slot)
+    }
+}
+impl Deref for BindlessHeapVar {
+    type Target = BindlessArrayVar;
+    fn deref(&self) -> &Self::Target {
+        &self.array
+    }
+}
@@ -0,0 +1,573 @@
+//! Layered textures: [`Tex2dArray`] and [`TexCube`].
+//!
+//! The backend interface only knows 2D and 3D textures, so both are emulated:
+//! every layer (or cube face) is a separate [`Tex2d`] placed into consecutive
+//! slots of a [`BindlessArray`], and sampling selects the slot on the device.
+//! Filtering never crosses layers. For cube maps this means each face is
+//! clamped at its edges, which can show faint seams with linear filtering of
+//! low resolution faces. Mip generation does filter across faces, see
+//! [`TexCube::generate_mips_async`].
+use super::mipmap::{address, footprint, load, store};
+use super::*;
+
+/// An array of 2D textures of the same size, format and mip count
+pub struct Tex2dArray<T: IoTexel> {
+    pub(crate) layers: Vec<Tex2d<T>>,
+    pub(crate) sampler: Sampler,
+    pub(crate) array: BindlessArray,
+}
+
+impl<T: IoTexel> Tex2dArray<T> {
+    pub(crate) fn new(device: &Device, layers: Vec<Tex2d<T>>, sampler: Sampler) -> Self {
+        let array = device.create_bindless_array(layers.len());
+        for (i, layer) in layers.iter().enumerate() {
+            array.emplace_tex2d_async(i, layer, sampler);
+        }
+        array.update();
+        Self {
+            layers,
+            sampler,
+            array,
+        }
+    }
+    pub fn layer(&self, layer: u32) -> &Tex2d<T> {
+        &self.layers[layer as usize]
+    }
+    pub fn layers(&self) -> u32 {
+        self.layers.len() as u32
+    }
+    pub fn view(&self, layer: u32, level: u32) -> Tex2dView<T> {
+        self.layer(layer).view(level)
+    }
+    pub fn width(&self) -> u32 {
+        self.layers[0].width()
+    }
+    pub fn height(&self) -> u32 {
+        self.layers[0].height()
+    }
+    pub fn levels(&self) -> u32 {
+        self.layers[0].handle.levels
+    }
+    pub fn storage(&self) -> PixelStorage {
+        self.layers[0].storage()
+    }
+    pub fn sampler(&self) -> Sampler {
+        self.sampler
+    }
+    pub fn var(&self) -> Tex2dArrayVar<T> {
+        Tex2dArrayVar::new(self.array.var())
+    }
+    /// Copies every layer and level of `self` to `other`, which must have the
+    /// same layer count, size and mip count
+    pub fn copy_to_texture_async(&self, other: &Tex2dArray<T>) -> Vec<Command<'static, 'static>> {
+        assert_eq!(self.layers(), other.layers());
+        assert_eq!(self.levels(), other.levels());
+        self.layers
+            .iter()
+            .zip(&other.layers)
+            .flat_map(|(src, dst)| {
+                (0..self.levels())
+                    .map(|level| src.view(level).copy_to_texture_async(&dst.view(level)))
+            })
+            .collect()
+    }
+    pub fn copy_to_texture(&self, other: &Tex2dArray<T>) {
+        submit_default_stream_and_sync(&self.array.device, self.copy_to_texture_async(other));
+    }
+    /// Create a new array with the same dimensions, storage and sampler as
+    /// `self` and copy the contents of `self` to it asynchronously
+    pub fn copy_async(&self, s: &Scope) -> Self {
+        let device = &self.array.device;
+        let copy = device.create_tex2d_array::<T>(
+            self.storage(),
+            self.width(),
+            self.height(),
+            self.layers(),
+            self.levels(),
+            self.sampler,
+        );
+        s.submit(self.copy_to_texture_async(&copy));
+        copy
+    }
+    /// Create a new array with the same dimensions, storage and sampler as
+    /// `self` and copy the contents of `self` to it.
+    pub fn copy(&self) -> Self {
+        let default_stream = self.array.device.default_stream();
+        default_stream.with_scope(|s| self.copy_async(s))
+    }
+}
+
+impl<T: IoTexel<RwType = Float4>> Tex2dArray<T> {
+    /// Generates the mips of every layer, see [`Tex2d::generate_mips_async`]
+    pub fn generate_mips_async(
+        &self,
+        mips: &MipGenerator,
+        options: impl Into<MipOptions>,
+    ) -> Vec<Command<'static, 'static>> {
+        let options = options.into();
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.generate_mips_async(mips, options))
+            .collect()
+    }
+    pub fn generate_mips(&self, mips: &MipGenerator, options: impl Into<MipOptions>) {
+        submit_default_stream_and_sync(&self.array.device, self.generate_mips_async(mips, options));
+    }
+}
+
+impl<T: IoTexel + fmt::Debug> fmt::Debug for Tex2dArray<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Tex2dArray<{}>({}, {}, {})",
+            std::any::type_name::<T>(),
+            self.width(),
+            self.height(),
+            self.layers(),
+        )
+    }
+}
+
+/// A cube map with square faces in the order +X, -X, +Y, -Y, +Z, -Z
+///
+/// Face `i` is layer `i` of the underlying [`Tex2dArray`]. Face coordinates
+/// follow the D3D and Vulkan convention, see [`cube_face_uv`].
+pub struct TexCube<T: IoTexel> {
+    pub(crate) faces: Tex2dArray<T>,
+}
+
+impl<T: IoTexel> TexCube<T> {
+    pub fn face(&self, face: u32) -> &Tex2d<T> {
+        self.faces.layer(face)
+    }
+    pub fn view(&self, face: u32, level: u32) -> Tex2dView<T> {
+        self.faces.view(face, level)
+    }
+    /// Edge length of the faces
+    pub fn size(&self) -> u32 {
+        self.faces.width()
+    }
+    pub fn levels(&self) -> u32 {
+        self.faces.levels()
+    }
+    pub fn storage(&self) -> PixelStorage {
+        self.faces.storage()
+    }
+    /// The faces as a 6-layer array
+    pub fn as_array(&self) -> &Tex2dArray<T> {
+        &self.faces
+    }
+    pub fn var(&self) -> TexCubeVar<T> {
+        TexCubeVar::new(self.faces.array.var())
+    }
+    pub fn copy_to_texture_async(&self, other: &TexCube<T>) -> Vec<Command<'static, 'static>> {
+        self.faces.copy_to_texture_async(&other.faces)
+    }
+    pub fn copy_to_texture(&self, other: &TexCube<T>) {
+        self.faces.copy_to_texture(&other.faces)
+    }
+    /// Create a new cube map with the same dimensions, storage and sampler as
+    /// `self` and copy the contents of `self` to it asynchronously
+    pub fn copy_async(&self, s: &Scope) -> Self {
+        Self {
+            faces: self.faces.copy_async(s),
+        }
+    }
+    /// Create a new cube map with the same dimensions, storage and sampler as
+    /// `self` and copy the contents of `self` to it.
+    pub fn copy(&self) -> Self {
+        Self {
+            faces: self.faces.copy(),
+        }
+    }
+}
+
+/// Reads texel `(x, y)` of `face` at `level`, continuing onto the adjacent
+/// face if it lies outside
+#[tracked]
+fn cube_read<T: IoTexel>(
+    src: &TexCubeVar<T>,
+    face: Expr<u32>,
+    x: Expr<i32>,
+    y: Expr<i32>,
+    size: Expr<u32>,
+    level: Expr<u32>,
+) -> Expr<Float4> {
+    let n = size.as_f32();
+    let uv = Float2::expr(x.as_f32() + 0.5, y.as_f32() + 0.5) / n;
+    let (face, uv) = cube_face_uv(cube_face_direction(face, uv));
+    let p = (uv * n).floor();
+    let coord = Uint2::expr(
+        address(MipAddress::Clamp, p.x.as_i32(), size),
+        address(MipAddress::Clamp, p.y.as_i32(), size),
+    );
+    src.face(face).read_level(coord, level)
+}
+
+#[tracked]
+fn downsample_cube<T: IoTexel<RwType = Float4>>(
+    options: MipOptions,
+    src: &TexCubeVar<T>,
+    dst: &Tex2dVar<T>,
+    face: Expr<u32>,
+    src_level: Expr<u32>,
+    src_size: Expr<u32>,
+) {
+    let filter = options.filter;
+    let p = dispatch_id().xy();
+    let dst_size = dst.size();
+    let (sx, cx, scale_x) = footprint(filter, p.x, src_size, dst_size.x);
+    let (sy, cy, scale_y) = footprint(filter, p.y, src_size, dst_size.y);
+    let sum = Float4::var_zeroed();
+    let weights = f32::var_zeroed();
+    let taps = filter.max_taps();
+    for ty in 0u32.expr()..taps.expr() {
+        let iy = sy + ty.as_i32();
+        let wy = filter.weight(iy, cy, scale_y);
+        if wy != 0.0 {
+            for tx in 0u32.expr()..taps.expr() {
+                let ix = sx + tx.as_i32();
+                let w = wy * filter.weight(ix, cx, scale_x);
+                if w != 0.0 {
+                    let v = cube_read(src, face, ix, iy, src_size, src_level);
+                    *sum += load(options, v) * w;
+                    *weights += w;
+                }
+            }
+        }
+    }
+    dst.write(p, store::<T>(options, sum.load() / weights.load()));
+}
+
+impl<T: IoTexel<RwType = Float4>> TexCube<T> {
+    /// Fills levels `1..` of every face from level 0
+    ///
+    /// Filters reaching past the edge of a face read the adjacent face, so
+    /// that the faces of the smaller levels match along their shared edges.
+    /// [`MipOptions::address`] is ignored.
+    pub fn generate_mips_async(
+        &self,
+        mips: &MipGenerator,
+        options: impl Into<MipOptions>,
+    ) -> Vec<Command<'static, 'static>> {
+        let options = options.into();
+        let kernel = mips.kernel(options, |device| {
+            Kernel::<fn(TexCube<T>, Tex2d<T>, u32, u32, u32)>::new(
+                device,
+                &|src: TexCubeVar<T>, dst: Tex2dVar<T>, face, src_level, src_size| {
+                    downsample_cube(options, &src, &dst, face, src_level, src_size)
+                },
+            )
+        });
+        (1..self.levels())
+            .flat_map(|level| {
+                let src_size = (self.size() >> (level - 1)).max(1);
+                let kernel = kernel.clone();
+                (0..6u32).map(move |face| {
+                    let dst = self.view(face, level);
+                    let [w, h, _] = dst.size();
+                    kernel.dispatch_async([w, h, 1], self, &dst, &face, &(level - 1), &src_size)
+                })
+            })
+            .collect()
+    }
+    pub fn generate_mips(&self, mips: &MipGenerator, options: impl Into<MipOptions>) {
+        submit_default_stream_and_sync(
+            &self.faces.array.device,
+            self.generate_mips_async(mips, options),
+        );
+    }
+}
+
+impl<T: IoTexel + fmt::Debug> fmt::Debug for TexCube<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TexCube<{}>({})",
+            std::any::type_name::<T>(),
+            self.size(),
+        )
+    }
+}
+
+/// Selects the cube face hit by `dir` and returns it with the coordinates
+/// in `[0, 1]^2` on that face
+///
+/// `dir` does not need to be normalized but must not be zero.
+#[tracked]
+pub fn cube_face_uv(dir: impl AsExpr<Value = Float3>) -> (Expr<u32>, Expr<Float2>) {
+    let d = dir.as_expr();
+    let a = d.abs();
+    let x_major = (a.x >= a.y) & (a.x >= a.z);
+    let y_major = !x_major & (a.y >= a.z);
+    let face = select(
+        x_major,
+        select(d.x >= 0.0, 0u32.expr(), 1u32.expr()),
+        select(
+            y_major,
+            select(d.y >= 0.0, 2u32.expr(), 3u32.expr()),
+            select(d.z >= 0.0, 4u32.expr(), 5u32.expr()),
+        ),
+    );
+    let ma = select(x_major, a.x, select(y_major, a.y, a.z));
+    let sc = select(
+        x_major,
+        select(d.x >= 0.0, -d.z, d.z),
+        select(y_major | (d.z >= 0.0), d.x, -d.x),
+    );
+    let tc = select(y_major, select(d.y >= 0.0, d.z, -d.z), -d.y);
+    (face, Float2::expr(sc, tc) / ma * 0.5 + 0.5)
+}
+
+/// Inverse of [`cube_face_uv`], returns an unnormalized direction
+#[tracked]
+pub fn cube_face_direction(
+    face: impl AsExpr<Value = u32>,
+    uv: impl AsExpr<Value = Float2>,
+) -> Expr<Float3> {
+    let face = face.as_expr();
+    let st = uv.as_expr() * 2.0 - 1.0;
+    let (s, t) = (st.x, st.y);
+    let one = 1.0f32.expr();
+    let x_face = select(
+        face == 0u32,
+        Float3::expr(one, -t, -s),
+        Float3::expr(-one, -t, s),
+    );
+    let y_face = select(
+        face == 2u32,
+        Float3::expr(s, one, t),
+        Float3::expr(s, -one, -t),
+    );
+    let z_face = select(
+        face == 4u32,
+        Float3::expr(s, -t, one),
+        Float3::expr(-s, -t, -one),
+    );
+    select(face < 2u32, x_face, select(face < 4u32, y_face, z_face))
+}
+
+/// Host version of [`cube_face_uv`]
+pub fn cube_face_uv_host(dir: Float3) -> (u32, Float2) {
+    let (x, y, z) = (dir.x, dir.y, dir.z);
+    let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+    let (face, ma, sc, tc) = if ax >= ay && ax >= az {
+        if x >= 0.0 {
+            (0, ax, -z, -y)
+        } else {
+            (1, ax, z, -y)
+        }
+    } else if ay >= az {
+        if y >= 0.0 {
+            (2, ay, x, z)
+        } else {
+            (3, ay, x, -z)
+        }
+    } else if z >= 0.0 {
+        (4, az, x, -y)
+    } else {
+        (5, az, -x, -y)
+    };
+    (face, Float2::new(sc / ma * 0.5 + 0.5, tc / ma * 0.5 + 0.5))
+}
+
+/// Host version of [`cube_face_direction`]
+pub fn cube_face_direction_host(face: u32, uv: Float2) -> Float3 {
+    let (s, t) = (uv.x * 2.0 - 1.0, uv.y * 2.0 - 1.0);
+    match face {
+        0 => Float3::new(1.0, -t, -s),
+        1 => Float3::new(-1.0, -t, s),
+        2 => Float3::new(s, 1.0, t),
+        3 => Float3::new(s, -1.0, -t),
+        4 => Float3::new(s, -t, 1.0),
+        5 => Float3::new(-s, -t, -1.0),
+        _ => panic!("cube face {} out of range", face),
+    }
+}
+
+/// Layers of a [`Tex2dArray`] stored in consecutive slots of a bindless array,
+/// starting at `base`
+#[derive(Clone)]
+pub struct BindlessTex2dArrayVar {
+    array: BindlessArrayVar,
+    base: Expr<u32>,
+}
+
+impl BindlessTex2dArrayVar {
+    #[tracked]
+    pub fn layer(&self, layer: impl AsExpr<Value = u32>) -> BindlessTex2dVar {
+        self.array.tex2d(self.base + layer.as_expr())
+    }
+    pub fn sample(
+        &self,
+        uv: impl AsExpr<Value = Float2>,
+        layer: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        self.layer(layer).sample(uv)
+    }
+    pub fn sample_level(
+        &self,
+        uv: impl AsExpr<Value = Float2>,
+        layer: impl AsExpr<Value = u32>,
+        level: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        self.layer(layer).sample_level(uv, level)
+    }
+    pub fn sample_grad(
+        &self,
+        uv: impl AsExpr<Value = Float2>,
+        layer: impl AsExpr<Value = u32>,
+        ddx: impl AsExpr<Value = Float2>,
+        ddy: impl AsExpr<Value = Float2>,
+    ) -> Expr<Float4> {
+        self.layer(layer).sample_grad(uv, ddx, ddy)
+    }
+    pub fn read(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        layer: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        self.layer(layer).read(coord)
+    }
+    pub fn read_level(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        layer: impl AsExpr<Value = u32>,
+        level: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        self.layer(layer).read_level(coord, level)
+    }
+    /// Size of the layers
+    pub fn size(&self) -> Expr<Uint2> {
+        self.layer(0u32).size()
+    }
+    pub fn size_level(&self, level: impl AsExpr<Value = u32>) -> Expr<Uint2> {
+        self.layer(0u32).size_level(level)
+    }
+}
+
+/// Faces of a [`TexCube`] stored in 6 consecutive slots of a bindless array
+#[derive(Clone)]
+pub struct BindlessTexCubeVar {
+    faces: BindlessTex2dArrayVar,
+}
+
+impl BindlessTexCubeVar {
+    pub fn face(&self, face: impl AsExpr<Value = u32>) -> BindlessTex2dVar {
+        self.faces.layer(face)
+    }
+    pub fn sample(&self, dir: impl AsExpr<Value = Float3>) -> Expr<Float4> {
+        let (face, uv) = cube_face_uv(dir);
+        self.faces.sample(uv, face)
+    }
+    pub fn sample_level(
+        &self,
+        dir: impl AsExpr<Value = Float3>,
+        level: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        let (face, uv) = cube_face_uv(dir);
+        self.faces.sample_level(uv, face, level)
+    }
+    pub fn read(
+        &self,
+        coord: impl AsExpr<Value = Uint2>,
+        face: impl AsExpr<Value = u32>,
+    ) -> Expr<Float4> {
+        self.faces.read(coord, face)
+    }
+    /// Edge length of the faces
+    pub fn size(&self) -> Expr<u32> {
+        self.faces.size().x
+    }
+}
+
+impl BindlessArrayVar {
+    /// Layers of a [`Tex2dArray`] placed with [`BindlessArray::emplace_tex2d_array_async`]
+    pub fn tex2d_array(&self, base: impl AsExpr<Value = u32>) -> BindlessTex2dArrayVar {
+        BindlessTex2dArrayVar {
+            array: self.clone(),
+            base: base.as_expr(),
+        }
+    }
+    /// Faces of a [`TexCube`] placed with [`BindlessArray::emplace_tex_cube_async`]
+    pub fn tex_cube(&self, base: impl AsExpr<Value = u32>) -> BindlessTexCubeVar {
+        BindlessTexCubeVar {
+            faces: self.tex2d_array(base),
+        }
+    }
+}
+
+impl BindlessArray {
+    /// Places the layers of `texture` into slots `base..base + layers`
+    pub fn emplace_tex2d_array_async<T: IoTexel>(
+        &self,
+        base: usize,
+        texture: &Tex2dArray<T>,
+        sampler: Sampler,
+    ) {
+        for (i, layer) in texture.layers.iter().enumerate() {
+            self.emplace_tex2d_async(base + i, layer, sampler);
+        }
+    }
+    /// Places the faces of `texture` into slots `base..base + 6`
+    pub fn emplace_tex_cube_async<T: IoTexel>(
+        &self,
+        base: usize,
+        texture: &TexCube<T>,
+        sampler: Sampler,
+    ) {
+        self.emplace_tex2d_array_async(base, &texture.faces, sampler);
+    }
+    pub fn remove_tex2d_array_async(&self, base: usize, layers: u32) {
+        for i in 0..layers as usize {
+            self.remove_tex2d_async(base + i);
+        }
+    }
+    pub fn remove_tex_cube_async(&self, base: usize) {
+        self.remove_tex2d_array_async(base, 6);
+    }
+}
+
+/// Kernel parameter of a [`Tex2dArray`], sampled with the array's sampler
+pub struct Tex2dArrayVar<T: IoTexel> {
+    layers: BindlessTex2dArrayVar,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: IoTexel> Tex2dArrayVar<T> {
+    pub(crate) fn new(array: BindlessArrayVar) -> Self {
+        Self {
+            layers: array.tex2d_array(0u32),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: IoTexel> std::ops::Deref for Tex2dArrayVar<T> {
+    type Target = BindlessTex2dArrayVar;
+    fn deref(&self) -> &Self::Target {
+        &self.layers
+    }
+}
+
+/// Kernel parameter of a [`TexCube`], sampled with the cube map's sampler
+pub struct TexCubeVar<T: IoTexel> {
+    faces: BindlessTexCubeVar,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: IoTexel> TexCubeVar<T> {
+    pub(crate) fn new(array: BindlessArrayVar) -> Self {
+        Self {
+            faces: array.tex_cube(0u32),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: IoTexel> std::ops::Deref for TexCubeVar<T> {
+    type Target = BindlessTexCubeVar;
+    fn deref(&self) -> &Self::Target {
+        &self.faces
+    }
+}
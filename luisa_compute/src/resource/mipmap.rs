@@ -0,0 +1,339 @@
+//! Mipmap generation for [`Tex2d`] and [`Tex3d`].
+//!
+//! Each level is filtered from the previous one. A destination texel covers
+//! `size / size_next` source texels along each axis, which is 2 for even sizes
+//! and up to 3 for odd ones, so odd-sized levels are resampled rather than
+//! averaged in fixed 2x2 blocks.
+//!
+//! The kernels are compiled once per texel type and [`MipOptions`] and cached
+//! in a [`MipGenerator`], which should be kept around for the lifetime of the
+//! device.
+use std::any::{Any, TypeId};
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use super::*;
+use crate::lang::functions::{linear_to_srgb, srgb_to_linear};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MipFilter {
+    /// Area average of the covered source texels
+    Box,
+    /// Kaiser-windowed sinc with a radius of 3 texels of the next level
+    Kaiser,
+    /// Lanczos with a radius of 3 texels of the next level
+    Lanczos,
+}
+
+/// How source texels outside the texture are addressed by the wider filters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MipAddress {
+    /// Repeats the edge texels
+    Clamp,
+    Wrap,
+    Mirror,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MipOptions {
+    pub filter: MipFilter,
+    /// Whether the color channels hold sRGB-encoded values, which are then
+    /// filtered in linear space; alpha is always filtered as is
+    pub srgb: bool,
+    /// Ignored by cube maps, whose filters continue onto the adjacent faces
+    pub address: MipAddress,
+}
+
+impl From<MipFilter> for MipOptions {
+    fn from(filter: MipFilter) -> Self {
+        Self {
+            filter,
+            srgb: false,
+            address: MipAddress::Clamp,
+        }
+    }
+}
+
+const RADIUS: f32 = 3.0;
+const KAISER_ALPHA: f32 = 4.0;
+
+impl MipFilter {
+    /// Upper bound of the taps along one axis for a size ratio of at most 3
+    pub(super) fn max_taps(&self) -> u32 {
+        match self {
+            MipFilter::Box => 4,
+            _ => (2.0 * RADIUS * 3.0) as u32 + 2,
+        }
+    }
+    /// Radius of the filter in source texels
+    fn radius(&self, scale: Expr<f32>) -> Expr<f32> {
+        match self {
+            MipFilter::Box => track!(scale * 0.5),
+            _ => track!(scale * RADIUS),
+        }
+    }
+    /// Weight of source texel `i` for a destination texel centered at `center`
+    pub(super) fn weight(&self, i: Expr<i32>, center: Expr<f32>, scale: Expr<f32>) -> Expr<f32> {
+        let lo = i.as_f32();
+        match self {
+            MipFilter::Box => {
+                let r = self.radius(scale);
+                track!(((center + r).min_(lo + 1.0) - (center - r).max_(lo)).max_(0.0f32))
+            }
+            MipFilter::Kaiser => {
+                let d = track!((lo + 0.5 - center) / scale);
+                let t = track!((1.0 - (d / RADIUS) * (d / RADIUS)).max_(0.0f32));
+                let window =
+                    track!(bessel_i0(KAISER_ALPHA * t.sqrt()) / bessel_i0_host(KAISER_ALPHA));
+                track!(select(d.abs() < RADIUS, sinc(d) * window, 0.0f32.expr()))
+            }
+            MipFilter::Lanczos => {
+                let d = track!((lo + 0.5 - center) / scale);
+                track!(select(
+                    d.abs() < RADIUS,
+                    sinc(d) * sinc(d / RADIUS),
+                    0.0f32.expr()
+                ))
+            }
+        }
+    }
+}
+
+#[tracked]
+fn sinc(x: Expr<f32>) -> Expr<f32> {
+    let px = x * PI;
+    select(x.abs() < 1e-4, 1.0f32.expr(), px.sin() / px)
+}
+
+/// Modified Bessel function of the first kind of order zero
+fn bessel_i0(x: Expr<f32>) -> Expr<f32> {
+    let y = track!(x * x * 0.25);
+    let mut term = 1.0f32.expr();
+    let mut sum = 1.0f32.expr();
+    for k in 1..16 {
+        term = track!(term * y / (k * k) as f32);
+        sum = track!(sum + term);
+    }
+    sum
+}
+
+fn bessel_i0_host(x: f32) -> f32 {
+    let y = x * x * 0.25;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for k in 1..16 {
+        term *= y / (k * k) as f32;
+        sum += term;
+    }
+    sum
+}
+
+#[tracked]
+pub(super) fn address(mode: MipAddress, i: Expr<i32>, size: Expr<u32>) -> Expr<u32> {
+    let size = size.as_i32();
+    match mode {
+        MipAddress::Clamp => i.clamp(0i32.expr(), size - 1).as_u32(),
+        MipAddress::Wrap => (((i % size) + size) % size).as_u32(),
+        MipAddress::Mirror => {
+            let period = size * 2;
+            let m = ((i % period) + period) % period;
+            select(m < size, m, period - 1 - m).as_u32()
+        }
+    }
+}
+
+pub(super) fn load(options: MipOptions, v: Expr<Float4>) -> Expr<Float4> {
+    if options.srgb {
+        let c = srgb_to_linear(v.xyz());
+        Float4::expr(c.x, c.y, c.z, v.w)
+    } else {
+        v
+    }
+}
+
+pub(super) fn store<T: IoTexel<RwType = Float4>>(options: MipOptions, v: Expr<Float4>) -> Expr<T> {
+    let v = if options.srgb {
+        let c = linear_to_srgb(v.xyz());
+        Float4::expr(c.x, c.y, c.z, v.w)
+    } else {
+        v
+    };
+    T::convert_from_read(v)
+}
+
+/// First source texel, filter center and size ratio along one axis for destination texel `p`
+pub(super) fn footprint(
+    filter: MipFilter,
+    p: Expr<u32>,
+    src_size: Expr<u32>,
+    dst_size: Expr<u32>,
+) -> (Expr<i32>, Expr<f32>, Expr<f32>) {
+    let scale = track!(src_size.as_f32() / dst_size.as_f32());
+    let center = track!((p.as_f32() + 0.5) * scale);
+    let start = track!((center - filter.radius(scale)).floor().as_i32());
+    (start, center, scale)
+}
+
+#[tracked]
+fn downsample_2d<T: IoTexel<RwType = Float4>>(
+    options: MipOptions,
+    src: &Tex2dVar<T>,
+    dst: &Tex2dVar<T>,
+) {
+    let filter = options.filter;
+    let p = dispatch_id().xy();
+    let (src_size, dst_size) = (src.size(), dst.size());
+    let (sx, cx, scale_x) = footprint(filter, p.x, src_size.x, dst_size.x);
+    let (sy, cy, scale_y) = footprint(filter, p.y, src_size.y, dst_size.y);
+    let sum = Float4::var_zeroed();
+    let weights = f32::var_zeroed();
+    let taps = filter.max_taps();
+    for ty in 0u32.expr()..taps.expr() {
+        let iy = sy + ty.as_i32();
+        let wy = filter.weight(iy, cy, scale_y);
+        if wy != 0.0 {
+            for tx in 0u32.expr()..taps.expr() {
+                let ix = sx + tx.as_i32();
+                let w = wy * filter.weight(ix, cx, scale_x);
+                if w != 0.0 {
+                    let uv = Uint2::expr(
+                        address(options.address, ix, src_size.x),
+                        address(options.address, iy, src_size.y),
+                    );
+                    *sum += load(options, T::convert_to_write(src.read(uv))) * w;
+                    *weights += w;
+                }
+            }
+        }
+    }
+    dst.write(p, store::<T>(options, sum.load() / weights.load()));
+}
+
+#[tracked]
+fn downsample_3d<T: IoTexel<RwType = Float4>>(
+    options: MipOptions,
+    src: &Tex3dVar<T>,
+    dst: &Tex3dVar<T>,
+) {
+    let filter = options.filter;
+    let p = dispatch_id();
+    let (src_size, dst_size) = (src.size(), dst.size());
+    let (sx, cx, scale_x) = footprint(filter, p.x, src_size.x, dst_size.x);
+    let (sy, cy, scale_y) = footprint(filter, p.y, src_size.y, dst_size.y);
+    let (sz, cz, scale_z) = footprint(filter, p.z, src_size.z, dst_size.z);
+    let sum = Float4::var_zeroed();
+    let weights = f32::var_zeroed();
+    let taps = filter.max_taps();
+    for tz in 0u32.expr()..taps.expr() {
+        let iz = sz + tz.as_i32();
+        let wz = filter.weight(iz, cz, scale_z);
+        if wz != 0.0 {
+            for ty in 0u32.expr()..taps.expr() {
+                let iy = sy + ty.as_i32();
+                let wy = wz * filter.weight(iy, cy, scale_y);
+                if wy != 0.0 {
+                    for tx in 0u32.expr()..taps.expr() {
+                        let ix = sx + tx.as_i32();
+                        let w = wy * filter.weight(ix, cx, scale_x);
+                        if w != 0.0 {
+                            let uvw = Uint3::expr(
+                                address(options.address, ix, src_size.x),
+                                address(options.address, iy, src_size.y),
+                                address(options.address, iz, src_size.z),
+                            );
+                            *sum += load(options, T::convert_to_write(src.read(uvw))) * w;
+                            *weights += w;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    dst.write(p, store::<T>(options, sum.load() / weights.load()));
+}
+
+/// Compiles and caches the kernels filling mip levels
+pub struct MipGenerator {
+    device: Device,
+    kernels: RefCell<HashMap<(TypeId, MipOptions), Rc<dyn Any>>>,
+}
+
+impl MipGenerator {
+    pub fn new(device: &Device) -> Self {
+        Self {
+            device: device.clone(),
+            kernels: RefCell::new(HashMap::new()),
+        }
+    }
+    /// Returns the kernel cached for `options`, building it if needed
+    pub(super) fn kernel<K: Any>(
+        &self,
+        options: MipOptions,
+        build: impl FnOnce(&Device) -> K,
+    ) -> Rc<K> {
+        let key = (TypeId::of::<K>(), options);
+        if let Some(kernel) = self.kernels.borrow().get(&key) {
+            return kernel.clone().downcast::<K>().unwrap();
+        }
+        let kernel = Rc::new(build(&self.device));
+        self.kernels.borrow_mut().insert(key, kernel.clone());
+        kernel
+    }
+}
+
+impl<T: IoTexel<RwType = Float4>> Tex2d<T> {
+    /// Fills levels `1..` from level 0, each level filtered from the previous one
+    pub fn generate_mips_async(
+        &self,
+        mips: &MipGenerator,
+        options: impl Into<MipOptions>,
+    ) -> Vec<Command<'static, 'static>> {
+        let options = options.into();
+        let kernel = mips.kernel(options, |device| {
+            Kernel::<fn(Tex2d<T>, Tex2d<T>)>::new(device, &|src: Tex2dVar<T>, dst: Tex2dVar<T>| {
+                downsample_2d(options, &src, &dst)
+            })
+        });
+        (1..self.handle.levels)
+            .map(|level| {
+                let dst = self.view(level);
+                let [w, h, _] = dst.size();
+                kernel.dispatch_async([w, h, 1], &self.view(level - 1), &dst)
+            })
+            .collect()
+    }
+    pub fn generate_mips(&self, mips: &MipGenerator, options: impl Into<MipOptions>) {
+        submit_default_stream_and_sync(
+            &self.handle.device,
+            self.generate_mips_async(mips, options),
+        );
+    }
+}
+
+impl<T: IoTexel<RwType = Float4>> Tex3d<T> {
+    /// Fills levels `1..` from level 0, each level filtered from the previous one
+    pub fn generate_mips_async(
+        &self,
+        mips: &MipGenerator,
+        options: impl Into<MipOptions>,
+    ) -> Vec<Command<'static, 'static>> {
+        let options = options.into();
+        let kernel = mips.kernel(options, |device| {
+            Kernel::<fn(Tex3d<T>, Tex3d<T>)>::new(device, &|src: Tex3dVar<T>, dst: Tex3dVar<T>| {
+                downsample_3d(options, &src, &dst)
+            })
+        });
+        (1..self.handle.levels)
+            .map(|level| {
+                let dst = self.view(level);
+                kernel.dispatch_async(dst.size(), &self.view(level - 1), &dst)
+            })
+            .collect()
+    }
+    pub fn generate_mips(&self, mips: &MipGenerator, options: impl Into<MipOptions>) {
+        submit_default_stream_and_sync(
+            &self.handle.device,
+            self.generate_mips_async(mips, options),
+        );
+    }
+}
@@ -0,0 +1,745 @@
+//! Sparse resources: [`SparseTex2d`], [`SparseTex3d`] and [`SparseBuffer`].
+//!
+//! A sparse resource is split into fixed-size tiles, and only tiles that are
+//! mapped occupy memory. The backend interface has no tiled resources, so all
+//! backends use a software page table: a `Buffer<u32>` maps every virtual tile
+//! to a slot of a dense pool, and the `read`/`write` helpers of the kernel-side
+//! vars do the indirection.
+//!
+//! Reads of unmapped tiles return zero and record the tile in a feedback
+//! buffer. The host collects these with `requested_tiles`, maps and uploads
+//! them, and the next dispatch sees the data, which is the usual streaming loop
+//! of virtual textures.
+//!
+//! The `*_async` map and unmap methods update the host side of the page table
+//! when they return, and only the device side waits for the command. Queries
+//! such as `is_mapped` and `copy_tile_from_async` rely on the new mapping right
+//! away, so the returned commands must be submitted, in order and before any
+//! upload to the tiles they map.
+use super::*;
+
+const UNMAPPED: u32 = u32::MAX;
+
+/// Virtual tile to pool slot mapping shared by all sparse resources
+struct PageTable {
+    device: Device,
+    tiles: u32,
+    capacity: u32,
+    entries: Buffer<u32>,
+    feedback: Buffer<u32>,
+    mapped: RefCell<HashMap<u32, u32>>,
+    free: RefCell<Vec<u32>>,
+    set: Kernel<fn(Buffer<u32>, u32, u32)>,
+}
+
+impl PageTable {
+    fn new(device: &Device, tiles: u32, capacity: u32) -> Self {
+        assert!(tiles > 0, "sparse resource must not be empty");
+        assert!(capacity > 0, "capacity must be greater than 0");
+        let entries = device.create_buffer_from_fn(tiles as usize, |_| UNMAPPED);
+        let feedback = device.create_buffer_from_fn(tiles as usize, |_| 0u32);
+        let set = Kernel::<fn(Buffer<u32>, u32, u32)>::new(
+            device,
+            &|entries: BufferVar<u32>, tile: Expr<u32>, slot: Expr<u32>| {
+                entries.write(tile, slot);
+            },
+        );
+        Self {
+            device: device.clone(),
+            tiles,
+            capacity,
+            entries,
+            feedback,
+            mapped: RefCell::new(HashMap::new()),
+            free: RefCell::new((0..capacity).rev().collect()),
+            set,
+        }
+    }
+    fn slot(&self, tile: u32) -> Option<u32> {
+        self.mapped.borrow().get(&tile).copied()
+    }
+    /// Assigns a free slot to `tile`, returning the slot and the command
+    /// updating the device side of the table
+    fn map_async(&self, tile: u32) -> (u32, Command<'static, 'static>) {
+        assert!(tile < self.tiles, "tile {} out of range", tile);
+        let slot = match self.slot(tile) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.free.borrow_mut().pop().unwrap_or_else(|| {
+                    panic!(
+                        "all {} physical tiles are mapped, unmap some before mapping more",
+                        self.capacity
+                    )
+                });
+                self.mapped.borrow_mut().insert(tile, slot);
+                slot
+            }
+        };
+        let command = self
+            .set
+            .dispatch_async([1, 1, 1], &self.entries, &tile, &slot);
+        (slot, command)
+    }
+    fn unmap_async(&self, tile: u32) -> Command<'static, 'static> {
+        assert!(tile < self.tiles, "tile {} out of range", tile);
+        if let Some(slot) = self.mapped.borrow_mut().remove(&tile) {
+            self.free.borrow_mut().push(slot);
+        }
+        self.set
+            .dispatch_async([1, 1, 1], &self.entries, &tile, &UNMAPPED)
+    }
+    /// Tiles read while unmapped since the last call, in ascending order
+    fn requested(&self) -> Vec<u32> {
+        let feedback = self.feedback.view(..).copy_to_vec();
+        self.feedback.view(..).fill(0);
+        feedback
+            .iter()
+            .enumerate()
+            .filter(|(tile, &requested)| requested != 0 && self.slot(*tile as u32).is_none())
+            .map(|(tile, _)| tile as u32)
+            .collect()
+    }
+    fn var(&self) -> PageTableVar {
+        PageTableVar {
+            entries: self.entries.var(),
+            feedback: self.feedback.var(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PageTableVar {
+    entries: BufferVar<u32>,
+    feedback: BufferVar<u32>,
+}
+
+impl PageTableVar {
+    /// Whether `tile` is mapped and its slot
+    fn resolve(&self, tile: Expr<u32>) -> (Expr<bool>, Expr<u32>) {
+        let slot = self.entries.read(tile);
+        (track!(slot != UNMAPPED), slot)
+    }
+    /// Like [`PageTableVar::resolve`], but records a request for unmapped tiles
+    fn lookup(&self, tile: Expr<u32>) -> (Expr<bool>, Expr<u32>) {
+        let (resident, slot) = self.resolve(tile);
+        track!({
+            if !resident {
+                self.feedback.write(tile, 1u32);
+            }
+        });
+        (resident, slot)
+    }
+}
+
+/// Splits `capacity` slots into a grid of `N` axes that is close to a cube
+fn pool_grid<const N: usize>(capacity: u32) -> [u32; N] {
+    let mut grid = [1; N];
+    let mut remaining = capacity;
+    for (axis, g) in grid.iter_mut().enumerate() {
+        let n = (remaining as f64).powf(1.0 / (N - axis) as f64).ceil() as u32;
+        *g = n.max(1);
+        remaining = remaining.div_ceil(*g);
+    }
+    grid
+}
+
+fn tile_counts<const N: usize>(size: [u32; N], tile_size: u32) -> [u32; N] {
+    assert!(size.iter().all(|&s| s > 0), "size must be greater than 0");
+    size.map(|s| s.div_ceil(tile_size))
+}
+
+/// A 2D texture of which only mapped tiles of
+/// [`SparseTex2d::TILE_SIZE`]² texels are backed by memory
+pub struct SparseTex2d<T: IoTexel> {
+    size: [u32; 2],
+    tiles: [u32; 2],
+    pool_tiles: [u32; 2],
+    pool: Tex2d<T>,
+    table: PageTable,
+    upload: Kernel<fn(Buffer<T>, Tex2d<T>, Uint2)>,
+}
+
+impl<T: IoTexel> SparseTex2d<T> {
+    pub const TILE_SIZE: u32 = 128;
+    pub(crate) fn new(
+        device: &Device,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        capacity: u32,
+    ) -> Self {
+        let t = Self::TILE_SIZE;
+        let size = [width, height];
+        let tiles = tile_counts(size, t);
+        let table = PageTable::new(device, tiles[0] * tiles[1], capacity);
+        let pool_tiles = pool_grid::<2>(capacity);
+        let pool = device.create_tex2d::<T>(storage, pool_tiles[0] * t, pool_tiles[1] * t, 1);
+        let upload = |src: BufferVar<T>, dst: Tex2dVar<T>, origin: Expr<Uint2>| {
+            let p = dispatch_id().xy();
+            dst.write(track!(origin + p), src.read(track!(p.x + p.y * t)));
+        };
+        let upload = Kernel::<fn(Buffer<T>, Tex2d<T>, Uint2)>::new(device, &upload);
+        Self {
+            size,
+            tiles,
+            pool_tiles,
+            pool,
+            table,
+            upload,
+        }
+    }
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+    /// Number of tiles along each axis
+    pub fn tiles(&self) -> [u32; 2] {
+        self.tiles
+    }
+    /// Number of tiles that can be mapped at the same time
+    pub fn capacity(&self) -> u32 {
+        self.table.capacity
+    }
+    fn tile_index(&self, tile: [u32; 2]) -> u32 {
+        assert!(
+            tile[0] < self.tiles[0] && tile[1] < self.tiles[1],
+            "tile {:?} out of range",
+            tile
+        );
+        tile[0] + tile[1] * self.tiles[0]
+    }
+    pub fn is_mapped(&self, tile: [u32; 2]) -> bool {
+        self.table.slot(self.tile_index(tile)).is_some()
+    }
+    /// Backs `tile` with memory, its contents are undefined until uploaded
+    ///
+    /// The host side of the mapping, which `is_mapped` and `copy_tile_from_async`
+    /// use, is updated right away, so the command must be submitted before any
+    /// upload to the tile.
+    pub fn map_tile_async(&self, tile: [u32; 2]) -> Command<'static, 'static> {
+        self.table.map_async(self.tile_index(tile)).1
+    }
+    /// Releases the memory of `tile`, which then reads as zero
+    ///
+    /// The slot is freed right away and may be handed out by the next map, so
+    /// the command must be submitted before the commands of that map.
+    pub fn unmap_tile_async(&self, tile: [u32; 2]) -> Command<'static, 'static> {
+        self.table.unmap_async(self.tile_index(tile))
+    }
+    pub fn map_tile(&self, tile: [u32; 2]) {
+        submit_default_stream_and_sync(&self.table.device, [self.map_tile_async(tile)]);
+    }
+    pub fn unmap_tile(&self, tile: [u32; 2]) {
+        submit_default_stream_and_sync(&self.table.device, [self.unmap_tile_async(tile)]);
+    }
+    /// Uploads the texels of a mapped tile, stored row by row
+    pub fn copy_tile_from_async<'a>(
+        &self,
+        tile: [u32; 2],
+        data: &'a [T],
+    ) -> Vec<Command<'a, 'static>> {
+        let t = Self::TILE_SIZE;
+        assert_eq!(data.len(), (t * t) as usize);
+        let slot = self
+            .table
+            .slot(self.tile_index(tile))
+            .unwrap_or_else(|| panic!("tile {:?} is not mapped", tile));
+        let origin = Uint2::new(slot % self.pool_tiles[0] * t, slot / self.pool_tiles[0] * t);
+        let staging = self.table.device.create_buffer::<T>(data.len());
+        vec![
+            staging.view(..).copy_from_async(data),
+            self.upload
+                .dispatch_async([t, t, 1], &staging, &self.pool, &origin),
+        ]
+    }
+    pub fn copy_tile_from(&self, tile: [u32; 2], data: &[T]) {
+        submit_default_stream_and_sync(&self.table.device, self.copy_tile_from_async(tile, data));
+    }
+    /// Unmapped tiles that kernels tried to read since the last call
+    pub fn requested_tiles(&self) -> Vec<[u32; 2]> {
+        self.table
+            .requested()
+            .into_iter()
+            .map(|i| [i % self.tiles[0], i / self.tiles[0]])
+            .collect()
+    }
+    pub fn var(&self) -> SparseTex2dVar<T> {
+        SparseTex2dVar {
+            pool: self.pool.var(),
+            table: self.table.var(),
+            size: self.size,
+            tiles: self.tiles,
+            pool_tiles: self.pool_tiles,
+        }
+    }
+}
+
+impl<T: IoTexel> fmt::Debug for SparseTex2d<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SparseTex2d")
+            .field("size", &self.size)
+            .field("tiles", &self.tiles)
+            .field("capacity", &self.table.capacity)
+            .field("mapped", &self.table.mapped.borrow().len())
+            .finish()
+    }
+}
+
+/// A 3D texture of which only mapped tiles of
+/// [`SparseTex3d::TILE_SIZE`]³ texels are backed by memory
+pub struct SparseTex3d<T: IoTexel> {
+    size: [u32; 3],
+    tiles: [u32; 3],
+    pool_tiles: [u32; 3],
+    pool: Tex3d<T>,
+    table: PageTable,
+    upload: Kernel<fn(Buffer<T>, Tex3d<T>, Uint3)>,
+}
+
+impl<T: IoTexel> SparseTex3d<T> {
+    pub const TILE_SIZE: u32 = 32;
+    pub(crate) fn new(
+        device: &Device,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        depth: u32,
+        capacity: u32,
+    ) -> Self {
+        let t = Self::TILE_SIZE;
+        let size = [width, height, depth];
+        let tiles = tile_counts(size, t);
+        let table = PageTable::new(device, tiles[0] * tiles[1] * tiles[2], capacity);
+        let pool_tiles = pool_grid::<3>(capacity);
+        let pool = device.create_tex3d::<T>(
+            storage,
+            pool_tiles[0] * t,
+            pool_tiles[1] * t,
+            pool_tiles[2] * t,
+            1,
+        );
+        let upload = |src: BufferVar<T>, dst: Tex3dVar<T>, origin: Expr<Uint3>| {
+            let p = dispatch_id();
+            let i = track!(p.x + (p.y + p.z * t) * t);
+            dst.write(track!(origin + p), src.read(i));
+        };
+        let upload = Kernel::<fn(Buffer<T>, Tex3d<T>, Uint3)>::new(device, &upload);
+        Self {
+            size,
+            tiles,
+            pool_tiles,
+            pool,
+            table,
+            upload,
+        }
+    }
+    pub fn size(&self) -> [u32; 3] {
+        self.size
+    }
+    /// Number of tiles along each axis
+    pub fn tiles(&self) -> [u32; 3] {
+        self.tiles
+    }
+    /// Number of tiles that can be mapped at the same time
+    pub fn capacity(&self) -> u32 {
+        self.table.capacity
+    }
+    fn tile_index(&self, tile: [u32; 3]) -> u32 {
+        assert!(
+            (0..3).all(|i| tile[i] < self.tiles[i]),
+            "tile {:?} out of range",
+            tile
+        );
+        tile[0] + (tile[1] + tile[2] * self.tiles[1]) * self.tiles[0]
+    }
+    pub fn is_mapped(&self, tile: [u32; 3]) -> bool {
+        self.table.slot(self.tile_index(tile)).is_some()
+    }
+    /// Backs `tile` with memory, its contents are undefined until uploaded
+    ///
+    /// The host side of the mapping, which `is_mapped` and `copy_tile_from_async`
+    /// use, is updated right away, so the command must be submitted before any
+    /// upload to the tile.
+    pub fn map_tile_async(&self, tile: [u32; 3]) -> Command<'static, 'static> {
+        self.table.map_async(self.tile_index(tile)).1
+    }
+    /// Releases the memory of `tile`, which then reads as zero
+    ///
+    /// The slot is freed right away and may be handed out by the next map, so
+    /// the command must be submitted before the commands of that map.
+    pub fn unmap_tile_async(&self, tile: [u32; 3]) -> Command<'static, 'static> {
+        self.table.unmap_async(self.tile_index(tile))
+    }
+    pub fn map_tile(&self, tile: [u32; 3]) {
+        submit_default_stream_and_sync(&self.table.device, [self.map_tile_async(tile)]);
+    }
+    pub fn unmap_tile(&self, tile: [u32; 3]) {
+        submit_default_stream_and_sync(&self.table.device, [self.unmap_tile_async(tile)]);
+    }
+    /// Uploads the texels of a mapped tile, stored as `x + TILE_SIZE * (y + TILE_SIZE * z)`
+    pub fn copy_tile_from_async<'a>(
+        &self,
+        tile: [u32; 3],
+        data: &'a [T],
+    ) -> Vec<Command<'a, 'static>> {
+        let t = Self::TILE_SIZE;
+        assert_eq!(data.len(), (t * t * t) as usize);
+        let slot = self
+            .table
+            .slot(self.tile_index(tile))
+            .unwrap_or_else(|| panic!("tile {:?} is not mapped", tile));
+        let [px, py, _] = self.pool_tiles;
+        let origin = Uint3::new(slot % px * t, slot / px % py * t, slot / (px * py) * t);
+        let staging = self.table.device.create_buffer::<T>(data.len());
+        vec![
+            staging.view(..).copy_from_async(data),
+            self.upload
+                .dispatch_async([t, t, t], &staging, &self.pool, &origin),
+        ]
+    }
+    pub fn copy_tile_from(&self, tile: [u32; 3], data: &[T]) {
+        submit_default_stream_and_sync(&self.table.device, self.copy_tile_from_async(tile, data));
+    }
+    /// Unmapped tiles that kernels tried to read since the last call
+    pub fn requested_tiles(&self) -> Vec<[u32; 3]> {
+        let [tx, ty, _] = self.tiles;
+        self.table
+            .requested()
+            .into_iter()
+            .map(|i| [i % tx, i / tx % ty, i / (tx * ty)])
+            .collect()
+    }
+    pub fn var(&self) -> SparseTex3dVar<T> {
+        SparseTex3dVar {
+            pool: self.pool.var(),
+            table: self.table.var(),
+            size: self.size,
+            tiles: self.tiles,
+            pool_tiles: self.pool_tiles,
+        }
+    }
+}
+
+impl<T: IoTexel> fmt::Debug for SparseTex3d<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SparseTex3d")
+            .field("size", &self.size)
+            .field("tiles", &self.tiles)
+            .field("capacity", &self.table.capacity)
+            .field("mapped", &self.table.mapped.borrow().len())
+            .finish()
+    }
+}
+
+/// A buffer of which only mapped tiles of [`SparseBuffer::TILE_BYTES`] are
+/// backed by memory
+pub struct SparseBuffer<T: Value> {
+    len: usize,
+    tile_len: usize,
+    pool: Buffer<T>,
+    table: PageTable,
+}
+
+impl<T: Value> SparseBuffer<T> {
+    pub const TILE_BYTES: usize = 65536;
+    pub(crate) fn new(device: &Device, len: usize, capacity: u32) -> Self {
+        let tile_len = (Self::TILE_BYTES / std::mem::size_of::<T>()).max(1);
+        assert!(len > 0, "sparse buffer must not be empty");
+        let tiles = len.div_ceil(tile_len);
+        assert!(tiles <= u32::MAX as usize, "too many tiles");
+        Self {
+            len,
+            tile_len,
+            pool: device.create_buffer(tile_len * capacity as usize),
+            table: PageTable::new(device, tiles as u32, capacity),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Number of elements per tile
+    pub fn tile_len(&self) -> usize {
+        self.tile_len
+    }
+    pub fn tiles(&self) -> u32 {
+        self.table.tiles
+    }
+    /// Number of tiles that can be mapped at the same time
+    pub fn capacity(&self) -> u32 {
+        self.table.capacity
+    }
+    pub fn is_mapped(&self, tile: u32) -> bool {
+        self.table.slot(tile).is_some()
+    }
+    /// Backs `tile` with memory, its contents are undefined until uploaded
+    ///
+    /// The host side of the mapping, which `is_mapped` and `copy_tile_from_async`
+    /// use, is updated right away, so the command must be submitted before any
+    /// upload to the tile.
+    pub fn map_tile_async(&self, tile: u32) -> Command<'static, 'static> {
+        self.table.map_async(tile).1
+    }
+    /// Releases the memory of `tile`, which then reads as zero
+    ///
+    /// The slot is freed right away and may be handed out by the next map, so
+    /// the command must be submitted before the commands of that map.
+    pub fn unmap_tile_async(&self, tile: u32) -> Command<'static, 'static> {
+        self.table.unmap_async(tile)
+    }
+    pub fn map_tile(&self, tile: u32) {
+        submit_default_stream_and_sync(&self.table.device, [self.map_tile_async(tile)]);
+    }
+    pub fn unmap_tile(&self, tile: u32) {
+        submit_default_stream_and_sync(&self.table.device, [self.unmap_tile_async(tile)]);
+    }
+    /// Physical elements of a mapped tile
+    pub fn tile_view(&self, tile: u32) -> BufferView<T> {
+        let slot = self
+            .table
+            .slot(tile)
+            .unwrap_or_else(|| panic!("tile {} is not mapped", tile)) as usize;
+        self.pool
+            .view(slot * self.tile_len..(slot + 1) * self.tile_len)
+    }
+    /// Uploads the elements of a mapped tile
+    pub fn copy_tile_from_async<'a>(&self, tile: u32, data: &'a [T]) -> Command<'a, 'static> {
+        self.tile_view(tile).copy_from_async(data)
+    }
+    pub fn copy_tile_from(&self, tile: u32, data: &[T]) {
+        self.tile_view(tile).copy_from(data);
+    }
+    /// Unmapped tiles that kernels tried to read since the last call
+    pub fn requested_tiles(&self) -> Vec<u32> {
+        self.table.requested()
+    }
+    pub fn var(&self) -> SparseBufferVar<T> {
+        assert!(
+            self.len <= u32::MAX as usize,
+            "kernels index sparse buffers with u32"
+        );
+        SparseBufferVar {
+            pool: self.pool.var(),
+            table: self.table.var(),
+            len: self.len as u32,
+            tile_len: self.tile_len as u32,
+        }
+    }
+}
+
+impl<T: Value> fmt::Debug for SparseBuffer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SparseBuffer")
+            .field("len", &self.len)
+            .field("tile_len", &self.tile_len)
+            .field("capacity", &self.table.capacity)
+            .field("mapped", &self.table.mapped.borrow().len())
+            .finish()
+    }
+}
+
+/// Kernel-side handle of a [`SparseTex2d`], captured with [`SparseTex2d::var`]
+#[derive(Clone)]
+pub struct SparseTex2dVar<T: IoTexel> {
+    pool: Tex2dVar<T>,
+    table: PageTableVar,
+    size: [u32; 2],
+    tiles: [u32; 2],
+    pool_tiles: [u32; 2],
+}
+
+impl<T: IoTexel> SparseTex2dVar<T> {
+    fn tile(&self, coord: Expr<Uint2>) -> Expr<u32> {
+        let t = SparseTex2d::<T>::TILE_SIZE;
+        let tx = self.tiles[0];
+        track!(coord.x / t + coord.y / t * tx)
+    }
+    /// Pool texel of `coord` inside `slot`
+    fn physical(&self, slot: Expr<u32>, coord: Expr<Uint2>) -> Expr<Uint2> {
+        let t = SparseTex2d::<T>::TILE_SIZE;
+        let px = self.pool_tiles[0];
+        track!(Uint2::expr(slot % px, slot / px) * t + coord % t)
+    }
+    pub fn size(&self) -> Expr<Uint2> {
+        Uint2::new(self.size[0], self.size[1]).expr()
+    }
+    /// Whether `coord` lies inside the texture, asserted if runtime checks are enabled
+    fn contains(&self, coord: Expr<Uint2>) -> Expr<bool> {
+        let inside = track!((coord < self.size()).all());
+        if need_runtime_check() {
+            lc_assert!(inside);
+        }
+        inside
+    }
+    /// Whether the tile containing `coord` is mapped, without requesting it
+    pub fn is_resident(&self, coord: impl AsExpr<Value = Uint2>) -> Expr<bool> {
+        let coord = coord.as_expr();
+        track!(self.contains(coord) && self.table.resolve(self.tile(coord)).0)
+    }
+    /// Reads the texel at `coord`, texels of unmapped tiles read as zero and
+    /// request their tile
+    ///
+    /// Texels outside the texture read as zero without requesting anything.
+    pub fn read(&self, coord: impl AsExpr<Value = Uint2>) -> Expr<T> {
+        let coord = coord.as_expr();
+        let value = T::var_zeroed();
+        track!({
+            if self.contains(coord) {
+                let (resident, slot) = self.table.lookup(self.tile(coord));
+                if resident {
+                    *value = self.pool.read(self.physical(slot, coord));
+                }
+            }
+        });
+        value.load()
+    }
+    /// Writes the texel at `coord`, writes to unmapped tiles or outside the
+    /// texture are dropped
+    pub fn write(&self, coord: impl AsExpr<Value = Uint2>, value: impl AsExpr<Value = T>) {
+        let (coord, value) = (coord.as_expr(), value.as_expr());
+        track!({
+            if self.contains(coord) {
+                let (resident, slot) = self.table.resolve(self.tile(coord));
+                if resident {
+                    self.pool.write(self.physical(slot, coord), value);
+                }
+            }
+        });
+    }
+}
+
+/// Kernel-side handle of a [`SparseTex3d`], captured with [`SparseTex3d::var`]
+#[derive(Clone)]
+pub struct SparseTex3dVar<T: IoTexel> {
+    pool: Tex3dVar<T>,
+    table: PageTableVar,
+    size: [u32; 3],
+    tiles: [u32; 3],
+    pool_tiles: [u32; 3],
+}
+
+impl<T: IoTexel> SparseTex3dVar<T> {
+    fn tile(&self, coord: Expr<Uint3>) -> Expr<u32> {
+        let t = SparseTex3d::<T>::TILE_SIZE;
+        let [tx, ty, _] = self.tiles;
+        let c = track!(coord / t);
+        track!(c.x + (c.y + c.z * ty) * tx)
+    }
+    /// Pool texel of `coord` inside `slot`
+    fn physical(&self, slot: Expr<u32>, coord: Expr<Uint3>) -> Expr<Uint3> {
+        let t = SparseTex3d::<T>::TILE_SIZE;
+        let [px, py, _] = self.pool_tiles;
+        track!(Uint3::expr(slot % px, slot / px % py, slot / (px * py)) * t + coord % t)
+    }
+    pub fn size(&self) -> Expr<Uint3> {
+        Uint3::new(self.size[0], self.size[1], self.size[2]).expr()
+    }
+    /// Whether `coord` lies inside the texture, asserted if runtime checks are enabled
+    fn contains(&self, coord: Expr<Uint3>) -> Expr<bool> {
+        let inside = track!((coord < self.size()).all());
+        if need_runtime_check() {
+            lc_assert!(inside);
+        }
+        inside
+    }
+    /// Whether the tile containing `coord` is mapped, without requesting it
+    pub fn is_resident(&self, coord: impl AsExpr<Value = Uint3>) -> Expr<bool> {
+        let coord = coord.as_expr();
+        track!(self.contains(coord) && self.table.resolve(self.tile(coord)).0)
+    }
+    /// Reads the texel at `coord` through the page table, texels of unmapped
+    /// tiles read as zero and request their tile
+    ///
+    /// Texels outside the texture read as zero without requesting anything.
+    pub fn read(&self, coord: impl AsExpr<Value = Uint3>) -> Expr<T> {
+        let coord = coord.as_expr();
+        let value = T::var_zeroed();
+        track!({
+            if self.contains(coord) {
+                let (resident, slot) = self.table.lookup(self.tile(coord));
+                if resident {
+                    *value = self.pool.read(self.physical(slot, coord));
+                }
+            }
+        });
+        value.load()
+    }
+    /// Writes the texel at `coord`, writes to unmapped tiles or outside the
+    /// texture are dropped
+    pub fn write(&self, coord: impl AsExpr<Value = Uint3>, value: impl AsExpr<Value = T>) {
+        let (coord, value) = (coord.as_expr(), value.as_expr());
+        track!({
+            if self.contains(coord) {
+                let (resident, slot) = self.table.resolve(self.tile(coord));
+                if resident {
+                    self.pool.write(self.physical(slot, coord), value);
+                }
+            }
+        });
+    }
+}
+
+/// Kernel-side handle of a [`SparseBuffer`], captured with [`SparseBuffer::var`]
+#[derive(Clone)]
+pub struct SparseBufferVar<T: Value> {
+    pool: BufferVar<T>,
+    table: PageTableVar,
+    len: u32,
+    tile_len: u32,
+}
+
+impl<T: Value> SparseBufferVar<T> {
+    fn physical(&self, slot: Expr<u32>, i: Expr<u32>) -> Expr<u32> {
+        let tile_len = self.tile_len;
+        track!(slot * tile_len + i % tile_len)
+    }
+    pub fn len_expr(&self) -> Expr<u32> {
+        self.len.expr()
+    }
+    /// Whether `i` lies inside the buffer, asserted if runtime checks are enabled
+    fn contains(&self, i: Expr<u32>) -> Expr<bool> {
+        let inside = track!(i < self.len_expr());
+        if need_runtime_check() {
+            lc_assert!(inside);
+        }
+        inside
+    }
+    /// Whether the tile containing element `i` is mapped, without requesting it
+    pub fn is_resident(&self, i: impl AsExpr<Value = u32>) -> Expr<bool> {
+        let i = i.as_expr();
+        let tile_len = self.tile_len;
+        track!(self.contains(i) && self.table.resolve(i / tile_len).0)
+    }
+    /// Reads element `i`, elements of unmapped tiles read as zero and request
+    /// their tile
+    ///
+    /// Elements past the end read as zero without requesting anything.
+    pub fn read(&self, i: impl AsExpr<Value = u32>) -> Expr<T> {
+        let i = i.as_expr();
+        let tile_len = self.tile_len;
+        let value = T::var_zeroed();
+        track!({
+            if self.contains(i) {
+                let (resident, slot) = self.table.lookup(i / tile_len);
+                if resident {
+                    *value = self.pool.read(self.physical(slot, i));
+                }
+            }
+        });
+        value.load()
+    }
+    /// Writes element `i`, writes to unmapped tiles or past the end are dropped
+    pub fn write(&self, i: impl AsExpr<Value = u32>, value: impl AsExpr<Value = T>) {
+        let (i, value) = (i.as_expr(), value.as_expr());
+        let tile_len = self.tile_len;
+        track!({
+            if self.contains(i) {
+                let (resident, slot) = self.table.resolve(i / tile_len);
+                if resident {
+                    self.pool.write(self.physical(slot, i), value);
+                }
+            }
+        });
+    }
+}
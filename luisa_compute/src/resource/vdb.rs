@@ -0,0 +1,732 @@
+//! Sparse volumes in the NanoVDB format: [`VdbGrid`].
+//!
+//! The grid buffer is uploaded unchanged into a [`ByteBuffer`] and traversed on
+//! the device with [`ByteBufferVar::read_as`], so grids written by OpenVDB or
+//! NanoVDB tools can be used directly. [`nanovdb_from_voxels`] builds such a
+//! buffer from a list of voxels.
+//!
+//! The tree has the fixed NanoVDB configuration: a root table of 4096³ tiles,
+//! upper nodes of 32³ children, lower nodes of 16³ children and leaves of 8³
+//! voxels. Only 32-bit scalar grids are supported, and grid buffers must be
+//! smaller than 4 GiB since offsets are read as `u32`.
+use std::collections::BTreeMap;
+
+use super::*;
+
+/// NanoVDB `"NanoVDB0"`
+const MAGIC: u64 = 0x304244566f6e614e;
+/// NanoVDB `"NanoVDB1"`, written by newer versions for grids
+const MAGIC_GRID: u64 = 0x314244566f6e614e;
+const MAJOR_VERSION: u32 = 32;
+
+const GRID_SIZE: u32 = 672;
+const TREE_SIZE: u32 = 64;
+const ROOT_SIZE: u32 = 64;
+const ROOT_TILE_SIZE: u32 = 32;
+/// Offset of the child table in upper nodes
+const UPPER_TABLE: u32 = 8256;
+const UPPER_SIZE: u32 = UPPER_TABLE + 8 * 32768;
+/// Offset of the child table in lower nodes
+const LOWER_TABLE: u32 = 1088;
+const LOWER_SIZE: u32 = LOWER_TABLE + 8 * 4096;
+/// Offset of the voxel values in leaves
+const LEAF_VALUES: u32 = 96;
+const LEAF_SIZE: u32 = LEAF_VALUES + 4 * 512;
+
+/// Offsets into the grid header
+mod header {
+    pub const VERSION: usize = 16;
+    pub const GRID_SIZE: usize = 32;
+    pub const NAME: usize = 40;
+    pub const MAP: usize = 296;
+    pub const INV_MAT: u32 = 332;
+    pub const VEC: u32 = 368;
+    pub const WORLD_BBOX: usize = 560;
+    pub const VOXEL_SIZE: usize = 608;
+    pub const GRID_TYPE: usize = 636;
+    pub const DATA1: usize = 656;
+}
+
+/// Values that can be stored in a [`VdbGrid`]
+pub trait VdbValue: Value + PartialOrd {
+    /// `GridType` tag in the NanoVDB header
+    const GRID_TYPE: u32;
+    fn to_bits(self) -> u32;
+    fn to_f64(self) -> f64;
+}
+
+impl VdbValue for f32 {
+    const GRID_TYPE: u32 = 1;
+    fn to_bits(self) -> u32 {
+        f32::to_bits(self)
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl VdbValue for i32 {
+    const GRID_TYPE: u32 = 4;
+    fn to_bits(self) -> u32 {
+        self as u32
+    }
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+/// Index to world transform of a grid: a uniform scale followed by a translation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VdbTransform {
+    pub voxel_size: f64,
+    /// World position of voxel `(0, 0, 0)`
+    pub origin: [f64; 3],
+}
+
+impl Default for VdbTransform {
+    fn default() -> Self {
+        Self {
+            voxel_size: 1.0,
+            origin: [0.0; 3],
+        }
+    }
+}
+
+/// Header fields of a loaded grid
+#[derive(Clone, Debug, PartialEq)]
+pub struct VdbGridInfo {
+    pub name: String,
+    pub voxel_size: [f64; 3],
+    /// Bounds of the active voxels in world space
+    pub world_bbox: [[f64; 3]; 2],
+    pub active_voxels: u64,
+    /// Number of leaves, lower and upper internal nodes
+    pub node_count: [u32; 3],
+}
+
+/// A NanoVDB grid on the device
+pub struct VdbGrid<T: VdbValue> {
+    pub(crate) data: ByteBuffer,
+    info: VdbGridInfo,
+    _marker: PhantomData<T>,
+}
+
+impl<T: VdbValue> VdbGrid<T> {
+    /// Uploads the first grid of a NanoVDB buffer, which must hold values of type `T`
+    pub(crate) fn from_nanovdb(device: &Device, bytes: &[u8]) -> Result<Self, String> {
+        let info = parse_header::<T>(bytes)?;
+        let size = read_u64(bytes, header::GRID_SIZE) as usize;
+        Ok(Self {
+            data: device.create_buffer_from_slice(&bytes[..size]),
+            info,
+            _marker: PhantomData,
+        })
+    }
+    pub fn info(&self) -> &VdbGridInfo {
+        &self.info
+    }
+    /// The raw NanoVDB buffer
+    pub fn data(&self) -> &ByteBuffer {
+        &self.data
+    }
+    #[inline]
+    pub fn var(&self) -> VdbGridVar<T> {
+        VdbGridVar::new(self.data.var())
+    }
+}
+
+impl<T: VdbValue> fmt::Debug for VdbGrid<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VdbGrid")
+            .field("info", &self.info)
+            .field("size_bytes", &self.data.len())
+            .finish()
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_f64(bytes: &[u8], offset: usize) -> f64 {
+    f64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn parse_header<T: VdbValue>(bytes: &[u8]) -> Result<VdbGridInfo, String> {
+    if bytes.len() < (GRID_SIZE + TREE_SIZE) as usize {
+        return Err(format!("{} bytes are too short for a grid", bytes.len()));
+    }
+    let magic = read_u64(bytes, 0);
+    if magic != MAGIC && magic != MAGIC_GRID {
+        return Err(format!("invalid magic number {:#x}", magic));
+    }
+    let major = read_u32(bytes, header::VERSION) >> 21;
+    if major != MAJOR_VERSION {
+        return Err(format!(
+            "unsupported major version {}, expected {}",
+            major, MAJOR_VERSION
+        ));
+    }
+    let grid_type = read_u32(bytes, header::GRID_TYPE);
+    if grid_type != T::GRID_TYPE {
+        return Err(format!(
+            "grid type {} does not match the requested type {}",
+            grid_type,
+            T::GRID_TYPE
+        ));
+    }
+    let size = read_u64(bytes, header::GRID_SIZE);
+    if size > bytes.len() as u64 {
+        return Err(format!(
+            "grid of {} bytes is truncated to {}",
+            size,
+            bytes.len()
+        ));
+    }
+    if size > u32::MAX as u64 {
+        return Err(format!("grids of {} bytes are not supported", size));
+    }
+    let name = &bytes[header::NAME..header::MAP];
+    let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+    let vec3 = |offset: usize| [0, 1, 2].map(|i| read_f64(bytes, offset + 8 * i));
+    let tree = GRID_SIZE as usize;
+    Ok(VdbGridInfo {
+        name: String::from_utf8_lossy(name).into_owned(),
+        voxel_size: vec3(header::VOXEL_SIZE),
+        world_bbox: [vec3(header::WORLD_BBOX), vec3(header::WORLD_BBOX + 24)],
+        active_voxels: read_u64(bytes, tree + 56),
+        node_count: [0, 1, 2].map(|i| read_u32(bytes, tree + 32 + 4 * i)),
+    })
+}
+
+/// Key of the root tile containing `ijk`
+fn root_key(ijk: [i32; 3]) -> u64 {
+    let [x, y, z] = ijk.map(|c| (c as u32 >> 12) as u64);
+    z | (y << 21) | (x << 42)
+}
+
+/// Index of the child containing `ijk` in a node with `2^log2_dim` children
+/// along each axis, each covering `2^log2_child` voxels
+fn child_index(ijk: [i32; 3], log2_dim: u32, log2_child: u32) -> u32 {
+    let mask = (1u32 << (log2_dim + log2_child)) - 1;
+    let [x, y, z] = ijk.map(|c| (c as u32 & mask) >> log2_child);
+    (x << (2 * log2_dim)) | (y << log2_dim) | z
+}
+
+/// Minimum, maximum, mean and standard deviation of active values
+#[derive(Clone, Copy)]
+struct Stats<T> {
+    min: T,
+    max: T,
+    sum: f64,
+    sum_sq: f64,
+    count: u64,
+    bbox: [[i32; 3]; 2],
+}
+
+impl<T: VdbValue> Stats<T> {
+    fn new(background: T) -> Self {
+        Self {
+            min: background,
+            max: background,
+            sum: 0.0,
+            sum_sq: 0.0,
+            count: 0,
+            bbox: [[i32::MAX; 3], [i32::MIN; 3]],
+        }
+    }
+    fn add(&mut self, ijk: [i32; 3], value: T) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max {
+            self.max = value;
+        }
+        let v = value.to_f64();
+        self.sum += v;
+        self.sum_sq += v * v;
+        self.count += 1;
+        self.include([ijk, ijk]);
+    }
+    fn include(&mut self, bbox: [[i32; 3]; 2]) {
+        let [min, max] = &mut self.bbox;
+        *min = std::array::from_fn(|i| min[i].min(bbox[0][i]));
+        *max = std::array::from_fn(|i| max[i].max(bbox[1][i]));
+    }
+    fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.min < self.min {
+            self.min = other.min;
+        }
+        if self.count == 0 || other.max > self.max {
+            self.max = other.max;
+        }
+        self.sum += other.sum;
+        self.sum_sq += other.sum_sq;
+        self.count += other.count;
+        self.include(other.bbox);
+    }
+    fn mean(&self) -> f32 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.sum / self.count as f64) as f32
+        }
+    }
+    fn std_dev(&self) -> f32 {
+        if self.count == 0 {
+            0.0
+        } else {
+            let mean = self.sum / self.count as f64;
+            (self.sum_sq / self.count as f64 - mean * mean)
+                .max(0.0)
+                .sqrt() as f32
+        }
+    }
+    /// Writes minimum, maximum, mean and standard deviation
+    fn write(&self, out: &mut Writer, offset: usize) {
+        out.u32(offset, self.min.to_bits());
+        out.u32(offset + 4, self.max.to_bits());
+        out.f32(offset + 8, self.mean());
+        out.f32(offset + 12, self.std_dev());
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn bytes(&mut self, offset: usize, data: &[u8]) {
+        self.0[offset..offset + data.len()].copy_from_slice(data);
+    }
+    fn u32(&mut self, offset: usize, v: u32) {
+        self.bytes(offset, &v.to_le_bytes());
+    }
+    fn u64(&mut self, offset: usize, v: u64) {
+        self.bytes(offset, &v.to_le_bytes());
+    }
+    fn f32(&mut self, offset: usize, v: f32) {
+        self.bytes(offset, &v.to_le_bytes());
+    }
+    fn f64(&mut self, offset: usize, v: f64) {
+        self.bytes(offset, &v.to_le_bytes());
+    }
+    fn coord(&mut self, offset: usize, ijk: [i32; 3]) {
+        for (i, c) in ijk.into_iter().enumerate() {
+            self.u32(offset + 4 * i, c as u32);
+        }
+    }
+    fn set_bit(&mut self, mask: usize, n: u32) {
+        let word = mask + 4 * (n / 32) as usize;
+        let bits = read_u32(&self.0, word) | (1 << (n % 32));
+        self.u32(word, bits);
+    }
+}
+
+struct Leaf<T> {
+    values: [T; 512],
+    active: [bool; 512],
+}
+
+/// Builds a NanoVDB buffer holding a single grid with the given active voxels,
+/// all other voxels have the `background` value
+///
+/// Later duplicates of a voxel overwrite earlier ones. The result can be saved
+/// as a `.nvdb` file or uploaded with [`Device::create_vdb_grid_from_nanovdb`].
+pub fn nanovdb_from_voxels<T: VdbValue>(
+    name: &str,
+    voxels: &[([i32; 3], T)],
+    background: T,
+    transform: VdbTransform,
+) -> Vec<u8> {
+    type Lowers<T> = BTreeMap<u32, BTreeMap<u32, Leaf<T>>>;
+    let mut tree = BTreeMap::<u64, ([i32; 3], Lowers<T>)>::new();
+    for &(ijk, value) in voxels {
+        let (_, uppers) = tree
+            .entry(root_key(ijk))
+            .or_insert_with(|| (ijk.map(|c| c & !4095), BTreeMap::new()));
+        let leaf = uppers
+            .entry(child_index(ijk, 5, 7))
+            .or_default()
+            .entry(child_index(ijk, 4, 3))
+            .or_insert_with(|| Leaf {
+                values: [background; 512],
+                active: [false; 512],
+            });
+        let l = child_index(ijk, 3, 0) as usize;
+        leaf.values[l] = value;
+        leaf.active[l] = true;
+    }
+    let uppers = tree.len() as u32;
+    let lowers = tree.values().map(|(_, u)| u.len() as u32).sum::<u32>();
+    let leaves = tree
+        .values()
+        .flat_map(|(_, u)| u.values())
+        .map(|l| l.len() as u32)
+        .sum::<u32>();
+    let root = GRID_SIZE + TREE_SIZE;
+    let upper_base = root + ROOT_SIZE + ROOT_TILE_SIZE * uppers;
+    let lower_base = upper_base + UPPER_SIZE * uppers;
+    let leaf_base = lower_base + LOWER_SIZE * lowers;
+    let size = leaf_base + LEAF_SIZE * leaves;
+    let mut out = Writer(vec![0; size as usize]);
+
+    let mut root_stats = Stats::new(background);
+    let (mut upper, mut lower, mut leaf) = (upper_base, lower_base, leaf_base);
+    for (tile, (key, (origin, children))) in tree.iter().enumerate() {
+        let tile = (root + ROOT_SIZE + ROOT_TILE_SIZE * tile as u32) as usize;
+        out.u64(tile, *key);
+        out.u64(tile + 8, (upper - root) as u64);
+        out.u32(tile + 20, background.to_bits());
+        let mut upper_stats = Stats::new(background);
+        for (&n, grandchildren) in children {
+            let n_origin = (n >> 10, (n >> 5) & 31, n & 31);
+            let lower_origin = [
+                origin[0] + (n_origin.0 << 7) as i32,
+                origin[1] + (n_origin.1 << 7) as i32,
+                origin[2] + (n_origin.2 << 7) as i32,
+            ];
+            out.u64(
+                (upper + UPPER_TABLE + 8 * n) as usize,
+                (lower - upper) as u64,
+            );
+            out.set_bit((upper + 32 + 4096) as usize, n);
+            let mut lower_stats = Stats::new(background);
+            for (&m, values) in grandchildren {
+                let leaf_origin = [
+                    lower_origin[0] + ((m >> 8) << 3) as i32,
+                    lower_origin[1] + (((m >> 4) & 15) << 3) as i32,
+                    lower_origin[2] + ((m & 15) << 3) as i32,
+                ];
+                out.u64(
+                    (lower + LOWER_TABLE + 8 * m) as usize,
+                    (leaf - lower) as u64,
+                );
+                out.set_bit((lower + 32 + 512) as usize, m);
+                let mut leaf_stats = Stats::new(background);
+                for l in 0..512u32 {
+                    let value = values.values[l as usize];
+                    out.u32((leaf + LEAF_VALUES + 4 * l) as usize, value.to_bits());
+                    if values.active[l as usize] {
+                        let ijk = [
+                            leaf_origin[0] + (l >> 6) as i32,
+                            leaf_origin[1] + ((l >> 3) & 7) as i32,
+                            leaf_origin[2] + (l & 7) as i32,
+                        ];
+                        leaf_stats.add(ijk, value);
+                        out.set_bit(leaf as usize + 16, l);
+                    }
+                }
+                let [min, max] = leaf_stats.bbox;
+                out.coord(leaf as usize, min);
+                let dif = std::array::from_fn::<u8, 3, _>(|i| (max[i] - min[i]) as u8);
+                out.bytes(leaf as usize + 12, &dif);
+                // bounding box is up to date
+                out.0[leaf as usize + 15] = 2;
+                leaf_stats.write(&mut out, leaf as usize + 80);
+                lower_stats.merge(&leaf_stats);
+                leaf += LEAF_SIZE;
+            }
+            out.coord(lower as usize, lower_stats.bbox[0]);
+            out.coord(lower as usize + 12, lower_stats.bbox[1]);
+            lower_stats.write(&mut out, (lower + 32 + 1024) as usize);
+            for m in 0..4096 {
+                if !grandchildren.contains_key(&m) {
+                    out.u32((lower + LOWER_TABLE + 8 * m) as usize, background.to_bits());
+                }
+            }
+            upper_stats.merge(&lower_stats);
+            lower += LOWER_SIZE;
+        }
+        out.coord(upper as usize, upper_stats.bbox[0]);
+        out.coord(upper as usize + 12, upper_stats.bbox[1]);
+        upper_stats.write(&mut out, (upper + 32 + 8192) as usize);
+        for n in 0..32768 {
+            if !children.contains_key(&n) {
+                out.u32((upper + UPPER_TABLE + 8 * n) as usize, background.to_bits());
+            }
+        }
+        root_stats.merge(&upper_stats);
+        upper += UPPER_SIZE;
+    }
+
+    let root = root as usize;
+    out.coord(root, root_stats.bbox[0]);
+    out.coord(root + 12, root_stats.bbox[1]);
+    out.u32(root + 24, uppers);
+    out.u32(root + 28, background.to_bits());
+    root_stats.write(&mut out, root + 32);
+
+    let tree = GRID_SIZE as usize;
+    let node_offset = |base: u32, count: u32| {
+        if count == 0 {
+            0
+        } else {
+            (base - GRID_SIZE) as u64
+        }
+    };
+    out.u64(tree, node_offset(leaf_base, leaves));
+    out.u64(tree + 8, node_offset(lower_base, lowers));
+    out.u64(tree + 16, node_offset(upper_base, uppers));
+    out.u64(tree + 24, TREE_SIZE as u64);
+    out.u32(tree + 32, leaves);
+    out.u32(tree + 36, lowers);
+    out.u32(tree + 40, uppers);
+    out.u64(tree + 56, root_stats.count);
+
+    out.u64(0, MAGIC);
+    out.u32(header::VERSION, (MAJOR_VERSION << 21) | (6 << 10));
+    // bbox, min/max, average, standard deviation, breadth first
+    out.u32(20, 2 | 4 | 8 | 16 | 32);
+    out.u32(28, 1);
+    out.u64(header::GRID_SIZE, size as u64);
+    let name = &name.as_bytes()[..name.len().min(255)];
+    out.bytes(header::NAME, name);
+    let (s, o) = (transform.voxel_size, transform.origin);
+    let map = header::MAP;
+    for (i, &o) in o.iter().enumerate() {
+        out.f32(map + 16 * i, s as f32);
+        out.f32(map + 36 + 16 * i, (1.0 / s) as f32);
+        out.f32(map + 72 + 4 * i, o as f32);
+        out.f64(map + 88 + 32 * i, s);
+        out.f64(map + 160 + 32 * i, 1.0 / s);
+        out.f64(map + 232 + 8 * i, o);
+        out.f64(header::VOXEL_SIZE + 8 * i, s);
+    }
+    out.f32(map + 84, 1.0);
+    out.f64(map + 256, 1.0);
+    if root_stats.count > 0 {
+        let [min, max] = root_stats.bbox;
+        for (i, &o) in o.iter().enumerate() {
+            out.f64(header::WORLD_BBOX + 8 * i, o + min[i] as f64 * s);
+            out.f64(header::WORLD_BBOX + 24 + 8 * i, o + (max[i] + 1) as f64 * s);
+        }
+    }
+    out.u32(header::GRID_TYPE, T::GRID_TYPE);
+    out.u64(header::DATA1, root_stats.count);
+    out.0
+}
+
+/// Result of a tree lookup
+#[derive(Clone, Copy)]
+pub struct VdbProbe<T: VdbValue> {
+    pub value: Expr<T>,
+    pub active: Expr<bool>,
+    /// Edge length in voxels of the node or tile holding the value, 1 inside leaves
+    pub dim: Expr<u32>,
+}
+
+/// A span of a ray through an active voxel or tile, see [`VdbGridVar::march`]
+#[derive(Clone, Copy)]
+pub struct VdbSegment<T: VdbValue> {
+    /// First voxel of the tile or node, or the voxel itself inside leaves
+    pub ijk: Expr<Int3>,
+    pub t0: Expr<f32>,
+    pub t1: Expr<f32>,
+    pub value: Expr<T>,
+    pub dim: Expr<u32>,
+}
+
+/// Kernel-side handle of a [`VdbGrid`]
+#[derive(Clone)]
+pub struct VdbGridVar<T: VdbValue> {
+    data: ByteBufferVar,
+    _marker: PhantomData<T>,
+}
+
+impl<T: VdbValue> VdbGridVar<T> {
+    pub(crate) fn new(data: ByteBufferVar) -> Self {
+        Self {
+            data,
+            _marker: PhantomData,
+        }
+    }
+    fn root(&self) -> Expr<u32> {
+        track!(GRID_SIZE + self.data.read_as::<u32>(GRID_SIZE + 24))
+    }
+    pub fn background(&self) -> Expr<T> {
+        self.data.read_as::<T>(track!(self.root() + 28))
+    }
+    fn bit(&self, mask: Expr<u32>, n: Expr<u32>) -> Expr<bool> {
+        let word = self.data.read_as::<u32>(track!(mask + (n >> 5u32) * 4));
+        track!(((word >> (n & 31u32)) & 1u32) != 0u32)
+    }
+    fn map(&self, offset: u32, v: Expr<Float3>) -> Expr<Float3> {
+        let m = |i: u32| self.data.read_as::<f32>(offset + 4 * i);
+        track!(Float3::expr(
+            m(0) * v.x + m(1) * v.y + m(2) * v.z,
+            m(3) * v.x + m(4) * v.y + m(5) * v.z,
+            m(6) * v.x + m(7) * v.y + m(8) * v.z,
+        ))
+    }
+    fn translation(&self) -> Expr<Float3> {
+        let v = |i: u32| self.data.read_as::<f32>(header::VEC + 4 * i);
+        Float3::expr(v(0), v(1), v(2))
+    }
+    /// Index space position of `pos`, voxel `ijk` is centered at `ijk`
+    pub fn world_to_index(&self, pos: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        let pos = pos.as_expr();
+        self.map(header::INV_MAT, track!(pos - self.translation()))
+    }
+    /// Index space direction of the world space direction `dir`
+    pub fn world_to_index_dir(&self, dir: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        self.map(header::INV_MAT, dir.as_expr())
+    }
+    pub fn index_to_world(&self, pos: impl AsExpr<Value = Float3>) -> Expr<Float3> {
+        let pos = self.map(header::MAP as u32, pos.as_expr());
+        track!(pos + self.translation())
+    }
+    /// Looks up `ijk`, descending as far as the tree goes
+    #[tracked]
+    pub fn probe(&self, ijk: impl AsExpr<Value = Int3>) -> VdbProbe<T> {
+        let ijk = ijk.as_expr();
+        let c = ijk.as_uint3();
+        let data = &self.data;
+        let root = self.root();
+        let value = self.background().var();
+        let active = false.var();
+        let dim = 4096u32.var();
+        // root keys are compared as two 32-bit halves
+        let (kx, ky, kz) = (c.x >> 12u32, c.y >> 12u32, c.z >> 12u32);
+        let key_lo = kz | ((ky & 0x7ffu32) << 21u32);
+        let key_hi = (ky >> 11u32) | (kx << 10u32);
+        let upper = 0u32.var();
+        let i = 0u32.var();
+        let table_size = data.read_as::<u32>(root + 24);
+        while i < table_size {
+            let tile = root + ROOT_SIZE + i * ROOT_TILE_SIZE;
+            if data.read_as::<u32>(tile) == key_lo && data.read_as::<u32>(tile + 4) == key_hi {
+                let child = data.read_as::<u32>(tile + 8);
+                if child != 0u32 {
+                    *upper = root + child;
+                } else {
+                    *value = data.read_as::<T>(tile + 20);
+                    *active = data.read_as::<u32>(tile + 16) != 0u32;
+                }
+                *i = table_size;
+            }
+            *i += 1;
+        }
+        if upper != 0u32 {
+            let n = (((c.x & 4095u32) >> 7u32) << 10u32)
+                | (((c.y & 4095u32) >> 7u32) << 5u32)
+                | ((c.z & 4095u32) >> 7u32);
+            let n_tile = upper + UPPER_TABLE + n * 8;
+            if self.bit(upper + 32 + 4096, n) {
+                let lower = upper + data.read_as::<u32>(n_tile);
+                let m = (((c.x & 127u32) >> 3u32) << 8u32)
+                    | (((c.y & 127u32) >> 3u32) << 4u32)
+                    | ((c.z & 127u32) >> 3u32);
+                let m_tile = lower + LOWER_TABLE + m * 8;
+                if self.bit(lower + 32 + 512, m) {
+                    let leaf = lower + data.read_as::<u32>(m_tile);
+                    let l = ((c.x & 7u32) << 6u32) | ((c.y & 7u32) << 3u32) | (c.z & 7u32);
+                    *value = data.read_as::<T>(leaf + LEAF_VALUES + l * 4);
+                    *active = self.bit(leaf + 16, l);
+                    *dim = 1u32;
+                } else {
+                    *value = data.read_as::<T>(m_tile);
+                    *active = self.bit(lower + 32, m);
+                    *dim = 8u32;
+                }
+            } else {
+                *value = data.read_as::<T>(n_tile);
+                *active = self.bit(upper + 32, n);
+                *dim = 128u32;
+            }
+        }
+        VdbProbe {
+            value: value.load(),
+            active: active.load(),
+            dim: dim.load(),
+        }
+    }
+    /// Value of voxel `ijk`
+    pub fn read(&self, ijk: impl AsExpr<Value = Int3>) -> Expr<T> {
+        self.probe(ijk).value
+    }
+    pub fn is_active(&self, ijk: impl AsExpr<Value = Int3>) -> Expr<bool> {
+        self.probe(ijk).active
+    }
+    /// Hierarchical DDA along the ray `origin + t * dir` for `t` in `[t_min, t_max]`
+    ///
+    /// Calls `f` for every span through an active voxel or tile, in order along
+    /// the ray, and stops once `f` returns `false`. Inactive tiles and empty
+    /// nodes are skipped in a single step. Unlike [`VdbGridVar::read`], voxel
+    /// `ijk` covers the index space cell `[ijk, ijk + 1)` here, as in NanoVDB.
+    #[tracked]
+    pub fn march(
+        &self,
+        origin: impl AsExpr<Value = Float3>,
+        dir: impl AsExpr<Value = Float3>,
+        t_min: impl AsExpr<Value = f32>,
+        t_max: impl AsExpr<Value = f32>,
+        f: impl Fn(&VdbSegment<T>) -> Expr<bool>,
+    ) {
+        let o = self.world_to_index(origin);
+        let d = self.world_to_index_dir(dir);
+        let t_max = t_max.as_expr();
+        let len = d.length();
+        // probe slightly past the current position to leave the previous cell
+        let eps = 1e-3f32 / len;
+        let t = t_min.as_expr().var();
+        let alive = (len > 0.0f32).var();
+        while alive.load() && t < t_max {
+            let ijk = (o + d * (t + eps)).floor().as_int3();
+            let probe = self.probe(ijk);
+            let size = probe.dim.as_i32();
+            let lo = Int3::expr(ijk.x & -size, ijk.y & -size, ijk.z & -size);
+            let lo_f = lo.as_float3();
+            let hi_f = lo_f + size.as_f32();
+            let exit = |lo: Expr<f32>, hi: Expr<f32>, o: Expr<f32>, d: Expr<f32>| {
+                select(
+                    d > 0.0f32,
+                    (hi - o) / d,
+                    select(d < 0.0f32, (lo - o) / d, f32::MAX.expr()),
+                )
+            };
+            let t1 = exit(lo_f.x, hi_f.x, o.x, d.x)
+                .min_(exit(lo_f.y, hi_f.y, o.y, d.y))
+                .min_(exit(lo_f.z, hi_f.z, o.z, d.z))
+                .min_(t_max)
+                .max_(t + eps);
+            if probe.active {
+                let segment = VdbSegment {
+                    ijk: lo,
+                    t0: t.load(),
+                    t1,
+                    value: probe.value,
+                    dim: probe.dim,
+                };
+                *alive = f(&segment);
+            }
+            *t = t1;
+        }
+    }
+}
+
+impl VdbGridVar<f32> {
+    /// Trilinearly interpolated value at the world space position `pos`
+    pub fn sample(&self, pos: impl AsExpr<Value = Float3>) -> Expr<f32> {
+        self.sample_index(self.world_to_index(pos))
+    }
+    /// Trilinearly interpolated value at the index space position `pos`
+    pub fn sample_index(&self, pos: impl AsExpr<Value = Float3>) -> Expr<f32> {
+        let pos = pos.as_expr();
+        let base = pos.floor();
+        let t = track!(pos - base);
+        let base = base.as_int3();
+        let v = |dx: i32, dy: i32, dz: i32| self.read(track!(base + Int3::new(dx, dy, dz).expr()));
+        let lerp = |a: Expr<f32>, b: Expr<f32>, t: Expr<f32>| track!(a + (b - a) * t);
+        let x00 = lerp(v(0, 0, 0), v(1, 0, 0), t.x);
+        let x10 = lerp(v(0, 1, 0), v(1, 1, 0), t.x);
+        let x01 = lerp(v(0, 0, 1), v(1, 0, 1), t.x);
+        let x11 = lerp(v(0, 1, 1), v(1, 1, 1), t.x);
+        lerp(lerp(x00, x10, t.y), lerp(x01, x11, t.y), t.z)
+    }
+}
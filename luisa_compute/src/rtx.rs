@@ -8,12 +8,15 @@ use crate::runtime::*;
 use crate::{ResourceTracker, *};
 use luisa_compute_ir::ir::CurveBasisSet;
 use luisa_compute_ir::ir::{AccelBinding, Binding, Func, Instruction, IrBuilder, Node, Type};
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::ops::Deref;
+use std::time::{Duration, Instant};
+pub mod alpha;
 pub mod curve;
+pub use alpha::*;
 pub use api::{
-    AccelBuildModificationFlags, AccelBuildRequest, AccelOption, AccelUsageHint, MeshType,
-    PixelFormat, PixelStorage,
+    AccelBuildModificationFlags, AccelBuildRequest, AccelOption, AccelUsageHint, CurveBasis,
+    MeshType, PixelFormat, PixelStorage,
 };
 pub use curve::*;
 use luisa_compute_api_types as api;
@@ -22,6 +25,8 @@ pub(crate) struct AccelHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::Accel,
     pub(crate) native_handle: *mut std::ffi::c_void,
+    pub(crate) option: AccelOption,
+    pub(crate) stats: Arc<Mutex<AccelBuildStats>>,
 }
 impl Drop for AccelHandle {
     fn drop(&mut self) {
@@ -30,22 +35,120 @@ impl Drop for AccelHandle {
 }
 unsafe impl Send for AccelHandle {}
 unsafe impl Sync for AccelHandle {}
+
+/// Statistics about the builds submitted for a [`Mesh`] or an [`Accel`]
+///
+/// Use it to pick a per-object strategy, e.g. refit deforming meshes every frame
+/// and only rebuild them when tracing becomes slow.
+///
+/// Builds are counted when their command is submitted to a stream, not when it is created.
+/// The memory size is only an estimate, the backends don't report it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AccelBuildStats {
+    /// Number of [`AccelBuildRequest::ForceBuild`] builds submitted
+    pub build_count: u64,
+    /// Number of [`AccelBuildRequest::PreferUpdate`] builds (refits) submitted
+    pub update_count: u64,
+    /// Number of [`Accel::update_instances_only`] uploads submitted, which
+    /// leave the BVH and the other statistics untouched
+    pub instance_update_count: u64,
+    /// Size in bytes of the inputs of the last build.
+    /// Vertex and index buffers for a [`Mesh`], instance descriptions for an [`Accel`]
+    pub input_size: usize,
+    /// Estimated size in bytes of the acceleration structure after the last build.
+    ///
+    /// Backends don't report the actual size, so this assumes a binary BVH with
+    /// one primitive per leaf plus a copy of the primitives. Only use it to compare objects.
+    pub estimated_memory_size: usize,
+    /// Time from submitting the last build until the stream finished it
+    pub last_build_time: Option<Duration>,
+}
+/// Bytes per BVH node (two child bounds and their indices) assumed by
+/// [`AccelBuildStats::estimated_memory_size`]
+const BVH_NODE_SIZE: usize = 64;
+/// What a build command submitted to the backend does
+#[derive(Clone, Copy)]
+enum BuildKind {
+    Build(AccelBuildRequest),
+    /// Only uploads the instances of an [`Accel`], see [`Accel::update_instances_only`]
+    InstancesOnly,
+}
+impl AccelBuildStats {
+    fn estimate_memory_size(primitive_count: usize, primitive_size: usize) -> usize {
+        let nodes = primitive_count.max(1) - 1;
+        nodes * BVH_NODE_SIZE + primitive_count * primitive_size
+    }
+    /// Returns the `on_submit` and `callback` of a build command
+    fn record(
+        stats: &Arc<Mutex<Self>>,
+        kind: BuildKind,
+        input_size: usize,
+        estimated_memory_size: usize,
+    ) -> (
+        Box<dyn FnOnce() + Send + 'static>,
+        Box<dyn FnOnce() + Send + 'static>,
+    ) {
+        let start = Arc::new(Mutex::new(None));
+        let on_submit = {
+            let stats = stats.clone();
+            let start = start.clone();
+            Box::new(move || {
+                let mut stats = stats.lock();
+                match kind {
+                    BuildKind::Build(AccelBuildRequest::ForceBuild) => stats.build_count += 1,
+                    BuildKind::Build(AccelBuildRequest::PreferUpdate) => stats.update_count += 1,
+                    BuildKind::InstancesOnly => {
+                        stats.instance_update_count += 1;
+                        return;
+                    }
+                }
+                stats.input_size = input_size;
+                stats.estimated_memory_size = estimated_memory_size;
+                *start.lock() = Some(Instant::now());
+            })
+        };
+        let stats = stats.clone();
+        let callback = Box::new(move || {
+            if let Some(start) = *start.lock() {
+                stats.lock().last_build_time = Some(start.elapsed());
+            }
+        });
+        (on_submit, callback)
+    }
+}
 #[derive(Clone)]
 pub(crate) enum InstanceHandle {
     Mesh(Arc<MeshHandle>),
     Procedural(Arc<ProceduralPrimitiveHandle>),
+    Curve(Arc<CurveHandle>),
 }
 impl InstanceHandle {
     pub(crate) fn handle(&self) -> u64 {
         match self {
             InstanceHandle::Mesh(h) => h.handle.0,
             InstanceHandle::Procedural(h) => h.handle.0,
+            InstanceHandle::Curve(h) => h.handle.0,
+        }
+    }
+    /// Meshes with an alpha mask are never opaque
+    fn opaque(&self, requested: bool) -> bool {
+        match self {
+            InstanceHandle::Mesh(h) => requested && h.alpha_mask.read().is_none(),
+            _ => requested,
         }
     }
 }
+/// Opacity asked for when an instance was pushed and the one last sent to the backend.
+/// They differ when the alpha mask of the mesh changes afterwards
+#[derive(Clone, Copy)]
+pub(crate) struct InstanceOpacity {
+    requested: bool,
+    flagged: bool,
+}
 pub struct Accel {
     pub(crate) handle: Arc<AccelHandle>,
     pub(crate) instance_handles: RwLock<Vec<Option<InstanceHandle>>>,
+    pub(crate) opacity: RwLock<Vec<InstanceOpacity>>,
     pub(crate) modifications: RwLock<HashMap<usize, api::AccelBuildModification>>,
 }
 pub(crate) struct ProceduralPrimitiveHandle {
@@ -87,6 +190,7 @@ impl ProceduralPrimitive {
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            on_submit: None,
         }
     }
     pub fn build(&self, request: AccelBuildRequest) {
@@ -97,10 +201,11 @@ pub(crate) struct MeshHandle {
     pub(crate) device: Device,
     pub(crate) handle: api::Mesh,
     pub(crate) native_handle: *mut std::ffi::c_void,
-    #[allow(dead_code)]
     pub(crate) vbuffer: Arc<BufferHandle>,
-    #[allow(dead_code)]
     pub(crate) ibuffer: Arc<BufferHandle>,
+    pub(crate) alpha_mask: RwLock<Option<AlphaMask>>,
+    pub(crate) option: AccelOption,
+    pub(crate) stats: Arc<Mutex<AccelBuildStats>>,
 }
 impl Drop for MeshHandle {
     fn drop(&mut self) {
@@ -124,9 +229,48 @@ impl Mesh {
     pub fn native_handle(&self) -> *mut std::ffi::c_void {
         self.handle.native_handle
     }
+    /// Number of vertices in the vertex buffer
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_buffer_size / self.vertex_stride
+    }
+    /// Number of triangles in the index buffer
+    pub fn triangle_count(&self) -> usize {
+        self.index_buffer_size / self.index_stride
+    }
+    /// The vertex buffer as bytes, for reading the vertices in kernels
+    pub(crate) fn vertex_bytes(&self) -> ByteBufferView {
+        BufferView {
+            device: self.handle.device.clone(),
+            handle: Arc::downgrade(&self.handle.vbuffer),
+            offset: self.vertex_buffer_offset,
+            len: self.vertex_buffer_size,
+            total_size_bytes: self.vertex_buffer_offset + self.vertex_buffer_size,
+            _marker: PhantomData,
+        }
+    }
+    /// The index buffer as bytes, three `u32` per triangle
+    pub(crate) fn index_bytes(&self) -> ByteBufferView {
+        BufferView {
+            device: self.handle.device.clone(),
+            handle: Arc::downgrade(&self.handle.ibuffer),
+            offset: self.index_buffer_offset,
+            len: self.index_buffer_size,
+            total_size_bytes: self.index_buffer_offset + self.index_buffer_size,
+            _marker: PhantomData,
+        }
+    }
     pub fn build_async(&self, request: AccelBuildRequest) -> Command<'static, 'static> {
         let mut rt = ResourceTracker::new();
         rt.add(self.handle.clone());
+        let (on_submit, callback) = AccelBuildStats::record(
+            &self.handle.stats,
+            BuildKind::Build(request),
+            self.vertex_buffer_size + self.index_buffer_size,
+            AccelBuildStats::estimate_memory_size(
+                self.triangle_count(),
+                3 * std::mem::size_of::<[f32; 3]>(),
+            ),
+        );
         Command {
             inner: api::Command::MeshBuild(api::MeshBuildCommand {
                 mesh: self.handle.handle,
@@ -142,14 +286,143 @@ impl Mesh {
             }),
             marker: PhantomData,
             resource_tracker: rt,
-            callback: None,
+            callback: Some(callback),
+            on_submit: Some(on_submit),
         }
     }
     pub fn build(&self, request: AccelBuildRequest) {
         submit_default_stream_and_sync(&self.handle.device, [self.build_async(request)]);
     }
+    /// Updates the BVH in place after the vertices have been modified.
+    /// Much cheaper than a full rebuild but tracing gets slower as the
+    /// mesh deforms further away from the last full build.
+    ///
+    /// The mesh must be created with [`AccelOption::allow_update`] set.
+    pub fn refit_async(&self) -> Command<'static, 'static> {
+        assert!(
+            self.handle.option.allow_update,
+            "Mesh must be created with `AccelOption::allow_update` to be refitted"
+        );
+        self.build_async(AccelBuildRequest::PreferUpdate)
+    }
+    pub fn refit(&self) {
+        submit_default_stream_and_sync(&self.handle.device, [self.refit_async()]);
+    }
+    pub fn option(&self) -> AccelOption {
+        self.handle.option
+    }
+    /// Statistics of the submitted builds, whose memory size is an estimate,
+    /// see [`AccelBuildStats::estimated_memory_size`]
+    pub fn build_stats(&self) -> AccelBuildStats {
+        *self.handle.stats.lock()
+    }
+    /// Sets an alpha mask used by [`AccelVar::trace_closest_with_alpha`] and
+    /// [`AccelVar::trace_any_with_alpha`] to cut out parts of the mesh
+    ///
+    /// `tex`: bindless slot of the alpha texture
+    ///
+    /// `uvs`: bindless slot of a `Buffer<[Float2; 3]>` with the corner uvs of each triangle
+    ///
+    /// The instances of the mesh are made non-opaque by the next build of the
+    /// [`Accel`]s containing it.
+    pub fn set_alpha_mask(&self, tex: u32, uvs: u32) {
+        self.set_alpha_mask_with(AlphaMask::new(tex, uvs));
+    }
+    pub fn set_alpha_mask_with(&self, mask: AlphaMask) {
+        *self.handle.alpha_mask.write() = Some(mask);
+    }
+    pub fn clear_alpha_mask(&self) {
+        *self.handle.alpha_mask.write() = None;
+    }
+    pub fn alpha_mask(&self) -> Option<AlphaMask> {
+        *self.handle.alpha_mask.read()
+    }
 }
 
+pub(crate) struct CurveHandle {
+    pub(crate) device: Device,
+    pub(crate) handle: api::Curve,
+    pub(crate) native_handle: *mut std::ffi::c_void,
+    #[allow(dead_code)]
+    pub(crate) cp_buffer: Arc<BufferHandle>,
+    #[allow(dead_code)]
+    pub(crate) seg_buffer: Arc<BufferHandle>,
+}
+impl Drop for CurveHandle {
+    fn drop(&mut self) {
+        self.device.inner.destroy_curve(self.handle);
+    }
+}
+unsafe impl Send for CurveHandle {}
+unsafe impl Sync for CurveHandle {}
+/// A curve primitive (e.g. hair or fur strands) built from a control point buffer
+/// and a segment buffer.
+///
+/// Each control point is a `Float4` where `xyz` is the position and `w` is the radius.
+/// Each segment is the index of its first control point. Depending on the basis,
+/// a segment uses 2 (piecewise linear) or 4 (cubic) consecutive control points.
+///
+/// Remember to set [`AccelTraceOptions::curve_bases`] (see [`Curve::basis_set`])
+/// when tracing an [`Accel`] containing curves.
+pub struct Curve {
+    pub(crate) handle: Arc<CurveHandle>,
+    pub(crate) basis: CurveBasis,
+    pub(crate) cp_buffer: api::Buffer,
+    pub(crate) cp_buffer_offset: usize,
+    pub(crate) cp_count: usize,
+    pub(crate) cp_stride: usize,
+    pub(crate) seg_buffer: api::Buffer,
+    pub(crate) seg_buffer_offset: usize,
+    pub(crate) seg_count: usize,
+}
+impl Curve {
+    pub fn native_handle(&self) -> *mut std::ffi::c_void {
+        self.handle.native_handle
+    }
+    pub fn basis(&self) -> CurveBasis {
+        self.basis
+    }
+    /// The [`CurveBasisSet`] to pass to [`AccelTraceOptions::curve_bases`]
+    pub fn basis_set(&self) -> CurveBasisSet {
+        match self.basis {
+            CurveBasis::PiecewiseLinear => CurveBasisSet::PIECEWISE_LINEAR,
+            CurveBasis::CubicBSpline => CurveBasisSet::CUBIC_BSPLINE,
+            CurveBasis::CatmullRom => CurveBasisSet::CATMULL_ROM,
+            CurveBasis::Bezier => CurveBasisSet::BEZIER,
+        }
+    }
+    pub fn control_point_count(&self) -> usize {
+        self.cp_count
+    }
+    pub fn segment_count(&self) -> usize {
+        self.seg_count
+    }
+    pub fn build_async(&self, request: AccelBuildRequest) -> Command<'static, 'static> {
+        let mut rt = ResourceTracker::new();
+        rt.add(self.handle.clone());
+        Command {
+            inner: api::Command::CurveBuild(api::CurveBuildCommand {
+                curve: self.handle.handle,
+                request,
+                basis: self.basis,
+                cp_count: self.cp_count,
+                seg_count: self.seg_count,
+                cp_buffer: self.cp_buffer,
+                cp_buffer_offset: self.cp_buffer_offset,
+                cp_buffer_stride: self.cp_stride,
+                seg_buffer: self.seg_buffer,
+                seg_buffer_offset: self.seg_buffer_offset,
+            }),
+            marker: PhantomData,
+            resource_tracker: rt,
+            callback: None,
+            on_submit: None,
+        }
+    }
+    pub fn build(&self, request: AccelBuildRequest) {
+        submit_default_stream_and_sync(&self.handle.device, [self.build_async(request)]);
+    }
+}
 impl Accel {
     fn push_handle(
         &self,
@@ -159,6 +432,8 @@ impl Accel {
         opaque: bool,
         user_id: u32,
     ) {
+        let requested = opaque;
+        let opaque = handle.opaque(requested);
         let mut flags =
             api::AccelBuildModificationFlags::PRIMITIVE | AccelBuildModificationFlags::TRANSFORM;
 
@@ -186,6 +461,10 @@ impl Accel {
         );
 
         instance_handles.push(Some(handle));
+        self.opacity.write().push(InstanceOpacity {
+            requested,
+            flagged: opaque,
+        });
     }
     fn set_handle(
         &self,
@@ -196,8 +475,11 @@ impl Accel {
         opaque: bool,
         user_id: u32,
     ) {
-        let mut flags = api::AccelBuildModificationFlags::PRIMITIVE;
-        dbg!(flags);
+        let requested = opaque;
+        let opaque = handle.opaque(requested);
+        let mut flags =
+            api::AccelBuildModificationFlags::PRIMITIVE | AccelBuildModificationFlags::TRANSFORM;
+
         flags |= api::AccelBuildModificationFlags::VISIBILITY
             | api::AccelBuildModificationFlags::USER_ID;
 
@@ -220,7 +502,12 @@ impl Accel {
         );
         let mut instance_handles = self.instance_handles.write();
         instance_handles[index] = Some(handle);
+        self.opacity.write()[index] = InstanceOpacity {
+            requested,
+            flagged: opaque,
+        };
     }
+    /// Instances of meshes with an alpha mask are never opaque
     pub fn push_mesh(&self, mesh: &Mesh, transform: Mat4, ray_mask: u32, opaque: bool) {
         self.push_handle(
             InstanceHandle::Mesh(mesh.handle.clone()),
@@ -244,6 +531,16 @@ impl Accel {
             0,
         )
     }
+    /// Curves are always treated as opaque
+    pub fn push_curve(&self, curve: &Curve, transform: Mat4, ray_mask: u32) {
+        self.push_handle(
+            InstanceHandle::Curve(curve.handle.clone()),
+            transform,
+            ray_mask,
+            true,
+            0,
+        )
+    }
     pub fn set_mesh(
         &self,
         index: usize,
@@ -277,24 +574,66 @@ impl Accel {
             0,
         )
     }
+    pub fn set_curve(&self, index: usize, curve: &Curve, transform: Mat4, ray_mask: u32) {
+        self.set_handle(
+            index,
+            InstanceHandle::Curve(curve.handle.clone()),
+            transform,
+            ray_mask,
+            true,
+            0,
+        )
+    }
     pub fn pop(&self) {
         let mut modifications = self.modifications.write();
         let mut instance_handles = self.instance_handles.write();
         let n = instance_handles.len();
         modifications.remove(&n);
         instance_handles.pop().unwrap();
+        self.opacity.write().pop();
     }
     pub fn build(&self, request: api::AccelBuildRequest) {
         submit_default_stream_and_sync(&self.handle.device, [self.build_async(request)])
     }
     pub fn build_async(&self, request: api::AccelBuildRequest) -> Command<'static, 'static> {
+        self.build_async_impl(request, false)
+    }
+    /// Uploads the pending instance modifications (transforms, masks, opacity...)
+    /// without rebuilding or refitting the top level BVH.
+    ///
+    /// Useful when only [`AccelVar::instance_transform`] etc. need to see the
+    /// new values. Call [`Accel::build_async`] before tracing against them.
+    pub fn update_instances_only_async(&self) -> Command<'static, 'static> {
+        self.build_async_impl(AccelBuildRequest::PreferUpdate, true)
+    }
+    pub fn update_instances_only(&self) {
+        submit_default_stream_and_sync(&self.handle.device, [self.update_instances_only_async()])
+    }
+    fn build_async_impl(
+        &self,
+        request: api::AccelBuildRequest,
+        update_instance_buffer_only: bool,
+    ) -> Command<'static, 'static> {
         let mut rt = ResourceTracker::new();
         let instance_handles = self.instance_handles.read();
         rt.add(self.handle.clone());
         let mut modifications = self.modifications.write();
+        self.flag_alpha_masked(&instance_handles, &mut modifications);
         let m = modifications.drain().map(|(_, v)| v).collect::<Vec<_>>();
         let m = Arc::new(m);
         rt.add(m.clone());
+        let instance_size = std::mem::size_of::<api::AccelBuildModification>();
+        let kind = if update_instance_buffer_only {
+            BuildKind::InstancesOnly
+        } else {
+            BuildKind::Build(request)
+        };
+        let (on_submit, callback) = AccelBuildStats::record(
+            &self.handle.stats,
+            kind,
+            instance_handles.len() * instance_size,
+            AccelBuildStats::estimate_memory_size(instance_handles.len(), instance_size),
+        );
         Command {
             marker: PhantomData,
             inner: api::Command::AccelBuild(api::AccelBuildCommand {
@@ -303,12 +642,77 @@ impl Accel {
                 instance_count: instance_handles.len() as u32,
                 modifications: m.as_ptr(),
                 modifications_count: m.len(),
-                update_instance_buffer_only: false,
+                update_instance_buffer_only,
             }),
             resource_tracker: rt,
-            callback: None,
+            callback: Some(callback),
+            on_submit: Some(on_submit),
+        }
+    }
+    /// Re-flags the instances whose mesh got or lost an alpha mask since they were pushed
+    fn flag_alpha_masked(
+        &self,
+        instance_handles: &[Option<InstanceHandle>],
+        modifications: &mut HashMap<usize, api::AccelBuildModification>,
+    ) {
+        let (on, off) = (
+            api::AccelBuildModificationFlags::OPAQUE_ON,
+            api::AccelBuildModificationFlags::OPAQUE_OFF,
+        );
+        let mut opacity = self.opacity.write();
+        for (i, (h, o)) in instance_handles.iter().zip(opacity.iter_mut()).enumerate() {
+            if let Some(h) = h {
+                let opaque = h.opaque(o.requested);
+                if opaque == o.flagged {
+                    continue;
+                }
+                o.flagged = opaque;
+                let flag = if opaque { on } else { off };
+                modifications
+                    .entry(i)
+                    .and_modify(|m| {
+                        m.flags.remove(on | off);
+                        m.flags |= flag;
+                    })
+                    .or_insert(api::AccelBuildModification {
+                        mesh: h.handle(),
+                        affine: Mat4::identity().into_affine3x4(),
+                        flags: flag,
+                        visibility: 0,
+                        index: i as u32,
+                        user_id: 0,
+                    });
+            }
         }
     }
+    pub fn option(&self) -> AccelOption {
+        self.handle.option
+    }
+    /// Statistics of the submitted builds, whose memory size is an estimate,
+    /// see [`AccelBuildStats::estimated_memory_size`]
+    pub fn build_stats(&self) -> AccelBuildStats {
+        *self.handle.stats.lock()
+    }
+    /// Uploads [`Accel::alpha_masks`] to a new buffer, ready to be used as [`AlphaTest::masks`]
+    ///
+    /// Create a new one after pushing instances or changing alpha masks.
+    pub fn create_alpha_mask_buffer(&self) -> Buffer<AlphaMask> {
+        self.handle
+            .device
+            .create_buffer_from_slice(&self.alpha_masks())
+    }
+    /// Per-instance alpha masks, indexed by instance id.
+    /// Instances without a mask get [`AlphaMask::NONE`]
+    pub fn alpha_masks(&self) -> Vec<AlphaMask> {
+        self.instance_handles
+            .read()
+            .iter()
+            .map(|h| match h {
+                Some(InstanceHandle::Mesh(m)) => m.alpha_mask.read().unwrap_or(AlphaMask::NONE),
+                _ => AlphaMask::NONE,
+            })
+            .collect()
+    }
     pub fn var(&self) -> AccelVar {
         AccelVar::new(self)
     }
@@ -0,0 +1,132 @@
+use crate::internal_prelude::*;
+use crate::rtx::{
+    AccelTraceOptions, AccelVar, CommittedHit, Ray, SurfaceCandidate, TriangleInterpolate,
+};
+
+/// Alpha-tested cutout description of a mesh, see [`Mesh::set_alpha_mask`](crate::rtx::Mesh::set_alpha_mask)
+///
+/// Upload the per-instance table with [`Accel::create_alpha_mask_buffer`](crate::rtx::Accel::create_alpha_mask_buffer)
+/// and pass it to [`AccelVar::trace_closest_with_alpha`].
+#[repr(C)]
+#[derive(Clone, Copy, Value, Debug, PartialEq)]
+pub struct AlphaMask {
+    /// Bindless slot of the alpha texture. The `w` channel is used as alpha
+    pub tex: u32,
+    /// Bindless slot of a `Buffer<[Float2; 3]>` holding the uvs of the three corners of each triangle
+    pub uvs: u32,
+    /// Surfaces with alpha below `cutoff` are ignored
+    pub cutoff: f32,
+}
+impl AlphaMask {
+    /// Marks an instance without alpha mask
+    pub const NONE: Self = Self {
+        tex: u32::MAX,
+        uvs: u32::MAX,
+        cutoff: 0.0,
+    };
+    pub fn new(tex: u32, uvs: u32) -> Self {
+        Self {
+            tex,
+            uvs,
+            cutoff: 0.5,
+        }
+    }
+    pub fn with_cutoff(self, cutoff: f32) -> Self {
+        Self { cutoff, ..self }
+    }
+}
+impl Default for AlphaMask {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+impl AlphaMaskExpr {
+    pub fn is_none(&self) -> Expr<bool> {
+        self.tex.eq(u32::MAX)
+    }
+}
+
+/// Resources needed to perform the alpha test inside the any-hit path
+#[derive(Clone, Copy)]
+pub struct AlphaTest<'a> {
+    /// Bindless array containing the alpha textures and uv buffers
+    pub heap: &'a BindlessArrayVar,
+    /// Per-instance alpha masks, indexed by instance id
+    pub masks: &'a BufferVar<AlphaMask>,
+}
+impl<'a> AlphaTest<'a> {
+    /// Returns true if the candidate is not cut out by its alpha mask
+    #[tracked]
+    pub fn test(&self, c: &SurfaceCandidate) -> Expr<bool> {
+        let pass = true.var();
+        if c.is_triangle() {
+            let mask = self.masks.read(c.inst);
+            if !mask.is_none() {
+                let uvs = self.heap.buffer::<[Float2; 3]>(mask.uvs).read(c.prim);
+                let uv = c
+                    .triangle_barycentric_coord()
+                    .interpolate(uvs[0], uvs[1], uvs[2]);
+                let alpha = self.heap.tex2d(mask.tex).sample_level(uv, 0u32).w;
+                *pass = alpha >= mask.cutoff;
+            }
+        }
+        **pass
+    }
+}
+
+impl AccelVar {
+    /// Finds the closest hit, ignoring surfaces cut out by their alpha masks
+    ///
+    /// Instances with alpha masks must not be opaque, otherwise the any-hit path
+    /// is skipped. [`Accel::build`](crate::rtx::Accel::build) takes care of this
+    /// for meshes with a mask.
+    ///
+    /// Backends currently do not expose opacity micromaps, so the alpha test
+    /// always runs as an any-hit shader invocation.
+    ///
+    /// Only surface candidates are considered, so procedural primitives are
+    /// never reported. Use [`AccelVar::traverse`] with an `on_procedural_hit`
+    /// that intersects them when the scene contains any.
+    #[tracked]
+    pub fn trace_closest_with_alpha(
+        &self,
+        ray: impl AsExpr<Value = Ray>,
+        options: AccelTraceOptions,
+        alpha: AlphaTest,
+    ) -> Expr<CommittedHit> {
+        with_recorder(|r| {
+            r.add_required_curve_basis(options.curve_bases);
+        });
+        self.traverse(ray, options)
+            .on_surface_hit(|c| {
+                if alpha.test(&c) {
+                    c.commit();
+                }
+            })
+            .trace()
+    }
+    /// Returns true if any surface not cut out by its alpha mask is hit
+    ///
+    /// See [`AccelVar::trace_closest_with_alpha`], procedural primitives are
+    /// never reported either.
+    #[tracked]
+    pub fn trace_any_with_alpha(
+        &self,
+        ray: impl AsExpr<Value = Ray>,
+        options: AccelTraceOptions,
+        alpha: AlphaTest,
+    ) -> Expr<bool> {
+        with_recorder(|r| {
+            r.add_required_curve_basis(options.curve_bases);
+        });
+        let hit = self
+            .traverse_any(ray, options)
+            .on_surface_hit(|c| {
+                if alpha.test(&c) {
+                    c.commit();
+                }
+            })
+            .trace();
+        !hit.miss()
+    }
+}
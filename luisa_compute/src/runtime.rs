@@ -25,14 +25,17 @@ use ir::{
 
 use crate::backend::Backend;
 use crate::rtx;
-use crate::rtx::{Accel, Mesh, MeshHandle, ProceduralPrimitiveHandle};
+use crate::rtx::{Accel, CurveHandle, Mesh, MeshHandle, ProceduralPrimitiveHandle};
 
 use api::AccelOption;
 pub use luisa_compute_api_types as api;
 use luisa_compute_backend::proxy::ProxyBackend;
 
+mod denoiser;
+mod hot_reload;
 mod kernel;
 
+pub use hot_reload::*;
 pub use kernel::*;
 
 #[derive(Clone)]
@@ -103,6 +106,7 @@ impl Drop for DeviceHandle {
 }
 
 pub mod extension {
+    use super::denoiser::{BuiltinDenoiser, BuiltinImages, ImageBinding};
     use super::*;
     use api::denoiser_ext::{Feature, Image};
     pub use api::denoiser_ext::{FilterQuality, ImageColorSpace, ImageFormat, PrefilterMode};
@@ -114,6 +118,8 @@ pub mod extension {
         outputs: Vec<Image>,
         rt: ResourceTracker,
         names: Vec<CString>,
+        builtin_images: Vec<(ImageBinding, ImageBinding)>,
+        builtin_features: Vec<(String, ImageBinding)>,
     }
 
     impl DenoiserInput {
@@ -136,6 +142,8 @@ pub mod extension {
                 outputs: Vec::new(),
                 rt: ResourceTracker::new(),
                 names: Vec::new(),
+                builtin_images: Vec::new(),
+                builtin_features: Vec::new(),
             }
         }
 
@@ -182,6 +190,11 @@ pub mod extension {
             self.inputs.push(img);
             let img = self.buffer_to_image(output, format, color_space, input_scale);
             self.outputs.push(img);
+            let scale = input_scale.unwrap_or(1.0);
+            self.builtin_images.push((
+                ImageBinding::new(input, format, color_space, scale),
+                ImageBinding::new(output, format, color_space, scale),
+            ));
             self
         }
         /// Add a feature image to the denoiser input.
@@ -193,6 +206,7 @@ pub mod extension {
             format: ImageFormat,
             color_space: ImageColorSpace,
         ) -> &mut Self {
+            let feature = name.as_ref().to_string();
             let name = CString::new(name.as_ref()).unwrap();
             self.names.push(name);
             let img = self.buffer_to_image(image, format, color_space, None);
@@ -201,6 +215,8 @@ pub mod extension {
                 name_len: self.names.last().unwrap().as_bytes().len(),
                 image: img,
             });
+            self.builtin_features
+                .push((feature, ImageBinding::new(image, format, color_space, 1.0)));
             self
         }
         pub fn prefilter_mode(&mut self, mode: PrefilterMode) -> &mut Self {
@@ -221,28 +237,57 @@ pub mod extension {
     /// Denoiser extension
     pub struct DenoiserExt {
         pub(crate) device: Device,
-        pub(crate) inner: api::DenoiserExt,
+        /// `None` for the built-in denoiser
+        pub(crate) inner: Option<api::DenoiserExt>,
+    }
+
+    enum DenoiserBackend {
+        Native {
+            api: api::DenoiserExt,
+            inner: *mut api::denoiser_ext::Denoiser,
+        },
+        /// `None` until [`Denoiser::init`] is called
+        Builtin(Option<BuiltinDenoiser>),
     }
 
     pub struct Denoiser {
-        api: api::DenoiserExt,
-        inner: *mut api::denoiser_ext::Denoiser,
+        backend: DenoiserBackend,
         rt: Option<ResourceTracker>,
         device: Device,
         stream: Arc<StreamHandle>,
     }
 
     impl DenoiserExt {
+        /// The built-in denoiser, an edge-avoiding à-trous wavelet filter
+        /// written in the DSL that works on every backend.
+        ///
+        /// It understands the `"albedo"` and `"normal"` feature images and
+        /// ignores the prefilter mode. Its quality is well below a native
+        /// denoiser, so prefer [`DeviceExtensions::denoiser_ext`] when available.
+        pub fn builtin(device: &Device) -> Self {
+            Self {
+                device: device.clone(),
+                inner: None,
+            }
+        }
+        /// Whether this is the native denoiser rather than [`DenoiserExt::builtin`]
+        pub fn is_native(&self) -> bool {
+            self.inner.is_some()
+        }
         /// Create a denoiser instance that executes on the given stream.
         pub fn create(&self, stream: &Stream) -> Denoiser {
-            unsafe {
-                Denoiser {
-                    api: self.inner,
-                    inner: (self.inner.create)(&self.inner, stream.handle().0),
-                    rt: None,
-                    device: self.device.clone(),
-                    stream: stream.handle.clone(),
-                }
+            let backend = match &self.inner {
+                Some(api) => DenoiserBackend::Native {
+                    api: *api,
+                    inner: unsafe { (api.create)(api, stream.handle().0) },
+                },
+                None => DenoiserBackend::Builtin(None),
+            };
+            Denoiser {
+                backend,
+                rt: None,
+                device: self.device.clone(),
+                stream: stream.handle.clone(),
             }
         }
     }
@@ -251,40 +296,78 @@ pub mod extension {
         /// Initialize the denoiser with the given input.
         /// Blocks if the denoiser is still running.
         pub fn init(&mut self, mut input: DenoiserInput) {
-            unsafe {
-                let inner = &mut input.inner;
-                inner.inputs = input.inputs.as_ptr();
-                inner.inputs_count = input.inputs.len();
-                inner.features = input.features.as_ptr();
-                inner.features_count = input.features.len();
-                inner.outputs = input.outputs.as_ptr();
-                (self.api.init)(&self.api, self.inner, &input.inner);
+            match &mut self.backend {
+                DenoiserBackend::Native { api, inner } => unsafe {
+                    let desc = &mut input.inner;
+                    desc.inputs = input.inputs.as_ptr();
+                    desc.inputs_count = input.inputs.len();
+                    desc.features = input.features.as_ptr();
+                    desc.features_count = input.features.len();
+                    desc.outputs = input.outputs.as_ptr();
+                    (api.init)(api, *inner, &input.inner);
+                },
+                DenoiserBackend::Builtin(builtin) => {
+                    let feature = |name: &str| {
+                        input
+                            .builtin_features
+                            .iter()
+                            .find(|(n, _)| n == name)
+                            .map(|(_, image)| image.clone())
+                    };
+                    let images = BuiltinImages {
+                        width: input.inner.width,
+                        height: input.inner.height,
+                        images: std::mem::take(&mut input.builtin_images),
+                        albedo: feature("albedo"),
+                        normal: feature("normal"),
+                        quality: input.inner.filter_quality,
+                    };
+                    *builtin = Some(BuiltinDenoiser::new(&self.device, images));
+                }
             }
             self.rt = Some(input.rt);
         }
+        fn stream(&self) -> Stream {
+            Stream {
+                device: self.device.clone(),
+                handle: self.stream.clone(),
+            }
+        }
+        /// Runs the denoiser. The built-in denoiser always blocks until done.
         pub fn execute(&self, async_: bool) {
             let rt = if let Some(rt) = &self.rt {
                 Some(rt.upgrade())
             } else {
                 None
             };
-            unsafe { (self.api.execute)(&self.api, self.inner, async_) }
-            if !async_ {
-                let stream = Stream {
-                    device: self.device.clone(),
-                    handle: self.stream.clone(),
-                };
-                let scope = stream.scope();
-                scope.submit_with_callback([], move || {
-                    drop(rt);
-                });
+            match &self.backend {
+                DenoiserBackend::Native { api, inner } => {
+                    unsafe { (api.execute)(api, *inner, async_) }
+                    if !async_ {
+                        let stream = self.stream();
+                        let scope = stream.scope();
+                        scope.submit_with_callback([], move || {
+                            drop(rt);
+                        });
+                    }
+                }
+                DenoiserBackend::Builtin(builtin) => {
+                    let builtin = builtin.as_ref().expect("denoiser is not initialized");
+                    let stream = self.stream();
+                    let scope = stream.scope();
+                    scope.submit_with_callback(builtin.execute(), move || {
+                        drop(rt);
+                    });
+                }
             }
         }
     }
 
     impl Drop for Denoiser {
         fn drop(&mut self) {
-            unsafe { (self.api.destroy)(&mut self.api, self.inner) }
+            if let DenoiserBackend::Native { api, inner } = &mut self.backend {
+                unsafe { (api.destroy)(api, *inner) }
+            }
         }
     }
 }
@@ -294,6 +377,9 @@ pub use extension::DenoiserExt;
 pub trait DeviceExtensions {
     /// Gets the denoiser extension if available.
     fn denoiser_ext(&self) -> Option<DenoiserExt>;
+    /// Gets the denoiser extension, falling back to [`DenoiserExt::builtin`]
+    /// if it is not available.
+    fn denoiser_ext_or_builtin(&self) -> DenoiserExt;
 }
 
 impl DeviceExtensions for Device {
@@ -301,13 +387,17 @@ impl DeviceExtensions for Device {
         let ext = self.inner.denoiser_ext();
         if ext.valid() {
             Some(DenoiserExt {
-                inner: ext,
+                inner: Some(ext),
                 device: self.clone(),
             })
         } else {
             None
         }
     }
+    fn denoiser_ext_or_builtin(&self) -> DenoiserExt {
+        self.denoiser_ext()
+            .unwrap_or_else(|| DenoiserExt::builtin(self))
+    }
 }
 
 impl Device {
@@ -526,6 +616,11 @@ impl Device {
             lock: Arc::new(RawMutex::INIT),
         }
     }
+    /// Creates a [`BindlessHeap`] with `slots` initial slots. It grows automatically
+    pub fn create_bindless_heap(&self, slots: usize) -> BindlessHeap {
+        assert!(slots > 0, "slots must be greater than 0");
+        BindlessHeap::new(self, slots)
+    }
     pub fn create_tex2d<T: IoTexel>(
         &self,
         storage: PixelStorage,
@@ -613,6 +708,81 @@ impl Device {
         };
         tex
     }
+    /// Creates `layers` 2D textures of the same size sampled as one array,
+    /// see [`Tex2dArray`]
+    pub fn create_tex2d_array<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        layers: u32,
+        mips: u32,
+        sampler: Sampler,
+    ) -> Tex2dArray<T> {
+        assert!(layers > 0, "layers must be greater than 0");
+        let textures = (0..layers)
+            .map(|_| self.create_tex2d::<T>(storage, width, height, mips))
+            .collect();
+        Tex2dArray::new(self, textures, sampler)
+    }
+    /// Creates a cube map with `size` x `size` faces, see [`TexCube`]
+    pub fn create_tex_cube<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        size: u32,
+        mips: u32,
+        sampler: Sampler,
+    ) -> TexCube<T> {
+        TexCube {
+            faces: self.create_tex2d_array(storage, size, size, 6, mips, sampler),
+        }
+    }
+    /// Creates a 2D texture of which at most `capacity` tiles are mapped at a
+    /// time, see [`SparseTex2d`]
+    pub fn create_sparse_tex2d<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        capacity: u32,
+    ) -> SparseTex2d<T> {
+        SparseTex2d::new(self, storage, width, height, capacity)
+    }
+    /// Creates a 3D texture of which at most `capacity` tiles are mapped at a
+    /// time, see [`SparseTex3d`]
+    pub fn create_sparse_tex3d<T: IoTexel>(
+        &self,
+        storage: PixelStorage,
+        width: u32,
+        height: u32,
+        depth: u32,
+        capacity: u32,
+    ) -> SparseTex3d<T> {
+        SparseTex3d::new(self, storage, width, height, depth, capacity)
+    }
+    /// Creates a buffer of `len` elements of which at most `capacity` tiles
+    /// are mapped at a time, see [`SparseBuffer`]
+    pub fn create_sparse_buffer<T: Value>(&self, len: usize, capacity: u32) -> SparseBuffer<T> {
+        SparseBuffer::new(self, len, capacity)
+    }
+    /// Uploads the first grid of a NanoVDB buffer, such as the contents of a
+    /// `.nvdb` file, see [`VdbGrid`]
+    pub fn create_vdb_grid_from_nanovdb<T: VdbValue>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<VdbGrid<T>, String> {
+        VdbGrid::from_nanovdb(self, bytes)
+    }
+    /// Creates a grid with the given active voxels, see [`nanovdb_from_voxels`]
+    pub fn create_vdb_grid<T: VdbValue>(
+        &self,
+        voxels: &[([i32; 3], T)],
+        background: T,
+        transform: VdbTransform,
+    ) -> VdbGrid<T> {
+        let bytes = nanovdb_from_voxels("", voxels, background, transform);
+        VdbGrid::from_nanovdb(self, &bytes).unwrap()
+    }
 
     pub fn default_stream(&self) -> Stream {
         Stream {
@@ -675,6 +845,9 @@ impl Device {
                 native_handle,
                 vbuffer: vbuffer._handle(),
                 ibuffer: tbuffer._handle(),
+                alpha_mask: RwLock::new(None),
+                option,
+                stats: Arc::new(Mutex::new(rtx::AccelBuildStats::default())),
             }),
             vertex_buffer: vbuffer.handle(),
             vertex_buffer_offset: vbuffer.offset * std::mem::size_of::<V>() as usize,
@@ -687,6 +860,37 @@ impl Device {
         };
         mesh
     }
+    /// Creates a curve primitive
+    ///
+    /// `control_points`: `xyz` is the position and `w` is the radius of each control point
+    ///
+    /// `segments`: index of the first control point of each segment
+    pub fn create_curve(
+        &self,
+        basis: rtx::CurveBasis,
+        control_points: BufferView<Float4>,
+        segments: BufferView<u32>,
+        option: AccelOption,
+    ) -> rtx::Curve {
+        let curve = self.inner.create_curve(option);
+        rtx::Curve {
+            handle: Arc::new(CurveHandle {
+                device: self.clone(),
+                handle: api::Curve(curve.handle),
+                native_handle: curve.native_handle,
+                cp_buffer: control_points._handle(),
+                seg_buffer: segments._handle(),
+            }),
+            basis,
+            cp_buffer: control_points.handle(),
+            cp_buffer_offset: control_points.offset * std::mem::size_of::<Float4>(),
+            cp_count: control_points.len,
+            cp_stride: std::mem::size_of::<Float4>(),
+            seg_buffer: segments.handle(),
+            seg_buffer_offset: segments.offset * std::mem::size_of::<u32>(),
+            seg_count: segments.len,
+        }
+    }
     pub fn create_accel(&self, option: api::AccelOption) -> rtx::Accel {
         let accel = self.inner.create_accel(option);
         rtx::Accel {
@@ -694,8 +898,11 @@ impl Device {
                 device: self.clone(),
                 handle: api::Accel(accel.handle),
                 native_handle: accel.native_handle,
+                option,
+                stats: Arc::new(Mutex::new(rtx::AccelBuildStats::default())),
             }),
             instance_handles: RwLock::new(Vec::new()),
+            opacity: RwLock::new(Vec::new()),
             modifications: RwLock::new(HashMap::new()),
         }
     }
@@ -733,6 +940,24 @@ impl Device {
         let k = KernelBuildFn::build_kernel(&f, &mut builder);
         self.compile_kernel_def_with_options(&k, options)
     }
+    /// Loads the kernel exported as `symbol` by
+    /// [`export_hot_reload_kernel!`](crate::export_hot_reload_kernel) from the `cdylib` at `lib_path`
+    ///
+    /// The library is checked for changes in the background, e.g. while running
+    /// `cargo watch -x build` on the kernel crate, and the new kernel is swapped in by
+    /// [`HotReloadKernel::reload_if_changed`]. See [`HotReloadKernel`]
+    pub fn create_hot_reload_kernel<S: KernelSignature>(
+        &self,
+        lib_path: impl AsRef<std::path::Path>,
+        symbol: &str,
+    ) -> HotReloadKernel<S> {
+        HotReloadKernel::new(
+            self,
+            lib_path,
+            symbol,
+            std::time::Duration::from_millis(500),
+        )
+    }
     /// Compile a [`KernelDef`] into a [`Kernel`]. See [`Kernel`] for more
     /// details on kernel creation
     pub fn compile_kernel_def<S: KernelSignature>(&self, k: &KernelDef<S>) -> Kernel<S> {
@@ -1003,10 +1228,15 @@ impl<'a> Scope<'a> {
     }
     fn submit_impl<'cmd, F: FnOnce() + Send + 'static>(
         &self,
-        commands: Vec<Command<'cmd, 'a>>,
+        mut commands: Vec<Command<'cmd, 'a>>,
         callback: F,
     ) {
         self.synchronized.set(false);
+        for c in &mut commands {
+            if let Some(f) = c.on_submit.take() {
+                f();
+            }
+        }
         let api_commands = commands
             .iter()
             .map(|c| c.inner)
@@ -1176,6 +1406,8 @@ pub struct Command<'from_data, 'to_data> {
     // is this really necessary?
     pub(crate) marker: PhantomData<(&'from_data (), &'to_data ())>,
     pub(crate) callback: Option<Box<dyn FnOnce() + Send + 'static>>,
+    /// Runs right before the command is handed to the stream
+    pub(crate) on_submit: Option<Box<dyn FnOnce() + Send + 'static>>,
     #[allow(dead_code)]
     pub(crate) resource_tracker: ResourceTracker,
 }
@@ -1186,6 +1418,7 @@ impl<'cmd, 'scope> Command<'cmd, 'scope> {
             inner: self.inner,
             marker: PhantomData {},
             callback: self.callback,
+            on_submit: self.on_submit,
             resource_tracker: self.resource_tracker,
         }
     }
@@ -1459,6 +1692,27 @@ impl KernelArg for BindlessArray {
     }
 }
 
+impl<T: IoTexel> KernelArg for Tex2dArray<T> {
+    type Parameter = Tex2dArrayVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.bindless_array(&self.array);
+    }
+}
+
+impl<T: IoTexel> KernelArg for TexCube<T> {
+    type Parameter = TexCubeVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.bindless_array(&self.faces.array);
+    }
+}
+
+impl<T: VdbValue> KernelArg for VdbGrid<T> {
+    type Parameter = VdbGridVar<T>;
+    fn encode(&self, encoder: &mut KernelArgEncoder) {
+        encoder.buffer(&self.data);
+    }
+}
+
 impl KernelArg for Accel {
     type Parameter = rtx::AccelVar;
     fn encode(&self, encoder: &mut KernelArgEncoder) {
@@ -1528,6 +1782,7 @@ impl RawKernel {
             marker: PhantomData,
             resource_tracker: rt,
             callback: None,
+            on_submit: None,
         }
     }
     pub fn dispatch(self: &Arc<Self>, args: KernelArgEncoder, dispatch_size: [u32; 3]) {
@@ -1805,6 +2060,18 @@ impl AsKernelArg for BindlessArray {
     type Output = BindlessArray;
 }
 
+impl<T: IoTexel> AsKernelArg for Tex2dArray<T> {
+    type Output = Tex2dArray<T>;
+}
+
+impl<T: IoTexel> AsKernelArg for TexCube<T> {
+    type Output = TexCube<T>;
+}
+
+impl<T: VdbValue> AsKernelArg for VdbGrid<T> {
+    type Output = VdbGrid<T>;
+}
+
 impl AsKernelArg for Accel {
     type Output = Accel;
 }
@@ -0,0 +1,345 @@
+//! Built-in denoiser used when the native denoiser extension is unavailable.
+//!
+//! This is the spatial part of SVGF: the noisy color is demodulated by the
+//! albedo feature, a per-pixel luminance variance is estimated from its 3x3
+//! neighborhood, and a few iterations of an edge-avoiding à-trous wavelet
+//! filter guided by luminance, albedo and normals smooth the result before
+//! the albedo is multiplied back. It runs on every backend but is noticeably
+//! blurrier than a learned denoiser.
+use super::extension::{FilterQuality, ImageColorSpace, ImageFormat};
+use super::*;
+use crate::image::{linear_to_srgb, luminance, srgb_to_linear};
+
+/// An image of a [`DenoiserInput`](super::extension::DenoiserInput) as read
+/// and written by the built-in denoiser
+#[derive(Clone)]
+pub(crate) struct ImageBinding {
+    pub(crate) view: ByteBufferView,
+    pub(crate) format: ImageFormat,
+    pub(crate) color_space: ImageColorSpace,
+    pub(crate) scale: f32,
+    /// Distance between pixels in bytes
+    pub(crate) stride: u32,
+}
+
+impl ImageBinding {
+    pub(crate) fn new<T: Value>(
+        buffer: &BufferView<T>,
+        format: ImageFormat,
+        color_space: ImageColorSpace,
+        scale: f32,
+    ) -> Self {
+        let size = std::mem::size_of::<T>();
+        Self {
+            view: BufferView {
+                device: buffer.device.clone(),
+                handle: buffer.handle.clone(),
+                offset: buffer.offset * size,
+                len: buffer.len * size,
+                total_size_bytes: buffer.total_size_bytes,
+                _marker: PhantomData,
+            },
+            format,
+            color_space,
+            scale,
+            stride: size as u32,
+        }
+    }
+    /// Channel count, whether channels are halves, and the pixel stride
+    fn layout(&self) -> Uint3 {
+        let (channels, half) = match self.format {
+            ImageFormat::Float1 => (1, 0),
+            ImageFormat::Float2 => (2, 0),
+            ImageFormat::Float3 => (3, 0),
+            ImageFormat::Float4 => (4, 0),
+            ImageFormat::Half1 => (1, 1),
+            ImageFormat::Half2 => (2, 1),
+            ImageFormat::Half3 => (3, 1),
+            ImageFormat::Half4 => (4, 1),
+        };
+        Uint3::new(channels, half, self.stride)
+    }
+    fn srgb(&self) -> bool {
+        matches!(self.color_space, ImageColorSpace::LdrSrgb)
+    }
+}
+
+/// The images a [`BuiltinDenoiser`] is initialized with
+pub(crate) struct BuiltinImages {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// (noisy, output) pairs
+    pub(crate) images: Vec<(ImageBinding, ImageBinding)>,
+    pub(crate) albedo: Option<ImageBinding>,
+    pub(crate) normal: Option<ImageBinding>,
+    pub(crate) quality: FilterQuality,
+}
+
+#[tracked]
+fn read_pixel(image: &ByteBufferVar, i: Expr<u32>, layout: Expr<Uint3>) -> Expr<Float4> {
+    let base = i.as_u64() * layout.z.as_u64();
+    let channel = |c: u32| {
+        let x = f32::var_zeroed();
+        if c < layout.x {
+            if layout.y == 1u32 {
+                *x = image.read_as::<f16>(base + 2u64 * c as u64).as_f32();
+            } else {
+                *x = image.read_as::<f32>(base + 4u64 * c as u64);
+            }
+        }
+        x.load()
+    };
+    Float4::expr(channel(0), channel(1), channel(2), channel(3))
+}
+
+#[tracked]
+fn write_pixel(image: &ByteBufferVar, i: Expr<u32>, layout: Expr<Uint3>, v: Expr<Float4>) {
+    let base = i.as_u64() * layout.z.as_u64();
+    for (c, x) in [v.x, v.y, v.z, v.w].into_iter().enumerate() {
+        if (c as u32) < layout.x {
+            if layout.y == 1u32 {
+                image.write_as::<f16>(base + 2u64 * c as u64, x.as_f16());
+            } else {
+                image.write_as::<f32>(base + 4u64 * c as u64, x);
+            }
+        }
+    }
+}
+
+/// Albedo used for demodulation, kept away from zero so that black surfaces
+/// do not amplify noise
+#[tracked]
+fn demodulation(albedo: Expr<Float4>, has_albedo: Expr<bool>) -> Expr<Float3> {
+    select(
+        has_albedo,
+        albedo.xyz().max_(Float3::splat_expr(1e-3f32)),
+        Float3::splat_expr(1.0f32),
+    )
+}
+
+/// Number of à-trous iterations; each one doubles the filter footprint
+fn iterations(quality: FilterQuality) -> u32 {
+    match quality {
+        FilterQuality::Fast => 3,
+        FilterQuality::Accurate => 5,
+        _ => 4,
+    }
+}
+
+const SIGMA_LUMINANCE: f32 = 4.0;
+const SIGMA_NORMAL: f32 = 128.0;
+const SIGMA_ALBEDO: f32 = 0.1;
+
+pub(crate) struct BuiltinDenoiser {
+    images: BuiltinImages,
+    color: Buffer<Float4>,
+    albedo: Buffer<Float4>,
+    normal: Buffer<Float4>,
+    /// Irradiance and its variance, filtered back and forth between the two
+    ping: Buffer<Float4>,
+    pong: Buffer<Float4>,
+    decode: Kernel<fn(ByteBuffer, Buffer<Float4>, Uint3, bool, f32)>,
+    prepare: Kernel<fn(Buffer<Float4>, Buffer<Float4>, Buffer<Float4>, Uint2, bool)>,
+    atrous: Kernel<
+        fn(Buffer<Float4>, Buffer<Float4>, Buffer<Float4>, Buffer<Float4>, Uint2, u32, Bool2),
+    >,
+    encode: Kernel<fn(Buffer<Float4>, Buffer<Float4>, ByteBuffer, Uint3, bool, f32, bool)>,
+}
+
+impl BuiltinDenoiser {
+    pub(crate) fn new(device: &Device, images: BuiltinImages) -> Self {
+        let len = images.width as usize * images.height as usize;
+        let decode = Kernel::<fn(ByteBuffer, Buffer<Float4>, Uint3, bool, f32)>::new(
+            device,
+            &track!(|src, dst, layout, srgb, scale| {
+                let i = dispatch_id().x;
+                let v = read_pixel(&src, i, layout);
+                let c = select(srgb, srgb_to_linear(v.xyz()), v.xyz()) * scale;
+                dst.write(i, Float4::expr(c.x, c.y, c.z, v.w));
+            }),
+        );
+        let prepare =
+            Kernel::<fn(Buffer<Float4>, Buffer<Float4>, Buffer<Float4>, Uint2, bool)>::new(
+                device,
+                &track!(|color, albedo, dst, size, has_albedo| {
+                    let p = dispatch_id().xy();
+                    let at = |x: Expr<i32>, y: Expr<i32>| {
+                        let x = x.clamp(0i32.expr(), size.x.as_i32() - 1).as_u32();
+                        let y = y.clamp(0i32.expr(), size.y.as_i32() - 1).as_u32();
+                        x + y * size.x
+                    };
+                    let irradiance = |i: Expr<u32>| {
+                        color.read(i).xyz() / demodulation(albedo.read(i), has_albedo)
+                    };
+                    let moments = Float2::var_zeroed();
+                    for dy in [-1i32, 0, 1] {
+                        for dx in [-1i32, 0, 1] {
+                            let i = at(p.x.as_i32() + dx, p.y.as_i32() + dy);
+                            let l = luminance(irradiance(i));
+                            *moments += Float2::expr(l, l * l);
+                        }
+                    }
+                    let moments = moments.load() / 9.0f32;
+                    let variance = (moments.y - moments.x * moments.x).max_(0.0f32);
+                    let c = irradiance(p.x + p.y * size.x);
+                    dst.write(p.x + p.y * size.x, Float4::expr(c.x, c.y, c.z, variance));
+                }),
+            );
+        let atrous = Kernel::<
+            fn(Buffer<Float4>, Buffer<Float4>, Buffer<Float4>, Buffer<Float4>, Uint2, u32, Bool2),
+        >::new(
+            device,
+            &track!(|src, dst, albedo, normal, size, step, has| {
+                let p = dispatch_id().xy().as_int2();
+                let center = p.x.as_u32() + p.y.as_u32() * size.x;
+                let c = src.read(center);
+                let (a, n) = (albedo.read(center).xyz(), normal.read(center).xyz());
+                let l = luminance(c.xyz());
+                // the variance is blurred a little before use, as in SVGF
+                let variance = f32::var_zeroed();
+                for (dy, wy) in [(-1i32, 0.25f32), (0, 0.5), (1, 0.25)] {
+                    for (dx, wx) in [(-1i32, 0.25f32), (0, 0.5), (1, 0.25)] {
+                        let q = (p + Int2::expr(dx, dy))
+                            .clamp(Int2::splat_expr(0i32), size.as_int2() - 1);
+                        *variance += src.read(q.x.as_u32() + q.y.as_u32() * size.x).w * (wx * wy);
+                    }
+                }
+                let scale =
+                    1.0f32 / (SIGMA_LUMINANCE * variance.load().max_(0.0f32).sqrt() + 1e-6f32);
+                let sum = Float4::var_zeroed();
+                let weights = f32::var_zeroed();
+                let taps = [
+                    (-2i32, 1.0f32 / 16.0),
+                    (-1, 0.25),
+                    (0, 0.375),
+                    (1, 0.25),
+                    (2, 1.0 / 16.0),
+                ];
+                for (dy, ky) in taps {
+                    for (dx, kx) in taps {
+                        let q = p + Int2::expr(dx, dy) * step.as_i32();
+                        if (q >= 0i32).all() && (q < size.as_int2()).all() {
+                            let j = q.x.as_u32() + q.y.as_u32() * size.x;
+                            let s = src.read(j);
+                            let w =
+                                ((-(luminance(s.xyz()) - l).abs() * scale).exp() * (kx * ky)).var();
+                            if has.x {
+                                *w *= (-(albedo.read(j).xyz() - a).length() / SIGMA_ALBEDO).exp();
+                            }
+                            if has.y {
+                                *w *= n.dot(normal.read(j).xyz()).max_(0.0f32).powf(SIGMA_NORMAL);
+                            }
+                            let w = w.load();
+                            *sum += Float4::expr(s.x * w, s.y * w, s.z * w, s.w * w * w);
+                            *weights += w;
+                        }
+                    }
+                }
+                let weights = weights.load().max_(1e-10f32);
+                let sum = sum.load();
+                dst.write(
+                    center,
+                    Float4::expr(
+                        sum.x / weights,
+                        sum.y / weights,
+                        sum.z / weights,
+                        sum.w / (weights * weights),
+                    ),
+                );
+            }),
+        );
+        let encode =
+            Kernel::<fn(Buffer<Float4>, Buffer<Float4>, ByteBuffer, Uint3, bool, f32, bool)>::new(
+                device,
+                &track!(|filtered, albedo, dst, layout, srgb, scale, has_albedo| {
+                    let i = dispatch_id().x;
+                    let c =
+                        filtered.read(i).xyz() * demodulation(albedo.read(i), has_albedo) / scale;
+                    let c = select(srgb, linear_to_srgb(c), c);
+                    write_pixel(&dst, i, layout, Float4::expr(c.x, c.y, c.z, 1.0f32));
+                }),
+            );
+        Self {
+            color: device.create_buffer(len),
+            albedo: device.create_buffer(len),
+            normal: device.create_buffer(len),
+            ping: device.create_buffer(len),
+            pong: device.create_buffer(len),
+            images,
+            decode,
+            prepare,
+            atrous,
+            encode,
+        }
+    }
+
+    pub(crate) fn execute(&self) -> Vec<Command<'static, 'static>> {
+        let images = &self.images;
+        let size = Uint2::new(images.width, images.height);
+        let pixels = [images.width * images.height, 1, 1];
+        let grid = [images.width, images.height, 1];
+        let mut commands = vec![];
+        for (feature, buffer) in [
+            (&images.albedo, &self.albedo),
+            (&images.normal, &self.normal),
+        ] {
+            if let Some(feature) = feature {
+                // normals are never color-encoded
+                let srgb = feature.srgb() && std::ptr::eq(buffer, &self.albedo);
+                commands.push(self.decode.dispatch_async(
+                    pixels,
+                    &feature.view,
+                    buffer,
+                    &feature.layout(),
+                    &srgb,
+                    &1.0f32,
+                ));
+            }
+        }
+        let has_albedo = images.albedo.is_some();
+        let has = Bool2::new(has_albedo, images.normal.is_some());
+        for (input, output) in &images.images {
+            commands.push(self.decode.dispatch_async(
+                pixels,
+                &input.view,
+                &self.color,
+                &input.layout(),
+                &input.srgb(),
+                &input.scale,
+            ));
+            commands.push(self.prepare.dispatch_async(
+                grid,
+                &self.color,
+                &self.albedo,
+                &self.ping,
+                &size,
+                &has_albedo,
+            ));
+            let (mut src, mut dst) = (&self.ping, &self.pong);
+            for level in 0..iterations(images.quality) {
+                commands.push(self.atrous.dispatch_async(
+                    grid,
+                    src,
+                    dst,
+                    &self.albedo,
+                    &self.normal,
+                    &size,
+                    &(1u32 << level),
+                    &has,
+                ));
+                std::mem::swap(&mut src, &mut dst);
+            }
+            commands.push(self.encode.dispatch_async(
+                pixels,
+                src,
+                &self.albedo,
+                &output.view,
+                &output.layout(),
+                &output.srgb(),
+                &output.scale,
+                &has_albedo,
+            ));
+        }
+        commands
+    }
+}
@@ -0,0 +1,261 @@
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+use super::*;
+
+/// Describes a kernel exported by a hot-reloadable library.
+/// Use [`export_hot_reload_kernel!`](crate::export_hot_reload_kernel) to create one
+#[repr(C)]
+#[doc(hidden)]
+pub struct HotReloadKernelEntry {
+    version: *const u8,
+    version_len: usize,
+    signature: *const u8,
+    signature_len: usize,
+    build: unsafe extern "C" fn(device: *const Device) -> *mut c_void,
+}
+impl HotReloadKernelEntry {
+    pub fn new<S: KernelSignature>(
+        build: unsafe extern "C" fn(device: *const Device) -> *mut c_void,
+    ) -> Self {
+        let version = env!("CARGO_PKG_VERSION");
+        let signature = std::any::type_name::<S>();
+        Self {
+            version: version.as_ptr(),
+            version_len: version.len(),
+            signature: signature.as_ptr(),
+            signature_len: signature.len(),
+            build,
+        }
+    }
+    unsafe fn version(&self) -> &str {
+        std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.version, self.version_len))
+    }
+    unsafe fn signature(&self) -> &str {
+        std::str::from_utf8_unchecked(std::slice::from_raw_parts(
+            self.signature,
+            self.signature_len,
+        ))
+    }
+}
+
+/// Exports a kernel from a `cdylib` crate so that it can be loaded with
+/// [`Device::create_hot_reload_kernel`]
+///
+/// ```ignore
+/// // in the cdylib crate
+/// use luisa_compute::prelude::*;
+/// luisa_compute::export_hot_reload_kernel!(shade, fn(Buffer<f32>), track!(|buf| {
+///     buf.write(dispatch_id().x, 1.0f32);
+/// }));
+/// ```
+#[macro_export]
+macro_rules! export_hot_reload_kernel {
+    ($name:ident, $sig:ty, $f:expr) => {
+        #[no_mangle]
+        pub extern "C" fn $name() -> $crate::runtime::HotReloadKernelEntry {
+            unsafe extern "C" fn build(
+                device: *const $crate::runtime::Device,
+            ) -> *mut ::std::ffi::c_void {
+                let kernel = $crate::runtime::Kernel::<$sig>::new(&*device, &$f);
+                ::std::boxed::Box::into_raw(::std::boxed::Box::new(kernel)) as *mut _
+            }
+            $crate::runtime::HotReloadKernelEntry::new::<$sig>(build)
+        }
+    };
+}
+
+static LOAD_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// A kernel loaded from a library together with the library and its temporary copy
+struct LoadedKernel<S: KernelSignature> {
+    // dropped before `library`, the captured resources may have their drop glue in it
+    kernel: ManuallyDrop<Arc<Kernel<S>>>,
+    library: ManuallyDrop<libloading::Library>,
+    copy: PathBuf,
+}
+impl<S: KernelSignature> LoadedKernel<S> {
+    /// Whether the kernel is still held by a caller or by a pending command
+    fn in_use(&self) -> bool {
+        Arc::strong_count(&*self.kernel) > 1 || Arc::strong_count(&self.kernel.inner) > 1
+    }
+}
+impl<S: KernelSignature> Drop for LoadedKernel<S> {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.kernel);
+            ManuallyDrop::drop(&mut self.library);
+        }
+        let _ = std::fs::remove_file(&self.copy);
+    }
+}
+
+/// A kernel recorded in a dynamically loaded library that can be reloaded
+/// whenever the library is rebuilt.
+///
+/// The library is watched in the background but never reloaded implicitly.
+/// Call [`HotReloadKernel::reload_if_changed`] at a convenient point, e.g. once
+/// per frame, then dispatch [`HotReloadKernel::kernel`]. If a reload fails
+/// (e.g. the signature changed), the error is logged and the previous kernel
+/// keeps being used.
+///
+/// This is a development tool: the library must be built with the same compiler
+/// and the same version of `luisa_compute` as the host. Superseded kernels and
+/// their libraries are unloaded on the next reload once no command references them.
+pub struct HotReloadKernel<S: KernelSignature> {
+    // the last one is the current kernel
+    loaded: Mutex<Vec<LoadedKernel<S>>>,
+    load_count: AtomicUsize,
+    device: Device,
+    path: PathBuf,
+    symbol: String,
+    changed: Arc<AtomicBool>,
+}
+
+impl<S: KernelSignature> HotReloadKernel<S> {
+    pub(crate) fn new(
+        device: &Device,
+        path: impl AsRef<Path>,
+        symbol: &str,
+        poll_interval: Duration,
+    ) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let changed = Arc::new(AtomicBool::new(false));
+        let kernel = Self {
+            loaded: Mutex::new(vec![]),
+            load_count: AtomicUsize::new(0),
+            device: device.clone(),
+            path: path.clone(),
+            symbol: symbol.to_string(),
+            changed: changed.clone(),
+        };
+        kernel
+            .reload()
+            .unwrap_or_else(|e| panic!("Failed to load hot reload kernel: {}", e));
+        let changed = Arc::downgrade(&changed);
+        std::thread::spawn(move || {
+            let mut last_modified = modified_time(&path);
+            loop {
+                std::thread::sleep(poll_interval);
+                let Some(changed) = changed.upgrade() else {
+                    break;
+                };
+                let modified = modified_time(&path);
+                if modified.is_some() && modified != last_modified {
+                    last_modified = modified;
+                    changed.store(true, Ordering::Release);
+                }
+            }
+        });
+        kernel
+    }
+    /// Loads the library again and swaps in the new kernel
+    pub fn reload(&self) -> Result<(), String> {
+        self.unload_unused();
+        // the dynamic loader caches libraries by path, so load a fresh copy each time
+        let id = LOAD_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let file_name = self
+            .path
+            .file_name()
+            .ok_or_else(|| format!("Invalid library path: {}", self.path.display()))?;
+        let copy = std::env::temp_dir().join(format!(
+            "luisa_hot_reload_{}_{}_{}",
+            std::process::id(),
+            id,
+            file_name.to_string_lossy()
+        ));
+        std::fs::copy(&self.path, &copy)
+            .map_err(|e| format!("Failed to copy {}: {}", self.path.display(), e))?;
+        let loaded = self.load(&copy);
+        if loaded.is_err() {
+            let _ = std::fs::remove_file(&copy);
+        }
+        let (kernel, library) = loaded?;
+        self.loaded.lock().push(LoadedKernel {
+            kernel: ManuallyDrop::new(Arc::new(kernel)),
+            library: ManuallyDrop::new(library),
+            copy,
+        });
+        self.load_count.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+    fn load(&self, copy: &Path) -> Result<(Kernel<S>, libloading::Library), String> {
+        let library = unsafe { libloading::Library::new(copy) }
+            .map_err(|e| format!("Failed to load {}: {}", copy.display(), e))?;
+        let kernel = unsafe {
+            let entry = library
+                .get::<extern "C" fn() -> HotReloadKernelEntry>(self.symbol.as_bytes())
+                .map_err(|e| format!("Symbol `{}` not found: {}", self.symbol, e))?;
+            let entry = entry();
+            if entry.version() != env!("CARGO_PKG_VERSION") {
+                return Err(format!(
+                    "Library is built against luisa_compute {} but host uses {}",
+                    entry.version(),
+                    env!("CARGO_PKG_VERSION")
+                ));
+            }
+            let expected = std::any::type_name::<S>();
+            if entry.signature() != expected {
+                return Err(format!(
+                    "Kernel `{}` has signature `{}`, expected `{}`",
+                    self.symbol,
+                    entry.signature(),
+                    expected
+                ));
+            }
+            *Box::from_raw((entry.build)(&self.device) as *mut Kernel<S>)
+        };
+        Ok((kernel, library))
+    }
+    /// Unloads the superseded kernels that are no longer referenced
+    fn unload_unused(&self) {
+        let mut loaded = self.loaded.lock();
+        let current = loaded.pop();
+        loaded.retain(LoadedKernel::in_use);
+        loaded.extend(current);
+    }
+    /// Reloads the kernel if the library changed on disk.
+    /// Returns true if a new kernel was swapped in
+    pub fn reload_if_changed(&self) -> bool {
+        if !self.changed.swap(false, Ordering::AcqRel) {
+            return false;
+        }
+        match self.reload() {
+            Ok(()) => {
+                log::info!("Reloaded kernel `{}`", self.symbol);
+                true
+            }
+            Err(e) => {
+                log::error!("Failed to reload kernel `{}`: {}", self.symbol, e);
+                false
+            }
+        }
+    }
+    /// The latest successfully loaded kernel
+    pub fn kernel(&self) -> Arc<Kernel<S>> {
+        (*self.loaded.lock().last().unwrap().kernel).clone()
+    }
+    /// Number of times the kernel has been loaded successfully
+    pub fn version(&self) -> usize {
+        self.load_count.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: KernelSignature> Drop for HotReloadKernel<S> {
+    fn drop(&mut self) {
+        // unloading a library still used by a pending command would pull its code
+        // from under the command, leak those instead
+        for k in self.loaded.get_mut().drain(..) {
+            if k.in_use() {
+                std::mem::forget(k);
+            }
+        }
+    }
+}
@@ -165,6 +165,27 @@ impl KernelParameter for BindlessArrayVar {
     }
 }
 
+impl<T: IoTexel> KernelParameter for Tex2dArrayVar<T> {
+    type Arg = Tex2dArray<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        Tex2dArrayVar::new(builder.bindless_array())
+    }
+}
+
+impl<T: IoTexel> KernelParameter for TexCubeVar<T> {
+    type Arg = TexCube<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        TexCubeVar::new(builder.bindless_array())
+    }
+}
+
+impl<T: VdbValue> KernelParameter for VdbGridVar<T> {
+    type Arg = VdbGrid<T>;
+    fn def_param(builder: &mut KernelBuilder) -> Self {
+        VdbGridVar::new(builder.buffer())
+    }
+}
+
 macro_rules! impl_kernel_param_for_tuple {
     ($first:ident  $($rest:ident)*) => {
         impl<$first:KernelParameter, $($rest: KernelParameter),*> KernelParameter for ($first, $($rest,)*) {
@@ -1820,3 +1820,1752 @@ fn test_tracked() {
         panic!();
     }
 }
+#[test]
+fn curve_intersection() {
+    use luisa::rtx::*;
+    let device = get_device();
+    // a single straight strand along the x axis
+    let control_points = device.create_buffer_from_slice(&[
+        Float4::new(-1.0, 0.0, 0.0, 0.1),
+        Float4::new(1.0, 0.0, 0.0, 0.1),
+    ]);
+    let segments = device.create_buffer_from_slice(&[0u32]);
+    let curve = device.create_curve(
+        CurveBasis::PiecewiseLinear,
+        control_points.view(..),
+        segments.view(..),
+        AccelOption::default(),
+    );
+    curve.build(AccelBuildRequest::ForceBuild);
+    let accel = device.create_accel(AccelOption::default());
+    accel.push_curve(&curve, Mat4::identity(), 0xff);
+    accel.build(AccelBuildRequest::ForceBuild);
+    let n = 8u32;
+    let out = device.create_buffer::<Float4>(2 * n as usize);
+    let curve_bases = curve.basis_set();
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            // the first half of the rays hit the strand, the rest pass above it
+            let x = -0.875f32 + 0.25f32 * (i % n).as_f32();
+            let y = select(i < n, 0.0f32.expr(), 0.5f32.expr());
+            let ray = Ray::new_expr(
+                Expr::<[f32; 3]>::from(Float3::expr(x, y, -1.0)),
+                0.0,
+                Expr::<[f32; 3]>::from(Float3::expr(0.0, 0.0, 1.0)),
+                1e9,
+            );
+            let hit = accel.var().intersect(
+                ray,
+                AccelTraceOptions {
+                    curve_bases,
+                    ..Default::default()
+                },
+            );
+            let valid = select(hit.valid(), 1.0f32.expr(), 0.0f32.expr());
+            let is_curve = select(hit.is_curve(), 1.0f32.expr(), 0.0f32.expr());
+            out.write(
+                i,
+                Float4::expr(valid, is_curve, hit.committed_ray_t, hit.curve_parameter()),
+            );
+        }),
+    );
+    kernel.dispatch([2 * n, 1, 1]);
+    let out = out.copy_to_vec();
+    for i in 0..n as usize {
+        let x = -0.875 + 0.25 * i as f32;
+        let hit = out[i];
+        assert_eq!(hit.x, 1.0, "ray {} missed", i);
+        assert_eq!(hit.y, 1.0, "ray {} hit a triangle", i);
+        // the front of the tube is one radius before the axis
+        assert!((hit.z - 0.9).abs() < 1e-3, "t = {}", hit.z);
+        assert!((hit.w - (x + 1.0) * 0.5).abs() < 1e-2, "u = {}", hit.w);
+        assert_eq!(out[i + n as usize].x, 0.0);
+    }
+}
+#[test]
+fn accel_refit_and_instance_update() {
+    use luisa::rtx::*;
+    let device = get_device();
+    let triangle = |z: f32| {
+        [
+            Float3::new(-1.0, -1.0, z),
+            Float3::new(1.0, -1.0, z),
+            Float3::new(0.0, 1.0, z),
+        ]
+    };
+    let vertices = device.create_buffer_from_slice(&triangle(0.0));
+    let indices = device.create_buffer_from_slice::<Index>(&[[0, 1, 2]]);
+    let option = AccelOption {
+        allow_update: true,
+        ..Default::default()
+    };
+    let mesh = device.create_mesh(vertices.view(..), indices.view(..), option);
+    // commands that are never submitted are not counted
+    let _ = mesh.build_async(AccelBuildRequest::ForceBuild);
+    assert_eq!(mesh.build_stats().build_count, 0);
+    mesh.build(AccelBuildRequest::ForceBuild);
+    let stats = mesh.build_stats();
+    assert_eq!(stats.build_count, 1);
+    assert_eq!(stats.update_count, 0);
+    assert_eq!(
+        stats.input_size,
+        3 * std::mem::size_of::<Float3>() + std::mem::size_of::<Index>()
+    );
+    assert!(stats.estimated_memory_size > 0);
+    assert!(stats.last_build_time.is_some());
+
+    let accel = device.create_accel(option);
+    accel.push_mesh(&mesh, Mat4::identity(), 0xff, true);
+    accel.build(AccelBuildRequest::ForceBuild);
+    assert_eq!(accel.build_stats().build_count, 1);
+    let t = device.create_buffer::<f32>(1);
+    let transform = device.create_buffer::<Mat4>(1);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let accel = accel.var();
+            let ray = Ray::new_expr(
+                Expr::<[f32; 3]>::from(Float3::expr(0.0, 0.0, -1.0)),
+                0.0,
+                Expr::<[f32; 3]>::from(Float3::expr(0.0, 0.0, 1.0)),
+                1e9,
+            );
+            let hit = accel.intersect(ray, AccelTraceOptions::default());
+            t.write(0, hit.committed_ray_t);
+            transform.write(0, accel.instance_transform(0u32.expr()));
+        }),
+    );
+    kernel.dispatch([1, 1, 1]);
+    assert!((t.copy_to_vec()[0] - 1.0).abs() < 1e-4);
+
+    // move the vertices and refit both levels
+    vertices.copy_from(&triangle(1.0));
+    {
+        let stream = device.default_stream();
+        let scope = stream.scope();
+        scope.submit([
+            mesh.refit_async(),
+            accel.build_async(AccelBuildRequest::PreferUpdate),
+        ]);
+    }
+    let stats = mesh.build_stats();
+    assert_eq!(stats.build_count, 1);
+    assert_eq!(stats.update_count, 1);
+    kernel.dispatch([1, 1, 1]);
+    assert!((t.copy_to_vec()[0] - 2.0).abs() < 1e-4);
+
+    // only upload the new transform of the instance
+    let mut moved = Mat4::identity();
+    moved.cols[3] = Float4::new(0.0, 0.0, 3.0, 1.0);
+    accel.set_mesh(0, &mesh, moved, 0xff, true);
+    {
+        let stream = device.default_stream();
+        stream.scope().submit([accel.update_instances_only_async()]);
+    }
+    kernel.dispatch([1, 1, 1]);
+    assert_eq!(transform.copy_to_vec()[0].cols[3].z, 3.0);
+    let stats = accel.build_stats();
+    assert_eq!(stats.update_count, 1);
+    assert_eq!(stats.instance_update_count, 1);
+}
+#[test]
+fn alpha_tested_trace() {
+    use luisa::rtx::*;
+    let device = get_device();
+    let triangle = |z: f32| {
+        [
+            Float3::new(-4.0, -4.0, z),
+            Float3::new(4.0, -4.0, z),
+            Float3::new(0.0, 4.0, z),
+        ]
+    };
+    let indices = device.create_buffer_from_slice::<Index>(&[[0, 1, 2]]);
+    let front_vertices = device.create_buffer_from_slice(&triangle(0.0));
+    let back_vertices = device.create_buffer_from_slice(&triangle(1.0));
+    let front = device.create_mesh(
+        front_vertices.view(..),
+        indices.view(..),
+        AccelOption::default(),
+    );
+    let back = device.create_mesh(
+        back_vertices.view(..),
+        indices.view(..),
+        AccelOption::default(),
+    );
+    front.build(AccelBuildRequest::ForceBuild);
+    back.build(AccelBuildRequest::ForceBuild);
+    let accel = device.create_accel(AccelOption::default());
+    accel.push_mesh(&front, Mat4::identity(), 0x1, true);
+    accel.push_mesh(&back, Mat4::identity(), 0x2, true);
+    accel.build(AccelBuildRequest::ForceBuild);
+
+    // the left half of the front triangle is cut out
+    let alpha = device.create_tex2d::<Float4>(PixelStorage::Float4, 2, 1, 1);
+    alpha.view(0).copy_from(&[
+        Float4::new(1.0, 1.0, 1.0, 0.0),
+        Float4::new(1.0, 1.0, 1.0, 1.0),
+    ]);
+    let uvs = device.create_buffer_from_slice(&[[
+        Float2::new(0.0, 0.0),
+        Float2::new(1.0, 0.0),
+        Float2::new(0.5, 1.0),
+    ]]);
+    let heap = device.create_bindless_array(2);
+    heap.set_tex2d(0, &alpha, Sampler::default());
+    heap.emplace_buffer(1, &uvs);
+    // the mask is set after the mesh was pushed as opaque
+    front.set_alpha_mask(0, 1);
+    accel.build(AccelBuildRequest::ForceBuild);
+    let masks = accel.create_alpha_mask_buffer();
+    assert_eq!(
+        masks.copy_to_vec(),
+        vec![AlphaMask::new(0, 1), AlphaMask::NONE]
+    );
+
+    let out = device.create_buffer::<Float2>(2);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let i = dispatch_id().x;
+            let x = select(i == 0, (-0.75f32).expr(), 0.75f32.expr());
+            let ray = Ray::new_expr(
+                Expr::<[f32; 3]>::from(Float3::expr(x, 0.0, -1.0)),
+                0.0,
+                Expr::<[f32; 3]>::from(Float3::expr(0.0, 0.0, 1.0)),
+                1e9,
+            );
+            let accel = accel.var();
+            let heap = heap.var();
+            let masks = masks.var();
+            let alpha = AlphaTest {
+                heap: &heap,
+                masks: &masks,
+            };
+            let hit = accel.trace_closest_with_alpha(ray, AccelTraceOptions::default(), alpha);
+            // only the front triangle is visible to this ray mask
+            let any = accel.trace_any_with_alpha(
+                ray,
+                AccelTraceOptions {
+                    mask: 0x1u32.expr(),
+                    ..Default::default()
+                },
+                alpha,
+            );
+            let any = select(any, 1.0f32.expr(), 0.0f32.expr());
+            out.write(i, Float2::expr(hit.committed_ray_t, any));
+        }),
+    );
+    kernel.dispatch([2, 1, 1]);
+    let out = out.copy_to_vec();
+    // through the cutout onto the back triangle
+    assert!((out[0].x - 2.0).abs() < 1e-4, "t = {}", out[0].x);
+    assert_eq!(out[0].y, 0.0);
+    assert!((out[1].x - 1.0).abs() < 1e-4, "t = {}", out[1].x);
+    assert_eq!(out[1].y, 1.0);
+}
+#[test]
+fn hot_reload_kernel_load_error() {
+    let device = get_device();
+    let dir = std::env::temp_dir();
+    let lib = dir.join(format!("luisa_hot_reload_test_{}.so", std::process::id()));
+    std::fs::write(&lib, b"not a library").unwrap();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        device.create_hot_reload_kernel::<fn(Buffer<f32>)>(&lib, "kernel")
+    }));
+    std::fs::remove_file(&lib).unwrap();
+    let message = match result {
+        Ok(_) => panic!("loading an invalid library succeeded"),
+        Err(e) => e.downcast::<String>().unwrap(),
+    };
+    assert!(message.contains("Failed to load"), "{}", message);
+    // the temporary copy of the library is removed
+    let prefix = format!("luisa_hot_reload_{}_", std::process::id());
+    let is_copy = |e: &std::fs::DirEntry| e.file_name().to_string_lossy().starts_with(&prefix);
+    let copies = std::fs::read_dir(&dir)
+        .unwrap()
+        .filter(|e| is_copy(e.as_ref().unwrap()));
+    assert_eq!(copies.count(), 0);
+}
+#[test]
+fn hot_reload_kernel() {
+    use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+    use std::time::{Duration, Instant, SystemTime};
+    let device = get_device();
+    // `cargo test` builds the examples next to the `deps` directory of this test
+    let exe = std::env::current_exe().unwrap();
+    let examples = exe.parent().unwrap().parent().unwrap().join("examples");
+    let built = examples.join(format!("{}hot_reload_kernel{}", DLL_PREFIX, DLL_SUFFIX));
+    assert!(
+        built.exists(),
+        "{} is missing, run `cargo build --example hot_reload_kernel`",
+        built.display()
+    );
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    let lib = dir.join(format!("luisa_hot_reload_test_{}_fill{}", pid, DLL_SUFFIX));
+    std::fs::copy(&built, &lib).unwrap();
+    let prefix = format!("luisa_hot_reload_{}_", pid);
+    let suffix = format!("fill{}", DLL_SUFFIX);
+    let copies = || {
+        let mut copies = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(&prefix) && name.ends_with(&suffix))
+            .collect::<Vec<_>>();
+        copies.sort();
+        copies
+    };
+
+    let kernel = device.create_hot_reload_kernel::<fn(Buffer<f32>)>(&lib, "fill");
+    assert_eq!(kernel.version(), 1);
+    let buf = device.create_buffer::<f32>(4);
+    let expected = vec![1.0, 2.0, 3.0, 4.0];
+    kernel.kernel().dispatch([4, 1, 1], &buf);
+    assert_eq!(buf.copy_to_vec(), expected);
+    let first = copies();
+    assert_eq!(first.len(), 1);
+
+    // rebuilding the library only flags it, the kernel is swapped on request
+    std::fs::copy(&built, &lib).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&lib)
+        .unwrap()
+        .set_modified(SystemTime::now() + Duration::from_secs(10))
+        .unwrap();
+    let start = Instant::now();
+    while !kernel.reload_if_changed() {
+        assert!(start.elapsed() < Duration::from_secs(10), "rebuild missed");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(kernel.version(), 2);
+    buf.fill(0.0);
+    kernel.kernel().dispatch([4, 1, 1], &buf);
+    assert_eq!(buf.copy_to_vec(), expected);
+
+    // an explicit reload unloads the superseded library, which nothing uses anymore
+    kernel.reload().unwrap();
+    assert_eq!(kernel.version(), 3);
+    let loaded = copies();
+    assert_eq!(loaded.len(), 2);
+    assert!(!loaded.contains(&first[0]));
+    buf.fill(0.0);
+    kernel.kernel().dispatch([4, 1, 1], &buf);
+    assert_eq!(buf.copy_to_vec(), expected);
+
+    drop(kernel);
+    assert!(copies().is_empty());
+    std::fs::remove_file(&lib).unwrap();
+}
+#[test]
+fn bindless_heap_slots() {
+    let device = get_device();
+    let heap = device.create_bindless_heap(2);
+    let buffers = (1..=4)
+        .map(|i| device.create_buffer_from_slice(&[i as f32]))
+        .collect::<Vec<_>>();
+    let a = heap.insert_buffer(&buffers[0]);
+    let b = heap.insert_buffer(&buffers[1]);
+    assert_eq!((a.slot(), b.slot()), (0, 1));
+    assert_eq!(heap.capacity(), 2);
+    // a removed slot is not reused until the removal is submitted, so the heap grows
+    heap.remove_buffer(a);
+    let c = heap.insert_buffer(&buffers[2]);
+    assert_eq!(c.slot(), 2);
+    assert_eq!(heap.capacity(), 4);
+    assert_eq!(heap.generation(), 1);
+    assert_eq!(heap.len(), (2, 0, 0));
+    heap.update();
+    let d = heap.insert_buffer(&buffers[3]);
+    assert_eq!(d, a);
+    heap.update();
+
+    // typed handles are stored in a buffer and resolved on the device
+    let handles = device.create_buffer_from_slice(&[b, c, d]);
+    let out = device.create_buffer::<f32>(3);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let heap = heap.var();
+            let i = dispatch_id().x;
+            out.write(i, heap.buffer(handles.read(i)).read(0));
+        }),
+    );
+    kernel.dispatch([3, 1, 1]);
+    assert_eq!(out.copy_to_vec(), vec![2.0, 3.0, 4.0]);
+}
+#[derive(Clone, Copy, Debug, Value, PartialEq)]
+#[repr(C, u32)]
+enum Shape {
+    Circle { r: f32 },
+    Rect(Float2),
+    Empty,
+}
+#[test]
+fn tagged_union() {
+    let device = get_device();
+    let shapes = device.create_buffer_from_fn(1024, |i| match i % 3 {
+        0 => Shape::Circle { r: i as f32 },
+        1 => Shape::Rect(Float2::new(i as f32, 2.0)),
+        _ => Shape::Empty,
+    });
+    let areas = device.create_buffer::<f32>(1024);
+    let copies = device.create_buffer::<Shape>(1024);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let shape = shapes.read(tid);
+            let (area, copy) = shape.match_(|s| match s {
+                ShapeMatch::Circle { r } => (3.0 * r * r, Shape::new_circle_expr(r)),
+                ShapeMatch::Rect(wh) => (wh.x * wh.y, Shape::new_rect_expr(wh)),
+                ShapeMatch::Empty => (0.0f32.expr(), Shape::new_empty_expr()),
+            });
+            areas.write(tid, area);
+            copies.write(tid, copy);
+        }),
+    );
+    kernel.dispatch([1024, 1, 1]);
+    let areas = areas.copy_to_vec();
+    let copies = copies.copy_to_vec();
+    let shapes = shapes.copy_to_vec();
+    for i in 0..1024 {
+        let x = i as f32;
+        let expected = match i % 3 {
+            0 => 3.0 * x * x,
+            1 => x * 2.0,
+            _ => 0.0,
+        };
+        assert_eq!(areas[i], expected);
+        assert_eq!(copies[i], shapes[i]);
+    }
+}
+#[test]
+fn matrix_affine_inverse() {
+    let device = get_device();
+    let mut rng = thread_rng();
+    let n = 256;
+    let transforms = device.create_buffer_from_fn(n, |_| {
+        let mut m = Float3x4::identity();
+        for c in 0..4 {
+            for r in 0..3 {
+                m.cols[c].elements[r] += rng.gen_range(-0.25..0.25);
+            }
+        }
+        m
+    });
+    let points = device.create_buffer_from_fn(n, |_| Float3::new(rng.gen(), rng.gen(), rng.gen()));
+    let out = device.create_buffer::<Float3>(n);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let m = transforms.read(tid);
+            let p = m.transform_point(points.read(tid));
+            // round trip through the inverse of the 4x4 transform
+            let m4 = m.to_affine4x4();
+            let q = m4.inverse() * p.extend(1.0);
+            out.write(tid, q.xyz());
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let out = out.copy_to_vec();
+    let points = points.copy_to_vec();
+    for i in 0..n {
+        for j in 0..3 {
+            let (a, b) = (out[i].elements[j], points[i].elements[j]);
+            assert!((a - b).abs() < 1e-4, "{}: {} != {}", i, a, b);
+        }
+    }
+}
+#[test]
+fn quat_rotate() {
+    let device = get_device();
+    let mut rng = thread_rng();
+    let n = 256;
+    let quats = device.create_buffer_from_fn(n, |_| {
+        let axis = Float3::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(0.1..1.0),
+        );
+        let len = (axis.x * axis.x + axis.y * axis.y + axis.z * axis.z).sqrt();
+        let axis = Float3::new(axis.x / len, axis.y / len, axis.z / len);
+        Quat::from_axis_angle(axis, rng.gen_range(-3.0..3.0))
+    });
+    let points = device.create_buffer_from_fn(n, |_| Float3::new(rng.gen(), rng.gen(), rng.gen()));
+    let out = device.create_buffer::<Float3>(n * 2);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let q = quats.read(tid);
+            let p = points.read(tid);
+            // rotating by the quaternion recovered from the matrix must agree
+            let r = Quat::from_mat3_expr(q.to_mat3());
+            out.write(tid * 2, q * p);
+            out.write(tid * 2 + 1, r.to_mat3() * p);
+        }),
+    );
+    kernel.dispatch([n as u32, 1, 1]);
+    let out = out.copy_to_vec();
+    for i in 0..n {
+        for j in 0..3 {
+            let (a, b) = (out[2 * i].elements[j], out[2 * i + 1].elements[j]);
+            assert!((a - b).abs() < 1e-4, "{}: {} != {}", i, a, b);
+        }
+    }
+}
+#[test]
+fn random_host_device_match() {
+    use luisa::lang::random::*;
+    let device = get_device();
+    let n = 256u32;
+    let k = 8u32;
+    let ints = device.create_buffer::<u32>((n * k * 4) as usize);
+    let floats = device.create_buffer::<Float2>((n * 4) as usize);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let pcg = Pcg32::new_expr(tid.as_u64(), 3u64).var();
+            let xoshiro = Xoshiro128::new_expr(tid.as_u64()).var();
+            let philox = Philox4x32::new_expr(tid.as_u64(), 5u64).var();
+            let sobol = SobolSampler::new_expr(tid, 17u32).var();
+            for i in 0u32..k {
+                let base = (tid * k + i) * 4;
+                ints.write(base, pcg.next_u32());
+                ints.write(base + 1, xoshiro.next_u32_bounded(1000u32));
+                ints.write(base + 2, philox.next_u32());
+                ints.write(base + 3, sobol.next_u32());
+            }
+            floats.write(tid * 4, pcg.next_float2());
+            floats.write(tid * 4 + 1, xoshiro.next_float2());
+            floats.write(tid * 4 + 2, philox.next_float2());
+            floats.write(tid * 4 + 3, sobol.next_float2());
+        }),
+    );
+    kernel.dispatch([n, 1, 1]);
+    let ints = ints.copy_to_vec();
+    let floats = floats.copy_to_vec();
+    for tid in 0..n {
+        let mut pcg = Pcg32::new(tid as u64, 3);
+        let mut xoshiro = Xoshiro128::new(tid as u64);
+        let mut philox = Philox4x32::new(tid as u64, 5);
+        let mut sobol = SobolSampler::new(tid, 17);
+        for i in 0..k {
+            let base = ((tid * k + i) * 4) as usize;
+            assert_eq!(ints[base], pcg.next_u32());
+            assert_eq!(ints[base + 1], xoshiro.next_u32_bounded(1000));
+            assert_eq!(ints[base + 2], philox.next_u32());
+            assert_eq!(ints[base + 3], sobol.next_u32());
+        }
+        let base = (tid * 4) as usize;
+        assert_eq!(floats[base], pcg.next_float2());
+        assert_eq!(floats[base + 1], xoshiro.next_float2());
+        assert_eq!(floats[base + 2], philox.next_float2());
+        assert_eq!(floats[base + 3], sobol.next_float2());
+    }
+}
+#[test]
+fn blue_noise() {
+    use luisa::lang::random::*;
+    let size = 32u32;
+    let n = size * size;
+    let ranks = void_and_cluster(size);
+    let mut sorted = ranks.clone();
+    sorted.sort();
+    assert_eq!(sorted, (0..n).collect::<Vec<_>>());
+    // thresholding the mask leaves no two neighboring pixels
+    let on = |x: u32, y: u32| ranks[((y % size) * size + x % size) as usize] < n / 8;
+    for y in 0..size {
+        for x in 0..size {
+            assert!(!on(x, y) || !(on(x + 1, y) || on(x, y + 1)));
+        }
+    }
+
+    let device = get_device();
+    let noise = BlueNoise::new(&device, size);
+    let (w, h) = (2 * size, size);
+    let out = device.create_buffer::<f32>((w * h) as usize);
+    let kernel = Kernel::<fn(u32)>::new(
+        &device,
+        &track!(|frame| {
+            let p = dispatch_id().xy();
+            out.write(p.y * w + p.x, noise.sample(p, frame, 3u32));
+        }),
+    );
+    kernel.dispatch([w, h, 1], &5);
+    let out = out.copy_to_vec();
+    for y in 0..h {
+        for x in 0..w {
+            let expected = noise.sample_host(Uint2::new(x, y), 5, 3);
+            assert_eq!(out[(y * w + x) as usize], expected);
+        }
+    }
+}
+#[test]
+fn sampling_distributions() {
+    use luisa::lang::sampling::*;
+    let device = get_device();
+    let weights = [1.0f32, 0.0, 3.0, 4.0];
+    let dist = Distribution1D::new(&device, &weights);
+    let alias = AliasTable::new(&device, &weights);
+    let n = 1024u32;
+    let out = device.create_buffer::<Uint2>(n as usize);
+    let pmfs = device.create_buffer::<Float2>(n as usize);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let tid = dispatch_id().x;
+            let u = (tid.as_f32() + 0.5) / n as f32;
+            let (i, pmf_i) = dist.sample_discrete(u);
+            let (j, pmf_j) = alias.sample(u);
+            out.write(tid, Uint2::expr(i, j));
+            pmfs.write(tid, Float2::expr(pmf_i, pmf_j));
+        }),
+    );
+    kernel.dispatch([n, 1, 1]);
+    let out = out.copy_to_vec();
+    let pmfs = pmfs.copy_to_vec();
+    let mut counts = [[0u32; 4]; 2];
+    for (s, p) in out.iter().zip(pmfs.iter()) {
+        counts[0][s.x as usize] += 1;
+        counts[1][s.y as usize] += 1;
+        assert!((p.x - weights[s.x as usize] / 8.0).abs() < 1e-6);
+        assert!((p.y - weights[s.y as usize] / 8.0).abs() < 1e-6);
+    }
+    for k in 0..4 {
+        let expected = weights[k] / 8.0 * n as f32;
+        assert_eq!(counts[0][k] as f32, expected);
+        assert!((counts[1][k] as f32 - expected).abs() <= 2.0);
+    }
+}
+#[test]
+fn block_primitives() {
+    // large enough to go through the shared memory path of every primitive
+    block_primitives_with(64);
+    // the last warp of every block is only partly filled
+    block_primitives_with(48);
+}
+fn block_primitives_with(block: u32) {
+    use luisa::lang::block::*;
+    let device = get_device();
+    let n = block * 16;
+    let mut rng = thread_rng();
+    let input = device.create_buffer_from_fn(n as usize, |_| rng.gen_range(0u32..1000));
+    let sums = device.create_buffer::<Uint2>(n as usize);
+    let sorted = device.create_buffer::<u32>(n as usize);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            set_block_size([block, 1, 1]);
+            let tid = dispatch_id().x;
+            let v = input.read(tid);
+            let max = block_reduce(v, |a, b| a.max_(b));
+            let (prefix, total) = block_prefix_sum(v);
+            let (scan, _) = block_scan_exclusive(v, 0u32, |a, b| a + b);
+            lc_assert!(scan == prefix);
+            lc_assert!(block_sum(v) == total);
+            sums.write(tid, Uint2::expr(prefix, max));
+            sorted.write(tid, block_radix_sort(v, 0..10));
+        }),
+    );
+    kernel.dispatch([n, 1, 1]);
+    let input = input.copy_to_vec();
+    let sums = sums.copy_to_vec();
+    let sorted = sorted.copy_to_vec();
+    for (b, chunk) in input.chunks(block as usize).enumerate() {
+        let max = *chunk.iter().max().unwrap();
+        let mut expected = chunk.to_vec();
+        expected.sort();
+        let mut prefix = 0;
+        for i in 0..block as usize {
+            let k = b * block as usize + i;
+            assert_eq!(sums[k], Uint2::new(prefix, max));
+            assert_eq!(sorted[k], expected[i]);
+            prefix += chunk[i];
+        }
+    }
+}
+#[test]
+fn coop_matrix_mma() {
+    use luisa::lang::coop_matrix::*;
+    use luisa::lang::functions::warp_active_count_bits;
+    let device = get_device();
+    // run exactly one full warp, whatever its size on this backend
+    let lanes = device.create_buffer::<u32>(1);
+    Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            set_block_size([32, 1, 1]);
+            lanes.write(0, warp_active_count_bits(true));
+        }),
+    )
+    .dispatch([32, 1, 1]);
+    let warp = lanes.copy_to_vec()[0];
+    let mut rng = thread_rng();
+    let a = device.create_buffer_from_fn(256, |_| f16::from_f32(rng.gen_range(-1.0..1.0)));
+    let b = device.create_buffer_from_fn(256, |_| f16::from_f32(rng.gen_range(-1.0..1.0)));
+    let c = device.create_buffer_from_fn(256, |_| rng.gen_range(-1.0f32..1.0));
+    let d = device.create_buffer::<f32>(256);
+    let owners = device.create_buffer::<f32>(256);
+    let kernel = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            set_block_size([warp, 1, 1]);
+            let a = CoopMatrix::<f16, 16, 16>::load(&a.var(), 0u32, 16u32, MatrixLayout::RowMajor);
+            let b =
+                CoopMatrix::<f16, 16, 16>::load(&b.var(), 0u32, 16u32, MatrixLayout::ColumnMajor);
+            let c = CoopMatrix::<f32, 16, 16>::load(&c.var(), 0u32, 16u32, MatrixLayout::RowMajor);
+            CoopMatrix::mma(&a, &b, &c).store(&d.var(), 0u32, 16u32, MatrixLayout::RowMajor);
+            // deliberately different on every lane, to see which lane stores what
+            let lane = CoopMatrix::<f32, 16, 16>::fill(dispatch_id().x.as_f32());
+            lane.store(&owners.var(), 0u32, 16u32, MatrixLayout::RowMajor);
+        }),
+    );
+    kernel.dispatch([warp, 1, 1]);
+    let owners = owners.copy_to_vec();
+    for (i, owner) in owners.iter().enumerate() {
+        assert_eq!(*owner, (i as u32 % warp) as f32);
+    }
+    let (a, b, c, d) = (
+        a.copy_to_vec(),
+        b.copy_to_vec(),
+        c.copy_to_vec(),
+        d.copy_to_vec(),
+    );
+    for r in 0..16 {
+        for col in 0..16 {
+            let mut expected = c[r * 16 + col];
+            for k in 0..16 {
+                expected += a[r * 16 + k].to_f32() * b[col * 16 + k].to_f32();
+            }
+            assert!((d[r * 16 + col] - expected).abs() < 1e-3);
+        }
+    }
+}
+#[test]
+fn nn_train() {
+    use luisa::nn::*;
+    let device = get_device();
+    let net = Sequential::<f32>::new(
+        &device,
+        &[
+            Linear::new(1, 16, Activation::Tanh),
+            Linear::new(16, 1, Activation::None),
+        ],
+        0,
+    );
+    let config = AdamConfig {
+        learning_rate: 1e-2,
+        ..Default::default()
+    };
+    let mut adam = Adam::new(&device, &net, config);
+    let n = 256u32;
+    let loss = device.create_buffer::<f32>(1);
+    let train = Kernel::<fn()>::new(
+        &device,
+        &track!(|| {
+            let x = dispatch_id().x.as_f32() / n as f32 * 2.0 - 1.0;
+            let l = net.train(&[x], |y| {
+                let e = y[0] - x * x;
+                e * e
+            });
+            loss.var().atomic_fetch_add(0, l / n as f32);
+        }),
+    );
+    let mut losses = vec![];
+    for _ in 0..200 {
+        loss.view(..).fill(0.0);
+        train.dispatch([n, 1, 1]);
+        adam.step(1.0 / n as f32);
+        losses.push(loss.copy_to_vec()[0]);
+    }
+    assert!(losses[199] < losses[0] * 0.1, "{:?}", losses);
+}
+#[test]
+fn adam_repeated_steps() {
+    use luisa::nn::*;
+    let device = get_device();
+    let net = Sequential::<f32>::new(&device, &[Linear::new(2, 2, Activation::None)], 0);
+    let mut adam = Adam::new(&device, &net, AdamConfig::default());
+    let before = net.params().copy_to_vec();
+    for _ in 0..3 {
+        net.grads().view(..).fill(1.0);
+        adam.step(1.0);
+        assert!(net.grads().copy_to_vec().iter().all(|g| *g == 0.0));
+    }
+    let after = net.params().copy_to_vec();
+    // with a constant gradient every Adam step moves a parameter by about the
+    // learning rate
+    let lr = adam.config().learning_rate;
+    for (b, a) in before.iter().zip(after.iter()) {
+        assert!((b - a - 3.0 * lr).abs() < 1e-5, "{} {}", b, a);
+    }
+}
+fn hash_grid_gradient_impl<T: luisa::nn::Weight>() {
+    use luisa::nn::*;
+    let device = get_device();
+    let config = HashGridConfig {
+        levels: 4,
+        features: 2,
+        log2_table_size: 8,
+        base_resolution: 4,
+        per_level_scale: 2.0,
+    };
+    let grid = HashGridEncoding::<T>::new(&device, config, 0);
+    let n = 64;
+    let mut rng = thread_rng();
+    let positions =
+        device.create_buffer_from_fn(n, |_| Float3::new(rng.gen(), rng.gen(), rng.gen()));
+    let coeffs = (0..grid.outputs())
+        .map(|_| rng.gen_range(-1.0f32..1.0))
+        .collect::<Vec<_>>();
+    let loss = device.create_buffer::<f32>(1);
+    let encode = grid.encode_callable();
+    // the loss is linear in the parameters, so a finite step matches the gradient exactly
+    let forward = Kernel::<fn()>::new(&device, &|| {
+        let e = encode
+            .call(positions.read(dispatch_id().x))
+            .get_array::<f32>(grid.outputs());
+        let l = coeffs
+            .iter()
+            .enumerate()
+            .fold(0.0f32.expr(), |acc, (i, &c)| {
+                track!(acc + e.read(i as u32) * c)
+            });
+        loss.var().atomic_fetch_add(0, l);
+    });
+    let backward = Kernel::<fn()>::new(&device, &|| {
+        let d = coeffs.iter().map(|c| c.expr()).collect::<Vec<_>>();
+        grid.backward(positions.read(dispatch_id().x), &d);
+    });
+    let eval = || {
+        loss.view(..).fill(0.0);
+        forward.dispatch([n as u32, 1, 1]);
+        loss.copy_to_vec()[0]
+    };
+    let l0 = eval();
+    backward.dispatch([n as u32, 1, 1]);
+    let grads = grid.grads.copy_to_vec();
+    assert!(grads.iter().any(|g| *g != 0.0));
+    let params = grid.params.copy_to_vec();
+    let stepped = params
+        .iter()
+        .map(|p| T::from_f32(p.to_f32() + rng.gen_range(-1e-2f32..1e-2)))
+        .collect::<Vec<_>>();
+    grid.params.view(..).copy_from(&stepped);
+    let l1 = eval();
+    // the step as stored, after rounding to the weight type
+    let expected = grads
+        .iter()
+        .zip(params.iter().zip(&stepped))
+        .map(|(g, (p, s))| g * (s.to_f32() - p.to_f32()))
+        .sum::<f32>();
+    assert!(
+        (l1 - l0 - expected).abs() < 1e-3 * (1.0 + expected.abs()),
+        "{} {}",
+        l1 - l0,
+        expected
+    );
+}
+#[test]
+fn hash_grid_gradient() {
+    hash_grid_gradient_impl::<f32>();
+}
+#[test]
+fn hash_grid_gradient_f16() {
+    hash_grid_gradient_impl::<f16>();
+}
+#[test]
+fn half_rounding_modes() {
+    use luisa::lang::precision::*;
+    let device = get_device();
+    let modes = [
+        RoundingMode::NearestEven,
+        RoundingMode::TowardZero,
+        RoundingMode::Up,
+        RoundingMode::Down,
+    ];
+    let mut rng = thread_rng();
+    let mut values = (0..1024)
+        .map(|_| rng.gen_range(-1.0f32..1.0) * 10f32.powi(rng.gen_range(-8..6)))
+        .collect::<Vec<_>>();
+    values.extend([0.0, -0.0, 1.0, 65504.0, 65519.0, 1e6, -1e6, 1e-10, -1e-10]);
+    let n = values.len();
+    let src = device.create_buffer_from_slice(&values);
+    let rounded = device.create_buffer::<f16>(n * modes.len());
+    let kernel = Kernel::<fn()>::new(&device, &|| {
+        let i = dispatch_id().x;
+        let x = src.read(i);
+        for (m, mode) in modes.iter().enumerate() {
+            rounded.write(track!(i + (m * n) as u32), f32_to_f16(x, *mode));
+        }
+    });
+    kernel.dispatch([n as u32, 1, 1]);
+    let rounded = rounded.copy_to_vec();
+    for (m, mode) in modes.iter().enumerate() {
+        for (i, x) in values.iter().enumerate() {
+            let h = rounded[m * n + i];
+            assert_eq!(h.to_bits(), f32_to_f16_host(*x, *mode).to_bits());
+            let y = h.to_f32();
+            match mode {
+                RoundingMode::TowardZero => assert!(y.abs() <= x.abs()),
+                RoundingMode::Up => assert!(y >= *x),
+                RoundingMode::Down => assert!(y <= *x),
+                RoundingMode::NearestEven => assert_eq!(h, f16::from_f32(*x)),
+            }
+        }
+    }
+    let converter = HalfConverter::new(&device, RoundingMode::NearestEven);
+    let half = device.create_buffer::<f16>(n);
+    let back = device.create_buffer::<f32>(n);
+    converter.to_half(&src, &half);
+    converter.to_float(&half, &back);
+    let back = back.copy_to_vec();
+    for (x, y) in values.iter().zip(&back) {
+        assert_eq!(f16::from_f32(*x).to_f32().to_bits(), y.to_bits());
+    }
+}
+#[test]
+fn half_atomics() {
+    use luisa::lang::precision::*;
+    let device = get_device();
+    let n = 64u32;
+    let h = f16::from_f32(0.0);
+    let sum = device.create_buffer_from_slice(&[h]);
+    let sum2 = device.create_buffer_from_slice(&[Half2::new(h, h)]);
+    let old = device.create_buffer::<f16>(n as usize);
+    let old2 = device.create_buffer::<Half2>(n as usize);
+    let kernel = Kernel::<fn()>::new(&device, &|| {
+        let i = dispatch_id().x;
+        let quarter = f16::from_f32(0.25).expr();
+        let half = f16::from_f32(-0.5).expr();
+        old.write(i, sum.var().atomic_ref(0).fetch_add(quarter));
+        old2.write(
+            i,
+            sum2.var()
+                .atomic_ref(0)
+                .fetch_add(Half2::expr(quarter, half)),
+        );
+    });
+    kernel.dispatch([n, 1, 1]);
+    // every partial sum is exact in f16, so each thread sees a distinct one
+    assert_eq!(sum.copy_to_vec()[0].to_f32(), n as f32 * 0.25);
+    let mut old = old
+        .copy_to_vec()
+        .iter()
+        .map(|x| x.to_f32())
+        .collect::<Vec<_>>();
+    old.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(old, (0..n).map(|i| i as f32 * 0.25).collect::<Vec<_>>());
+    let s = sum2.copy_to_vec()[0];
+    assert_eq!(
+        (s.x.to_f32(), s.y.to_f32()),
+        (n as f32 * 0.25, n as f32 * -0.5)
+    );
+    // the halves are updated by separate atomics, so only each component on
+    // its own is a sequence of partial sums
+    let old2 = old2.copy_to_vec();
+    let mut xs = old2.iter().map(|v| v.x.to_f32()).collect::<Vec<_>>();
+    let mut ys = old2.iter().map(|v| v.y.to_f32()).collect::<Vec<_>>();
+    xs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    ys.sort_by(|a, b| b.partial_cmp(a).unwrap());
+    assert_eq!(xs, (0..n).map(|i| i as f32 * 0.25).collect::<Vec<_>>());
+    assert_eq!(ys, (0..n).map(|i| i as f32 * -0.5).collect::<Vec<_>>());
+}
+#[test]
+fn half2_fma() {
+    use luisa::lang::precision::*;
+    let device = get_device();
+    let n = 1024;
+    let mut rng = thread_rng();
+    let mut gen = || {
+        Half2::new(
+            f16::from_f32(rng.gen_range(-1.0..1.0)),
+            f16::from_f32(rng.gen_range(-1.0..1.0)),
+        )
+    };
+    let a = (0..n).map(|_| gen()).collect::<Vec<_>>();
+    let b = (0..n).map(|_| gen()).collect::<Vec<_>>();
+    let c = (0..n).map(|_| gen()).collect::<Vec<_>>();
+    let (a_buf, b_buf, c_buf) = (
+        device.create_buffer_from_slice(&a),
+        device.create_buffer_from_slice(&b),
+        device.create_buffer_from_slice(&c),
+    );
+    let fma = device.create_buffer::<Half2>(n);
+    let fma_f32 = device.create_buffer::<Float2>(n);
+    let dot = device.create_buffer::<f32>(n);
+    let kernel = Kernel::<fn()>::new(&device, &|| {
+        let i = dispatch_id().x;
+        let (a, b, c) = (a_buf.read(i), b_buf.read(i), c_buf.read(i));
+        fma.write(i, hfma2(a, b, c));
+        fma_f32.write(i, hfma2_f32(a, b, c.as_float2()));
+        dot.write(i, hdot2_f32(a, b, c.x.as_::<f32>()));
+    });
+    kernel.dispatch([n as u32, 1, 1]);
+    let (fma, fma_f32, dot) = (fma.copy_to_vec(), fma_f32.copy_to_vec(), dot.copy_to_vec());
+    for i in 0..n {
+        let (a, b, c) = (a[i], b[i], c[i]);
+        let f = |a: f16, b: f16, c: f16| a.to_f32() * b.to_f32() + c.to_f32();
+        let expected = [f(a.x, b.x, c.x), f(a.y, b.y, c.y)];
+        // a product of halves is exact in f32, so only the final rounding differs
+        let ulp = 2.0f32.powi(-10);
+        assert!((fma[i].x.to_f32() - expected[0]).abs() <= ulp);
+        assert!((fma[i].y.to_f32() - expected[1]).abs() <= ulp);
+        assert!((fma_f32[i].x - expected[0]).abs() < 1e-6);
+        assert!((fma_f32[i].y - expected[1]).abs() < 1e-6);
+        let d = a.x.to_f32() * b.x.to_f32() + a.y.to_f32() * b.y.to_f32() + c.x.to_f32();
+        assert!((dot[i] - d).abs() < 1e-6);
+    }
+}
+#[test]
+fn generate_mips_odd_size() {
+    let device = get_device();
+    let (w, h) = (5u32, 3u32);
+    let tex = device.create_tex2d::<f32>(PixelStorage::Float1, w, h, 3);
+    let data = (0..w * h).map(|i| (i * i % 7) as f32).collect::<Vec<_>>();
+    tex.view(0).copy_from(&data);
+    let texel = |x: u32, y: u32| data[(y * w + x) as usize];
+    let mips = MipGenerator::new(&device);
+    tex.generate_mips(&mips, MipFilter::Box);
+    let level1 = tex.view(1).copy_to_vec::<f32>();
+    assert_eq!(level1.len(), 2);
+    // 5 -> 2 columns: each destination texel covers 2.5 source texels
+    for (x, weights) in [
+        (0, [1.0, 1.0, 0.5, 0.0, 0.0]),
+        (1, [0.0, 0.0, 0.5, 1.0, 1.0]),
+    ] {
+        let mut expected = 0.0;
+        for y in 0..h {
+            for sx in 0..w {
+                expected += texel(sx, y) * weights[sx as usize];
+            }
+        }
+        expected /= 2.5 * h as f32;
+        assert!((level1[x] - expected).abs() < 1e-4);
+    }
+    let level2 = tex.view(2).copy_to_vec::<f32>();
+    let mean = data.iter().sum::<f32>() / data.len() as f32;
+    assert!((level2[0] - mean).abs() < 1e-4);
+    for filter in [MipFilter::Kaiser, MipFilter::Lanczos] {
+        let constant = device.create_tex2d::<f32>(PixelStorage::Float1, w, h, 3);
+        constant.view(0).copy_from(&vec![0.5f32; (w * h) as usize]);
+        constant.generate_mips(&mips, filter);
+        for level in 1..3 {
+            for v in constant.view(level).copy_to_vec::<f32>() {
+                assert!((v - 0.5).abs() < 1e-4);
+            }
+        }
+    }
+}
+#[test]
+fn generate_mips_cube_seams() {
+    let device = get_device();
+    let mips = MipGenerator::new(&device);
+    let size = 16u32;
+    let cube = device.create_tex_cube::<f32>(PixelStorage::Float1, size, 2, Sampler::default());
+    for face in 0..6 {
+        cube.view(face, 0)
+            .copy_from(&vec![face as f32; (size * size) as usize]);
+    }
+    cube.generate_mips(&mips, MipFilter::Kaiser);
+    let half = size / 2;
+    for face in 0..6 {
+        let level1 = cube.view(face, 1).copy_to_vec::<f32>();
+        let texel = |x: u32, y: u32| level1[(y * half + x) as usize];
+        // the filter of the center texels stays within the face
+        for (x, y) in [(3, 3), (3, 4), (4, 3), (4, 4)] {
+            assert!((texel(x, y) - face as f32).abs() < 1e-4);
+        }
+        // while the edge texels also read the adjacent faces
+        for (x, y) in [(0, 4), (7, 4), (4, 0), (4, 7)] {
+            assert!(
+                (texel(x, y) - face as f32).abs() > 1e-2,
+                "{} {}",
+                face,
+                texel(x, y)
+            );
+        }
+    }
+}
+#[test]
+fn tex_cube_face_selection() {
+    let device = get_device();
+    let size = 4u32;
+    let cube = device.create_tex_cube::<f32>(PixelStorage::Float1, size, 1, Sampler::default());
+    for face in 0..6 {
+        cube.view(face, 0)
+            .copy_from(&vec![face as f32; (size * size) as usize]);
+    }
+    let mut rng = StdRng::seed_from_u64(0);
+    let dirs = (0..64)
+        .map(|_| {
+            Float3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+        })
+        .collect::<Vec<_>>();
+    let dir_buf = device.create_buffer_from_slice(&dirs);
+    let sampled = device.create_buffer::<f32>(dirs.len());
+    let round_trip = device.create_buffer::<Float3>(dirs.len());
+    let kernel = Kernel::<fn(TexCube<f32>)>::new(&device, &|cube: TexCubeVar<f32>| {
+        let i = dispatch_id().x;
+        let dir = dir_buf.read(i);
+        sampled.write(i, cube.sample(dir).x);
+        let (face, uv) = cube_face_uv(dir);
+        round_trip.write(i, cube_face_direction(face, uv));
+    });
+    kernel.dispatch([dirs.len() as u32, 1, 1], &cube);
+    let sampled = sampled.copy_to_vec();
+    let round_trip = round_trip.copy_to_vec();
+    for (i, &dir) in dirs.iter().enumerate() {
+        let (face, uv) = cube_face_uv_host(dir);
+        assert_eq!(sampled[i], face as f32);
+        assert!(uv.x >= 0.0 && uv.x <= 1.0 && uv.y >= 0.0 && uv.y <= 1.0);
+        // the direction is recovered up to its length along the major axis
+        let back = cube_face_direction_host(face, uv);
+        let ma = dir.x.abs().max(dir.y.abs()).max(dir.z.abs());
+        for (a, b) in [(back.x, dir.x), (back.y, dir.y), (back.z, dir.z)] {
+            assert!((a * ma - b).abs() < 1e-4);
+        }
+        let r = round_trip[i];
+        for (a, b) in [(r.x, back.x), (r.y, back.y), (r.z, back.z)] {
+            assert!((a - b).abs() < 1e-4);
+        }
+    }
+}
+#[test]
+fn bc_encode_decode() {
+    let device = get_device();
+    let decode = |format: BcFormat, data: &Buffer<u32>, size: u32| {
+        let out = device.create_buffer::<Float4>((size * size) as usize);
+        let sampled = device.create_buffer::<Float4>((size * size) as usize);
+        let inv_size = 1.0 / size as f32;
+        let kernel = Kernel::<fn(Buffer<u32>)>::new(&device, &|data: BufferVar<u32>| {
+            let p = dispatch_id().xy();
+            let i = track!(p.y * size + p.x);
+            out.write(i, format.read(&data, size, p));
+            let uv = track!((p.as_::<Float2>() + 0.5f32) * inv_size);
+            sampled.write(i, format.sample(&data, Uint2::new(size, size), uv));
+        });
+        kernel.dispatch([size, size, 1], data);
+        let (out, sampled) = (out.copy_to_vec(), sampled.copy_to_vec());
+        for (a, b) in out.iter().zip(&sampled) {
+            for (x, y) in [(a.x, b.x), (a.y, b.y), (a.z, b.z), (a.w, b.w)] {
+                assert!((x - y).abs() < 1e-5);
+            }
+        }
+        out
+    };
+
+    // c0 pure red and c1 pure blue in four-color mode, texel i uses index i % 4
+    let indices = (0..16).fold(0u32, |bits, i| bits | ((i % 4) << (2 * i)));
+    let block = device.create_buffer_from_slice(&[0x001f_f800u32, indices]);
+    let texels = decode(BcFormat::Bc1, &block, 4);
+    let palette = [
+        [1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [2.0 / 3.0, 0.0, 1.0 / 3.0],
+        [1.0 / 3.0, 0.0, 2.0 / 3.0],
+    ];
+    for (i, t) in texels.iter().enumerate() {
+        let expected = palette[i % 4];
+        assert!((t.x - expected[0]).abs() < 1e-5);
+        assert!((t.y - expected[1]).abs() < 1e-5);
+        assert!((t.z - expected[2]).abs() < 1e-5);
+        assert_eq!(t.w, 1.0);
+    }
+
+    // a gradient along a line in color space on which green and alpha fall as
+    // red and blue rise, so the endpoints must not simply be the bounding box
+    // corners
+    let size = 8u32;
+    let image = (0..size * size)
+        .map(|i| {
+            let t = ((i % size) + (i / size)) as f32 / (2 * (size - 1)) as f32;
+            Float4::new(t, 1.0 - t, 0.5 * t, 1.0 - 0.25 * t)
+        })
+        .collect::<Vec<_>>();
+    let tex = device.create_tex2d::<Float4>(PixelStorage::Float4, size, size, 1);
+    tex.view(0).copy_from(&image);
+    for (format, channels, tolerance) in [
+        (BcFormat::Bc1, 3, 0.1),
+        (BcFormat::Bc4, 1, 0.04),
+        (BcFormat::Bc5, 2, 0.04),
+        (BcFormat::Bc7, 4, 0.04),
+    ] {
+        let encoder = BcEncoder::new(&device, format);
+        let blocks = device.create_buffer::<u32>(format.image_words(size, size));
+        encoder.encode(&tex.view(0), &blocks);
+        let decoded = decode(format, &blocks, size);
+        for (a, b) in image.iter().zip(&decoded) {
+            let (a, b) = ([a.x, a.y, a.z, a.w], [b.x, b.y, b.z, b.w]);
+            for c in 0..channels {
+                assert!(
+                    (a[c] - b[c]).abs() < tolerance,
+                    "{:?}: {} vs {}",
+                    format,
+                    a[c],
+                    b[c]
+                );
+            }
+        }
+    }
+}
+#[test]
+fn sparse_page_table() {
+    let device = get_device();
+    let volume = device.create_sparse_tex3d::<f32>(PixelStorage::Float1, 64, 64, 64, 2);
+    assert_eq!(volume.tiles(), [2, 2, 2]);
+    let t = SparseTex3d::<f32>::TILE_SIZE;
+    let data = (0..t * t * t).map(|i| i as f32).collect::<Vec<_>>();
+    volume.map_tile([1, 0, 0]);
+    volume.copy_tile_from([1, 0, 0], &data);
+    assert!(volume.is_mapped([1, 0, 0]) && !volume.is_mapped([0, 1, 0]));
+
+    let coords = [Uint3::new(t + 1, 2, 3), Uint3::new(5, t + 8, 0)];
+    let coord_buf = device.create_buffer_from_slice(&coords);
+    let out = device.create_buffer::<f32>(coords.len());
+    let resident = device.create_buffer::<u32>(coords.len());
+    let read = Kernel::<fn()>::new(&device, &|| {
+        let volume = volume.var();
+        let i = dispatch_id().x;
+        let coord = coord_buf.read(i);
+        out.write(i, volume.read(coord));
+        resident.write(i, volume.is_resident(coord).as_u32());
+    });
+    read.dispatch([coords.len() as u32, 1, 1]);
+    assert_eq!(
+        out.copy_to_vec(),
+        [data[(1 + t * (2 + t * 3)) as usize], 0.0]
+    );
+    assert_eq!(resident.copy_to_vec(), [1, 0]);
+    assert_eq!(volume.requested_tiles(), [[0, 1, 0]]);
+    assert!(volume.requested_tiles().is_empty());
+
+    volume.unmap_tile([1, 0, 0]);
+    read.dispatch([coords.len() as u32, 1, 1]);
+    assert_eq!(out.copy_to_vec(), [0.0, 0.0]);
+    assert_eq!(volume.requested_tiles(), [[1, 0, 0], [0, 1, 0]]);
+
+    let buffer = device.create_sparse_buffer::<f32>(100_000, 1);
+    let tile_len = buffer.tile_len();
+    assert_eq!(buffer.tiles() as usize, 100_000usize.div_ceil(tile_len));
+    buffer.map_tile(2);
+    buffer.copy_tile_from(2, &vec![3.0; tile_len]);
+    let out = device.create_buffer::<f32>(2);
+    let index = (2 * tile_len + 7) as u32;
+    Kernel::<fn()>::new(&device, &|| {
+        let buffer = buffer.var();
+        out.write(0, buffer.read(index));
+        out.write(1, buffer.read(0u32));
+    })
+    .dispatch([1, 1, 1]);
+    assert_eq!(out.copy_to_vec(), [3.0, 0.0]);
+    assert_eq!(buffer.requested_tiles(), [0]);
+}
+#[test]
+fn vdb_grid_lookup_and_march() {
+    let device = get_device();
+    let mut voxels = (10..=20).map(|i| ([i, 0, 0], i as f32)).collect::<Vec<_>>();
+    voxels.push(([5000, -3, 7], 7.5));
+    voxels.push(([-9, -200, 40], -1.0));
+    let transform = VdbTransform {
+        voxel_size: 0.5,
+        origin: [1.0, 0.0, 0.0],
+    };
+    let bytes = nanovdb_from_voxels("density", &voxels, 0.25f32, transform);
+    assert!(device.create_vdb_grid_from_nanovdb::<i32>(&bytes).is_err());
+    assert!(device
+        .create_vdb_grid_from_nanovdb::<f32>(&bytes[8..])
+        .is_err());
+    let grid = device.create_vdb_grid_from_nanovdb::<f32>(&bytes).unwrap();
+    assert_eq!(grid.info().name, "density");
+    assert_eq!(grid.info().active_voxels, 13);
+    assert_eq!(grid.info().node_count, [4, 3, 3]);
+
+    let mut queries = voxels.clone();
+    queries.extend([([9, 0, 0], 0.25), ([15, 1, 0], 0.25), ([-5000, 3, 3], 0.25)]);
+    let coords = queries
+        .iter()
+        .map(|(c, _)| Int3::new(c[0], c[1], c[2]))
+        .collect::<Vec<_>>();
+    let coord_buf = device.create_buffer_from_slice(&coords);
+    let values = device.create_buffer::<f32>(coords.len());
+    let results = device.create_buffer::<f32>(4);
+    let kernel = Kernel::<fn(VdbGrid<f32>)>::new(&device, &|grid: VdbGridVar<f32>| {
+        let i = dispatch_id().x;
+        values.write(i, grid.read(coord_buf.read(i)));
+        track!({
+            if i == 0u32 {
+                let pos = grid.index_to_world(Float3::expr(12.5, 0.0, 0.0));
+                results.write(0u32, grid.sample(pos));
+                let count = 0u32.var();
+                let length = 0.0f32.var();
+                let integral = 0.0f32.var();
+                let origin = grid.index_to_world(Float3::expr(-50.0, 0.5, 0.5));
+                grid.march(
+                    origin,
+                    Float3::expr(1.0, 0.0, 0.0),
+                    0.0f32,
+                    100.0f32,
+                    |s: &VdbSegment<f32>| {
+                        track!({
+                            *count += 1u32;
+                            *length += s.t1 - s.t0;
+                            *integral += (s.t1 - s.t0) * s.value;
+                        });
+                        true.expr()
+                    },
+                );
+                results.write(1u32, count.load().as_f32());
+                results.write(2u32, length.load());
+                results.write(3u32, integral.load());
+            }
+        });
+    });
+    kernel.dispatch([coords.len() as u32, 1, 1], &grid);
+    let values = values.copy_to_vec();
+    for ((c, expected), v) in queries.iter().zip(&values) {
+        assert_eq!(v, expected, "voxel {:?}", c);
+    }
+    let results = results.copy_to_vec();
+    assert!((results[0] - 12.5).abs() < 1e-4);
+    assert_eq!(results[1], 11.0);
+    assert!((results[2] - 5.5).abs() < 1e-3);
+    assert!((results[3] - 82.5).abs() < 1e-2);
+}
+#[test]
+fn image_processing() {
+    use luisa::image::*;
+    let device = get_device();
+    let images = ImageProcessor::new(&device);
+    let (w, h) = (8u32, 6u32);
+    let n = (w * h) as usize;
+    let tex = |data: &[Float4]| {
+        let t = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+        t.view(0).copy_from(data);
+        t
+    };
+    let close = |a: Float4, b: Float4| {
+        (a.x - b.x).abs() < 1e-4
+            && (a.y - b.y).abs() < 1e-4
+            && (a.z - b.z).abs() < 1e-4
+            && (a.w - b.w).abs() < 1e-4
+    };
+    let grey = Float4::new(0.5, 0.5, 0.5, 1.0);
+    let src = tex(&vec![grey; n]);
+    let dst = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    images.convert(&src, &dst, ColorSpace::Srgb, ColorSpace::LinearSrgb);
+    let linear = dst.view(0).copy_to_vec::<Float4>();
+    assert!((linear[0].x - 0.214041).abs() < 1e-4);
+    assert_eq!(linear[0].w, 1.0);
+    let back = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    images.convert(&dst, &back, ColorSpace::LinearSrgb, ColorSpace::Srgb);
+    assert!(back
+        .view(0)
+        .copy_to_vec::<Float4>()
+        .iter()
+        .all(|&c| close(c, grey)));
+    images.convert(&dst, &back, ColorSpace::LinearSrgb, ColorSpace::AcesCg);
+    images.convert(&back, &back, ColorSpace::AcesCg, ColorSpace::Rec2020);
+    images.convert(&back, &back, ColorSpace::Rec2020, ColorSpace::LinearSrgb);
+    let round_trip = back.view(0).copy_to_vec::<Float4>();
+    assert!(round_trip.iter().zip(&linear).all(|(&a, &b)| close(a, b)));
+
+    // constant images are preserved by every filter
+    for blur in [Blur::Gaussian { sigma: 1.5 }, Blur::Box { radius: 2 }] {
+        images.blur(&src, &dst, blur);
+        assert!(dst
+            .view(0)
+            .copy_to_vec::<Float4>()
+            .iter()
+            .all(|&c| close(c, grey)));
+    }
+    for filter in [ResizeFilter::Bilinear, ResizeFilter::Lanczos3] {
+        for (rw, rh) in [(3u32, 4u32), (13, 9)] {
+            let resized = device.create_tex2d::<Float4>(PixelStorage::Float4, rw, rh, 1);
+            images.resize(&src, &resized, filter);
+            let data = resized.view(0).copy_to_vec::<Float4>();
+            assert!(data.iter().all(|&c| close(c, grey)));
+        }
+    }
+    // a box blur of an impulse away from the edges keeps its energy, here
+    // through row-major buffers
+    let mut impulse = vec![Float4::new(0.0, 0.0, 0.0, 0.0); n];
+    impulse[(3 * w + 4) as usize] = Float4::new(25.0, 0.0, 0.0, 25.0);
+    let impulse = device.create_buffer_from_slice(&impulse);
+    let blurred = device.create_buffer::<Float4>(n);
+    images.blur(
+        &BufferImage::new(&impulse.view(..), w, h),
+        &BufferImage::new(&blurred.view(..), w, h),
+        Blur::Box { radius: 2 },
+    );
+    let blurred = blurred.copy_to_vec();
+    let energy = blurred.iter().map(|c| c.x).sum::<f32>();
+    assert!((energy - 25.0).abs() < 1e-3);
+    assert!((blurred[(3 * w + 4) as usize].x - 1.0).abs() < 1e-4);
+
+    let bright = tex(&vec![Float4::new(1.0, 1.0, 1.0, 1.0); n]);
+    images.tonemap(
+        &bright,
+        &dst,
+        TonemapOptions {
+            operator: Tonemap::Reinhard,
+            exposure: 1.0,
+            output: ColorSpace::LinearSrgb,
+        },
+    );
+    // 2 / (1 + 2)
+    let mapped = dst.view(0).copy_to_vec::<Float4>();
+    assert!((mapped[0].x - 2.0 / 3.0).abs() < 1e-4);
+
+    let options = HistogramOptions::default();
+    let histogram = images.luminance_histogram(&src, &options);
+    assert_eq!(histogram.iter().sum::<u32>(), w * h);
+    assert_eq!(histogram.iter().filter(|&&c| c > 0).count(), 1);
+    let exposure = images.auto_exposure(&tex(&linear), &options);
+    let bin_width = (options.max_log2 - options.min_log2) / options.bins as f32;
+    assert!((exposure - (0.18f32 / 0.214041).log2()).abs() <= bin_width * 0.5 + 1e-4);
+
+    let fg = tex(&vec![Float4::new(0.25, 0.0, 0.0, 0.5); n]);
+    let bg = tex(&vec![Float4::new(0.0, 0.0, 1.0, 1.0); n]);
+    images.composite(&fg, &bg, &dst, CompositeOp::Over);
+    let over = dst.view(0).copy_to_vec::<Float4>();
+    assert!(close(over[0], Float4::new(0.25, 0.0, 0.5, 1.0)));
+}
+#[test]
+fn builtin_denoiser() {
+    use luisa::runtime::extension::{DenoiserExt, DenoiserInput, ImageColorSpace, ImageFormat};
+    let device = get_device();
+    let (w, h) = (32u32, 16u32);
+    let n = (w * h) as usize;
+    let mut rng = StdRng::seed_from_u64(0);
+    // left and right halves differ in color and normal, with noise on top
+    let base = |x: u32| if x < w / 2 { 0.2f32 } else { 0.8 };
+    let noisy = (0..n)
+        .map(|i| {
+            let v = base(i as u32 % w) + rng.gen_range(-0.1..0.1);
+            Float4::new(v, v, v, 1.0)
+        })
+        .collect::<Vec<_>>();
+    let normals = (0..n)
+        .map(|i| {
+            if (i as u32 % w) < w / 2 {
+                Float4::new(0.0, 0.0, 1.0, 0.0)
+            } else {
+                Float4::new(1.0, 0.0, 0.0, 0.0)
+            }
+        })
+        .collect::<Vec<_>>();
+    let color = device.create_buffer_from_slice(&noisy);
+    let albedo = device.create_buffer_from_slice(&vec![Float4::new(1.0, 1.0, 1.0, 0.0); n]);
+    let normal = device.create_buffer_from_slice(&normals);
+    let output = device.create_buffer::<Float4>(n);
+    let mut denoiser = DenoiserExt::builtin(&device).create(&device.default_stream());
+    let mut input = DenoiserInput::new(w, h);
+    input
+        .push_noisy_image(
+            &color.view(..),
+            &output.view(..),
+            ImageFormat::Float3,
+            ImageColorSpace::Hdr,
+            None,
+        )
+        .push_feature_image(
+            "albedo",
+            &albedo.view(..),
+            ImageFormat::Float3,
+            ImageColorSpace::Hdr,
+        )
+        .push_feature_image(
+            "normal",
+            &normal.view(..),
+            ImageFormat::Float3,
+            ImageColorSpace::Hdr,
+        );
+    denoiser.init(input);
+    denoiser.execute(false);
+    let output = output.copy_to_vec();
+    let error = |image: &[Float4]| {
+        image
+            .iter()
+            .enumerate()
+            .map(|(i, c)| (c.x - base(i as u32 % w)).powi(2))
+            .sum::<f32>()
+            / n as f32
+    };
+    assert!(error(&output) < error(&noisy) * 0.5);
+    // the edge along the normal discontinuity is kept
+    for y in 0..h {
+        let row = (y * w) as usize;
+        assert!(output[row + (w / 2 - 1) as usize].x < 0.4);
+        assert!(output[row + (w / 2) as usize].x > 0.6);
+    }
+}
+#[test]
+fn temporal_reprojection() {
+    use luisa::image::*;
+    use luisa::lang::types::vector::Mat4;
+    let device = get_device();
+    let (w, h) = (16u32, 8u32);
+    let n = (w * h) as usize;
+    let texture = |data: &[Float4]| {
+        let t = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+        t.view(0).copy_from(data);
+        t
+    };
+    // positions at the pixel centers in normalized device coordinates, seen
+    // through an identity camera that moved by a quarter of the screen width
+    let positions = (0..n as u32)
+        .map(|i| {
+            let (x, y) = (i % w, i / w);
+            let ndc_x = (x as f32 + 0.5) / w as f32 * 2.0 - 1.0;
+            let ndc_y = 1.0 - (y as f32 + 0.5) / h as f32 * 2.0;
+            Float4::new(ndc_x, ndc_y, 0.5, 1.0)
+        })
+        .collect::<Vec<_>>();
+    let mut previous = Mat4::identity().to_column_array();
+    previous[3][0] = 0.25;
+    let camera = CameraMotion {
+        previous: Mat4::from_column_array(&previous),
+        current: Mat4::identity(),
+    };
+    let accumulator = TemporalAccumulator::new(
+        &device,
+        w,
+        h,
+        TemporalOptions {
+            max_samples: 16.0,
+            variance_clip: 0.0,
+        },
+    );
+    let motion = device.create_tex2d::<Float2>(PixelStorage::Float2, w, h, 1);
+    accumulator.motion_vectors(&texture(&positions), &motion, &camera);
+    for m in motion.view(0).copy_to_vec::<Float2>() {
+        assert!((m.x + 2.0).abs() < 1e-4 && m.y.abs() < 1e-4);
+    }
+
+    // content moving 2 pixels to the left is reprojected exactly
+    let frame = |shift: f32| {
+        texture(
+            &(0..n as u32)
+                .map(|i| Float4::new((i % w) as f32 + shift, 0.0, 0.0, 1.0))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let still = device.create_tex2d::<Float2>(PixelStorage::Float2, w, h, 1);
+    still.view(0).copy_from(&vec![Float2::new(0.0, 0.0); n]);
+    accumulator.accumulate(&frame(0.0), &still);
+    let output = accumulator.accumulate(&frame(2.0), &motion);
+    for (i, c) in output
+        .view(0)
+        .copy_to_vec::<Float4>()
+        .into_iter()
+        .enumerate()
+    {
+        let x = i as u32 % w;
+        assert!((c.x - (x as f32 + 2.0)).abs() < 1e-4);
+        // the rightmost columns were off-screen in the previous frame
+        assert_eq!(c.w, if x + 2 < w { 2.0 } else { 1.0 });
+    }
+    // a still camera keeps accumulating up to the sample cap
+    accumulator.reset();
+    for i in 0..20 {
+        accumulator.accumulate(&frame(i as f32), &still);
+    }
+    let output = accumulator.output().view(0).copy_to_vec::<Float4>();
+    assert_eq!(output[0].w, 16.0);
+    let clipped = TemporalAccumulator::new(&device, w, h, TemporalOptions::default());
+    let constant = texture(&vec![Float4::new(0.5, 0.5, 0.5, 1.0); n]);
+    clipped.accumulate(&frame(0.0), &still);
+    let output = clipped.accumulate(&constant, &still);
+    // with a flat neighborhood the history is clipped to the current frame
+    assert!(output
+        .view(0)
+        .copy_to_vec::<Float4>()
+        .iter()
+        .all(|c| (c.x - 0.5).abs() < 1e-4));
+}
+#[test]
+fn image_comparison() {
+    use luisa::image::*;
+    let device = get_device();
+    let images = ImageProcessor::new(&device);
+    let (w, h) = (24u32, 16u32);
+    let n = (w * h) as usize;
+    let tex = |data: &[Float4]| {
+        let t = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+        t.view(0).copy_from(data);
+        t
+    };
+    let constant = |v: f32| tex(&vec![Float4::new(v, v, v, 1.0); n]);
+    let gradient = tex(&(0..n as u32)
+        .map(|i| {
+            let (x, y) = ((i % w) as f32 / w as f32, (i / w) as f32 / h as f32);
+            Float4::new(x, y, x * y, 1.0)
+        })
+        .collect::<Vec<_>>());
+    assert_eq!(images.compare(&gradient, &gradient, ImageMetric::Mse), 0.0);
+    assert!((images.compare(&gradient, &gradient, ImageMetric::Ssim) - 1.0).abs() < 1e-4);
+    assert!(images.compare(&gradient, &gradient, ImageMetric::FLIP) < 1e-4);
+
+    let (a, b) = (constant(0.5), constant(0.25));
+    let mse = images.compare(&a, &b, ImageMetric::Mse);
+    assert!((mse - 0.0625).abs() < 1e-6);
+    assert!((psnr(mse) - 12.0412).abs() < 1e-3);
+    // only the luminance term of SSIM differs for constant images
+    let ssim = images.compare(&a, &b, ImageMetric::Ssim);
+    let expected = (2.0 * 0.5 * 0.25 + 1e-4) / (0.5f32 * 0.5 + 0.25 * 0.25 + 1e-4);
+    assert!((ssim - expected).abs() < 1e-4);
+    // black against white is close to the largest color difference
+    let (black, white) = (constant(0.0), constant(1.0));
+    let error_map = device.create_buffer::<Float4>(n);
+    let flip = images.compare_with_map(
+        &black,
+        &white,
+        &BufferImage::new(&error_map.view(..), w, h),
+        ImageMetric::FLIP,
+    );
+    assert!(flip > 0.95 && flip < 1.0);
+    assert!(error_map
+        .copy_to_vec()
+        .iter()
+        .all(|e| (e.x - flip).abs() < 1e-3));
+
+    luisa::assert_images_close!(&images, &gradient, &gradient, ImageMetric::FLIP, 0.01);
+    let failure = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        luisa::assert_images_close!(&images, &black, &white, ImageMetric::Mse, 0.5);
+    }))
+    .unwrap_err();
+    let message = failure.downcast_ref::<String>().unwrap();
+    let written = message.split("written to ").nth(1).unwrap();
+    let paths = written.split(", ").collect::<Vec<_>>();
+    assert_eq!(paths.len(), 3);
+    for path in paths {
+        assert!(std::path::Path::new(path).exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
+#[test]
+fn rasterization() {
+    use luisa::lang::types::vector::Mat4;
+    use luisa::raster::*;
+    use luisa::rtx::*;
+    let device = get_device();
+    let (w, h) = (32u32, 32u32);
+    let n = (w * h) as usize;
+    // vertices are already in clip space, the depth is z
+    let vertices = device.create_buffer_from_slice(&[
+        // a triangle in front of the center
+        Float4::new(-0.5, -0.5, 0.25, 1.0),
+        Float4::new(0.5, -0.5, 0.25, 1.0),
+        Float4::new(0.0, 0.5, 0.25, 1.0),
+        // a clockwise triangle covering everything, culled
+        Float4::new(-1.0, -1.0, 0.1, 1.0),
+        Float4::new(-1.0, 3.0, 0.1, 1.0),
+        Float4::new(3.0, -1.0, 0.1, 1.0),
+        // a full-screen quad behind
+        Float4::new(-1.0, -1.0, 0.5, 1.0),
+        Float4::new(1.0, -1.0, 0.5, 1.0),
+        Float4::new(1.0, 1.0, 0.5, 1.0),
+        Float4::new(-1.0, 1.0, 0.5, 1.0),
+    ]);
+    let indices =
+        device.create_buffer_from_slice::<Index>(&[[0, 1, 2], [3, 4, 5], [6, 7, 8], [6, 8, 9]]);
+    let mesh = device.create_mesh(vertices.view(..), indices.view(..), AccelOption::default());
+    let shader = RasterShader::<Float4, Float4, Mat4>::new(
+        &device,
+        RasterState::default(),
+        &|v, m| {
+            let p = *m * v;
+            (p, p)
+        },
+        &|f, v, _| {
+            let id = Float4::expr(f.primitive_id.as_f32(), f.depth, 0.0f32, 1.0f32);
+            vec![id, f.bary.interpolate(v[0], v[1], v[2])]
+        },
+    );
+    assert_eq!(shader.color_targets(), 2);
+    let ids = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    let positions = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    let depth = DepthBuffer::new(&device, w, h);
+    let targets = RenderTargets {
+        colors: &[&ids, &positions],
+        depth: Some(&depth),
+    };
+    {
+        let stream = device.default_stream();
+        let scope = stream.scope();
+        scope.submit([depth.clear_async(1.0)]);
+        scope.draw(&shader, &mesh, &targets, &Mat4::identity());
+    }
+    let ids = ids.view(0).copy_to_vec::<Float4>();
+    let positions = positions.view(0).copy_to_vec::<Float4>();
+    let depths = depth.texture().view(0).copy_to_vec::<f32>();
+    let center = (16 * w + 16) as usize;
+    assert_eq!(ids[center].x, 0.0);
+    assert_eq!(depths[center], 0.25);
+    for (i, id) in ids.iter().enumerate() {
+        let (x, y) = (i as u32 % w, i as u32 / w);
+        // away from the center triangle and the diagonal of the quad
+        if (x < 4 || x >= w - 4 || y < 4 || y >= h - 4) && x + y != w - 1 {
+            let expected = if x + y > w - 1 { 2.0 } else { 3.0 };
+            assert_eq!(id.x, expected);
+            assert_eq!(depths[i], 0.5);
+        }
+        // interpolated clip positions land on the pixel centers
+        let ndc_x = (x as f32 + 0.5) / w as f32 * 2.0 - 1.0;
+        let ndc_y = 1.0 - (y as f32 + 0.5) / h as f32 * 2.0;
+        assert!((positions[i].x - ndc_x).abs() < 1e-4);
+        assert!((positions[i].y - ndc_y).abs() < 1e-4);
+        assert!((positions[i].z - depths[i]).abs() < 1e-4);
+    }
+
+    // triangles beyond the capacity of a tile go through the global list,
+    // the nearest one is also the last
+    let stack = 300u32;
+    let vertices = device.create_buffer_from_slice(
+        &(0..stack)
+            .flat_map(|i| {
+                let z = 1.0 - (i + 1) as f32 / (stack + 1) as f32;
+                [
+                    Float4::new(-1.0, -1.0, z, 1.0),
+                    Float4::new(3.0, -1.0, z, 1.0),
+                    Float4::new(-1.0, 3.0, z, 1.0),
+                ]
+            })
+            .collect::<Vec<_>>(),
+    );
+    let indices = device.create_buffer_from_slice::<Index>(
+        &(0..stack)
+            .map(|i| [3 * i, 3 * i + 1, 3 * i + 2])
+            .collect::<Vec<_>>(),
+    );
+    let mesh = device.create_mesh(vertices.view(..), indices.view(..), AccelOption::default());
+    let ids = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    let positions = device.create_tex2d::<Float4>(PixelStorage::Float4, w, h, 1);
+    depth.clear(1.0);
+    {
+        let stream = device.default_stream();
+        stream.scope().draw(
+            &shader,
+            &mesh,
+            &RenderTargets {
+                colors: &[&ids, &positions],
+                depth: Some(&depth),
+            },
+            &Mat4::identity(),
+        );
+    }
+    let ids = ids.view(0).copy_to_vec::<Float4>();
+    assert_eq!(ids.len(), n);
+    assert!(ids.iter().all(|id| id.x == (stack - 1) as f32));
+}
@@ -4,6 +4,7 @@ use proc_macro2::{Ident, TokenStream, TokenTree};
 use quote::{quote, quote_spanned};
 use syn::ext::IdentExt;
 use syn::parse::Parse;
+use syn::punctuated::Punctuated;
 use syn::spanned::Spanned;
 use syn::{Attribute, Item, ItemEnum, ItemStruct, Token, Visibility};
 
@@ -373,6 +374,9 @@ impl Compiler {
     pub fn derive_value_for_enum(&mut self, enum_: &ItemEnum) -> TokenStream {
         let attrs = self.parse_luisa_attributes(&enum_.attrs);
         self.set_crate_path_from_attrs(&attrs);
+        if enum_.variants.iter().any(|v| !v.fields.is_empty()) {
+            return self.derive_value_for_tagged_union(enum_);
+        }
         let repr = enum_
             .attrs
             .iter()
@@ -449,6 +453,161 @@ impl Compiler {
             }
         }
     }
+    /// Enums with payloads are laid out as a `u32` tag followed by the largest payload
+    pub fn derive_value_for_tagged_union(&mut self, enum_: &ItemEnum) -> TokenStream {
+        let repr = enum_
+            .attrs
+            .iter()
+            .filter_map(|attr| match &attr.meta {
+                syn::Meta::List(list) if list.path.is_ident("repr") => list
+                    .parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+                    .ok(),
+                _ => None,
+            })
+            .flatten()
+            .map(|ident| ident.to_string())
+            .collect::<Vec<_>>();
+        if !(repr.contains(&"C".to_string()) && repr.contains(&"u32".to_string())) {
+            panic!("Enum with fields must have repr(C, u32) attribute.");
+        }
+        if !enum_.generics.params.is_empty() {
+            panic!("Generic enum with fields is not supported");
+        }
+        let span = enum_.span();
+        let crate_path = self.crate_path.clone();
+        let lang_path = self.lang_path();
+        let name = &enum_.ident;
+        let vis = &enum_.vis;
+        let expr_proxy_name = syn::Ident::new(&format!("{}Expr", name), name.span());
+        let var_proxy_name = syn::Ident::new(&format!("{}Var", name), name.span());
+        let atomic_ref_proxy_name = syn::Ident::new(&format!("{}AtomicRef", name), name.span());
+        let match_name = syn::Ident::new(&format!("{}Match", name), name.span());
+
+        let mut payload_defs = vec![];
+        let mut match_variants = vec![];
+        let mut ctors = vec![];
+        let mut cases = vec![];
+        for (i, v) in enum_.variants.iter().enumerate() {
+            if v.discriminant.is_some() {
+                panic!("Enum with fields must not have explicit discriminants");
+            }
+            let tag = i as u32;
+            let case = i as i32;
+            let v_name = &v.ident;
+            let ctor_name = syn::Ident::new(
+                &format!("new_{}_expr", to_snake_case(&v_name.to_string())),
+                v_name.span(),
+            );
+            if v.fields.is_empty() {
+                match_variants.push(quote_spanned!(span=> #v_name));
+                ctors.push(quote_spanned!(span=>
+                    #vis fn #ctor_name() -> #lang_path::types::Expr<#name> {
+                        #lang_path::types::tagged_union::__tagged_union_new_unit::<#name>(#tag)
+                    }
+                ));
+                cases.push(quote_spanned!(span=>
+                    .case(#case, || f(#match_name::#v_name))
+                ));
+                continue;
+            }
+            let payload_name =
+                syn::Ident::new(&format!("{}{}Payload", name, v_name), v_name.span());
+            let field_names = v
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(j, f)| {
+                    f.ident
+                        .clone()
+                        .unwrap_or_else(|| syn::Ident::new(&format!("_{}", j), f.span()))
+                })
+                .collect::<Vec<_>>();
+            let field_types = v.fields.iter().map(|f| &f.ty).collect::<Vec<_>>();
+            let payload: ItemStruct = syn::parse_quote_spanned!(span=>
+                #[repr(C)]
+                #[derive(Clone, Copy)]
+                #[doc(hidden)]
+                #vis struct #payload_name {
+                    #(pub #field_names: #field_types),*
+                }
+            );
+            let payload_value = self.derive_value_for_struct(&payload);
+            payload_defs.push(quote_spanned!(span=>
+                #payload
+                #payload_value
+            ));
+            let (match_variant, construct) = match &v.fields {
+                syn::Fields::Named(_) => (
+                    quote_spanned!(span=> #v_name { #(#field_names: #lang_path::types::Expr<#field_types>),* }),
+                    quote_spanned!(span=> #match_name::#v_name { #(#field_names: __payload.#field_names),* }),
+                ),
+                _ => (
+                    quote_spanned!(span=> #v_name ( #(#lang_path::types::Expr<#field_types>),* )),
+                    quote_spanned!(span=> #match_name::#v_name ( #(__payload.#field_names),* )),
+                ),
+            };
+            match_variants.push(match_variant);
+            ctors.push(quote_spanned!(span=>
+                #vis fn #ctor_name(#(#field_names: impl #lang_path::types::AsExpr<Value = #field_types>),*) -> #lang_path::types::Expr<#name> {
+                    use #lang_path::*;
+                    let node = #lang_path::__compose::<#payload_name>(&[ #( #lang_path::ToNode::node(&#field_names.as_expr()).get() ),* ]);
+                    let payload = <#lang_path::types::Expr::<#payload_name> as #lang_path::FromNode>::from_node(node.into());
+                    #lang_path::types::tagged_union::__tagged_union_new::<#name, #payload_name>(#tag, payload)
+                }
+            ));
+            cases.push(quote_spanned!(span=>
+                .case(#case, || {
+                    let __payload = #lang_path::types::tagged_union::__tagged_union_payload::<#name, #payload_name>(__self);
+                    f(#construct)
+                })
+            ));
+        }
+        quote_spanned! {span=>
+            #(#payload_defs)*
+
+            impl #lang_path::types::Value for #name {
+                type Expr = #expr_proxy_name;
+                type Var = #var_proxy_name;
+                type AtomicRef = #atomic_ref_proxy_name;
+            }
+            impl #lang_path::ir::TypeOf for #name {
+                fn type_() -> #lang_path::ir::CArc<#lang_path::ir::Type> {
+                    #lang_path::types::tagged_union::__tagged_union_type(
+                        std::mem::size_of::<#name>(),
+                        std::mem::align_of::<#name>(),
+                    )
+                }
+            }
+
+            #crate_path::impl_simple_expr_proxy!(#expr_proxy_name for #name);
+            #crate_path::impl_simple_var_proxy!(#var_proxy_name for #name);
+            #crate_path::impl_simple_atomic_ref_proxy!(#atomic_ref_proxy_name for #name);
+
+            /// Fields of the active variant, passed to the closure of `match_`
+            #[allow(dead_code)]
+            #vis enum #match_name {
+                #(#match_variants),*
+            }
+            #[allow(dead_code)]
+            impl #name {
+                #(#ctors)*
+            }
+            impl #expr_proxy_name {
+                #vis fn tag(&self) -> #lang_path::types::Expr<u32> {
+                    use #lang_path::types::ExprProxy;
+                    #lang_path::types::tagged_union::__tagged_union_tag(*self.as_expr_from_proxy())
+                }
+                /// Records a `switch` over the tag and calls `f` with the fields of each variant
+                #vis fn match_<R: #lang_path::Aggregate>(&self, f: impl Fn(#match_name) -> R) -> R {
+                    use #lang_path::types::ExprProxy;
+                    let __self = *self.as_expr_from_proxy();
+                    #lang_path::control_flow::switch::<R>(self.tag().as_i32())
+                        #(#cases)*
+                        .finish()
+                }
+            }
+        }
+    }
     pub fn derive_value_for_struct(&mut self, struct_: &ItemStruct) -> TokenStream {
         let attrs = self.parse_luisa_attributes(&struct_.attrs);
         self.set_crate_path_from_attrs(&attrs);
@@ -839,3 +998,18 @@ impl Compiler {
         }
     }
 }
+
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i != 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}